@@ -3,15 +3,17 @@
 //! The types defined here get used throughout iamb.
 use std::borrow::Cow;
 use std::collections::hash_map::IntoIter;
-use std::collections::{BTreeSet, HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 use std::convert::TryFrom;
 use std::fmt::{self, Display};
 use std::hash::Hash;
+use std::ops::Bound;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use emojis::Emoji;
+use mime::Mime;
 use ratatui::{
     buffer::Buffer,
     layout::{Alignment, Rect},
@@ -32,12 +34,13 @@ use url::Url;
 
 use matrix_sdk::{
     encryption::verification::SasVerification,
-    room::Room as MatrixRoom,
+    notification_settings::RoomNotificationMode,
+    room::{Room as MatrixRoom, RoomMember},
     ruma::{
         events::{
             reaction::ReactionEvent,
             relation::{Replacement, Thread},
-            room::encrypted::RoomEncryptedEvent,
+            room::encrypted::{OriginalRoomEncryptedEvent, RoomEncryptedEvent},
             room::message::{
                 OriginalRoomMessageEvent,
                 Relation,
@@ -51,8 +54,12 @@ use matrix_sdk::{
         },
         presence::PresenceState,
         EventId,
+        MilliSecondsSinceUnixEpoch,
+        OwnedDeviceId,
         OwnedEventId,
+        OwnedRoomAliasId,
         OwnedRoomId,
+        OwnedTransactionId,
         OwnedUserId,
         RoomId,
         RoomVersionId,
@@ -75,6 +82,7 @@ use modalkit::{
         completion::{complete_path, CompletionMap},
         context::EditContext,
         cursor::Cursor,
+        history::HistoryList,
         rope::EditRope,
         store::Store,
     },
@@ -85,14 +93,23 @@ use modalkit::{
     errors::{UIError, UIResult},
     key::TerminalKey,
     keybindings::SequenceStatus,
-    prelude::{CommandType, WordStyle},
+    prelude::{CommandType, MoveDir1D, WordStyle},
 };
 
 use crate::config::ImagePreviewProtocolValues;
 use crate::message::ImageStatus;
-use crate::preview::{source_from_event, spawn_insert_preview};
+use crate::preview::{source_from_event, spawn_insert_preview, spawn_url_preview};
 use crate::{
-    message::{Message, MessageEvent, MessageKey, MessageTimeStamp, Messages},
+    message::{
+        CustomEvent,
+        MembershipEvent,
+        MembershipGroup,
+        Message,
+        MessageEvent,
+        MessageKey,
+        MessageTimeStamp,
+        Messages,
+    },
     worker::Requester,
     ApplicationSettings,
 };
@@ -113,6 +130,15 @@ fn is_mxid_char(c: char) -> bool {
 }
 
 const ROOM_FETCH_DEBOUNCE: Duration = Duration::from_secs(2);
+const MEMBER_FETCH_DEBOUNCE: Duration = Duration::from_secs(5);
+const PIN_FETCH_DEBOUNCE: Duration = Duration::from_secs(5);
+
+/// The most messages to fetch for a server-side search, since each page is a separate request.
+pub const MAX_SEARCH_HITS: usize = 500;
+
+/// How many recently used reactions to remember, which is as many as `:react recent` can offer
+/// under the keys 1 through 9.
+pub const MAX_RECENT_REACTIONS: usize = 9;
 
 /// Empty type used solely to implement [ApplicationInfo].
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -137,11 +163,22 @@ pub enum VerifyAction {
 /// An action taken against the currently selected message.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum MessageAction {
+    /// Accept or decline the verification request sent in a message.
+    ///
+    /// The [bool] argument indicates whether to accept the request.
+    AnswerVerification(bool),
+
     /// Cance the current reply or edit.
     ///
     /// The [bool] argument indicates whether to skip confirmation for clearing the message bar.
     Cancel(bool),
 
+    /// Stop editing a message, and clear the message bar.
+    CancelEdit,
+
+    /// Stop replying to a message, keeping the message bar's contents.
+    CancelReply,
+
     /// Download an attachment to the given path.
     ///
     /// The second argument controls whether to overwrite any already existing file at the
@@ -149,18 +186,64 @@ pub enum MessageAction {
     Download(Option<String>, DownloadFlags),
 
     /// Edit a sent message.
-    Edit,
+    Edit(MessageTarget),
+
+    /// Forward a message to another room, given by its identifier, alias or name.
+    Forward(String),
+
+    /// Move the scrollback cursor to the message that the selected one replies to.
+    GotoParent,
+
+    /// Move the scrollback cursor to a message.
+    Jump(OwnedEventId),
+
+    /// Open one of a message's links, given by the label shown after it, or ask which one to
+    /// open when there are several.
+    OpenLink(Option<char>),
 
     /// React to a message with an Emoji.
     React(String),
 
+    /// Pick one of the recently used Emoji to react to a message with.
+    ReactRecent,
+
     /// Redact a message, with an optional reason.
     ///
     /// The [bool] argument indicates whether to skip confirmation.
-    Redact(Option<String>, bool),
+    Redact(Option<String>, bool, MessageTarget),
+
+    /// Move the user's read marker back to just before a message, so that it shows up as unread.
+    MarkUnread,
+
+    /// Show who has read up to a message, and when.
+    Receipts,
+
+    /// Add a message to the selection that [MessageAction::Download] and
+    /// [MessageAction::Redact] act on, or remove it if it's already been selected.
+    Select,
+
+    /// Empty the selection of messages.
+    SelectClear,
 
     /// Reply to a message.
-    Reply,
+    ///
+    /// The [bool] argument indicates whether to keep replying to this message after sending,
+    /// until the reply is cancelled.
+    Reply(bool),
+
+    /// Reply in the thread that a message starts, without leaving the room's main timeline.
+    ReplyThread,
+
+    /// Restore the room key for a message that couldn't be decrypted from the key backup, and
+    /// try again.
+    RestoreKeys,
+
+    /// Take back the most recently sent message, cancelling it if it's still waiting to be sent,
+    /// and redacting it otherwise.
+    Unsend,
+
+    /// Queue the selected message to be sent again after the server rejected it.
+    Resend,
 
     /// Unreact to a message.
     ///
@@ -169,6 +252,37 @@ pub enum MessageAction {
     Unreact(Option<String>),
 }
 
+impl MessageAction {
+    /// Whether this action stops replying to or editing a message.
+    pub fn is_cancel(&self) -> bool {
+        matches!(self, MessageAction::CancelEdit | MessageAction::CancelReply)
+    }
+
+    /// The message that this action should be performed on.
+    pub fn target(&self) -> MessageTarget {
+        match self {
+            MessageAction::Edit(target) => target.clone(),
+            MessageAction::Jump(event_id) => MessageTarget::Event(event_id.clone()),
+            MessageAction::Redact(_, _, target) => target.clone(),
+            _ => MessageTarget::Selected,
+        }
+    }
+}
+
+/// Which message in the scrollback a [MessageAction] applies to.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub enum MessageTarget {
+    /// The message under the scrollback cursor.
+    #[default]
+    Selected,
+
+    /// The most recent message sent by the user.
+    LastOwn,
+
+    /// A specific message in the room's timeline.
+    Event(OwnedEventId),
+}
+
 /// The type of room being created.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum CreateRoomType {
@@ -209,6 +323,9 @@ bitflags::bitflags! {
 
         /// Open file after downloading.
         const OPEN = 0b00000010;
+
+        /// Skip asking before downloading a large attachment.
+        const CONFIRMED = 0b00000100;
     }
 }
 
@@ -235,6 +352,10 @@ pub enum SortFieldRoom {
 
     /// Sort rooms by the timestamps of their most recent messages.
     Recent,
+
+    /// Sort rooms by their position in the account's breadcrumbs, falling back to how recently
+    /// they had activity.
+    Breadcrumbs,
 }
 
 /// Fields that users can be sorted by.
@@ -295,6 +416,7 @@ impl<'de> Visitor<'de> for SortRoomVisitor {
             "favorite" => SortFieldRoom::Favorite,
             "lowpriority" => SortFieldRoom::LowPriority,
             "recent" => SortFieldRoom::Recent,
+            "breadcrumbs" => SortFieldRoom::Breadcrumbs,
             "unread" => SortFieldRoom::Unread,
             "name" => SortFieldRoom::Name,
             "alias" => SortFieldRoom::Alias,
@@ -361,9 +483,21 @@ impl<'de> Visitor<'de> for SortUserVisitor {
 /// A room property.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum RoomField {
+    /// The room's avatar image.
+    Avatar,
+
+    /// The room's canonical alias.
+    CanonicalAlias,
+
+    /// The strftime-style format used to show message timestamps in this room.
+    MessageTimestamp,
+
     /// The room name.
     Name,
 
+    /// Which messages in the room should notify the user.
+    NotificationMode,
+
     /// A room tag.
     Tag(TagName),
 
@@ -371,6 +505,25 @@ pub enum RoomField {
     Topic,
 }
 
+/// Parse a level given to `:room notify`.
+pub fn parse_notification_mode(level: &str) -> Option<RoomNotificationMode> {
+    match level {
+        "all" => Some(RoomNotificationMode::AllMessages),
+        "mentions" => Some(RoomNotificationMode::MentionsAndKeywordsOnly),
+        "mute" => Some(RoomNotificationMode::Mute),
+        _ => None,
+    }
+}
+
+/// Describe a notification mode using the level names accepted by `:room notify`.
+pub fn notification_mode_name(mode: RoomNotificationMode) -> &'static str {
+    match mode {
+        RoomNotificationMode::AllMessages => "all",
+        RoomNotificationMode::MentionsAndKeywordsOnly => "mentions",
+        RoomNotificationMode::Mute => "mute",
+    }
+}
+
 /// An action that operates on a focused room.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum RoomAction {
@@ -380,23 +533,90 @@ pub enum RoomAction {
     /// Reject an invitation to join this room.
     InviteReject,
 
+    /// Copy a shareable matrix.to link for this room to the clipboard.
+    InviteLink,
+
     /// Invite a user to this room.
     InviteSend(OwnedUserId),
 
+    /// Take back the pending invitation of a user to this room.
+    InviteRevoke(OwnedUserId),
+
+    /// Kick a user out of this room, with an optional reason.
+    Kick(OwnedUserId, Option<String>),
+
+    /// Ban a user from this room, with an optional reason.
+    Ban(OwnedUserId, Option<String>),
+
+    /// Lift a user's ban from this room.
+    Unban(OwnedUserId),
+
+    /// Edit a room property in an external editor, and then set it.
+    Edit(RoomField),
+
+    /// Open a gallery of the images in this room.
+    Gallery,
+
+    /// Open the list of messages pinned in this room.
+    Pins,
+
     /// Leave this room.
     Leave(bool),
 
+    /// Mark this room as read.
+    ///
+    /// The [bool] argument indicates whether to only mark up to the selected message.
+    MarkRead(bool),
+
+    /// Leave this space, along with each of its rooms that the user has joined.
+    ///
+    /// The [bool] argument indicates whether to skip confirmation.
+    LeaveSpace(bool),
+
     /// Open the members window.
     Members(Box<CommandContext>),
 
+    /// Show how many members are in this room, and how many are online.
+    MemberCount,
+
+    /// Send a sample notification for this room.
+    NotificationTest,
+
+    /// Select the closest message in a direction whose body contains some text.
+    Search(String, MoveDir1D),
+
+    /// Ask the server for messages matching some text, and list them in a new window.
+    SearchServer(String),
+
     /// Set whether a room is a direct message.
     SetDirect(bool),
 
+    /// Show the room's canonical alias.
+    ShowCanonicalAlias,
+
+    /// Show the URL of the room's avatar.
+    ShowAvatar,
+
+    /// Show which messages in the room will notify the user.
+    ShowNotificationMode,
+
+    /// Show the timestamp format used in the room.
+    ShowTimestampFormat,
+
     /// Set a room property.
     Set(RoomField, String),
 
     /// Unset a room property.
     Unset(RoomField),
+
+    /// Upgrade this room to a new room version, optionally skipping confirmation.
+    Upgrade(Option<RoomVersionId>, bool),
+
+    /// Check whether this room's version is outdated.
+    VersionCheck,
+
+    /// Stop warning about this room's outdated version.
+    VersionDismiss,
 }
 
 /// An action that sends a message to a room.
@@ -405,14 +625,25 @@ pub enum SendAction {
     /// Send the text in the message bar.
     Submit,
 
+    /// Send the text in the message bar, without checking whether it looks like pasted file
+    /// paths.
+    SubmitText,
+
     /// Send text provided from an external editor.
     SubmitFromEditor,
 
     /// Upload a file.
-    Upload(String),
+    ///
+    /// The second and third arguments are an optional caption to send as the message body, and
+    /// an optional mime type to use instead of guessing it from the file's extension.
+    Upload(String, Option<String>, Option<Mime>),
 
     /// Upload the image data.
     UploadImage(usize, usize, Cow<'static, [u8]>),
+
+    /// Replace the message bar contents with the next older sent message containing the text
+    /// that was in the message bar when the search started.
+    SearchHistory,
 }
 
 /// An action performed against the user's homeserver.
@@ -420,7 +651,22 @@ pub enum SendAction {
 pub enum HomeserverAction {
     /// Create a new room with an optional localpart.
     CreateRoom(Option<String>, CreateRoomType, CreateRoomFlags),
-    Logout(String, bool),
+
+    /// Create a new space with the given name, and add the given rooms to it as children.
+    CreateSpace(String, Vec<String>),
+
+    /// Log out of the given account and quit.
+    ///
+    /// The first [bool] argument indicates whether to also erase the local store, including the
+    /// encryption keys, and the second whether the logout has already been confirmed.
+    Logout(String, bool, bool),
+
+    /// Remove the cached room state so that it gets fetched again on the next start, while
+    /// keeping the encryption keys.
+    ClearCache(bool),
+
+    /// Leave each of the given rooms, carrying on past any that fail.
+    LeaveRooms(Vec<OwnedRoomId>),
 }
 
 /// An action performed against the user's room keys.
@@ -432,17 +678,54 @@ pub enum KeysAction {
     Import(String, String),
 }
 
+/// An action that sets up or restores the account's encryption secrets.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RecoveryAction {
+    /// Bootstrap cross-signing if needed, and back up the secrets under a new recovery key.
+    Setup,
+    /// Import the secrets stored on the homeserver using a recovery key or passphrase.
+    Restore(String),
+}
+
+/// An action performed on the `:debug events` log.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DebugAction {
+    /// Set or clear the event type that the log is filtered down to.
+    Filter(Option<String>),
+
+    /// Stop or resume recording incoming events.
+    TogglePause,
+}
+
 /// An action that the main program loop should.
 ///
 /// See [the commands module][super::commands] for where these are usually created.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum IambAction {
+    /// Check the loaded configuration for problems.
+    CheckConfig,
+
+    /// Remove the oldest image previews until the cache fits in its size limit.
+    CleanCache,
+
+    /// Perform an action on the `:debug events` log.
+    Debug(DebugAction),
+
+    /// Set our presence, along with an optional status message.
+    SetPresence(PresenceState, Option<String>),
+
+    /// Add a user to, or remove them from, the account's ignored users.
+    SetIgnored(OwnedUserId, bool),
+
     /// Perform an action against the homeserver.
     Homeserver(HomeserverAction),
 
     /// Perform an action over room keys.
     Keys(KeysAction),
 
+    /// Set up or restore from the account's key backup.
+    Recovery(RecoveryAction),
+
     /// Perform an action on the currently selected message.
     Message(MessageAction),
 
@@ -461,14 +744,32 @@ pub enum IambAction {
     /// Request a new verification with the specified user.
     VerifyRequest(String),
 
+    /// Accept a change to the specified user's identity, and stop warning about it.
+    VerifyAcknowledge(String),
+
+    /// Stop warning about the user's own unverified sessions.
+    VerifyDismiss,
+
     /// Toggle the focus within the focused room.
     ToggleScrollbackFocus,
+
+    /// Switch the room list between grouping rooms by space and showing them all together.
+    ToggleRoomGrouping,
 }
 
 impl IambAction {
     /// Indicates whether this action will draw over the screen.
     pub fn scribbles(&self) -> bool {
-        matches!(self, IambAction::Send(SendAction::SubmitFromEditor))
+        matches!(
+            self,
+            IambAction::Send(SendAction::SubmitFromEditor) | IambAction::Room(RoomAction::Edit(_))
+        )
+    }
+}
+
+impl From<DebugAction> for IambAction {
+    fn from(act: DebugAction) -> Self {
+        IambAction::Debug(act)
     }
 }
 
@@ -499,57 +800,93 @@ impl From<SendAction> for IambAction {
 impl ApplicationAction for IambAction {
     fn is_edit_sequence(&self, _: &EditContext) -> SequenceStatus {
         match self {
+            IambAction::CheckConfig => SequenceStatus::Break,
+            IambAction::SetPresence(..) => SequenceStatus::Break,
+            IambAction::SetIgnored(..) => SequenceStatus::Break,
+            IambAction::CleanCache => SequenceStatus::Break,
+            IambAction::Debug(..) => SequenceStatus::Break,
             IambAction::Homeserver(..) => SequenceStatus::Break,
             IambAction::Keys(..) => SequenceStatus::Break,
+            IambAction::Recovery(..) => SequenceStatus::Break,
             IambAction::Message(..) => SequenceStatus::Break,
             IambAction::Room(..) => SequenceStatus::Break,
             IambAction::OpenLink(..) => SequenceStatus::Break,
             IambAction::Send(..) => SequenceStatus::Break,
             IambAction::ToggleScrollbackFocus => SequenceStatus::Break,
+            IambAction::ToggleRoomGrouping => SequenceStatus::Break,
             IambAction::Verify(..) => SequenceStatus::Break,
             IambAction::VerifyRequest(..) => SequenceStatus::Break,
+            IambAction::VerifyAcknowledge(..) => SequenceStatus::Break,
+            IambAction::VerifyDismiss => SequenceStatus::Break,
         }
     }
 
     fn is_last_action(&self, _: &EditContext) -> SequenceStatus {
         match self {
+            IambAction::CheckConfig => SequenceStatus::Atom,
+            IambAction::SetPresence(..) => SequenceStatus::Atom,
+            IambAction::SetIgnored(..) => SequenceStatus::Atom,
+            IambAction::CleanCache => SequenceStatus::Atom,
+            IambAction::Debug(..) => SequenceStatus::Atom,
             IambAction::Homeserver(..) => SequenceStatus::Atom,
             IambAction::Keys(..) => SequenceStatus::Atom,
+            IambAction::Recovery(..) => SequenceStatus::Atom,
             IambAction::Message(..) => SequenceStatus::Atom,
             IambAction::OpenLink(..) => SequenceStatus::Atom,
             IambAction::Room(..) => SequenceStatus::Atom,
             IambAction::Send(..) => SequenceStatus::Atom,
             IambAction::ToggleScrollbackFocus => SequenceStatus::Atom,
+            IambAction::ToggleRoomGrouping => SequenceStatus::Atom,
             IambAction::Verify(..) => SequenceStatus::Atom,
             IambAction::VerifyRequest(..) => SequenceStatus::Atom,
+            IambAction::VerifyAcknowledge(..) => SequenceStatus::Atom,
+            IambAction::VerifyDismiss => SequenceStatus::Atom,
         }
     }
 
     fn is_last_selection(&self, _: &EditContext) -> SequenceStatus {
         match self {
+            IambAction::CheckConfig => SequenceStatus::Ignore,
+            IambAction::SetPresence(..) => SequenceStatus::Ignore,
+            IambAction::SetIgnored(..) => SequenceStatus::Ignore,
+            IambAction::CleanCache => SequenceStatus::Ignore,
+            IambAction::Debug(..) => SequenceStatus::Ignore,
             IambAction::Homeserver(..) => SequenceStatus::Ignore,
             IambAction::Keys(..) => SequenceStatus::Ignore,
+            IambAction::Recovery(..) => SequenceStatus::Ignore,
             IambAction::Message(..) => SequenceStatus::Ignore,
             IambAction::Room(..) => SequenceStatus::Ignore,
             IambAction::OpenLink(..) => SequenceStatus::Ignore,
             IambAction::Send(..) => SequenceStatus::Ignore,
             IambAction::ToggleScrollbackFocus => SequenceStatus::Ignore,
+            IambAction::ToggleRoomGrouping => SequenceStatus::Ignore,
             IambAction::Verify(..) => SequenceStatus::Ignore,
             IambAction::VerifyRequest(..) => SequenceStatus::Ignore,
+            IambAction::VerifyAcknowledge(..) => SequenceStatus::Ignore,
+            IambAction::VerifyDismiss => SequenceStatus::Ignore,
         }
     }
 
     fn is_switchable(&self, _: &EditContext) -> bool {
         match self {
+            IambAction::CheckConfig => false,
+            IambAction::SetPresence(..) => false,
+            IambAction::SetIgnored(..) => false,
+            IambAction::CleanCache => false,
+            IambAction::Debug(..) => false,
             IambAction::Homeserver(..) => false,
             IambAction::Message(..) => false,
             IambAction::Room(..) => false,
             IambAction::Keys(..) => false,
+            IambAction::Recovery(..) => false,
             IambAction::Send(..) => false,
             IambAction::OpenLink(..) => false,
             IambAction::ToggleScrollbackFocus => false,
+            IambAction::ToggleRoomGrouping => false,
             IambAction::Verify(..) => false,
             IambAction::VerifyRequest(..) => false,
+            IambAction::VerifyAcknowledge(..) => false,
+            IambAction::VerifyDismiss => false,
         }
     }
 }
@@ -589,6 +926,66 @@ pub type IambResult<T> = UIResult<T, IambInfo>;
 /// it's reacting to.
 pub type MessageReactions = HashMap<OwnedEventId, (String, OwnedUserId)>;
 
+#[derive(Deserialize)]
+struct BundledEvent {
+    unsigned: BundledUnsigned,
+}
+
+#[derive(Deserialize)]
+struct BundledUnsigned {
+    #[serde(rename = "m.relations")]
+    relations: BundledRelations,
+}
+
+#[derive(Deserialize)]
+struct BundledRelations {
+    #[serde(rename = "m.annotation")]
+    annotation: BundledAnnotations,
+}
+
+#[derive(Deserialize)]
+struct BundledAnnotations {
+    chunk: Vec<BundledAnnotation>,
+}
+
+#[derive(Deserialize)]
+struct BundledAnnotation {
+    #[serde(rename = "type")]
+    event_type: String,
+    key: String,
+    count: usize,
+}
+
+/// Get the reaction counts that the server bundled into an event's `unsigned` data, if any.
+///
+/// Newer homeservers no longer bundle annotations, in which case this returns `None` and the
+/// counts come entirely from the individual reaction events.
+pub fn bundled_reaction_counts(json: &str) -> Option<HashMap<String, usize>> {
+    let ev = serde_json::from_str::<BundledEvent>(json).ok()?;
+    let counts = ev
+        .unsigned
+        .relations
+        .annotation
+        .chunk
+        .into_iter()
+        .filter(|a| a.event_type == "m.reaction")
+        .map(|a| (a.key, a.count))
+        .collect();
+
+    Some(counts)
+}
+
+/// The content field that iamb sets to `true` on a message it forwards.
+pub const FORWARDED_FIELD: &str = "chat.iamb.forwarded";
+
+/// Whether a message's JSON marks it as forwarded from another room.
+pub fn is_forwarded(json: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(json)
+        .ok()
+        .and_then(|ev| ev.get("content")?.get(FORWARDED_FIELD).cloned())
+        .is_some_and(|field| field == serde_json::Value::Bool(true))
+}
+
 /// Errors encountered during application use.
 #[derive(thiserror::Error, Debug)]
 pub enum IambError {
@@ -596,10 +993,34 @@ pub enum IambError {
     #[error("Invalid user identifier: {0}")]
     InvalidUserId(String),
 
+    /// The homeserver doesn't allow logging in with a password.
+    #[error("This homeserver doesn't support password login, try one of: {0}")]
+    PasswordLoginUnsupported(String),
+
+    /// Logging in needs authentication stages that iamb can't complete.
+    #[error("This homeserver needs extra login steps that iamb doesn't support: {0}")]
+    UnsupportedLoginStages(String),
+
+    /// An invalid room alias was specified.
+    #[error("Invalid room alias: {0}")]
+    InvalidRoomAlias(String),
+
+    /// A room alias that was expected to point to the current room points somewhere else.
+    #[error("{0} doesn't point to this room")]
+    AliasMismatch(OwnedRoomAliasId),
+
+    /// The user's power level in a room is too low for what they tried to do.
+    #[error("You don't have permission to do that in this room")]
+    PermissionDenied,
+
     /// An invalid verification identifier was specified.
     #[error("Invalid verification user/device pair: {0}")]
     InvalidVerificationId(String),
 
+    /// There's no changed identity waiting to be acknowledged for a user.
+    #[error("No identity change to acknowledge for {0}")]
+    NoIdentityAlert(OwnedUserId),
+
     /// A failure related to the cryptographic store.
     #[error("Cryptographic storage error: {0}")]
     CryptoStore(#[from] matrix_sdk::encryption::CryptoStoreError),
@@ -635,10 +1056,26 @@ pub enum IambError {
     #[error("Selected message does not have any attachments")]
     NoAttachment,
 
+    /// A failure due to the selected message not having any links to open.
+    #[error("Selected message does not have any links")]
+    NoLinks,
+
     /// A failure due to not having a message selected.
     #[error("No message currently selected")]
     NoSelectedMessage,
 
+    /// A failure due to the selected message not being a reply.
+    #[error("Selected message isn't a reply")]
+    NotAReply,
+
+    /// A failure due to a message not being in the loaded scrollback.
+    #[error("Message {0} isn't loaded in this room's scrollback")]
+    MessageNotLoaded(OwnedEventId),
+
+    /// A failure due to not having sent any messages in the current room.
+    #[error("You haven't sent any messages here")]
+    NoOwnMessages,
+
     /// A failure due to not having a room or space selected.
     #[error("Current window is not a room or space")]
     NoSelectedRoomOrSpace,
@@ -651,6 +1088,14 @@ pub enum IambError {
     #[error("You do not have a current invitation to this room")]
     NotInvited,
 
+    /// A failure due to not currently being able to reach the homeserver.
+    #[error("Cannot do that while offline")]
+    Offline,
+
+    /// A failure due to sending faster than the room allows.
+    #[error("Slow mode is on in this room; wait {0}s before sending again")]
+    SendCooldown(u64),
+
     /// A failure due to not being a joined room member.
     #[error("You need to join the room before you can do that")]
     NotJoined,
@@ -678,6 +1123,42 @@ pub enum IambError {
     /// A failure while trying to show an image preview.
     #[error("Preview error: {0}")]
     Preview(String),
+
+    /// A failure due to asking the server to search an encrypted room.
+    #[error(
+        "The server can't search encrypted rooms; use :search to look through loaded messages"
+    )]
+    EncryptedSearch,
+
+    /// Notifications are turned off in the configuration.
+    #[error("Notifications are disabled; set notifications.enabled in the config to use them")]
+    NotificationsDisabled,
+
+    /// A failure to show a desktop notification.
+    #[error("Desktop notifications are unavailable: {0}")]
+    Notification(String),
+
+    /// A failure while setting up or restoring from the account's key backup.
+    #[error("Recovery error: {0}")]
+    Recovery(#[from] matrix_sdk::encryption::recovery::RecoveryError),
+
+    /// Room keys can't be fetched from the key backup, since it isn't set up on this device.
+    #[error("Key backup isn't enabled on this device; use :recovery restore first")]
+    NoKeyBackup,
+
+    /// The account already has a key backup that we aren't connected to.
+    #[error("This account already has a key backup; use :recovery restore with its recovery key")]
+    RecoveryExists,
+
+    /// The homeserver wants the user to authenticate again before changing cross-signing keys.
+    #[error(
+        "The homeserver needs you to sign in again to set up cross-signing; set it up from another client, then use :recovery restore"
+    )]
+    CrossSigningAuth,
+
+    /// A failure to update the push rules for a room.
+    #[error("Failed to update notification settings: {0}")]
+    NotificationSettings(#[from] matrix_sdk::NotificationSettingsError),
 }
 
 impl From<IambError> for UIError<IambInfo> {
@@ -740,7 +1221,53 @@ impl UnreadInfo {
     }
 }
 
-/// Information about room's the user's joined.
+/// The messages that a message bar's draft is tied to, by event identifier.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DraftTargets {
+    /// The message being replied to.
+    pub reply_to: Option<OwnedEventId>,
+
+    /// The reply target that stays in place after sending.
+    pub sticky_reply: Option<OwnedEventId>,
+
+    /// The root of the thread being replied in.
+    pub reply_thread: Option<OwnedEventId>,
+
+    /// The message being edited.
+    pub editing: Option<OwnedEventId>,
+}
+
+/// A message found by searching a room on the server.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SearchHit {
+    /// The matching message.
+    pub event_id: OwnedEventId,
+
+    /// Who sent the message.
+    pub sender: OwnedUserId,
+
+    /// The sender's display name when the message was sent, if the server knew it.
+    pub sender_name: Option<String>,
+
+    /// When the message was sent.
+    pub timestamp: MilliSecondsSinceUnixEpoch,
+
+    /// The message body, without any reply fallback.
+    pub body: String,
+}
+
+/// Where a room's scrollback was left, saved between runs so that it can be reopened there.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ScrollPosition {
+    /// The selected message.
+    pub event_id: OwnedEventId,
+
+    /// When the selected message was sent, in milliseconds since the Unix epoch, used to find
+    /// the closest message if this one can't be found again.
+    pub timestamp: u64,
+}
+
+/// Information about rooms the user's joined.
 #[derive(Default)]
 pub struct RoomInfo {
     /// The display name for this room.
@@ -765,9 +1292,23 @@ pub struct RoomInfo {
     /// older than the oldest loaded event, that user will not be included.
     pub user_receipts: HashMap<OwnedUserId, OwnedEventId>,
 
+    /// When each user sent their most recent read receipt, if the server told us.
+    pub receipt_times: HashMap<OwnedUserId, MilliSecondsSinceUnixEpoch>,
+
+    /// A map of thread roots to the most recent read marker for each user within that thread.
+    ///
+    /// These are only populated by clients and servers that support threaded read receipts.
+    pub thread_receipts: HashMap<OwnedEventId, HashMap<OwnedUserId, OwnedEventId>>,
+
     /// A map of message identifiers to a map of reaction events.
     pub reactions: HashMap<OwnedEventId, MessageReactions>,
 
+    /// Reaction counts that the server bundled with messages.
+    ///
+    /// These only serve as a lower bound for each count, since the individual reaction events
+    /// are more up-to-date once they've been loaded.
+    pub bundled_reactions: HashMap<OwnedEventId, HashMap<String, usize>>,
+
     /// A map of message identifiers to thread replies.
     threads: HashMap<OwnedEventId, Messages>,
 
@@ -777,6 +1318,10 @@ pub struct RoomInfo {
     /// Where to continue fetching from when we continue loading scrollback history.
     pub fetch_id: RoomFetchStatus,
 
+    /// Where to continue fetching newer messages from, while there's a gap between the messages
+    /// loaded around an older jump target and the more recent ones.
+    pub fetch_gap: Option<String>,
+
     /// The time that we last fetched scrollback for this room.
     pub fetch_last: Option<Instant>,
 
@@ -786,8 +1331,55 @@ pub struct RoomInfo {
     /// The display names for users in this room.
     pub display_names: HashMap<OwnedUserId, String>,
 
+    /// The users who have currently joined this room.
+    pub members: HashSet<OwnedUserId>,
+
     /// The last time the room was rendered, used to detect if it is currently open.
     pub draw_last: Option<Instant>,
+
+    /// The events pinned in this room, in the order listed by its `m.room.pinned_events` state.
+    pub pinned: Vec<OwnedEventId>,
+
+    /// When the pinned events were last fetched for `:pins`.
+    pub pinned_fetched: Option<Instant>,
+
+    /// Whether the pinned events are currently being fetched.
+    pub fetching_pinned: bool,
+
+    /// What each of this room's message bars was replying to or editing, keyed by thread, so
+    /// that they can be picked back up after switching to another room.
+    pub draft_targets: HashMap<Option<OwnedEventId>, DraftTargets>,
+
+    /// How many notifications the server has counted since the user's read receipt.
+    pub unread: usize,
+
+    /// How many of the unread notifications are highlights, like mentions.
+    pub highlight: usize,
+
+    /// The joined and invited members shown by `:members`, and when they were fetched.
+    pub member_list: Option<(Instant, Vec<RoomMember>)>,
+
+    /// Whether the member list is currently being fetched.
+    pub fetching_members: bool,
+
+    /// The text last searched for on the server with `:room search`, and the messages found.
+    pub search_results: Option<(String, Vec<SearchHit>)>,
+
+    /// The token for fetching the next page of search results, if there are more.
+    pub search_next_batch: Option<String>,
+
+    /// Whether there are still more search results to fetch.
+    pub searching: bool,
+
+    /// Whether a page of search results is currently being fetched.
+    pub fetching_search: bool,
+
+    /// A strftime-style format for showing message timestamps in place of the default `%T`.
+    pub timestamp_format: Option<String>,
+
+    /// The notification mode that the user has picked for this room, if they've overridden the
+    /// account default.
+    pub notification_mode: Option<RoomNotificationMode>,
 }
 
 impl RoomInfo {
@@ -807,6 +1399,28 @@ impl RoomInfo {
         }
     }
 
+    /// Re-key a queued message's local echo under the event identifier that the server assigned,
+    /// so that it gets replaced once the real event comes down the sync.
+    pub fn update_echo(&mut self, thread: Option<OwnedEventId>, old: &EventId, new: OwnedEventId) {
+        let synced = self.keys.contains_key(&new);
+        let messages = self.get_thread_mut(thread);
+        let key = (MessageTimeStamp::LocalEcho, old.to_owned());
+
+        let Some(msg) = messages.remove(&key) else {
+            return;
+        };
+
+        if synced {
+            return;
+        }
+
+        if let MessageEvent::Local(_, content) = msg.event {
+            let event = MessageEvent::Local(new.clone(), content);
+            let msg = Message::new(event, msg.sender, MessageTimeStamp::LocalEcho);
+            messages.insert((MessageTimeStamp::LocalEcho, new), msg);
+        }
+    }
+
     /// Get the event for the last message in a thread (or the thread root if there are no
     /// in-thread replies yet).
     ///
@@ -835,9 +1449,9 @@ impl RoomInfo {
 
     /// Get the reactions and their counts for a message.
     pub fn get_reactions(&self, event_id: &EventId) -> Vec<(&str, usize)> {
-        if let Some(reacts) = self.reactions.get(event_id) {
-            let mut counts = HashMap::new();
+        let mut counts = HashMap::<&str, usize>::new();
 
+        if let Some(reacts) = self.reactions.get(event_id) {
             let mut seen_user_reactions = BTreeSet::new();
 
             for (key, user) in reacts.values() {
@@ -847,13 +1461,36 @@ impl RoomInfo {
                     *count += 1;
                 }
             }
+        }
+
+        // The bundled counts already include any individual events we've loaded, so take the
+        // larger of the two instead of adding them together.
+        if let Some(bundled) = self.bundled_reactions.get(event_id) {
+            for (key, n) in bundled.iter().filter(|(_, n)| **n > 0) {
+                let count = counts.entry(key.as_str()).or_default();
+                *count = (*count).max(*n);
+            }
+        }
 
-            let mut reactions = counts.into_iter().collect::<Vec<_>>();
-            reactions.sort();
+        let mut reactions = counts.into_iter().collect::<Vec<_>>();
+        reactions.sort();
 
-            reactions
-        } else {
-            vec![]
+        reactions
+    }
+
+    /// Record the reaction counts that the server bundled with a message.
+    pub fn set_bundled_reactions(
+        &mut self,
+        event_id: OwnedEventId,
+        counts: HashMap<String, usize>,
+    ) {
+        self.bundled_reactions.insert(event_id, counts);
+    }
+
+    /// Flag a loaded message as having been forwarded from another room.
+    pub fn set_forwarded(&mut self, event_id: &EventId) {
+        if let Some(msg) = self.get_event_mut(event_id) {
+            msg.forwarded = true;
         }
     }
 
@@ -862,6 +1499,22 @@ impl RoomInfo {
         self.keys.get(event_id)?.to_message_key()
     }
 
+    /// Whether an event is one of this room's pinned messages.
+    pub fn is_pinned(&self, event_id: &EventId) -> bool {
+        self.pinned.iter().any(|pinned| pinned == event_id)
+    }
+
+    /// Map a saved draft target back to its [MessageKey], as long as the message is still loaded
+    /// and hasn't been redacted.
+    pub fn get_draft_target(&self, event_id: &EventId) -> Option<MessageKey> {
+        let EventLocation::Message(thread, key) = self.keys.get(event_id)? else {
+            return None;
+        };
+        let msg = self.get_thread(thread.as_deref())?.get(key)?;
+
+        (!msg.event.is_redacted()).then(|| key.clone())
+    }
+
     /// Get an event for an identifier.
     pub fn get_event(&self, event_id: &EventId) -> Option<&Message> {
         self.messages.get(self.get_message_key(event_id)?)
@@ -894,8 +1547,15 @@ impl RoomInfo {
                 }
             },
             Some(EventLocation::Reaction(event_id)) => {
-                if let Some(reactions) = self.reactions.get_mut(event_id) {
-                    reactions.remove(redacts);
+                let removed = self.reactions.get_mut(event_id).and_then(|r| r.remove(redacts));
+
+                // Keep any bundled count from holding onto the redacted reaction.
+                if let Some((key, _)) = removed {
+                    let bundled = self.bundled_reactions.get_mut(event_id);
+
+                    if let Some(n) = bundled.and_then(|b| b.get_mut(&key)) {
+                        *n = n.saturating_sub(1);
+                    }
                 }
 
                 self.keys.remove(redacts);
@@ -952,6 +1612,8 @@ impl RoomInfo {
                 content.apply_replacement(new_msgtype);
             },
             MessageEvent::Redacted(_) |
+            MessageEvent::Custom(_) |
+            MessageEvent::Membership(_) |
             MessageEvent::EncryptedOriginal(_) |
             MessageEvent::EncryptedRedacted(_) => {
                 return;
@@ -961,20 +1623,129 @@ impl RoomInfo {
         msg.html = msg.event.html();
     }
 
-    /// Indicates whether this room has unread messages.
+    /// Indicates whether this room has unread messages, either in the main timeline or in any of
+    /// its threads.
     pub fn unreads(&self, settings: &ApplicationSettings) -> UnreadInfo {
+        let user_id = &settings.profile.user_id;
         let last_message = self.messages.last_key_value();
-        let last_receipt = self.get_receipt(&settings.profile.user_id);
+        let last_receipt = self.get_receipt(user_id);
 
-        match (last_message, last_receipt) {
+        let mut info = match (last_message, last_receipt) {
             (Some(((ts, recent), _)), Some(last_read)) => {
                 UnreadInfo { unread: last_read != recent, latest: Some(*ts) }
             },
             (Some(((ts, _), _)), None) => UnreadInfo { unread: false, latest: Some(*ts) },
             (None, _) => UnreadInfo::default(),
+        };
+
+        if !info.unread {
+            info.unread = self.threads.keys().any(|root| self.thread_unread(root, user_id));
+        }
+
+        return info;
+    }
+
+    /// Get the first message in the main timeline after the user's read receipt.
+    ///
+    /// If the user's read receipt isn't for a loaded message, then this returns [None].
+    pub fn first_unread(&self, user_id: &UserId) -> Option<&MessageKey> {
+        let receipt = self.get_receipt(user_id)?;
+        let key = self.get_message_key(receipt)?;
+
+        self.messages
+            .range((Bound::Excluded(key), Bound::Unbounded))
+            .next()
+            .map(|(key, _)| key)
+    }
+
+    /// Move the user's read receipt in the main timeline forward to the given message.
+    ///
+    /// This returns false if the user has already read up to or past the message.
+    pub fn mark_read_until(&mut self, user_id: &UserId, key: &MessageKey) -> bool {
+        let read = self
+            .get_receipt(user_id)
+            .and_then(|event_id| self.get_message_key(event_id));
+
+        if matches!(read, Some(read) if read >= key) {
+            return false;
+        }
+
+        self.set_receipt(user_id.to_owned(), key.1.clone(), None);
+
+        true
+    }
+
+    /// Mark all of the loaded messages in this room as read, including replies in threads.
+    ///
+    /// This returns false if there was nothing left to read.
+    pub fn mark_read(&mut self, user_id: &UserId) -> bool {
+        let thread_lasts = self
+            .threads
+            .iter()
+            .filter_map(|(root, thread)| {
+                Some((root.clone(), thread.last_key_value()?.0 .1.clone()))
+            })
+            .filter(|(root, last)| self.get_thread_receipt(root, user_id) != Some(last))
+            .collect::<Vec<_>>();
+        let mut moved = !thread_lasts.is_empty();
+
+        for (root, last) in thread_lasts {
+            self.set_thread_receipt(root, user_id.to_owned(), last);
+        }
+
+        if let Some((key, _)) = self.messages.last_key_value() {
+            let key = key.clone();
+            moved |= self.mark_read_until(user_id, &key);
+        }
+
+        moved
+    }
+
+    /// Move the user's read receipt back to the message before the given one in the main
+    /// timeline, so that it and everything after it are unread again.
+    ///
+    /// This returns the message that the receipt now points at, or [None] if no earlier message
+    /// has been loaded.
+    pub fn mark_unread_from(&mut self, user_id: &UserId, key: &MessageKey) -> Option<OwnedEventId> {
+        let (prev, _) = self.messages.range(..key).next_back()?;
+        let prev = prev.1.clone();
+
+        self.set_receipt(user_id.to_owned(), prev.clone(), None);
+
+        Some(prev)
+    }
+
+    /// Indicates whether a thread has replies that the user hasn't read yet.
+    ///
+    /// If we haven't seen a threaded read receipt for the user in this thread, which happens
+    /// when the server doesn't support them, then the thread is treated as read.
+    pub fn thread_unread(&self, thread_root: &EventId, user_id: &UserId) -> bool {
+        let Some(((_, recent), _)) = self.threads.get(thread_root).and_then(|t| t.last_key_value())
+        else {
+            return false;
+        };
+
+        match self.get_thread_receipt(thread_root, user_id) {
+            Some(last_read) => last_read != recent,
+            None => false,
         }
     }
 
+    /// Count the joined members of this room, and how many of them are online.
+    pub fn member_counts(
+        &self,
+        presences: &CompletionMap<OwnedUserId, Option<UserPresence>>,
+    ) -> (usize, usize) {
+        let online = self
+            .members
+            .iter()
+            .filter_map(|user_id| presences.get(user_id)?.as_ref())
+            .filter(|presence| presence.state == PresenceState::Online)
+            .count();
+
+        (self.members.len(), online)
+    }
+
     /// Inserts events that couldn't be decrypted into the scrollback.
     pub fn insert_encrypted(&mut self, msg: RoomEncryptedEvent) {
         let event_id = msg.event_id().to_owned();
@@ -984,32 +1755,118 @@ impl RoomInfo {
         self.messages.insert(key, msg.into());
     }
 
-    /// Insert a new message.
-    pub fn insert_message(&mut self, msg: RoomMessageEvent) {
-        let event_id = msg.event_id().to_owned();
-        let key = (msg.origin_server_ts().into(), event_id.clone());
+    /// Get the encrypted messages in the main timeline that couldn't be decrypted yet.
+    pub fn undecrypted(&self) -> Vec<OriginalRoomEncryptedEvent> {
+        self.messages
+            .values()
+            .filter_map(|msg| {
+                match &msg.event {
+                    MessageEvent::EncryptedOriginal(ev) => Some(ev.as_ref().clone()),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
 
-        let loc = EventLocation::Message(None, key.clone());
-        self.keys.insert(event_id, loc);
-        self.messages.insert_message(key, msg);
+    /// Replace a message that couldn't be decrypted earlier with its decrypted form.
+    pub fn insert_decrypted(&mut self, msg: RoomMessageEvent) {
+        let key = (msg.origin_server_ts().into(), msg.event_id().to_owned());
+        let _ = self.messages.remove(&key);
+
+        self.insert(msg);
     }
 
-    fn insert_thread(&mut self, msg: RoomMessageEvent, thread_root: OwnedEventId) {
-        let event_id = msg.event_id().to_owned();
-        let key = (msg.origin_server_ts().into(), event_id.clone());
+    /// Insert an event of a custom type that has been rendered for display.
+    pub fn insert_custom(&mut self, ev: CustomEvent) {
+        let event_id = ev.event_id.clone();
+        let key = (ev.origin_server_ts.into(), event_id.clone());
 
-        let replies = self.threads.entry(thread_root.clone()).or_default();
-        let loc = EventLocation::Message(Some(thread_root), key.clone());
-        self.keys.insert(event_id, loc);
-        replies.insert_message(key, msg);
+        self.keys.insert(event_id, EventLocation::Message(None, key.clone()));
+        self.messages.insert(key, ev.into());
     }
 
-    /// Insert a new message event.
-    pub fn insert(&mut self, msg: RoomMessageEvent) {
-        match msg {
-            RoomMessageEvent::Original(OriginalRoomMessageEvent {
-                content: RoomMessageEventContent { relates_to: Some(ref relates_to), .. },
-                ..
+    /// Insert a membership change, grouping it together with any changes right next to it.
+    ///
+    /// Each group is stored under the key of its most recent change.
+    pub fn insert_membership(&mut self, ev: MembershipEvent) {
+        if self.keys.contains_key(&ev.event_id) {
+            return;
+        }
+
+        let key: MessageKey = (ev.origin_server_ts.into(), ev.event_id.clone());
+        let is_group = |msg: &Message| matches!(msg.event, MessageEvent::Membership(_));
+
+        // A group that comes straight after this change can take it without moving.
+        let next = self
+            .messages
+            .range((Bound::Excluded(&key), Bound::Unbounded))
+            .next()
+            .filter(|(_, msg)| is_group(msg))
+            .map(|(k, _)| k.clone());
+
+        if let Some(next) = next {
+            if let Some(Message { event: MessageEvent::Membership(group), .. }) =
+                self.messages.get_mut(&next)
+            {
+                group.insert(ev);
+            }
+
+            self.keys.insert(key.1, EventLocation::Message(None, next));
+            return;
+        }
+
+        // Otherwise, a group right before this change needs to move up to it.
+        let prev = self
+            .messages
+            .range(..&key)
+            .next_back()
+            .filter(|(_, msg)| is_group(msg))
+            .map(|(k, _)| k.clone());
+
+        let mut group = MembershipGroup::new(ev);
+
+        if let Some(prev) = prev {
+            if let Some(Message { event: MessageEvent::Membership(prev), .. }) =
+                self.messages.remove(&prev)
+            {
+                group.extend(*prev);
+            }
+        }
+
+        for ev in group.events() {
+            let loc = EventLocation::Message(None, key.clone());
+            self.keys.insert(ev.event_id.clone(), loc);
+        }
+
+        self.messages.insert(key, group.into());
+    }
+
+    /// Insert a new message.
+    pub fn insert_message(&mut self, msg: RoomMessageEvent) {
+        let event_id = msg.event_id().to_owned();
+        let key = (msg.origin_server_ts().into(), event_id.clone());
+
+        let loc = EventLocation::Message(None, key.clone());
+        self.keys.insert(event_id, loc);
+        self.messages.insert_message(key, msg);
+    }
+
+    fn insert_thread(&mut self, msg: RoomMessageEvent, thread_root: OwnedEventId) {
+        let event_id = msg.event_id().to_owned();
+        let key = (msg.origin_server_ts().into(), event_id.clone());
+
+        let replies = self.threads.entry(thread_root.clone()).or_default();
+        let loc = EventLocation::Message(Some(thread_root), key.clone());
+        self.keys.insert(event_id, loc);
+        replies.insert_message(key, msg);
+    }
+
+    /// Insert a new message event.
+    pub fn insert(&mut self, msg: RoomMessageEvent) {
+        match msg {
+            RoomMessageEvent::Original(OriginalRoomMessageEvent {
+                content: RoomMessageEventContent { relates_to: Some(ref relates_to), .. },
+                ..
             }) => {
                 match relates_to {
                     Relation::Replacement(repl) => self.insert_edit(repl.clone()),
@@ -1026,7 +1883,7 @@ impl RoomInfo {
     }
 
     /// Insert a new message event, and spawn a task for image-preview if it has an image
-    /// attachment.
+    /// attachment, or for a URL preview if it's just a link and `url_preview` is enabled.
     pub fn insert_with_preview(
         &mut self,
         room_id: OwnedRoomId,
@@ -1034,11 +1891,25 @@ impl RoomInfo {
         picker: Option<Picker>,
         ev: RoomMessageEvent,
         settings: &mut ApplicationSettings,
-        media: matrix_sdk::Media,
+        client: &matrix_sdk::Client,
     ) {
         let source = picker.and_then(|_| source_from_event(&ev));
+        let event_id = ev.event_id().to_owned();
         self.insert(ev);
 
+        if settings.tunables.url_preview {
+            let url = self
+                .get_event(&event_id)
+                .filter(|msg| msg.url_preview.is_none())
+                .and_then(Message::link_url);
+
+            if let Some(url) = url {
+                let store = store.clone();
+                let client = client.clone();
+                spawn_url_preview(store, room_id.clone(), event_id, url, client);
+            }
+        }
+
         if let Some((event_id, source)) = source {
             if let (Some(msg), Some(image_preview)) =
                 (self.get_event_mut(&event_id), &settings.tunables.image_preview)
@@ -1049,18 +1920,40 @@ impl RoomInfo {
                     room_id,
                     event_id,
                     source,
-                    media,
+                    client.media(),
                     settings.dirs.image_previews.clone(),
+                    image_preview.clone(),
                 )
             }
         }
     }
 
+    /// Start loading scrollback from a point in the room's history that isn't connected to the
+    /// messages loaded so far, like when jumping to an older message.
+    ///
+    /// Older history continues to be fetched backwards from the new point, while the gap up to the
+    /// previously loaded messages gets filled in by fetching forwards from it.
+    pub fn open_gap(&mut self, token: String) {
+        self.fetch_id = RoomFetchStatus::HaveMore(token.clone());
+        self.fetch_gap = Some(token);
+    }
+
     /// Indicates whether we've recently fetched scrollback for this room.
     pub fn recently_fetched(&self) -> bool {
         self.fetch_last.map_or(false, |i| i.elapsed() < ROOM_FETCH_DEBOUNCE)
     }
 
+    /// Indicates whether the cached member list is recent enough to show without fetching it
+    /// again.
+    pub fn members_recently_fetched(&self) -> bool {
+        matches!(&self.member_list, Some((i, _)) if i.elapsed() < MEMBER_FETCH_DEBOUNCE)
+    }
+
+    /// Indicates whether the pinned events are recent enough to show without fetching them again.
+    pub fn pinned_recently_fetched(&self) -> bool {
+        self.pinned_fetched.is_some_and(|i| i.elapsed() < PIN_FETCH_DEBOUNCE)
+    }
+
     fn clear_receipt(&mut self, user_id: &OwnedUserId) -> Option<()> {
         let old_event_id = self.user_receipts.get(user_id)?;
         let old_receipts = self.event_receipts.get_mut(old_event_id)?;
@@ -1073,19 +1966,79 @@ impl RoomInfo {
         None
     }
 
-    pub fn set_receipt(&mut self, user_id: OwnedUserId, event_id: OwnedEventId) {
+    pub fn set_receipt(
+        &mut self,
+        user_id: OwnedUserId,
+        event_id: OwnedEventId,
+        ts: Option<MilliSecondsSinceUnixEpoch>,
+    ) {
         self.clear_receipt(&user_id);
         self.event_receipts
             .entry(event_id.clone())
             .or_default()
             .insert(user_id.clone());
+
+        match ts {
+            Some(ts) => self.receipt_times.insert(user_id.clone(), ts),
+            None => self.receipt_times.remove(&user_id),
+        };
+
         self.user_receipts.insert(user_id, event_id);
     }
 
+    /// Get how many unread notifications this room has, and how many of them are highlights.
+    pub fn notification_counts(&self) -> (usize, usize) {
+        (self.unread, self.highlight)
+    }
+
+    pub fn set_notification_counts(&mut self, unread: usize, highlight: usize) {
+        self.unread = unread;
+        self.highlight = highlight;
+    }
+
     pub fn get_receipt(&self, user_id: &UserId) -> Option<&OwnedEventId> {
         self.user_receipts.get(user_id)
     }
 
+    /// Get the users who have read up to or past the message with the given key, along with
+    /// when they sent their receipt.
+    ///
+    /// Receipts with known times come first, oldest to newest, followed by the rest.
+    pub fn get_receipts_since(
+        &self,
+        key: &MessageKey,
+    ) -> Vec<(&OwnedUserId, Option<MilliSecondsSinceUnixEpoch>)> {
+        let mut receipts = self
+            .user_receipts
+            .iter()
+            .filter(|(_, event_id)| matches!(self.get_message_key(event_id), Some(k) if k >= key))
+            .map(|(user_id, _)| (user_id, self.receipt_times.get(user_id).copied()))
+            .collect::<Vec<_>>();
+
+        receipts.sort_by_key(|(user_id, ts)| (ts.is_none(), *ts, *user_id));
+        receipts
+    }
+
+    pub fn set_thread_receipt(
+        &mut self,
+        thread_root: OwnedEventId,
+        user_id: OwnedUserId,
+        event_id: OwnedEventId,
+    ) {
+        self.thread_receipts
+            .entry(thread_root)
+            .or_default()
+            .insert(user_id, event_id);
+    }
+
+    pub fn get_thread_receipt(
+        &self,
+        thread_root: &EventId,
+        user_id: &UserId,
+    ) -> Option<&OwnedEventId> {
+        self.thread_receipts.get(thread_root)?.get(user_id)
+    }
+
     fn get_typers(&self) -> &[OwnedUserId] {
         if let Some((t, users)) = &self.users_typing {
             if t.elapsed() < Duration::from_secs(4) {
@@ -1204,11 +2157,18 @@ fn picker_from_termios(protocol_type: Option<ProtocolType>) -> Option<Picker> {
     Some(picker)
 }
 
-/// Windows cannot guess the right protocol, and always needs type and font_size.
+/// Windows can't ask the terminal for its font size or protocol, so this falls back to a common
+/// font size, and to halfblocks unless a type was configured.
 #[cfg(windows)]
-fn picker_from_termios(_: Option<ProtocolType>) -> Option<Picker> {
-    tracing::error!("\"image_preview\" requires \"protocol\" with \"type\" and \"font_size\" options on Windows.");
-    None
+fn picker_from_termios(protocol_type: Option<ProtocolType>) -> Option<Picker> {
+    tracing::warn!(
+        "Set \"image_preview.protocol.font_size\" to get correctly sized previews on Windows"
+    );
+
+    let mut picker = Picker::new(crate::config::WINDOWS_FONT_SIZE);
+    picker.protocol_type = protocol_type.unwrap_or(ProtocolType::Halfblocks);
+
+    Some(picker)
 }
 
 fn picker_from_settings(settings: &ApplicationSettings) -> Option<Picker> {
@@ -1230,6 +2190,30 @@ fn picker_from_settings(settings: &ApplicationSettings) -> Option<Picker> {
     }
 }
 
+/// What a user's latest presence event said about them.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct UserPresence {
+    pub state: PresenceState,
+    pub status_msg: Option<String>,
+}
+
+impl UserPresence {
+    /// Describe the presence for showing after a user's name.
+    pub fn describe(&self) -> String {
+        let state = match &self.state {
+            PresenceState::Online => "online",
+            PresenceState::Unavailable => "away",
+            PresenceState::Offline => "offline",
+            state => state.as_str(),
+        };
+
+        match self.status_msg.as_deref().map(str::trim) {
+            Some(msg) if !msg.is_empty() => format!("{state}: {msg:?}"),
+            _ => state.to_string(),
+        }
+    }
+}
+
 /// Information gathered during server syncs about joined rooms.
 #[derive(Default)]
 pub struct SyncInfo {
@@ -1241,6 +2225,12 @@ pub struct SyncInfo {
 
     /// DMs that the user is a member of.
     pub dms: Vec<Arc<(MatrixRoom, Option<Tags>)>>,
+
+    /// Recently visited rooms, as stored in the account data by clients like Element.
+    pub breadcrumbs: Vec<OwnedRoomId>,
+
+    /// The rooms that each joined space lists as its children.
+    pub space_children: HashMap<OwnedRoomId, Vec<OwnedRoomId>>,
 }
 
 bitflags::bitflags! {
@@ -1250,6 +2240,10 @@ bitflags::bitflags! {
         const EMPTY = 0b00000000;
         const MESSAGES = 0b00000001;
         const MEMBERS =  0b00000010;
+        const GAP =      0b00000100;
+        const MEMBER_LIST = 0b00001000;
+        const PINNED =      0b00010000;
+        const SEARCH =      0b00100000;
     }
 }
 
@@ -1279,6 +2273,166 @@ impl IntoIterator for RoomNeeds {
     }
 }
 
+/// How much another user's cross-signing identity can be trusted.
+///
+/// The variants are ordered from most to least in need of attention.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum IdentityStatus {
+    /// The user's identity differs from the one first seen for them, and hasn't been verified
+    /// since it changed.
+    Changed,
+
+    /// The user's identity hasn't been verified.
+    Unverified,
+
+    /// The user hasn't set up cross-signing, so there's nothing to verify.
+    Missing,
+
+    /// The user's identity has been verified.
+    Verified,
+}
+
+impl IdentityStatus {
+    /// Determine the status of a user's identity, given whether it's been verified, its current
+    /// master key, and the master key that was recorded for them before.
+    pub fn new(verified: bool, key: Option<&str>, known: Option<&str>) -> Self {
+        match (key, known) {
+            (None, _) => IdentityStatus::Missing,
+            _ if verified => IdentityStatus::Verified,
+            (Some(key), Some(known)) if key != known => IdentityStatus::Changed,
+            (Some(_), _) => IdentityStatus::Unverified,
+        }
+    }
+}
+
+/// The trust status of a user that shares encrypted rooms with us.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IdentityInfo {
+    pub user_id: OwnedUserId,
+    pub status: IdentityStatus,
+
+    /// How many encrypted rooms we share with this user.
+    pub rooms: usize,
+}
+
+/// One of the user's own sessions that hasn't been verified.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SessionInfo {
+    pub device_id: OwnedDeviceId,
+    pub display_name: Option<String>,
+}
+
+/// A message composed while offline, waiting to be sent once the client reconnects.
+#[derive(Clone, Debug)]
+pub struct PendingMessage {
+    /// The room to send the message to.
+    pub room_id: OwnedRoomId,
+
+    /// The thread that the local echo was placed in.
+    pub thread: Option<OwnedEventId>,
+
+    /// The placeholder identifier used for the local echo, if one was shown.
+    pub echo_id: Option<OwnedEventId>,
+
+    /// The transaction identifier reused for every attempt, so that the server can tell when it
+    /// already received the message.
+    pub txn_id: OwnedTransactionId,
+
+    /// The message to send.
+    pub content: RoomMessageEventContent,
+
+    /// When the message can be sent, once the grace period for unsending it has passed.
+    pub send_after: Instant,
+
+    /// Whether the message is being sent right now.
+    pub sending: bool,
+}
+
+/// How many events the `:debug events` log keeps before dropping the oldest ones.
+pub const DEBUG_EVENT_LOG_LIMIT: usize = 500;
+
+/// An incoming event, as recorded in the `:debug events` log.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DebugEvent {
+    pub event_type: String,
+    pub room_id: OwnedRoomId,
+    pub sender: Option<OwnedUserId>,
+}
+
+/// The events received while syncing, when `debug_event_log` is enabled.
+#[derive(Default)]
+pub struct DebugEventLog {
+    /// The recorded events, oldest first.
+    pub events: VecDeque<DebugEvent>,
+
+    /// Whether recording has been paused.
+    pub paused: bool,
+
+    /// Only show events whose type contains this text.
+    pub filter: Option<String>,
+}
+
+impl DebugEventLog {
+    /// Record an event, unless paused.
+    pub fn push(&mut self, event: DebugEvent) {
+        if self.paused {
+            return;
+        }
+
+        if self.events.len() >= DEBUG_EVENT_LOG_LIMIT {
+            self.events.pop_front();
+        }
+
+        self.events.push_back(event);
+    }
+
+    /// The recorded events that match the current filter, oldest first.
+    pub fn filtered(&self) -> impl DoubleEndedIterator<Item = &DebugEvent> {
+        let filter = self.filter.as_deref();
+
+        self.events.iter().filter(move |ev| {
+            match filter {
+                Some(filter) => ev.event_type.contains(filter),
+                None => true,
+            }
+        })
+    }
+}
+
+/// The messages previously sent from each room's message bars.
+pub struct SentHistory {
+    size: usize,
+    rooms: HashMap<OwnedRoomId, HistoryList<EditRope>>,
+}
+
+impl SentHistory {
+    pub fn new(size: usize) -> Self {
+        // Without saving any history, still remember some for the rest of the session.
+        let size = if size == 0 { 100 } else { size };
+
+        SentHistory { size, rooms: HashMap::new() }
+    }
+
+    /// Get the history for a room.
+    pub fn get(&mut self, room_id: &RoomId) -> &mut HistoryList<EditRope> {
+        let size = self.size;
+
+        self.rooms
+            .entry(room_id.to_owned())
+            .or_insert_with(|| HistoryList::new(EditRope::from(""), size))
+    }
+
+    /// Get the messages sent in each room, oldest first.
+    pub fn entries(&self) -> HashMap<OwnedRoomId, Vec<String>> {
+        self.rooms
+            .iter()
+            .map(|(room_id, sent)| {
+                (room_id.clone(), sent.iter().map(ToString::to_string).collect())
+            })
+            .collect()
+    }
+}
+
 /// The main application state.
 pub struct ChatStore {
     /// `:`-commands
@@ -1293,8 +2447,11 @@ pub struct ChatStore {
     /// Map of room names.
     pub names: CompletionMap<String, OwnedRoomId>,
 
-    /// Presence information for other users.
-    pub presences: CompletionMap<OwnedUserId, PresenceState>,
+    /// Users seen so far, with the latest presence that each of them has reported, if any.
+    pub presences: CompletionMap<OwnedUserId, Option<UserPresence>>,
+
+    /// Users whose messages get dropped instead of being added to rooms.
+    pub ignored_users: HashSet<OwnedUserId>,
 
     /// In-progress and completed verifications.
     pub verifications: HashMap<String, SasVerification>,
@@ -1319,12 +2476,68 @@ pub struct ChatStore {
 
     /// Whether to ring the terminal bell on the next redraw.
     pub ring_bell: bool,
+
+    /// Whether the terminal currently has focus, as far as it has told us.
+    pub terminal_focused: bool,
+
+    /// Messages waiting to be sent once the client is back online.
+    pub send_queue: Vec<PendingMessage>,
+
+    /// Messages that the server rejected, kept around so that they can be resent.
+    pub failed_sends: Vec<PendingMessage>,
+
+    /// Errors from sending queued messages and other background tasks, waiting to be shown.
+    pub send_errors: Vec<String>,
+
+    /// Results from other background tasks, waiting to be shown.
+    pub notices: Vec<String>,
+
+    /// Previously sent (or abandoned) message bar contents in each room.
+    pub sent_history: SentHistory,
+
+    /// The Emoji most recently used to react to messages, starting with the latest.
+    pub recent_reactions: Vec<String>,
+
+    /// The trust status of the users we share encrypted rooms with, as of the last fetch.
+    pub identities: Vec<IdentityInfo>,
+
+    /// Users whose identity changed during this session, mapped to their new master key, until
+    /// the change is acknowledged.
+    pub identity_alerts: HashMap<OwnedUserId, String>,
+
+    /// The user's other sessions that haven't been verified yet.
+    pub unverified_sessions: Vec<SessionInfo>,
+
+    /// Whether to warn about [ChatStore::unverified_sessions] in the status line.
+    pub session_warning: bool,
+
+    /// Whether the room list is currently grouped by space.
+    pub room_list_grouped: bool,
+
+    /// Incoming events recorded for the `:debug events` window.
+    pub debug_events: DebugEventLog,
+
+    /// The room list groups that have been collapsed, where [None] is the group of rooms that
+    /// aren't in any space.
+    pub collapsed_groups: HashSet<Option<OwnedRoomId>>,
+
+    /// The message selected in each room's scrollback, for rooms that weren't left at the
+    /// most recent message.
+    pub scroll_positions: HashMap<OwnedRoomId, ScrollPosition>,
+
+    /// Rooms whose outdated version the user has been warned about and dismissed the warning.
+    pub version_warnings_dismissed: HashSet<OwnedRoomId>,
+
+    /// Rooms joined during this session whose version should be checked once it's known.
+    pub version_checks: HashSet<OwnedRoomId>,
 }
 
 impl ChatStore {
     /// Create a new [ChatStore].
     pub fn new(worker: Requester, settings: ApplicationSettings) -> Self {
         let picker = picker_from_settings(&settings);
+        let room_list_grouped = settings.tunables.room_list_group_by_space;
+        let sent_history = SentHistory::new(settings.tunables.sent_history_size);
 
         ChatStore {
             worker,
@@ -1336,11 +2549,29 @@ impl ChatStore {
             names: Default::default(),
             rooms: Default::default(),
             presences: Default::default(),
+            ignored_users: Default::default(),
             verifications: Default::default(),
             need_load: Default::default(),
             sync_info: Default::default(),
             draw_curr: None,
             ring_bell: false,
+            terminal_focused: true,
+            send_queue: Default::default(),
+            failed_sends: Default::default(),
+            send_errors: Default::default(),
+            notices: Default::default(),
+            sent_history,
+            recent_reactions: vec![],
+            identities: vec![],
+            identity_alerts: Default::default(),
+            unverified_sessions: vec![],
+            session_warning: false,
+            room_list_grouped,
+            debug_events: Default::default(),
+            collapsed_groups: Default::default(),
+            scroll_positions: Default::default(),
+            version_warnings_dismissed: Default::default(),
+            version_checks: Default::default(),
         }
     }
 
@@ -1376,6 +2607,13 @@ impl ChatStore {
         self.rooms.get_or_default(room_id.to_owned()).name = name.to_string().into();
     }
 
+    /// Move an Emoji to the front of the recently used reactions.
+    pub fn record_reaction(&mut self, emoji: &str) {
+        self.recent_reactions.retain(|r| r != emoji);
+        self.recent_reactions.insert(0, emoji.to_string());
+        self.recent_reactions.truncate(MAX_RECENT_REACTIONS);
+    }
+
     /// Insert a new E2EE verification.
     pub fn insert_sas(&mut self, sas: SasVerification) {
         let key = format!("{}/{}", sas.other_user_id(), sas.other_device().device_id());
@@ -1386,6 +2624,21 @@ impl ChatStore {
 
 impl ApplicationStore for ChatStore {}
 
+/// Whether a room version is old enough that rooms using it should be upgraded.
+///
+/// Versions before 6 don't enforce canonical JSON and integer bounds on events, and versions 1
+/// and 2 additionally rely on older, exploitable state resolution algorithms.
+pub fn room_version_outdated(version: &RoomVersionId) -> bool {
+    matches!(
+        version,
+        RoomVersionId::V1 |
+            RoomVersionId::V2 |
+            RoomVersionId::V3 |
+            RoomVersionId::V4 |
+            RoomVersionId::V5
+    )
+}
+
 /// Identified used to track window content.
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum IambId {
@@ -1398,6 +2651,15 @@ pub enum IambId {
     /// The `:members` window for a given Matrix room.
     MemberList(OwnedRoomId),
 
+    /// The `:gallery` window for a given Matrix room.
+    Gallery(OwnedRoomId),
+
+    /// The `:room pins` window for a given Matrix room.
+    PinList(OwnedRoomId),
+
+    /// The `:room search` results window for a given Matrix room.
+    SearchResults(OwnedRoomId),
+
     /// The `:rooms` window.
     RoomList,
 
@@ -1412,6 +2674,12 @@ pub enum IambId {
 
     /// The `:chats` window.
     ChatList,
+
+    /// The `:breadcrumbs` window.
+    BreadcrumbList,
+
+    /// The `:debug events` window.
+    DebugEvents,
 }
 
 impl Display for IambId {
@@ -1426,12 +2694,23 @@ impl Display for IambId {
             IambId::MemberList(room_id) => {
                 write!(f, "iamb://members/{room_id}")
             },
+            IambId::Gallery(room_id) => {
+                write!(f, "iamb://gallery/{room_id}")
+            },
+            IambId::PinList(room_id) => {
+                write!(f, "iamb://pins/{room_id}")
+            },
+            IambId::SearchResults(room_id) => {
+                write!(f, "iamb://search/{room_id}")
+            },
             IambId::DirectList => f.write_str("iamb://dms"),
             IambId::RoomList => f.write_str("iamb://rooms"),
             IambId::SpaceList => f.write_str("iamb://spaces"),
             IambId::VerifyList => f.write_str("iamb://verify"),
             IambId::Welcome => f.write_str("iamb://welcome"),
             IambId::ChatList => f.write_str("iamb://chats"),
+            IambId::BreadcrumbList => f.write_str("iamb://breadcrumbs"),
+            IambId::DebugEvents => f.write_str("iamb://debug-events"),
         }
     }
 }
@@ -1521,6 +2800,51 @@ impl<'de> Visitor<'de> for IambIdVisitor {
 
                 Ok(IambId::MemberList(room_id))
             },
+            Some("gallery") => {
+                let Some(path) = url.path_segments() else {
+                    return Err(E::custom("Invalid gallery window URL"));
+                };
+
+                let &[room_id] = path.collect::<Vec<_>>().as_slice() else {
+                    return Err(E::custom("Invalid gallery window URL"));
+                };
+
+                let Ok(room_id) = OwnedRoomId::try_from(room_id) else {
+                    return Err(E::custom("Invalid room identifier"));
+                };
+
+                Ok(IambId::Gallery(room_id))
+            },
+            Some("pins") => {
+                let Some(path) = url.path_segments() else {
+                    return Err(E::custom("Invalid pins window URL"));
+                };
+
+                let &[room_id] = path.collect::<Vec<_>>().as_slice() else {
+                    return Err(E::custom("Invalid pins window URL"));
+                };
+
+                let Ok(room_id) = OwnedRoomId::try_from(room_id) else {
+                    return Err(E::custom("Invalid room identifier"));
+                };
+
+                Ok(IambId::PinList(room_id))
+            },
+            Some("search") => {
+                let Some(path) = url.path_segments() else {
+                    return Err(E::custom("Invalid search window URL"));
+                };
+
+                let &[room_id] = path.collect::<Vec<_>>().as_slice() else {
+                    return Err(E::custom("Invalid search window URL"));
+                };
+
+                let Ok(room_id) = OwnedRoomId::try_from(room_id) else {
+                    return Err(E::custom("Invalid room identifier"));
+                };
+
+                Ok(IambId::SearchResults(room_id))
+            },
             Some("dms") => {
                 if url.path() != "" {
                     return Err(E::custom("iamb://dms takes no path"));
@@ -1563,6 +2887,20 @@ impl<'de> Visitor<'de> for IambIdVisitor {
 
                 Ok(IambId::ChatList)
             },
+            Some("breadcrumbs") => {
+                if url.path() != "" {
+                    return Err(E::custom("iamb://breadcrumbs takes no path"));
+                }
+
+                Ok(IambId::BreadcrumbList)
+            },
+            Some("debug-events") => {
+                if url.path() != "" {
+                    return Err(E::custom("iamb://debug-events takes no path"));
+                }
+
+                Ok(IambId::DebugEvents)
+            },
             Some(s) => Err(E::custom(format!("{s:?} is not a valid window"))),
             None => Err(E::custom("Invalid iamb window URL")),
         }
@@ -1609,6 +2947,15 @@ pub enum IambBufferId {
     /// The `:members` window for a room.
     MemberList(OwnedRoomId),
 
+    /// The `:gallery` window for a room.
+    Gallery(OwnedRoomId),
+
+    /// The `:room pins` window for a room.
+    PinList(OwnedRoomId),
+
+    /// The `:room search` results window for a room.
+    SearchResults(OwnedRoomId),
+
     /// The `:rooms` window.
     RoomList,
 
@@ -1623,6 +2970,12 @@ pub enum IambBufferId {
 
     /// The `:chats` window.
     ChatList,
+
+    /// The `:breadcrumbs` window.
+    BreadcrumbList,
+
+    /// The `:debug events` window.
+    DebugEvents,
 }
 
 impl IambBufferId {
@@ -1633,11 +2986,16 @@ impl IambBufferId {
             IambBufferId::Room(room, thread, _) => IambId::Room(room.clone(), thread.clone()),
             IambBufferId::DirectList => IambId::DirectList,
             IambBufferId::MemberList(room) => IambId::MemberList(room.clone()),
+            IambBufferId::Gallery(room) => IambId::Gallery(room.clone()),
+            IambBufferId::PinList(room) => IambId::PinList(room.clone()),
+            IambBufferId::SearchResults(room) => IambId::SearchResults(room.clone()),
             IambBufferId::RoomList => IambId::RoomList,
             IambBufferId::SpaceList => IambId::SpaceList,
             IambBufferId::VerifyList => IambId::VerifyList,
             IambBufferId::Welcome => IambId::Welcome,
             IambBufferId::ChatList => IambId::ChatList,
+            IambBufferId::BreadcrumbList => IambId::BreadcrumbList,
+            IambBufferId::DebugEvents => IambId::DebugEvents,
         };
 
         Some(id)
@@ -1662,16 +3020,23 @@ impl ApplicationInfo for IambInfo {
         match content {
             IambBufferId::Command(CommandType::Command) => complete_cmdbar(text, cursor, store),
             IambBufferId::Command(CommandType::Search) => vec![],
-            IambBufferId::Room(_, _, RoomFocus::MessageBar) => complete_msgbar(text, cursor, store),
+            IambBufferId::Room(room_id, _, RoomFocus::MessageBar) => {
+                complete_msgbar(text, cursor, room_id, store)
+            },
             IambBufferId::Room(_, _, RoomFocus::Scrollback) => vec![],
 
             IambBufferId::DirectList => vec![],
             IambBufferId::MemberList(_) => vec![],
+            IambBufferId::Gallery(_) => vec![],
+            IambBufferId::PinList(_) => vec![],
+            IambBufferId::SearchResults(_) => vec![],
             IambBufferId::RoomList => vec![],
             IambBufferId::SpaceList => vec![],
             IambBufferId::VerifyList => vec![],
             IambBufferId::Welcome => vec![],
             IambBufferId::ChatList => vec![],
+            IambBufferId::BreadcrumbList => vec![],
+            IambBufferId::DebugEvents => vec![],
         }
     }
 
@@ -1696,8 +3061,44 @@ fn complete_users(text: &EditRope, cursor: &mut Cursor, store: &ProgramStore) ->
         .collect()
 }
 
+/// Tab completion for the joined members of a room, matching either their user ID or display
+/// name.
+///
+/// Members whose display names match come first, but it's always their user ID that gets
+/// inserted, so that the mention can't be mistaken for someone else.
+fn complete_members(prefix: &str, info: &RoomInfo) -> Vec<String> {
+    let name_prefix = prefix.strip_prefix('@').unwrap_or(prefix).to_lowercase();
+    let mut by_name = vec![];
+    let mut by_id = vec![];
+
+    for user_id in info.members.iter() {
+        match info.display_names.get(user_id) {
+            Some(name) if name.to_lowercase().starts_with(&name_prefix) => {
+                by_name.push((name.as_str(), user_id));
+            },
+            _ if user_id.as_str().starts_with(prefix) => by_id.push(user_id),
+            _ => {},
+        }
+    }
+
+    by_name.sort();
+    by_id.sort();
+
+    by_name
+        .into_iter()
+        .map(|(_, user_id)| user_id)
+        .chain(by_id)
+        .map(|user_id| user_id.to_string())
+        .collect()
+}
+
 /// Tab completion within the message bar.
-fn complete_msgbar(text: &EditRope, cursor: &mut Cursor, store: &ProgramStore) -> Vec<String> {
+fn complete_msgbar(
+    text: &EditRope,
+    cursor: &mut Cursor,
+    room_id: &RoomId,
+    store: &ProgramStore,
+) -> Vec<String> {
     let id = text
         .get_prefix_word_mut(cursor, &MATRIX_ID_WORD)
         .unwrap_or_else(EditRope::empty);
@@ -1728,15 +3129,24 @@ fn complete_msgbar(text: &EditRope, cursor: &mut Cursor, store: &ProgramStore) -
             return iter.collect();
         },
 
-        // Complete usernames for @ and empty strings.
+        // Complete usernames for @ and empty strings, starting with the room's members.
         Some('@') | None => {
-            return store
+            let mut users = store
                 .application
-                .presences
-                .complete(id.as_ref())
-                .into_iter()
-                .map(|i| i.to_string())
-                .collect();
+                .rooms
+                .get(room_id)
+                .map(|info| complete_members(id.as_ref(), info))
+                .unwrap_or_default();
+
+            for user in store.application.presences.complete(id.as_ref()) {
+                let user = user.to_string();
+
+                if !users.contains(&user) {
+                    users.push(user);
+                }
+            }
+
+            return users;
         },
 
         // Unknown sigil.
@@ -1774,6 +3184,64 @@ fn complete_matrix_names(
         .collect()
 }
 
+/// Tab completion for rooms to join, by alias or by a piece of the room's name.
+///
+/// Aliases that start with the typed text come first, followed by rooms whose alias or name
+/// contains it anywhere. Rooms complete to an alias when one is known, and to their identifier
+/// otherwise, so that the result can always be joined.
+fn complete_rooms(text: &EditRope, cursor: &mut Cursor, store: &ProgramStore) -> Vec<String> {
+    let word = text
+        .get_prefix_word_mut(cursor, &MATRIX_ID_WORD)
+        .unwrap_or_else(EditRope::empty);
+    let word = Cow::from(&word);
+    let needle = word.trim_start_matches(['#', '!']).to_lowercase();
+
+    let ChatStore { names, rooms, sync_info, .. } = &store.application;
+    let room_name = |room_id: &RoomId| rooms.get(room_id).and_then(|info| info.name.clone());
+
+    let mut candidates = sync_info
+        .rooms
+        .iter()
+        .chain(sync_info.spaces.iter())
+        .map(|room| {
+            let room = &room.0;
+            let target = match room.canonical_alias() {
+                Some(alias) => alias.to_string(),
+                None => room.room_id().to_string(),
+            };
+            let name = room_name(room.room_id()).or_else(|| room.name());
+
+            (target, name)
+        })
+        .collect::<Vec<_>>();
+
+    // Aliases that have been joined or typed before, which may not be canonical.
+    for alias in names.complete("#") {
+        if candidates.iter().all(|(target, _)| target != &alias) {
+            let name = names.get(&alias).and_then(|room_id| room_name(room_id));
+            candidates.push((alias, name));
+        }
+    }
+
+    let mut prefixed = vec![];
+    let mut contained = vec![];
+
+    for (target, name) in candidates {
+        if target.starts_with(word.as_ref()) {
+            prefixed.push(target);
+        } else if target.to_lowercase().contains(&needle) ||
+            name.is_some_and(|name| name.to_lowercase().contains(&needle))
+        {
+            contained.push(target);
+        }
+    }
+
+    prefixed.sort();
+    contained.sort();
+    prefixed.extend(contained);
+    prefixed
+}
+
 /// Tab completion for Emoji shortcode names.
 fn complete_emoji(text: &EditRope, cursor: &mut Cursor, store: &ProgramStore) -> Vec<String> {
     let sc = text.get_prefix_word_mut(cursor, &WordStyle::Little);
@@ -1813,8 +3281,9 @@ fn complete_cmdarg(
         "download" | "keys" | "open" | "upload" => complete_path(text, cursor),
         "react" | "unreact" => complete_emoji(text, cursor, store),
 
-        "invite" => complete_users(text, cursor, store),
-        "join" | "split" | "vsplit" | "tabedit" => complete_matrix_names(text, cursor, store),
+        "dm" | "invite" => complete_users(text, cursor, store),
+        "join" => complete_rooms(text, cursor, store),
+        "split" | "vsplit" | "tabedit" => complete_matrix_names(text, cursor, store),
         "room" => vec![],
         "verify" => vec![],
         "vertical" | "horizontal" | "aboveleft" | "belowright" | "tab" => {
@@ -1858,14 +3327,22 @@ fn complete_cmdbar(text: &EditRope, cursor: &mut Cursor, store: &ProgramStore) -
 #[cfg(test)]
 pub mod tests {
     use super::*;
-    use crate::config::user_style_from_color;
+    use crate::config::{user_style_from_color, ImagePreviewValues};
+    use crate::message::MembershipKind;
     use crate::tests::*;
     use matrix_sdk::ruma::{
-        events::{reaction::ReactionEventContent, relation::Annotation, MessageLikeUnsigned},
+        event_id,
+        events::{
+            reaction::ReactionEventContent,
+            relation::Annotation,
+            room::redaction::RoomRedactionEventContent,
+            MessageLikeUnsigned,
+        },
         owned_event_id,
         owned_room_id,
         owned_user_id,
         MilliSecondsSinceUnixEpoch,
+        UInt,
     };
     use pretty_assertions::assert_eq;
     use ratatui::style::Color;
@@ -1932,6 +3409,110 @@ pub mod tests {
         ]);
     }
 
+    #[test]
+    fn test_bundled_reactions() {
+        let mut info = RoomInfo::default();
+        let msg_id = owned_event_id!("$my_message");
+
+        let json = r#"{
+            "type": "m.room.message",
+            "unsigned": {
+                "m.relations": {
+                    "m.annotation": {
+                        "chunk": [
+                            {"type": "m.reaction", "key": "🏠", "count": 3},
+                            {"type": "m.reaction", "key": "🙂", "count": 1}
+                        ]
+                    }
+                }
+            }
+        }"#;
+        let counts = bundled_reaction_counts(json).unwrap();
+        info.set_bundled_reactions(msg_id.clone(), counts);
+
+        assert_eq!(bundled_reaction_counts(r#"{"type": "m.room.message"}"#), None);
+        assert_eq!(info.get_reactions(&msg_id), vec![("🏠", 3), ("🙂", 1)]);
+
+        // Loading individual reactions that were already bundled doesn't double count.
+        let react = |event_id: &str, key: &str, user: &str| {
+            let content = ReactionEventContent::new(Annotation::new(msg_id.clone(), key.into()));
+
+            MessageLikeEvent::Original(matrix_sdk::ruma::events::OriginalMessageLikeEvent {
+                content,
+                event_id: OwnedEventId::from_str(event_id).unwrap(),
+                sender: OwnedUserId::from_str(user).unwrap(),
+                origin_server_ts: MilliSecondsSinceUnixEpoch::now(),
+                room_id: owned_room_id!("!foo:example.org"),
+                unsigned: MessageLikeUnsigned::new(),
+            })
+        };
+
+        info.insert_reaction(react("$house_1", "🏠", "@foo:example.org"));
+        info.insert_reaction(react("$smile_1", "🙂", "@foo:example.org"));
+        info.insert_reaction(react("$smile_2", "🙂", "@bar:example.org"));
+        assert_eq!(info.get_reactions(&msg_id), vec![("🏠", 3), ("🙂", 2)]);
+
+        // Redacting a reaction lowers the bundled count too.
+        let redaction = OriginalSyncRoomRedactionEvent {
+            content: RoomRedactionEventContent::new_v11(owned_event_id!("$house_1")),
+            redacts: Some(owned_event_id!("$house_1")),
+            event_id: owned_event_id!("$redaction"),
+            sender: owned_user_id!("@foo:example.org"),
+            origin_server_ts: MilliSecondsSinceUnixEpoch::now(),
+            unsigned: Default::default(),
+        };
+        info.redact(redaction, &RoomVersionId::V10);
+        assert_eq!(info.get_reactions(&msg_id), vec![("🏠", 2), ("🙂", 2)]);
+    }
+
+    #[test]
+    fn test_is_forwarded() {
+        let json = r#"{
+            "type": "m.room.message",
+            "content": {
+                "msgtype": "m.text",
+                "body": "hello",
+                "chat.iamb.forwarded": true
+            }
+        }"#;
+        assert!(is_forwarded(json));
+
+        let json = r#"{"type": "m.room.message", "content": {"msgtype": "m.text", "body": "hi"}}"#;
+        assert!(!is_forwarded(json));
+
+        let json = r#"{"type": "m.room.message", "content": {"chat.iamb.forwarded": false}}"#;
+        assert!(!is_forwarded(json));
+    }
+
+    #[test]
+    fn test_debug_event_log() {
+        let mut log = DebugEventLog::default();
+        let event = |event_type: &str| {
+            DebugEvent {
+                event_type: event_type.into(),
+                room_id: owned_room_id!("!foo:example.org"),
+                sender: None,
+            }
+        };
+
+        for _ in 0..DEBUG_EVENT_LOG_LIMIT {
+            log.push(event("m.room.message"));
+        }
+
+        // Once the log is full, the oldest events get dropped.
+        log.push(event("m.room.member"));
+        assert_eq!(log.events.len(), DEBUG_EVENT_LOG_LIMIT);
+        assert_eq!(log.events.back(), Some(&event("m.room.member")));
+
+        log.filter = Some("member".into());
+        assert_eq!(log.filtered().collect::<Vec<_>>(), vec![&event("m.room.member")]);
+
+        // Nothing gets recorded while paused.
+        log.paused = true;
+        log.push(event("m.room.member"));
+        assert_eq!(log.filtered().count(), 1);
+    }
+
     #[test]
     fn test_typing_spans() {
         let mut info = RoomInfo::default();
@@ -2009,6 +3590,289 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn test_member_counts() {
+        let mut info = RoomInfo::default();
+        let mut presences = CompletionMap::default();
+        assert_eq!(info.member_counts(&presences), (0, 0));
+
+        info.members.insert(TEST_USER1.clone());
+        info.members.insert(TEST_USER2.clone());
+        info.members.insert(TEST_USER3.clone());
+        assert_eq!(info.member_counts(&presences), (3, 0));
+
+        let presence = |state| Some(UserPresence { state, status_msg: None });
+        presences.insert(TEST_USER1.clone(), presence(PresenceState::Online));
+        presences.insert(TEST_USER2.clone(), presence(PresenceState::Unavailable));
+        presences.insert(TEST_USER3.clone(), None);
+        presences.insert(TEST_USER4.clone(), presence(PresenceState::Online));
+        assert_eq!(info.member_counts(&presences), (3, 1));
+    }
+
+    #[test]
+    fn test_first_unread() {
+        let mut info = mock_room();
+        let user_id = TEST_USER1.clone();
+
+        // Without a read receipt, there's nowhere to start from.
+        assert_eq!(info.first_unread(&user_id), None);
+
+        info.set_receipt(user_id.clone(), MSG3_EVID.clone(), None);
+        assert_eq!(info.first_unread(&user_id), Some(&*MSG4_KEY));
+
+        info.set_receipt(user_id.clone(), MSG5_EVID.clone(), None);
+        assert_eq!(info.first_unread(&user_id), Some(&*MSG1_KEY));
+
+        // Nothing is unread after the most recent message.
+        info.set_receipt(user_id.clone(), MSG1_EVID.clone(), None);
+        assert_eq!(info.first_unread(&user_id), None);
+    }
+
+    #[test]
+    fn test_mark_read() {
+        let mut info = mock_room();
+        let user_id = TEST_USER1.clone();
+        info.set_receipt(user_id.clone(), MSG3_EVID.clone(), None);
+
+        // Only mark up to a message that comes after the current receipt.
+        assert!(!info.mark_read_until(&user_id, &MSG2_KEY));
+        assert_eq!(info.get_receipt(&user_id), Some(&*MSG3_EVID));
+        assert!(info.mark_read_until(&user_id, &MSG5_KEY));
+        assert_eq!(info.get_receipt(&user_id), Some(&*MSG5_EVID));
+
+        assert!(info.mark_read(&user_id));
+        assert_eq!(info.get_receipt(&user_id), Some(&*MSG1_EVID));
+        assert_eq!(info.first_unread(&user_id), None);
+        assert!(!info.mark_read(&user_id));
+    }
+
+    #[test]
+    fn test_mark_unread_from() {
+        let mut info = mock_room();
+        let user_id = TEST_USER1.clone();
+        info.set_receipt(user_id.clone(), MSG5_EVID.clone(), None);
+
+        assert_eq!(info.mark_unread_from(&user_id, &MSG4_KEY), Some(MSG3_EVID.clone()));
+        assert_eq!(info.get_receipt(&user_id), Some(&*MSG3_EVID));
+        assert_eq!(info.first_unread(&user_id), Some(&*MSG4_KEY));
+
+        // There's nothing loaded before the first message to put the receipt on.
+        assert_eq!(info.mark_unread_from(&user_id, &MSG2_KEY), None);
+        assert_eq!(info.get_receipt(&user_id), Some(&*MSG3_EVID));
+    }
+
+    #[test]
+    fn test_insert_membership() {
+        let mut info = mock_room();
+        let change = |id: &str, ts: u64| {
+            MembershipEvent {
+                event_id: EventId::parse(id).unwrap(),
+                sender: TEST_USER2.clone(),
+                origin_server_ts: MilliSecondsSinceUnixEpoch(UInt::new(ts).unwrap()),
+                name: "User 2".into(),
+                kind: MembershipKind::Joined,
+            }
+        };
+        let group_len = |info: &RoomInfo, id: &str| {
+            match info.get_event(<&EventId>::try_from(id).unwrap()) {
+                Some(Message { event: MessageEvent::Membership(group), .. }) => {
+                    group.events().len()
+                },
+                _ => 0,
+            }
+        };
+
+        // Changes between MSG4 and MSG5 get grouped, and stored under the latest one.
+        info.insert_membership(change("$c:example.com", 3));
+        info.insert_membership(change("$e:example.com", 5));
+        info.insert_membership(change("$d:example.com", 4));
+        assert_eq!(group_len(&info, "$c:example.com"), 3);
+        assert_eq!(
+            info.get_message_key(event_id!("$d:example.com")).unwrap().1,
+            event_id!("$e:example.com")
+        );
+
+        // Changes on the other side of a message start a new group.
+        info.insert_membership(change("$i:example.com", 9));
+        assert_eq!(group_len(&info, "$i:example.com"), 1);
+        assert_eq!(group_len(&info, "$e:example.com"), 3);
+
+        // The same event isn't added twice.
+        info.insert_membership(change("$d:example.com", 4));
+        assert_eq!(group_len(&info, "$e:example.com"), 3);
+    }
+
+    #[test]
+    fn test_sent_history() {
+        let room1 = owned_room_id!("!foo:example.org");
+        let room2 = owned_room_id!("!bar:example.org");
+
+        let mut history = SentHistory::new(0);
+        history.get(&room1).select(EditRope::from("hello"));
+        history.get(&room2).select(EditRope::from("bye"));
+
+        let entries = history.entries();
+        assert_eq!(entries.len(), 2);
+        assert!(entries[&room1].contains(&"hello".to_string()));
+        assert!(!entries[&room1].contains(&"bye".to_string()));
+    }
+
+    #[test]
+    fn test_draft_target() {
+        let mut info = mock_room();
+
+        assert_eq!(info.get_draft_target(&MSG3_EVID), Some(MSG3_KEY.clone()));
+        assert_eq!(info.get_draft_target(&owned_event_id!("$unknown")), None);
+
+        // Targets that have been redacted since can't be restored.
+        let redaction = OriginalSyncRoomRedactionEvent {
+            content: RoomRedactionEventContent::new_v11(MSG3_EVID.clone()),
+            redacts: Some(MSG3_EVID.clone()),
+            event_id: owned_event_id!("$redaction"),
+            sender: TEST_USER1.clone(),
+            origin_server_ts: MilliSecondsSinceUnixEpoch::now(),
+            unsigned: Default::default(),
+        };
+        info.redact(redaction, &RoomVersionId::V10);
+        assert_eq!(info.get_draft_target(&MSG3_EVID), None);
+    }
+
+    #[test]
+    fn test_thread_unreads() {
+        let settings = mock_settings();
+        let user_id = settings.profile.user_id.clone();
+        let mut info = RoomInfo::default();
+
+        let thread = info.get_thread_mut(Some(MSG1_EVID.clone()));
+        thread.insert(MSG2_KEY.clone(), mock_message2());
+        thread.insert(MSG3_KEY.clone(), mock_message3());
+
+        // Without a threaded read receipt, the thread is treated as read.
+        assert_eq!(info.thread_unread(&MSG1_EVID, &user_id), false);
+        assert_eq!(info.unreads(&settings).is_unread(), false);
+
+        // Reading an older reply leaves the thread unread.
+        info.set_thread_receipt(MSG1_EVID.clone(), user_id.clone(), MSG2_EVID.clone());
+        assert_eq!(info.thread_unread(&MSG1_EVID, &user_id), true);
+        assert_eq!(info.unreads(&settings).is_unread(), true);
+
+        // Reading the latest reply marks the thread as read.
+        info.set_thread_receipt(MSG1_EVID.clone(), user_id.clone(), MSG3_EVID.clone());
+        assert_eq!(info.thread_unread(&MSG1_EVID, &user_id), false);
+        assert_eq!(info.unreads(&settings).is_unread(), false);
+    }
+
+    #[test]
+    fn test_receipts_since() {
+        let mut info = mock_room();
+        let ts = |ms: u32| Some(MilliSecondsSinceUnixEpoch(ms.into()));
+
+        info.set_receipt(TEST_USER1.clone(), MSG5_EVID.clone(), ts(20));
+        info.set_receipt(TEST_USER2.clone(), MSG3_EVID.clone(), ts(10));
+        info.set_receipt(TEST_USER3.clone(), MSG5_EVID.clone(), None);
+        info.set_receipt(TEST_USER4.clone(), MSG2_EVID.clone(), ts(5));
+
+        // Older receipts are left out, and ones without a time go last.
+        assert_eq!(info.get_receipts_since(&MSG3_KEY), vec![
+            (&*TEST_USER2, ts(10)),
+            (&*TEST_USER1, ts(20)),
+            (&*TEST_USER3, None),
+        ]);
+        assert_eq!(info.get_receipts_since(&MSG5_KEY), vec![
+            (&*TEST_USER1, ts(20)),
+            (&*TEST_USER3, None),
+        ]);
+
+        // A newer receipt without a time forgets the old one.
+        info.set_receipt(TEST_USER2.clone(), MSG5_EVID.clone(), None);
+        assert_eq!(info.get_receipts_since(&MSG5_KEY), vec![
+            (&*TEST_USER1, ts(20)),
+            (&*TEST_USER2, None),
+            (&*TEST_USER3, None),
+        ]);
+    }
+
+    #[test]
+    fn test_members_recently_fetched() {
+        let mut info = RoomInfo::default();
+        assert!(!info.members_recently_fetched());
+
+        info.member_list = Some((Instant::now(), vec![]));
+        assert!(info.members_recently_fetched());
+
+        let stale = Instant::now() - MEMBER_FETCH_DEBOUNCE;
+        info.member_list = Some((stale, vec![]));
+        assert!(!info.members_recently_fetched());
+    }
+
+    #[test]
+    fn test_pinned_recently_fetched() {
+        let mut info = RoomInfo::default();
+        assert!(!info.pinned_recently_fetched());
+
+        info.pinned_fetched = Some(Instant::now());
+        assert!(info.pinned_recently_fetched());
+
+        info.pinned_fetched = Some(Instant::now() - PIN_FETCH_DEBOUNCE);
+        assert!(!info.pinned_recently_fetched());
+    }
+
+    #[test]
+    fn test_update_echo() {
+        let mut info = RoomInfo::default();
+        let queued = owned_event_id!("$iamb-queued-1");
+        let content = RoomMessageEventContent::text_plain("hello");
+        let event = MessageEvent::Local(queued.clone(), Box::new(content));
+        let msg = Message::new(event, TEST_USER1.clone(), MessageTimeStamp::LocalEcho);
+        info.messages.insert((MessageTimeStamp::LocalEcho, queued.clone()), msg);
+
+        // The echo moves over to the identifier the server gave us.
+        info.update_echo(None, &queued, MSG1_EVID.clone());
+        let keys = info.messages.keys().cloned().collect::<Vec<_>>();
+        assert_eq!(keys, vec![(MessageTimeStamp::LocalEcho, MSG1_EVID.clone())]);
+        let msg = info.messages.values().next().unwrap();
+        assert_eq!(msg.event.event_id(), &*MSG1_EVID);
+
+        // If the event already came down the sync, the echo is just dropped.
+        info.keys
+            .insert(MSG2_EVID.clone(), EventLocation::Message(None, MSG2_KEY.clone()));
+        info.update_echo(None, &MSG1_EVID, MSG2_EVID.clone());
+        assert_eq!(info.messages.len(), 0);
+    }
+
+    #[test]
+    fn test_identity_status() {
+        let status = IdentityStatus::new(true, Some("key"), Some("key"));
+        assert_eq!(status, IdentityStatus::Verified);
+
+        let status = IdentityStatus::new(false, Some("key"), None);
+        assert_eq!(status, IdentityStatus::Unverified);
+
+        let status = IdentityStatus::new(false, Some("key"), Some("key"));
+        assert_eq!(status, IdentityStatus::Unverified);
+
+        let status = IdentityStatus::new(false, Some("new"), Some("old"));
+        assert_eq!(status, IdentityStatus::Changed);
+
+        // Verifying the new identity clears the warning.
+        let status = IdentityStatus::new(true, Some("new"), Some("old"));
+        assert_eq!(status, IdentityStatus::Verified);
+
+        let status = IdentityStatus::new(false, None, Some("old"));
+        assert_eq!(status, IdentityStatus::Missing);
+    }
+
+    #[test]
+    fn test_room_version_outdated() {
+        assert_eq!(room_version_outdated(&RoomVersionId::V1), true);
+        assert_eq!(room_version_outdated(&RoomVersionId::V5), true);
+        assert_eq!(room_version_outdated(&RoomVersionId::V6), false);
+        assert_eq!(room_version_outdated(&RoomVersionId::V10), false);
+
+        let custom = RoomVersionId::try_from("org.example.custom").unwrap();
+        assert_eq!(room_version_outdated(&custom), false);
+    }
+
     #[test]
     fn test_need_load() {
         let room_id = TEST_ROOM1_ID.clone();
@@ -2024,29 +3888,154 @@ pub mod tests {
         )],);
     }
 
+    #[test]
+    fn test_picker_forced_protocol() {
+        let mut settings = mock_settings();
+        assert!(picker_from_settings(&settings).is_none());
+
+        settings.tunables.image_preview = Some(ImagePreviewValues {
+            size: Default::default(),
+            protocol: Some(ImagePreviewProtocolValues {
+                r#type: Some(ProtocolType::Kitty),
+                font_size: Some((7, 14)),
+            }),
+            max_frames: 1,
+            max_fps: 10,
+        });
+
+        // A configured type and font size are used as-is, without asking the terminal.
+        let picker = picker_from_settings(&settings).unwrap();
+        assert_eq!(picker.protocol_type, ProtocolType::Kitty);
+        assert_eq!(picker.font_size, (7, 14));
+    }
+
+    #[test]
+    fn test_user_presence_describe() {
+        let presence =
+            |state, msg: Option<&str>| UserPresence { state, status_msg: msg.map(String::from) };
+
+        assert_eq!(presence(PresenceState::Online, None).describe(), "online");
+        assert_eq!(presence(PresenceState::Unavailable, None).describe(), "away");
+        assert_eq!(presence(PresenceState::Offline, Some(" ")).describe(), "offline");
+        assert_eq!(
+            presence(PresenceState::Unavailable, Some("In a meeting")).describe(),
+            "away: \"In a meeting\""
+        );
+    }
+
+    #[test]
+    fn test_insert_decrypted() {
+        let mut info = RoomInfo::default();
+        let event = |content: serde_json::Value| {
+            serde_json::json!({
+                "type": content["type"],
+                "event_id": "$decrypted",
+                "sender": "@user1:example.com",
+                "origin_server_ts": 1234,
+                "room_id": "!room:example.com",
+                "content": content["content"],
+            })
+        };
+
+        let encrypted = event(serde_json::json!({
+            "type": "m.room.encrypted",
+            "content": {
+                "algorithm": "m.megolm.v1.aes-sha2",
+                "ciphertext": "AwgAEnAC",
+                "device_id": "DEVICE",
+                "sender_key": "SENDERKEY",
+                "session_id": "SESSION",
+            },
+        }));
+        info.insert_encrypted(serde_json::from_value(encrypted).unwrap());
+        assert_eq!(info.undecrypted().len(), 1);
+
+        let decrypted = event(serde_json::json!({
+            "type": "m.room.message",
+            "content": { "msgtype": "m.text", "body": "hello" },
+        }));
+        info.insert_decrypted(serde_json::from_value(decrypted).unwrap());
+        assert_eq!(info.undecrypted().len(), 0);
+
+        let msg = info.get_event(event_id!("$decrypted")).unwrap();
+        assert_eq!(msg.event.body(), "hello");
+        assert_eq!(info.messages.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_record_reaction() {
+        let mut store = mock_store().await;
+        let app = &mut store.application;
+
+        app.record_reaction("👍");
+        app.record_reaction("🎉");
+        assert_eq!(app.recent_reactions, vec!["🎉", "👍"]);
+
+        // Using a reaction again moves it back to the front.
+        app.record_reaction("👍");
+        assert_eq!(app.recent_reactions, vec!["👍", "🎉"]);
+
+        // Only the most recent ones are kept.
+        for n in 0..MAX_RECENT_REACTIONS {
+            app.record_reaction(&n.to_string());
+        }
+        assert_eq!(app.recent_reactions.len(), MAX_RECENT_REACTIONS);
+        assert_eq!(app.recent_reactions[0], (MAX_RECENT_REACTIONS - 1).to_string());
+    }
+
     #[tokio::test]
     async fn test_complete_msgbar() {
         let store = mock_store().await;
 
         let text = EditRope::from("going for a walk :walk ");
         let mut cursor = Cursor::new(0, 22);
-        let res = complete_msgbar(&text, &mut cursor, &store);
+        let res = complete_msgbar(&text, &mut cursor, &TEST_ROOM1_ID, &store);
         assert_eq!(res, vec![":walking:", ":walking_man:", ":walking_woman:"]);
         assert_eq!(cursor, Cursor::new(0, 17));
 
         let text = EditRope::from("hello @user1 ");
         let mut cursor = Cursor::new(0, 12);
-        let res = complete_msgbar(&text, &mut cursor, &store);
+        let res = complete_msgbar(&text, &mut cursor, &TEST_ROOM1_ID, &store);
         assert_eq!(res, vec!["@user1:example.com"]);
         assert_eq!(cursor, Cursor::new(0, 6));
 
         let text = EditRope::from("see #room ");
         let mut cursor = Cursor::new(0, 9);
-        let res = complete_msgbar(&text, &mut cursor, &store);
+        let res = complete_msgbar(&text, &mut cursor, &TEST_ROOM1_ID, &store);
         assert_eq!(res, vec!["#room1:example.com"]);
         assert_eq!(cursor, Cursor::new(0, 4));
     }
 
+    #[tokio::test]
+    async fn test_complete_members() {
+        let mut store = mock_store().await;
+        let zed = owned_user_id!("@zed:example.com");
+        let albert = owned_user_id!("@albert:example.com");
+        let info = store.application.rooms.get_or_default(TEST_ROOM1_ID.clone());
+        info.members.insert(zed.clone());
+        info.members.insert(albert.clone());
+        info.display_names.insert(zed.clone(), "Alice".into());
+        info.display_names.insert(albert.clone(), "Bert".into());
+
+        // Display names are matched first, but the user ID is what gets inserted.
+        let text = EditRope::from("hi @al");
+        let mut cursor = Cursor::new(0, 6);
+        let res = complete_msgbar(&text, &mut cursor, &TEST_ROOM1_ID, &store);
+        assert_eq!(res, vec!["@zed:example.com", "@albert:example.com"]);
+        assert_eq!(cursor, Cursor::new(0, 3));
+
+        let text = EditRope::from("@BE");
+        let mut cursor = Cursor::new(0, 3);
+        let res = complete_msgbar(&text, &mut cursor, &TEST_ROOM1_ID, &store);
+        assert_eq!(res, vec!["@albert:example.com"]);
+
+        // Users from outside the room still complete after the members.
+        let text = EditRope::from("@user1");
+        let mut cursor = Cursor::new(0, 6);
+        let res = complete_msgbar(&text, &mut cursor, &TEST_ROOM1_ID, &store);
+        assert_eq!(res, vec!["@user1:example.com"]);
+    }
+
     #[tokio::test]
     async fn test_complete_cmdbar() {
         let store = mock_store().await;
@@ -2096,4 +4085,33 @@ pub mod tests {
         let res = complete_cmdbar(&text, &mut cursor, &store);
         assert_eq!(res, users);
     }
+
+    #[tokio::test]
+    async fn test_complete_join() {
+        let mut store = mock_store().await;
+        let info = store.application.rooms.get_or_default(TEST_ROOM1_ID.clone());
+        info.name = Some("Rust Chat".into());
+
+        let text = EditRope::from("join #ro");
+        let mut cursor = Cursor::new(0, 8);
+        let res = complete_cmdbar(&text, &mut cursor, &store);
+        assert_eq!(res, vec!["#room1:example.com"]);
+        assert_eq!(cursor, Cursor::new(0, 5));
+
+        // Any part of the alias or the room's name can be typed instead.
+        let text = EditRope::from("join oom1");
+        let mut cursor = Cursor::new(0, 9);
+        let res = complete_cmdbar(&text, &mut cursor, &store);
+        assert_eq!(res, vec!["#room1:example.com"]);
+
+        let text = EditRope::from("join chat");
+        let mut cursor = Cursor::new(0, 9);
+        let res = complete_cmdbar(&text, &mut cursor, &store);
+        assert_eq!(res, vec!["#room1:example.com"]);
+
+        let text = EditRope::from("join #nothing");
+        let mut cursor = Cursor::new(0, 13);
+        let res = complete_cmdbar(&text, &mut cursor, &store);
+        assert!(res.is_empty());
+    }
 }
@@ -4,30 +4,44 @@
 //! [modalkit::env::vim::command] for additional Vim commands we pull in.
 use std::convert::TryFrom;
 
-use matrix_sdk::ruma::{events::tag::TagName, OwnedUserId};
+use mime::Mime;
+
+use matrix_sdk::ruma::{
+    events::tag::TagName,
+    presence::PresenceState,
+    OwnedUserId,
+    RoomAliasId,
+    RoomVersionId,
+};
 
 use modalkit::{
     commands::{CommandError, CommandResult, CommandStep},
     env::vim::command::{CommandContext, CommandDescription, OptionType},
-    prelude::OpenTarget,
+    prelude::{MoveDir1D, OpenTarget},
 };
 
 use crate::base::{
+    parse_notification_mode,
     CreateRoomFlags,
     CreateRoomType,
+    DebugAction,
     DownloadFlags,
     HomeserverAction,
     IambAction,
     IambId,
     KeysAction,
     MessageAction,
+    MessageTarget,
     ProgramCommand,
     ProgramCommands,
+    RecoveryAction,
     RoomAction,
     RoomField,
     SendAction,
     VerifyAction,
 };
+use crate::emoji::shortcode_emoji;
+use crate::message::valid_timestamp_format;
 
 type ProgContext = CommandContext;
 type ProgResult = CommandResult<ProgramCommand>;
@@ -56,6 +70,81 @@ fn tag_name(name: String) -> Result<TagName, CommandError> {
     Ok(tag)
 }
 
+/// Parse a user identifier given as a command argument.
+fn user_arg(arg: &str) -> Result<OwnedUserId, CommandError> {
+    OwnedUserId::try_from(arg).map_err(|_| {
+        let msg = format!("Invalid user identifier: {arg}");
+        CommandError::Error(msg)
+    })
+}
+
+fn iamb_kick(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
+    let args = desc.arg.strings()?;
+
+    let ract = match args.as_slice() {
+        [user] => RoomAction::Kick(user_arg(user)?, None),
+        [user, reason] => RoomAction::Kick(user_arg(user)?, Some(reason.clone())),
+        _ => return Err(CommandError::InvalidArgument),
+    };
+
+    let step = CommandStep::Continue(IambAction::from(ract).into(), ctx.context.clone());
+
+    return Ok(step);
+}
+
+fn iamb_ignore(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
+    let args = desc.arg.strings()?;
+
+    let act = match args.as_slice() {
+        [user] => IambAction::SetIgnored(user_arg(user)?, true),
+        _ => return Err(CommandError::InvalidArgument),
+    };
+
+    let step = CommandStep::Continue(act.into(), ctx.context.clone());
+
+    return Ok(step);
+}
+
+fn iamb_unignore(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
+    let args = desc.arg.strings()?;
+
+    let act = match args.as_slice() {
+        [user] => IambAction::SetIgnored(user_arg(user)?, false),
+        _ => return Err(CommandError::InvalidArgument),
+    };
+
+    let step = CommandStep::Continue(act.into(), ctx.context.clone());
+
+    return Ok(step);
+}
+
+fn iamb_ban(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
+    let args = desc.arg.strings()?;
+
+    let ract = match args.as_slice() {
+        [user] => RoomAction::Ban(user_arg(user)?, None),
+        [user, reason] => RoomAction::Ban(user_arg(user)?, Some(reason.clone())),
+        _ => return Err(CommandError::InvalidArgument),
+    };
+
+    let step = CommandStep::Continue(IambAction::from(ract).into(), ctx.context.clone());
+
+    return Ok(step);
+}
+
+fn iamb_unban(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
+    let args = desc.arg.strings()?;
+
+    let ract = match args.as_slice() {
+        [user] => RoomAction::Unban(user_arg(user)?),
+        _ => return Err(CommandError::InvalidArgument),
+    };
+
+    let step = CommandStep::Continue(IambAction::from(ract).into(), ctx.context.clone());
+
+    return Ok(step);
+}
+
 fn iamb_invite(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
     let args = desc.arg.strings()?;
 
@@ -83,14 +172,14 @@ fn iamb_invite(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
                 return Err(CommandError::InvalidArgument);
             }
 
-            if let Ok(user) = OwnedUserId::try_from(args[1].as_str()) {
-                RoomAction::InviteSend(user)
-            } else {
-                let msg = format!("Invalid user identifier: {}", args[1]);
-                let err = CommandError::Error(msg);
-
-                return Err(err);
+            RoomAction::InviteSend(user_arg(&args[1])?)
+        },
+        "cancel" => {
+            if args.len() != 2 {
+                return Err(CommandError::InvalidArgument);
             }
+
+            RoomAction::InviteRevoke(user_arg(&args[1])?)
         },
         _ => {
             return Err(CommandError::InvalidArgument);
@@ -106,6 +195,13 @@ fn iamb_invite(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
 fn iamb_keys(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
     let mut args = desc.arg.strings()?;
 
+    if args.len() == 1 && args[0] == "restore" {
+        let mact = IambAction::Message(MessageAction::RestoreKeys);
+        let step = CommandStep::Continue(mact.into(), ctx.context.clone());
+
+        return Ok(step);
+    }
+
     if args.len() != 3 {
         return Err(CommandError::InvalidArgument);
     }
@@ -126,18 +222,49 @@ fn iamb_keys(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
     return Ok(step);
 }
 
+fn iamb_recovery(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
+    let args = desc.arg.strings()?;
+
+    let act = match args.as_slice() {
+        [setup] if setup == "setup" => RecoveryAction::Setup,
+        // Recovery keys are written as space-separated groups of characters.
+        [restore, key @ ..] if restore == "restore" && !key.is_empty() => {
+            RecoveryAction::Restore(key.join(" "))
+        },
+        _ => return Err(CommandError::InvalidArgument),
+    };
+
+    let step = CommandStep::Continue(IambAction::Recovery(act).into(), ctx.context.clone());
+
+    return Ok(step);
+}
+
 fn iamb_verify(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
     let mut args = desc.arg.strings()?;
 
     match args.len() {
-        0 => {
+        0 | 1 if args.iter().all(|arg| arg == "list") => {
             let open = ctx.switch(OpenTarget::Application(IambId::VerifyList));
             let step = CommandStep::Continue(open, ctx.context.clone());
 
             return Ok(step);
         },
+        1 if args[0] == "dismiss" => {
+            let step = CommandStep::Continue(IambAction::VerifyDismiss.into(), ctx.context.clone());
+
+            return Ok(step);
+        },
         1 => {
-            return Result::Err(CommandError::InvalidArgument);
+            let answer = match args[0].as_str() {
+                "accept" => true,
+                "decline" => false,
+                _ => return Result::Err(CommandError::InvalidArgument),
+            };
+
+            let mact = IambAction::from(MessageAction::AnswerVerification(answer));
+            let step = CommandStep::Continue(mact.into(), ctx.context.clone());
+
+            return Ok(step);
         },
         2 => {
             let act = match args[0].as_str() {
@@ -151,6 +278,12 @@ fn iamb_verify(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
 
                     return Ok(step);
                 },
+                "ack" | "acknowledge" => {
+                    let iact = IambAction::VerifyAcknowledge(args.remove(1));
+                    let step = CommandStep::Continue(iact.into(), ctx.context.clone());
+
+                    return Ok(step);
+                },
                 _ => return Result::Err(CommandError::InvalidArgument),
             };
 
@@ -165,6 +298,21 @@ fn iamb_verify(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
     }
 }
 
+fn iamb_dm(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
+    let args = desc.arg.strings()?;
+
+    let [user] = args.as_slice() else {
+        return Result::Err(CommandError::InvalidArgument);
+    };
+
+    // Opening a window by user identifier finds or creates an encrypted direct message room.
+    let user = user_arg(user)?;
+    let open = ctx.switch(OpenTarget::Name(user.to_string()));
+    let step = CommandStep::Continue(open, ctx.context.clone());
+
+    return Ok(step);
+}
+
 fn iamb_dms(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
     if !desc.arg.text.is_empty() {
         return Result::Err(CommandError::InvalidArgument);
@@ -187,6 +335,35 @@ fn iamb_members(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
     return Ok(step);
 }
 
+fn iamb_gallery(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
+    if !desc.arg.text.is_empty() {
+        return Result::Err(CommandError::InvalidArgument);
+    }
+
+    let open = IambAction::Room(RoomAction::Gallery);
+    let step = CommandStep::Continue(open.into(), ctx.context.clone());
+
+    return Ok(step);
+}
+
+fn iamb_search(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
+    let text = desc.arg.strings()?.join(" ");
+
+    if text.is_empty() {
+        return Result::Err(CommandError::InvalidArgument);
+    }
+
+    let dir = if desc.bang {
+        MoveDir1D::Next
+    } else {
+        MoveDir1D::Previous
+    };
+    let act = IambAction::Room(RoomAction::Search(text, dir));
+    let step = CommandStep::Continue(act.into(), ctx.context.clone());
+
+    return Ok(step);
+}
+
 fn iamb_leave(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
     if !desc.arg.text.is_empty() {
         return Result::Err(CommandError::InvalidArgument);
@@ -209,17 +386,54 @@ fn iamb_cancel(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
     return Ok(step);
 }
 
-fn iamb_edit(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
+fn iamb_checkconfig(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
     if !desc.arg.text.is_empty() {
         return Result::Err(CommandError::InvalidArgument);
     }
 
-    let mact = IambAction::from(MessageAction::Edit);
+    let step = CommandStep::Continue(IambAction::CheckConfig.into(), ctx.context.clone());
+
+    return Ok(step);
+}
+
+fn iamb_edit(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
+    let args = desc.arg.strings()?;
+
+    let act = match args.len() {
+        0 => MessageAction::Edit(MessageTarget::Selected),
+        1 if args[0] == "last" => MessageAction::Edit(MessageTarget::LastOwn),
+        1 if args[0] == "cancel" => MessageAction::CancelEdit,
+        _ => return Result::Err(CommandError::InvalidArgument),
+    };
+
+    let mact = IambAction::from(act);
+    let step = CommandStep::Continue(mact.into(), ctx.context.clone());
+
+    return Ok(step);
+}
+
+fn iamb_forward(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
+    let mut args = desc.arg.strings()?;
+
+    if args.len() != 1 {
+        return Result::Err(CommandError::InvalidArgument);
+    }
+
+    let mact = IambAction::from(MessageAction::Forward(args.remove(0)));
     let step = CommandStep::Continue(mact.into(), ctx.context.clone());
 
     return Ok(step);
 }
 
+/// Look up an Emoji given as itself, or as a shortcode with or without its colons.
+fn get_emoji(k: &str) -> Option<&'static str> {
+    let name = k.strip_prefix(':').and_then(|k| k.strip_suffix(':')).unwrap_or(k);
+
+    emojis::get(k)
+        .map(|emoji| emoji.as_str())
+        .or_else(|| shortcode_emoji(name))
+}
+
 fn iamb_react(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
     let args = desc.arg.strings()?;
 
@@ -229,7 +443,14 @@ fn iamb_react(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
 
     let k = args[0].as_str();
 
-    if let Some(emoji) = emojis::get(k).or_else(|| emojis::get_by_shortcode(k)) {
+    if k == "recent" {
+        let mact = IambAction::from(MessageAction::ReactRecent);
+        let step = CommandStep::Continue(mact.into(), ctx.context.clone());
+
+        return Ok(step);
+    }
+
+    if let Some(emoji) = get_emoji(k) {
         let mact = IambAction::from(MessageAction::React(emoji.to_string()));
         let step = CommandStep::Continue(mact.into(), ctx.context.clone());
 
@@ -251,7 +472,7 @@ fn iamb_unreact(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
     let mact = if let Some(k) = args.pop() {
         let k = k.as_str();
 
-        if let Some(emoji) = emojis::get(k).or_else(|| emojis::get_by_shortcode(k)) {
+        if let Some(emoji) = get_emoji(k) {
             IambAction::from(MessageAction::Unreact(Some(emoji.to_string())))
         } else {
             let msg = format!("Invalid Emoji or shortcode: {k}");
@@ -268,30 +489,140 @@ fn iamb_unreact(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
 }
 
 fn iamb_redact(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
-    let args = desc.arg.strings()?;
+    let mut args = desc.arg.strings()?;
+
+    // :redact last [reason]
+    let target = if args.first().map(String::as_str) == Some("last") {
+        args.remove(0);
+        MessageTarget::LastOwn
+    } else {
+        MessageTarget::Selected
+    };
 
     if args.len() > 1 {
         return Result::Err(CommandError::InvalidArgument);
     }
 
     let reason = args.into_iter().next();
-    let ract = IambAction::from(MessageAction::Redact(reason, desc.bang));
+    let ract = IambAction::from(MessageAction::Redact(reason, desc.bang, target));
     let step = CommandStep::Continue(ract.into(), ctx.context.clone());
 
     return Ok(step);
 }
 
-fn iamb_reply(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
+fn iamb_receipts(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
     if !desc.arg.text.is_empty() {
         return Result::Err(CommandError::InvalidArgument);
     }
 
-    let ract = IambAction::from(MessageAction::Reply);
+    let ract = IambAction::from(MessageAction::Receipts);
+    let step = CommandStep::Continue(ract.into(), ctx.context.clone());
+
+    return Ok(step);
+}
+
+fn iamb_select(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
+    let args = desc.arg.strings()?;
+
+    let mact = match args.as_slice() {
+        [] => MessageAction::Select,
+        [clear] if clear == "clear" => MessageAction::SelectClear,
+        _ => return Result::Err(CommandError::InvalidArgument),
+    };
+
+    let step = CommandStep::Continue(IambAction::from(mact).into(), ctx.context.clone());
+
+    return Ok(step);
+}
+
+fn iamb_reply(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
+    let args = desc.arg.strings()?;
+
+    let act = match args.as_slice() {
+        [] => MessageAction::Reply(false),
+        [cancel] if cancel == "cancel" => MessageAction::CancelReply,
+        [sticky] if sticky == "sticky" => MessageAction::Reply(true),
+        _ => return Result::Err(CommandError::InvalidArgument),
+    };
+
+    let ract = IambAction::from(act);
     let step = CommandStep::Continue(ract.into(), ctx.context.clone());
 
     return Ok(step);
 }
 
+fn iamb_thread(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
+    let args = desc.arg.strings()?;
+
+    let act = match args.as_slice() {
+        [] => MessageAction::ReplyThread,
+        [cancel] if cancel == "cancel" => MessageAction::CancelReply,
+        _ => return Result::Err(CommandError::InvalidArgument),
+    };
+
+    let step = CommandStep::Continue(IambAction::from(act).into(), ctx.context.clone());
+
+    return Ok(step);
+}
+
+fn iamb_parent(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
+    if !desc.arg.text.is_empty() {
+        return Result::Err(CommandError::InvalidArgument);
+    }
+
+    let mact = IambAction::from(MessageAction::GotoParent);
+    let step = CommandStep::Continue(mact.into(), ctx.context.clone());
+
+    return Ok(step);
+}
+
+fn iamb_unsend(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
+    if !desc.arg.text.is_empty() {
+        return Result::Err(CommandError::InvalidArgument);
+    }
+
+    let iact = IambAction::from(MessageAction::Unsend);
+    let step = CommandStep::Continue(iact.into(), ctx.context.clone());
+
+    return Ok(step);
+}
+
+fn iamb_resend(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
+    if !desc.arg.text.is_empty() {
+        return Result::Err(CommandError::InvalidArgument);
+    }
+
+    let iact = IambAction::from(MessageAction::Resend);
+    let step = CommandStep::Continue(iact.into(), ctx.context.clone());
+
+    return Ok(step);
+}
+
+fn iamb_read(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
+    let args = desc.arg.strings()?;
+
+    let ract = match args.as_slice() {
+        [] => RoomAction::MarkRead(false),
+        [here] if here == "here" => RoomAction::MarkRead(true),
+        _ => return Result::Err(CommandError::InvalidArgument),
+    };
+
+    let step = CommandStep::Continue(IambAction::from(ract).into(), ctx.context.clone());
+
+    return Ok(step);
+}
+
+fn iamb_markunread(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
+    if !desc.arg.text.is_empty() {
+        return Result::Err(CommandError::InvalidArgument);
+    }
+
+    let iact = IambAction::from(MessageAction::MarkUnread);
+    let step = CommandStep::Continue(iact.into(), ctx.context.clone());
+
+    return Ok(step);
+}
+
 fn iamb_editor(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
     if !desc.arg.text.is_empty() {
         return Result::Err(CommandError::InvalidArgument);
@@ -304,27 +635,59 @@ fn iamb_editor(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
 }
 
 fn iamb_rooms(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
+    let args = desc.arg.strings()?;
+
+    let act = match args.as_slice() {
+        [] => ctx.switch(OpenTarget::Application(IambId::RoomList)),
+        [group] if group == "group" => IambAction::ToggleRoomGrouping.into(),
+        _ => return Result::Err(CommandError::InvalidArgument),
+    };
+
+    let step = CommandStep::Continue(act, ctx.context.clone());
+
+    return Ok(step);
+}
+
+fn iamb_chats(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
     if !desc.arg.text.is_empty() {
         return Result::Err(CommandError::InvalidArgument);
     }
 
-    let open = ctx.switch(OpenTarget::Application(IambId::RoomList));
+    let open = ctx.switch(OpenTarget::Application(IambId::ChatList));
     let step = CommandStep::Continue(open, ctx.context.clone());
 
     return Ok(step);
 }
 
-fn iamb_chats(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
+fn iamb_breadcrumbs(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
     if !desc.arg.text.is_empty() {
         return Result::Err(CommandError::InvalidArgument);
     }
 
-    let open = ctx.switch(OpenTarget::Application(IambId::ChatList));
+    let open = ctx.switch(OpenTarget::Application(IambId::BreadcrumbList));
     let step = CommandStep::Continue(open, ctx.context.clone());
 
     return Ok(step);
 }
 
+fn iamb_space(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
+    let args = desc.arg.strings()?;
+
+    let iact = match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["create", name, children @ ..] => {
+            let children = children.iter().map(|c| c.to_string()).collect();
+            IambAction::from(HomeserverAction::CreateSpace(name.to_string(), children))
+        },
+        ["leave"] => IambAction::from(RoomAction::Leave(desc.bang)),
+        ["leave", "all"] => IambAction::from(RoomAction::LeaveSpace(desc.bang)),
+        _ => return Result::Err(CommandError::InvalidArgument),
+    };
+
+    let step = CommandStep::Continue(iact.into(), ctx.context.clone());
+
+    return Ok(step);
+}
+
 fn iamb_spaces(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
     if !desc.arg.text.is_empty() {
         return Result::Err(CommandError::InvalidArgument);
@@ -410,14 +773,88 @@ fn iamb_create(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
 fn iamb_room(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
     let mut args = desc.arg.strings()?;
 
-    if args.len() < 2 {
-        return Result::Err(CommandError::InvalidArgument);
+    // :room invite-link
+    if args.len() == 1 && args[0] == "invite-link" {
+        let act = IambAction::from(RoomAction::InviteLink);
+        let step = CommandStep::Continue(act.into(), ctx.context.clone());
+
+        return Ok(step);
     }
 
-    let field = args.remove(0);
-    let action = args.remove(0);
+    // :room pins
+    if args.len() == 1 && args[0] == "pins" {
+        let act = IambAction::from(RoomAction::Pins);
+        let step = CommandStep::Continue(act.into(), ctx.context.clone());
 
-    if args.len() > 1 {
+        return Ok(step);
+    }
+
+    // :room canonical [alias|unset]
+    if args.first().map(String::as_str) == Some("canonical") {
+        let act = match &args[1..] {
+            [] => RoomAction::ShowCanonicalAlias,
+            [unset] if unset == "unset" => RoomAction::Unset(RoomField::CanonicalAlias),
+            [alias] => {
+                if RoomAliasId::parse(alias).is_err() {
+                    let msg = format!("Invalid room alias: {alias}");
+                    return Result::Err(CommandError::Error(msg));
+                }
+
+                RoomAction::Set(RoomField::CanonicalAlias, alias.clone())
+            },
+            _ => return Result::Err(CommandError::InvalidArgument),
+        };
+        let step = CommandStep::Continue(IambAction::from(act).into(), ctx.context.clone());
+
+        return Ok(step);
+    }
+
+    // :room search <text>
+    if args.first().map(String::as_str) == Some("search") {
+        let query = args[1..].join(" ");
+
+        if query.is_empty() {
+            return Result::Err(CommandError::InvalidArgument);
+        }
+
+        let act = IambAction::from(RoomAction::SearchServer(query));
+        let step = CommandStep::Continue(act.into(), ctx.context.clone());
+
+        return Ok(step);
+    }
+
+    // :room avatar
+    if args.len() == 1 && args[0] == "avatar" {
+        let act = IambAction::from(RoomAction::ShowAvatar);
+        let step = CommandStep::Continue(act.into(), ctx.context.clone());
+
+        return Ok(step);
+    }
+
+    // :room notify
+    if args.len() == 1 && args[0] == "notify" {
+        let act = IambAction::from(RoomAction::ShowNotificationMode);
+        let step = CommandStep::Continue(act.into(), ctx.context.clone());
+
+        return Ok(step);
+    }
+
+    // :room timestamp
+    if args.len() == 1 && args[0] == "timestamp" {
+        let act = IambAction::from(RoomAction::ShowTimestampFormat);
+        let step = CommandStep::Continue(act.into(), ctx.context.clone());
+
+        return Ok(step);
+    }
+
+    if args.len() < 2 {
+        return Result::Err(CommandError::InvalidArgument);
+    }
+
+    let field = args.remove(0);
+    let action = args.remove(0);
+
+    if args.len() > 1 {
         return Result::Err(CommandError::InvalidArgument);
     }
 
@@ -430,6 +867,36 @@ fn iamb_room(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
         ("dm", "unset", None) => RoomAction::SetDirect(false).into(),
         ("dm", "unset", Some(_)) => return Result::Err(CommandError::InvalidArgument),
 
+        // :room members count
+        ("members", "count", None) => RoomAction::MemberCount.into(),
+        ("members", "count", Some(_)) => return Result::Err(CommandError::InvalidArgument),
+
+        // :room notifications test
+        ("notifications", "test", None) => RoomAction::NotificationTest.into(),
+        ("notifications", "test", Some(_)) => return Result::Err(CommandError::InvalidArgument),
+
+        // :room avatar set <path>
+        ("avatar", "set", Some(s)) => RoomAction::Set(RoomField::Avatar, s).into(),
+        ("avatar", "set", None) => return Result::Err(CommandError::InvalidArgument),
+
+        // :room avatar unset
+        ("avatar", "unset", None) => RoomAction::Unset(RoomField::Avatar).into(),
+        ("avatar", "unset", Some(_)) => return Result::Err(CommandError::InvalidArgument),
+
+        // :room notify default
+        ("notify", "default", None) => RoomAction::Unset(RoomField::NotificationMode).into(),
+
+        // :room notify <level>
+        ("notify", level, None) => {
+            if parse_notification_mode(level).is_none() {
+                let msg = format!("Invalid notification level: {level}");
+                return Result::Err(CommandError::Error(msg));
+            }
+
+            RoomAction::Set(RoomField::NotificationMode, level.into()).into()
+        },
+        ("notify", _, Some(_)) => return Result::Err(CommandError::InvalidArgument),
+
         // :room name set <room-name>
         ("name", "set", Some(s)) => RoomAction::Set(RoomField::Name, s).into(),
         ("name", "set", None) => return Result::Err(CommandError::InvalidArgument),
@@ -442,14 +909,48 @@ fn iamb_room(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
         ("topic", "set", Some(s)) => RoomAction::Set(RoomField::Topic, s).into(),
         ("topic", "set", None) => return Result::Err(CommandError::InvalidArgument),
 
+        // :room topic edit
+        ("topic", "edit", None) => RoomAction::Edit(RoomField::Topic).into(),
+        ("topic", "edit", Some(_)) => return Result::Err(CommandError::InvalidArgument),
+
         // :room topic unset
         ("topic", "unset", None) => RoomAction::Unset(RoomField::Topic).into(),
         ("topic", "unset", Some(_)) => return Result::Err(CommandError::InvalidArgument),
 
+        // :room timestamp set <format>
+        ("timestamp", "set", Some(s)) => {
+            if !valid_timestamp_format(&s) {
+                let msg = format!("Invalid timestamp format: {s}");
+                return Result::Err(CommandError::Error(msg));
+            }
+
+            RoomAction::Set(RoomField::MessageTimestamp, s).into()
+        },
+        ("timestamp", "set", None) => return Result::Err(CommandError::InvalidArgument),
+
+        // :room timestamp unset
+        ("timestamp", "unset", None) => RoomAction::Unset(RoomField::MessageTimestamp).into(),
+        ("timestamp", "unset", Some(_)) => return Result::Err(CommandError::InvalidArgument),
+
         // :room tag set <tag-name>
         ("tag", "set", Some(s)) => RoomAction::Set(RoomField::Tag(tag_name(s)?), "".into()).into(),
         ("tag", "set", None) => return Result::Err(CommandError::InvalidArgument),
 
+        // :room version check
+        ("version", "check", None) => RoomAction::VersionCheck.into(),
+        ("version", "check", Some(_)) => return Result::Err(CommandError::InvalidArgument),
+
+        // :room version dismiss
+        ("version", "dismiss", None) => RoomAction::VersionDismiss.into(),
+        ("version", "dismiss", Some(_)) => return Result::Err(CommandError::InvalidArgument),
+
+        // :room version upgrade [version]
+        ("version", "upgrade", v) => {
+            let v = v.map(RoomVersionId::try_from).transpose();
+            let v = v.map_err(|_| CommandError::InvalidArgument)?;
+            RoomAction::Upgrade(v, desc.bang).into()
+        },
+
         // :room tag unset <tag-name>
         ("tag", "unset", Some(s)) => RoomAction::Unset(RoomField::Tag(tag_name(s)?)).into(),
         ("tag", "unset", None) => return Result::Err(CommandError::InvalidArgument),
@@ -463,15 +964,58 @@ fn iamb_room(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
 }
 
 fn iamb_upload(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
+    let mut args = desc.arg.strings()?.into_iter();
+    let mut path = None;
+    let mut caption = None;
+    let mut mime = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--caption" if caption.is_none() => {
+                caption = Some(args.next().ok_or(CommandError::InvalidArgument)?);
+            },
+            "--mime" if mime.is_none() => {
+                let arg = args.next().ok_or(CommandError::InvalidArgument)?;
+                let Ok(parsed) = arg.parse::<Mime>() else {
+                    let msg = format!("Invalid mime type: {arg}");
+                    return Err(CommandError::Error(msg));
+                };
+
+                mime = Some(parsed);
+            },
+            _ if path.is_none() => path = Some(arg),
+            _ => return Result::Err(CommandError::InvalidArgument),
+        }
+    }
+
+    let path = path.ok_or(CommandError::InvalidArgument)?;
+    let sact = SendAction::Upload(path, caption, mime);
+    let iact = IambAction::from(sact);
+    let step = CommandStep::Continue(iact.into(), ctx.context.clone());
+
+    return Ok(step);
+}
+
+fn iamb_debug(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
     let mut args = desc.arg.strings()?;
 
-    if args.len() != 1 {
+    if args.first().map(String::as_str) != Some("events") {
         return Result::Err(CommandError::InvalidArgument);
     }
 
-    let sact = SendAction::Upload(args.remove(0));
-    let iact = IambAction::from(sact);
-    let step = CommandStep::Continue(iact.into(), ctx.context.clone());
+    let act = match args.len() {
+        1 => ctx.switch(OpenTarget::Application(IambId::DebugEvents)),
+        2 if args[1] == "pause" => IambAction::from(DebugAction::TogglePause).into(),
+        2 if args[1] == "filter" => IambAction::from(DebugAction::Filter(None)).into(),
+        3 if args[1] == "filter" => {
+            let filter = args.remove(2);
+
+            IambAction::from(DebugAction::Filter(Some(filter))).into()
+        },
+        _ => return Result::Err(CommandError::InvalidArgument),
+    };
+
+    let step = CommandStep::Continue(act, ctx.context.clone());
 
     return Ok(step);
 }
@@ -497,6 +1041,19 @@ fn iamb_download(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult
 fn iamb_open(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
     let mut args = desc.arg.strings()?;
 
+    if args.first().map(String::as_str) == Some("url") {
+        let label = match args.as_slice() {
+            [_] => None,
+            [_, label] => Some(label.parse::<char>().map_err(|_| CommandError::InvalidArgument)?),
+            _ => return Err(CommandError::InvalidArgument),
+        };
+
+        let iact = IambAction::from(MessageAction::OpenLink(label));
+        let step = CommandStep::Continue(iact.into(), ctx.context.clone());
+
+        return Ok(step);
+    }
+
     if args.len() > 1 {
         return Result::Err(CommandError::InvalidArgument);
     }
@@ -519,18 +1076,81 @@ fn iamb_logout(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
         return Result::Err(CommandError::InvalidArgument);
     }
 
-    let iact = IambAction::from(HomeserverAction::Logout(args[0].clone(), desc.bang));
+    let iact = IambAction::from(HomeserverAction::Logout(args[0].clone(), desc.bang, false));
+    let step = CommandStep::Continue(iact.into(), ctx.context.clone());
+
+    return Ok(step);
+}
+
+fn iamb_presence(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
+    let mut args = desc.arg.strings()?;
+
+    if args.is_empty() {
+        return Result::Err(CommandError::InvalidArgument);
+    }
+
+    let presence = match args.remove(0).as_str() {
+        "online" => PresenceState::Online,
+        "unavailable" => PresenceState::Unavailable,
+        "offline" => PresenceState::Offline,
+        _ => return Result::Err(CommandError::InvalidArgument),
+    };
+    let status_msg = (!args.is_empty()).then(|| args.join(" "));
+
+    let act = IambAction::SetPresence(presence, status_msg);
+    let step = CommandStep::Continue(act.into(), ctx.context.clone());
+
+    return Ok(step);
+}
+
+fn iamb_cache(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
+    if desc.arg.text.trim() != "clean" {
+        return Result::Err(CommandError::InvalidArgument);
+    }
+
+    let step = CommandStep::Continue(IambAction::CleanCache.into(), ctx.context.clone());
+
+    return Ok(step);
+}
+
+fn iamb_clearcache(desc: CommandDescription, ctx: &mut ProgContext) -> ProgResult {
+    if !desc.arg.text.is_empty() {
+        return Result::Err(CommandError::InvalidArgument);
+    }
+
+    let iact = IambAction::from(HomeserverAction::ClearCache(desc.bang));
     let step = CommandStep::Continue(iact.into(), ctx.context.clone());
 
     return Ok(step);
 }
 
 fn add_iamb_commands(cmds: &mut ProgramCommands) {
+    cmds.add_command(ProgramCommand { name: "ban".into(), aliases: vec![], f: iamb_ban });
+    cmds.add_command(ProgramCommand {
+        name: "breadcrumbs".into(),
+        aliases: vec![],
+        f: iamb_breadcrumbs,
+    });
+    cmds.add_command(ProgramCommand {
+        name: "cache".into(),
+        aliases: vec![],
+        f: iamb_cache,
+    });
     cmds.add_command(ProgramCommand {
         name: "cancel".into(),
         aliases: vec![],
         f: iamb_cancel,
     });
+    cmds.add_command(ProgramCommand {
+        name: "checkconfig".into(),
+        aliases: vec![],
+        f: iamb_checkconfig,
+    });
+    cmds.add_command(ProgramCommand {
+        name: "clearcache".into(),
+        aliases: vec![],
+        f: iamb_clearcache,
+    });
     cmds.add_command(ProgramCommand {
         name: "create".into(),
         aliases: vec![],
@@ -541,6 +1161,12 @@ fn add_iamb_commands(cmds: &mut ProgramCommands) {
         aliases: vec![],
         f: iamb_chats,
     });
+    cmds.add_command(ProgramCommand {
+        name: "debug".into(),
+        aliases: vec![],
+        f: iamb_debug,
+    });
+    cmds.add_command(ProgramCommand { name: "dm".into(), aliases: vec![], f: iamb_dm });
     cmds.add_command(ProgramCommand { name: "dms".into(), aliases: vec![], f: iamb_dms });
     cmds.add_command(ProgramCommand {
         name: "download".into(),
@@ -549,6 +1175,16 @@ fn add_iamb_commands(cmds: &mut ProgramCommands) {
     });
     cmds.add_command(ProgramCommand { name: "open".into(), aliases: vec![], f: iamb_open });
     cmds.add_command(ProgramCommand { name: "edit".into(), aliases: vec![], f: iamb_edit });
+    cmds.add_command(ProgramCommand {
+        name: "forward".into(),
+        aliases: vec![],
+        f: iamb_forward,
+    });
+    cmds.add_command(ProgramCommand {
+        name: "ignore".into(),
+        aliases: vec![],
+        f: iamb_ignore,
+    });
     cmds.add_command(ProgramCommand {
         name: "invite".into(),
         aliases: vec![],
@@ -556,21 +1192,53 @@ fn add_iamb_commands(cmds: &mut ProgramCommands) {
     });
     cmds.add_command(ProgramCommand { name: "join".into(), aliases: vec![], f: iamb_join });
     cmds.add_command(ProgramCommand { name: "keys".into(), aliases: vec![], f: iamb_keys });
+    cmds.add_command(ProgramCommand {
+        name: "recovery".into(),
+        aliases: vec![],
+        f: iamb_recovery,
+    });
+    cmds.add_command(ProgramCommand { name: "kick".into(), aliases: vec![], f: iamb_kick });
     cmds.add_command(ProgramCommand {
         name: "leave".into(),
         aliases: vec![],
         f: iamb_leave,
     });
+    cmds.add_command(ProgramCommand {
+        name: "gallery".into(),
+        aliases: vec![],
+        f: iamb_gallery,
+    });
+    cmds.add_command(ProgramCommand {
+        name: "markunread".into(),
+        aliases: vec![],
+        f: iamb_markunread,
+    });
     cmds.add_command(ProgramCommand {
         name: "members".into(),
         aliases: vec![],
         f: iamb_members,
     });
+    cmds.add_command(ProgramCommand {
+        name: "parent".into(),
+        aliases: vec![],
+        f: iamb_parent,
+    });
+    cmds.add_command(ProgramCommand {
+        name: "presence".into(),
+        aliases: vec![],
+        f: iamb_presence,
+    });
     cmds.add_command(ProgramCommand {
         name: "react".into(),
         aliases: vec![],
         f: iamb_react,
     });
+    cmds.add_command(ProgramCommand { name: "read".into(), aliases: vec![], f: iamb_read });
+    cmds.add_command(ProgramCommand {
+        name: "receipts".into(),
+        aliases: vec![],
+        f: iamb_receipts,
+    });
     cmds.add_command(ProgramCommand {
         name: "redact".into(),
         aliases: vec![],
@@ -581,22 +1249,62 @@ fn add_iamb_commands(cmds: &mut ProgramCommands) {
         aliases: vec![],
         f: iamb_reply,
     });
+    cmds.add_command(ProgramCommand {
+        name: "resend".into(),
+        aliases: vec![],
+        f: iamb_resend,
+    });
     cmds.add_command(ProgramCommand {
         name: "rooms".into(),
         aliases: vec![],
         f: iamb_rooms,
     });
     cmds.add_command(ProgramCommand { name: "room".into(), aliases: vec![], f: iamb_room });
+    cmds.add_command(ProgramCommand {
+        name: "search".into(),
+        aliases: vec![],
+        f: iamb_search,
+    });
+    cmds.add_command(ProgramCommand {
+        name: "select".into(),
+        aliases: vec![],
+        f: iamb_select,
+    });
+    cmds.add_command(ProgramCommand {
+        name: "space".into(),
+        aliases: vec![],
+        f: iamb_space,
+    });
     cmds.add_command(ProgramCommand {
         name: "spaces".into(),
         aliases: vec![],
         f: iamb_spaces,
     });
+    cmds.add_command(ProgramCommand {
+        name: "thread".into(),
+        aliases: vec![],
+        f: iamb_thread,
+    });
+    cmds.add_command(ProgramCommand {
+        name: "unban".into(),
+        aliases: vec![],
+        f: iamb_unban,
+    });
+    cmds.add_command(ProgramCommand {
+        name: "unignore".into(),
+        aliases: vec![],
+        f: iamb_unignore,
+    });
     cmds.add_command(ProgramCommand {
         name: "unreact".into(),
         aliases: vec![],
         f: iamb_unreact,
     });
+    cmds.add_command(ProgramCommand {
+        name: "unsend".into(),
+        aliases: vec![],
+        f: iamb_unsend,
+    });
     cmds.add_command(ProgramCommand {
         name: "upload".into(),
         aliases: vec![],
@@ -649,10 +1357,37 @@ mod tests {
         let act = WindowAction::Switch(OpenTarget::Application(IambId::VerifyList));
         assert_eq!(res, vec![(act.into(), ctx.clone())]);
 
+        let res = cmds.input_cmd(":verify list", ctx.clone()).unwrap();
+        let act = WindowAction::Switch(OpenTarget::Application(IambId::VerifyList));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd(":verify lists", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd(":verify accept", ctx.clone()).unwrap();
+        let act = IambAction::from(MessageAction::AnswerVerification(true));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd(":verify decline", ctx.clone()).unwrap();
+        let act = IambAction::from(MessageAction::AnswerVerification(false));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd(":verify dismiss", ctx.clone()).unwrap();
+        assert_eq!(res, vec![(IambAction::VerifyDismiss.into(), ctx.clone())]);
+
         let res = cmds.input_cmd(":verify request @user1:example.com", ctx.clone()).unwrap();
         let act = IambAction::VerifyRequest("@user1:example.com".into());
         assert_eq!(res, vec![(act.into(), ctx.clone())]);
 
+        let res = cmds.input_cmd(":verify ack @user1:example.com", ctx.clone()).unwrap();
+        let act = IambAction::VerifyAcknowledge("@user1:example.com".into());
+        assert_eq!(res, vec![(act.clone().into(), ctx.clone())]);
+
+        let res = cmds
+            .input_cmd(":verify acknowledge @user1:example.com", ctx.clone())
+            .unwrap();
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
         let res = cmds
             .input_cmd(":verify accept @user1:example.com/FOOBAR", ctx.clone())
             .unwrap();
@@ -687,6 +1422,30 @@ mod tests {
         assert_eq!(res, Err(CommandError::InvalidArgument));
     }
 
+    #[test]
+    fn test_cmd_dm() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("dm @user:example.com", ctx.clone()).unwrap();
+        let act = WindowAction::Switch(OpenTarget::Name("@user:example.com".into()));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("dm user", ctx.clone());
+        let err = CommandError::Error("Invalid user identifier: user".into());
+        assert_eq!(res, Err(err));
+
+        let res = cmds.input_cmd("dm #room:example.com", ctx.clone());
+        let err = CommandError::Error("Invalid user identifier: #room:example.com".into());
+        assert_eq!(res, Err(err));
+
+        let res = cmds.input_cmd("dm", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("dm @a:example.com @b:example.com", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
     #[test]
     fn test_cmd_join() {
         let mut cmds = setup_commands();
@@ -723,90 +1482,315 @@ mod tests {
     }
 
     #[test]
-    fn test_cmd_room_topic_set() {
+    fn test_cmd_room_invite_link() {
         let mut cmds = setup_commands();
         let ctx = EditContext::default();
 
-        let res = cmds
-            .input_cmd("room topic set \"Lots of fun discussion!\"", ctx.clone())
-            .unwrap();
-        let act = RoomAction::Set(RoomField::Topic, "Lots of fun discussion!".into());
+        let res = cmds.input_cmd("room invite-link", ctx.clone()).unwrap();
+        let act = IambAction::Room(RoomAction::InviteLink);
         assert_eq!(res, vec![(act.into(), ctx.clone())]);
 
-        let res = cmds
-            .input_cmd("room topic set The\\ Discussion\\ Room", ctx.clone())
-            .unwrap();
-        let act = RoomAction::Set(RoomField::Topic, "The Discussion Room".into());
-        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+        let res = cmds.input_cmd("room invite-link foo", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
 
-        let res = cmds.input_cmd("room topic set Development", ctx.clone()).unwrap();
-        let act = RoomAction::Set(RoomField::Topic, "Development".into());
+    #[test]
+    fn test_cmd_room_canonical() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("room canonical", ctx.clone()).unwrap();
+        let act = IambAction::Room(RoomAction::ShowCanonicalAlias);
         assert_eq!(res, vec![(act.into(), ctx.clone())]);
 
-        let res = cmds.input_cmd("room topic", ctx.clone());
-        assert_eq!(res, Err(CommandError::InvalidArgument));
+        let res = cmds.input_cmd("room canonical #main:example.com", ctx.clone()).unwrap();
+        let act = RoomAction::Set(RoomField::CanonicalAlias, "#main:example.com".into());
+        assert_eq!(res, vec![(IambAction::Room(act).into(), ctx.clone())]);
 
-        let res = cmds.input_cmd("room topic set", ctx.clone());
-        assert_eq!(res, Err(CommandError::InvalidArgument));
+        let res = cmds.input_cmd("room canonical unset", ctx.clone()).unwrap();
+        let act = IambAction::Room(RoomAction::Unset(RoomField::CanonicalAlias));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
 
-        let res = cmds.input_cmd("room topic set A B C", ctx.clone());
+        let res = cmds.input_cmd("room canonical main", ctx.clone());
+        let err = CommandError::Error("Invalid room alias: main".into());
+        assert_eq!(res, Err(err));
+
+        let res =
+            cmds.input_cmd("room canonical #main:example.com #other:example.com", ctx.clone());
         assert_eq!(res, Err(CommandError::InvalidArgument));
     }
 
     #[test]
-    fn test_cmd_room_name_invalid() {
+    fn test_cmd_room_avatar() {
         let mut cmds = setup_commands();
         let ctx = EditContext::default();
 
-        let res = cmds.input_cmd("room name", ctx.clone());
+        let res = cmds.input_cmd("room avatar", ctx.clone()).unwrap();
+        let act = IambAction::Room(RoomAction::ShowAvatar);
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("room avatar set ~/avatar.png", ctx.clone()).unwrap();
+        let act = RoomAction::Set(RoomField::Avatar, "~/avatar.png".into());
+        assert_eq!(res, vec![(IambAction::Room(act).into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("room avatar unset", ctx.clone()).unwrap();
+        let act = IambAction::Room(RoomAction::Unset(RoomField::Avatar));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("room avatar set", ctx.clone());
         assert_eq!(res, Err(CommandError::InvalidArgument));
 
-        let res = cmds.input_cmd("room name foo", ctx.clone());
+        let res = cmds.input_cmd("room avatar unset foo.png", ctx.clone());
         assert_eq!(res, Err(CommandError::InvalidArgument));
     }
 
     #[test]
-    fn test_cmd_room_name_set() {
+    fn test_cmd_room_notify() {
         let mut cmds = setup_commands();
         let ctx = EditContext::default();
 
-        let res = cmds.input_cmd("room name set Development", ctx.clone()).unwrap();
-        let act = RoomAction::Set(RoomField::Name, "Development".into());
+        let res = cmds.input_cmd("room notify", ctx.clone()).unwrap();
+        let act = IambAction::Room(RoomAction::ShowNotificationMode);
         assert_eq!(res, vec![(act.into(), ctx.clone())]);
 
-        let res = cmds
-            .input_cmd("room name set \"Application Development\"", ctx.clone())
-            .unwrap();
-        let act = RoomAction::Set(RoomField::Name, "Application Development".into());
+        for level in ["all", "mentions", "mute"].iter() {
+            let res = cmds.input_cmd(format!("room notify {level}"), ctx.clone()).unwrap();
+            let act = RoomAction::Set(RoomField::NotificationMode, level.to_string());
+            assert_eq!(res, vec![(IambAction::Room(act).into(), ctx.clone())]);
+        }
+
+        let res = cmds.input_cmd("room notify default", ctx.clone()).unwrap();
+        let act = IambAction::Room(RoomAction::Unset(RoomField::NotificationMode));
         assert_eq!(res, vec![(act.into(), ctx.clone())]);
 
-        let res = cmds.input_cmd("room name set", ctx.clone());
+        let res = cmds.input_cmd("room notify loud", ctx.clone());
+        let msg = "Invalid notification level: loud".to_string();
+        assert_eq!(res, Err(CommandError::Error(msg)));
+
+        let res = cmds.input_cmd("room notify mute now", ctx.clone());
         assert_eq!(res, Err(CommandError::InvalidArgument));
     }
 
     #[test]
-    fn test_cmd_room_name_unset() {
+    fn test_cmd_room_search() {
         let mut cmds = setup_commands();
         let ctx = EditContext::default();
 
-        let res = cmds.input_cmd("room name unset", ctx.clone()).unwrap();
-        let act = RoomAction::Unset(RoomField::Name);
-        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+        let res = cmds.input_cmd("room search release notes", ctx.clone()).unwrap();
+        let act = RoomAction::SearchServer("release notes".into());
+        assert_eq!(res, vec![(IambAction::Room(act).into(), ctx.clone())]);
 
-        let res = cmds.input_cmd("room name unset foo", ctx.clone());
+        let res = cmds.input_cmd("room search \"v1.0\"", ctx.clone()).unwrap();
+        let act = RoomAction::SearchServer("v1.0".into());
+        assert_eq!(res, vec![(IambAction::Room(act).into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("room search", ctx.clone());
         assert_eq!(res, Err(CommandError::InvalidArgument));
     }
 
     #[test]
-    fn test_cmd_room_dm_set() {
+    fn test_cmd_room_timestamp() {
         let mut cmds = setup_commands();
         let ctx = EditContext::default();
 
-        let res = cmds.input_cmd("room dm set", ctx.clone()).unwrap();
-        let act = RoomAction::SetDirect(true);
+        let res = cmds.input_cmd("room timestamp", ctx.clone()).unwrap();
+        let act = IambAction::Room(RoomAction::ShowTimestampFormat);
         assert_eq!(res, vec![(act.into(), ctx.clone())]);
 
-        let res = cmds.input_cmd("room dm set true", ctx.clone());
+        let res = cmds.input_cmd("room timestamp set \"%H:%M\"", ctx.clone()).unwrap();
+        let act = RoomAction::Set(RoomField::MessageTimestamp, "%H:%M".into());
+        assert_eq!(res, vec![(IambAction::Room(act).into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("room timestamp set \"%b %d %R\"", ctx.clone()).unwrap();
+        let act = RoomAction::Set(RoomField::MessageTimestamp, "%b %d %R".into());
+        assert_eq!(res, vec![(IambAction::Room(act).into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("room timestamp unset", ctx.clone()).unwrap();
+        let act = IambAction::Room(RoomAction::Unset(RoomField::MessageTimestamp));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("room timestamp set %Q", ctx.clone());
+        let err = CommandError::Error("Invalid timestamp format: %Q".into());
+        assert_eq!(res, Err(err));
+
+        let res = cmds.input_cmd("room timestamp set", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("room timestamp unset %T", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_room_members_count() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("room members count", ctx.clone()).unwrap();
+        let act = RoomAction::MemberCount;
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("room members", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("room members count foo", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_room_pins() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("room pins", ctx.clone()).unwrap();
+        let act = IambAction::Room(RoomAction::Pins);
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("room pins foo", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_room_notifications_test() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("room notifications test", ctx.clone()).unwrap();
+        let act = RoomAction::NotificationTest;
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("room notifications", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("room notifications test foo", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_room_version() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("room version check", ctx.clone()).unwrap();
+        let act = RoomAction::VersionCheck;
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("room version dismiss", ctx.clone()).unwrap();
+        let act = RoomAction::VersionDismiss;
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("room version upgrade", ctx.clone()).unwrap();
+        let act = RoomAction::Upgrade(None, false);
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("room! version upgrade 10", ctx.clone()).unwrap();
+        let act = RoomAction::Upgrade(Some(RoomVersionId::V10), true);
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("room version check foo", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("room version upgrade 10 11", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_room_topic_edit() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("room topic edit", ctx.clone()).unwrap();
+        let act = RoomAction::Edit(RoomField::Topic);
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("room topic edit Development", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_room_topic_set() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds
+            .input_cmd("room topic set \"Lots of fun discussion!\"", ctx.clone())
+            .unwrap();
+        let act = RoomAction::Set(RoomField::Topic, "Lots of fun discussion!".into());
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds
+            .input_cmd("room topic set The\\ Discussion\\ Room", ctx.clone())
+            .unwrap();
+        let act = RoomAction::Set(RoomField::Topic, "The Discussion Room".into());
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("room topic set Development", ctx.clone()).unwrap();
+        let act = RoomAction::Set(RoomField::Topic, "Development".into());
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("room topic", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("room topic set", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("room topic set A B C", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_room_name_invalid() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("room name", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("room name foo", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_room_name_set() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("room name set Development", ctx.clone()).unwrap();
+        let act = RoomAction::Set(RoomField::Name, "Development".into());
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds
+            .input_cmd("room name set \"Application Development\"", ctx.clone())
+            .unwrap();
+        let act = RoomAction::Set(RoomField::Name, "Application Development".into());
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("room name set", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_room_name_unset() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("room name unset", ctx.clone()).unwrap();
+        let act = RoomAction::Unset(RoomField::Name);
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("room name unset foo", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_room_dm_set() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("room dm set", ctx.clone()).unwrap();
+        let act = RoomAction::SetDirect(true);
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("room dm set true", ctx.clone());
         assert_eq!(res, Err(CommandError::InvalidArgument));
     }
 
@@ -975,6 +1959,18 @@ mod tests {
             IambAction::Room(RoomAction::InviteSend(user_id!("@user:example.com").to_owned()));
         assert_eq!(res, vec![(act.into(), ctx.clone())]);
 
+        let res = cmds.input_cmd("invite cancel @user:example.com", ctx.clone()).unwrap();
+        let act =
+            IambAction::Room(RoomAction::InviteRevoke(user_id!("@user:example.com").to_owned()));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("invite cancel user", ctx.clone());
+        let err = CommandError::Error("Invalid user identifier: user".into());
+        assert_eq!(res, Err(err));
+
+        let res = cmds.input_cmd("invite cancel", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
         let res = cmds.input_cmd("invite", ctx.clone());
         assert_eq!(res, Err(CommandError::InvalidArgument));
 
@@ -994,55 +1990,651 @@ mod tests {
         assert_eq!(res, Err(CommandError::InvalidArgument));
     }
 
+    #[test]
+    fn test_cmd_kick_ban() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+        let user = OwnedUserId::try_from("@user:example.com").unwrap();
+
+        let res = cmds.input_cmd("kick @user:example.com", ctx.clone()).unwrap();
+        let act = IambAction::Room(RoomAction::Kick(user.clone(), None));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("kick @user:example.com \"too loud\"", ctx.clone()).unwrap();
+        let act = IambAction::Room(RoomAction::Kick(user.clone(), Some("too loud".into())));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("ban @user:example.com spam", ctx.clone()).unwrap();
+        let act = IambAction::Room(RoomAction::Ban(user.clone(), Some("spam".into())));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("unban @user:example.com", ctx.clone()).unwrap();
+        let act = IambAction::Room(RoomAction::Unban(user.clone()));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("ban user", ctx.clone());
+        let err = CommandError::Error("Invalid user identifier: user".into());
+        assert_eq!(res, Err(err));
+
+        let res = cmds.input_cmd("kick", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("ban @user:example.com too loud", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("unban @user:example.com spam", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
     #[test]
     fn test_cmd_redact() {
         let mut cmds = setup_commands();
         let ctx = EditContext::default();
 
+        let sel = MessageTarget::Selected;
+        let last = MessageTarget::LastOwn;
+
         let res = cmds.input_cmd("redact", ctx.clone()).unwrap();
-        let act = IambAction::Message(MessageAction::Redact(None, false));
+        let act = IambAction::Message(MessageAction::Redact(None, false, sel.clone()));
         assert_eq!(res, vec![(act.into(), ctx.clone())]);
 
         let res = cmds.input_cmd("redact!", ctx.clone()).unwrap();
-        let act = IambAction::Message(MessageAction::Redact(None, true));
+        let act = IambAction::Message(MessageAction::Redact(None, true, sel.clone()));
         assert_eq!(res, vec![(act.into(), ctx.clone())]);
 
         let res = cmds.input_cmd("redact Removed", ctx.clone()).unwrap();
-        let act = IambAction::Message(MessageAction::Redact(Some("Removed".into()), false));
+        let act =
+            IambAction::Message(MessageAction::Redact(Some("Removed".into()), false, sel.clone()));
         assert_eq!(res, vec![(act.into(), ctx.clone())]);
 
         let res = cmds.input_cmd("redact \"Removed\"", ctx.clone()).unwrap();
-        let act = IambAction::Message(MessageAction::Redact(Some("Removed".into()), false));
+        let act =
+            IambAction::Message(MessageAction::Redact(Some("Removed".into()), false, sel.clone()));
         assert_eq!(res, vec![(act.into(), ctx.clone())]);
 
         let res = cmds.input_cmd("redact Removed Removed", ctx.clone());
         assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("redact last", ctx.clone()).unwrap();
+        let act = IambAction::Message(MessageAction::Redact(None, false, last.clone()));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("redact! last Typo", ctx.clone()).unwrap();
+        let act =
+            IambAction::Message(MessageAction::Redact(Some("Typo".into()), true, last.clone()));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
     }
 
     #[test]
-    fn test_cmd_keys() {
+    fn test_cmd_reply() {
         let mut cmds = setup_commands();
         let ctx = EditContext::default();
 
-        let res = cmds.input_cmd("keys import /a/b/c pword", ctx.clone()).unwrap();
-        let act = IambAction::Keys(KeysAction::Import("/a/b/c".into(), "pword".into()));
+        let res = cmds.input_cmd("reply", ctx.clone()).unwrap();
+        let act = IambAction::Message(MessageAction::Reply(false));
         assert_eq!(res, vec![(act.into(), ctx.clone())]);
 
-        let res = cmds.input_cmd("keys export /a/b/c pword", ctx.clone()).unwrap();
-        let act = IambAction::Keys(KeysAction::Export("/a/b/c".into(), "pword".into()));
+        let res = cmds.input_cmd("reply first", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("reply 1 2", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("reply sticky", ctx.clone()).unwrap();
+        let act = IambAction::Message(MessageAction::Reply(true));
         assert_eq!(res, vec![(act.into(), ctx.clone())]);
 
-        // Invalid invocations.
-        let res = cmds.input_cmd("keys", ctx.clone());
+        let res = cmds.input_cmd("reply sticky cancel", ctx.clone());
         assert_eq!(res, Err(CommandError::InvalidArgument));
 
-        let res = cmds.input_cmd("keys import", ctx.clone());
+        let res = cmds.input_cmd("reply cancel", ctx.clone()).unwrap();
+        let act = IambAction::Message(MessageAction::CancelReply);
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+    }
+
+    #[test]
+    fn test_cmd_rooms() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("rooms group", ctx.clone()).unwrap();
+        let act = IambAction::ToggleRoomGrouping;
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("rooms foo", ctx.clone());
         assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
 
-        let res = cmds.input_cmd("keys import foo", ctx.clone());
+    #[test]
+    fn test_cmd_thread() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("thread", ctx.clone()).unwrap();
+        let act = IambAction::Message(MessageAction::ReplyThread);
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("thread cancel", ctx.clone()).unwrap();
+        let act = IambAction::Message(MessageAction::CancelReply);
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("thread foo", ctx.clone());
         assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
 
-        let res = cmds.input_cmd("keys import foo bar baz", ctx.clone());
+    #[test]
+    fn test_cmd_unsend() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("unsend", ctx.clone()).unwrap();
+        let act = IambAction::Message(MessageAction::Unsend);
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("unsend last", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_forward() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("forward #room:example.com", ctx.clone()).unwrap();
+        let act = IambAction::Message(MessageAction::Forward("#room:example.com".into()));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("forward \"Some Room\"", ctx.clone()).unwrap();
+        let act = IambAction::Message(MessageAction::Forward("Some Room".into()));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("forward", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("forward a b", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_debug() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("debug events", ctx.clone()).unwrap();
+        let act = WindowAction::Switch(OpenTarget::Application(IambId::DebugEvents));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("debug events pause", ctx.clone()).unwrap();
+        let act = IambAction::from(DebugAction::TogglePause);
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("debug events filter m.room.member", ctx.clone()).unwrap();
+        let act = IambAction::from(DebugAction::Filter(Some("m.room.member".into())));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("debug events filter", ctx.clone()).unwrap();
+        let act = IambAction::from(DebugAction::Filter(None));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("debug", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("debug events filter a b", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("debug events stop", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_upload() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("upload photo.jpg", ctx.clone()).unwrap();
+        let act = SendAction::Upload("photo.jpg".into(), None, None);
+        assert_eq!(res, vec![(IambAction::from(act).into(), ctx.clone())]);
+
+        let cmd = "upload photo.jpg --caption \"At the beach\" --mime image/png";
+        let res = cmds.input_cmd(cmd, ctx.clone()).unwrap();
+        let caption = Some("At the beach".into());
+        let act = SendAction::Upload("photo.jpg".into(), caption, Some(mime::IMAGE_PNG));
+        assert_eq!(res, vec![(IambAction::from(act).into(), ctx.clone())]);
+
+        // Options can come before the path.
+        let res = cmds.input_cmd("upload --caption hi photo.jpg", ctx.clone()).unwrap();
+        let act = SendAction::Upload("photo.jpg".into(), Some("hi".into()), None);
+        assert_eq!(res, vec![(IambAction::from(act).into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("upload photo.jpg --mime notamime", ctx.clone());
+        assert_eq!(res, Err(CommandError::Error("Invalid mime type: notamime".into())));
+
+        let res = cmds.input_cmd("upload photo.jpg --caption", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("upload a.jpg b.jpg", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("upload", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_resend() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("resend", ctx.clone()).unwrap();
+        let act = IambAction::Message(MessageAction::Resend);
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("resend all", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_read() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("read", ctx.clone()).unwrap();
+        let act = IambAction::Room(RoomAction::MarkRead(false));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("read here", ctx.clone()).unwrap();
+        let act = IambAction::Room(RoomAction::MarkRead(true));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("read all", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_markunread() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("markunread", ctx.clone()).unwrap();
+        let act = IambAction::Message(MessageAction::MarkUnread);
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("markunread all", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_receipts() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("receipts", ctx.clone()).unwrap();
+        let act = IambAction::Message(MessageAction::Receipts);
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("receipts all", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_space() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("space leave", ctx.clone()).unwrap();
+        let act = IambAction::Room(RoomAction::Leave(false));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("space leave all", ctx.clone()).unwrap();
+        let act = IambAction::Room(RoomAction::LeaveSpace(false));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("space! leave all", ctx.clone()).unwrap();
+        let act = IambAction::Room(RoomAction::LeaveSpace(true));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("space create Projects", ctx.clone()).unwrap();
+        let act = HomeserverAction::CreateSpace("Projects".into(), vec![]);
+        assert_eq!(res, vec![(IambAction::from(act).into(), ctx.clone())]);
+
+        let res = cmds
+            .input_cmd("space create \"Side Projects\" #a:example.com !b:example.com", ctx.clone())
+            .unwrap();
+        let children = vec!["#a:example.com".into(), "!b:example.com".into()];
+        let act = HomeserverAction::CreateSpace("Side Projects".into(), children);
+        assert_eq!(res, vec![(IambAction::from(act).into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("space", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("space create", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("space leave some", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_logout() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("logout @user:example.com", ctx.clone()).unwrap();
+        let act = HomeserverAction::Logout("@user:example.com".into(), false, false);
+        assert_eq!(res, vec![(IambAction::from(act).into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("logout! @user:example.com", ctx.clone()).unwrap();
+        let act = HomeserverAction::Logout("@user:example.com".into(), true, false);
+        assert_eq!(res, vec![(IambAction::from(act).into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("logout", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_checkconfig() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("checkconfig", ctx.clone()).unwrap();
+        assert_eq!(res, vec![(IambAction::CheckConfig.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("checkconfig now", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_presence() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("presence online", ctx.clone()).unwrap();
+        let act = IambAction::SetPresence(PresenceState::Online, None);
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("presence unavailable in a meeting", ctx.clone()).unwrap();
+        let act = IambAction::SetPresence(PresenceState::Unavailable, Some("in a meeting".into()));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("presence offline \"gone fishing\"", ctx.clone()).unwrap();
+        let act = IambAction::SetPresence(PresenceState::Offline, Some("gone fishing".into()));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("presence", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("presence busy", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_ignore() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+        let user = OwnedUserId::try_from("@spam:example.com").unwrap();
+
+        let res = cmds.input_cmd("ignore @spam:example.com", ctx.clone()).unwrap();
+        let act = IambAction::SetIgnored(user.clone(), true);
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("unignore @spam:example.com", ctx.clone()).unwrap();
+        let act = IambAction::SetIgnored(user, false);
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("ignore spam", ctx.clone());
+        let msg = "Invalid user identifier: spam".to_string();
+        assert_eq!(res, Err(CommandError::Error(msg)));
+
+        let res = cmds.input_cmd("ignore", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("unignore @a:example.com @b:example.com", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_cache() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("cache clean", ctx.clone()).unwrap();
+        assert_eq!(res, vec![(IambAction::CleanCache.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("cache", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("cache clean all", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_parent() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("parent", ctx.clone()).unwrap();
+        let act = IambAction::from(MessageAction::GotoParent);
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("parent foo", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_open_url() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("open url", ctx.clone()).unwrap();
+        let act = IambAction::from(MessageAction::OpenLink(None));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("open url 2", ctx.clone()).unwrap();
+        let act = IambAction::from(MessageAction::OpenLink(Some('2')));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        // Labels past 9 are letters.
+        let res = cmds.input_cmd("open url B", ctx.clone()).unwrap();
+        let act = IambAction::from(MessageAction::OpenLink(Some('B')));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("open url 10", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("open url foo", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("open url 1 2", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_search() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("search hello", ctx.clone()).unwrap();
+        let act = RoomAction::Search("hello".into(), MoveDir1D::Previous);
+        assert_eq!(res, vec![(IambAction::Room(act).into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("search! hello world", ctx.clone()).unwrap();
+        let act = RoomAction::Search("hello world".into(), MoveDir1D::Next);
+        assert_eq!(res, vec![(IambAction::Room(act).into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("search \"a  b\"", ctx.clone()).unwrap();
+        let act = RoomAction::Search("a  b".into(), MoveDir1D::Previous);
+        assert_eq!(res, vec![(IambAction::Room(act).into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("search", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_select() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("select", ctx.clone()).unwrap();
+        let act = IambAction::Message(MessageAction::Select);
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("select clear", ctx.clone()).unwrap();
+        let act = IambAction::Message(MessageAction::SelectClear);
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("select all", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_react() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("react +1", ctx.clone()).unwrap();
+        let act = IambAction::Message(MessageAction::React("👍".into()));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("react recent", ctx.clone()).unwrap();
+        let act = IambAction::Message(MessageAction::ReactRecent);
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("react :thumbsup:", ctx.clone()).unwrap();
+        let act = IambAction::Message(MessageAction::React("👍".into()));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("react 🎉", ctx.clone()).unwrap();
+        let act = IambAction::Message(MessageAction::React("🎉".into()));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("react", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("react +1 +1", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("react notanemoji", ctx.clone());
+        let msg = "Invalid Emoji or shortcode: notanemoji";
+        assert_eq!(res, Err(CommandError::Error(msg.into())));
+    }
+
+    #[test]
+    fn test_cmd_unreact() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("unreact", ctx.clone()).unwrap();
+        let act = IambAction::Message(MessageAction::Unreact(None));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("unreact +1", ctx.clone()).unwrap();
+        let act = IambAction::Message(MessageAction::Unreact(Some("👍".into())));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("unreact :+1:", ctx.clone()).unwrap();
+        let act = IambAction::Message(MessageAction::Unreact(Some("👍".into())));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("unreact 🎉", ctx.clone()).unwrap();
+        let act = IambAction::Message(MessageAction::Unreact(Some("🎉".into())));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("unreact +1 +1", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("unreact notanemoji", ctx.clone());
+        let msg = "Invalid Emoji or shortcode: notanemoji";
+        assert_eq!(res, Err(CommandError::Error(msg.into())));
+    }
+
+    #[test]
+    fn test_cmd_edit() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("edit", ctx.clone()).unwrap();
+        let act = IambAction::Message(MessageAction::Edit(MessageTarget::Selected));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("edit last", ctx.clone()).unwrap();
+        let act = IambAction::Message(MessageAction::Edit(MessageTarget::LastOwn));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("edit cancel", ctx.clone()).unwrap();
+        let act = IambAction::Message(MessageAction::CancelEdit);
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("edit first", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_gallery() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("gallery", ctx.clone()).unwrap();
+        let act = IambAction::Room(RoomAction::Gallery);
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("gallery all", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_keys() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("keys import /a/b/c pword", ctx.clone()).unwrap();
+        let act = IambAction::Keys(KeysAction::Import("/a/b/c".into(), "pword".into()));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("keys export /a/b/c pword", ctx.clone()).unwrap();
+        let act = IambAction::Keys(KeysAction::Export("/a/b/c".into(), "pword".into()));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("keys restore", ctx.clone()).unwrap();
+        let act = IambAction::Message(MessageAction::RestoreKeys);
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        // Invalid invocations.
+        let res = cmds.input_cmd("keys", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("keys import", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("keys import foo", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("keys import foo bar baz", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("keys restore foo", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_cmd_recovery() {
+        let mut cmds = setup_commands();
+        let ctx = EditContext::default();
+
+        let res = cmds.input_cmd("recovery setup", ctx.clone()).unwrap();
+        let act = IambAction::Recovery(RecoveryAction::Setup);
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        let res = cmds.input_cmd("recovery restore EsTa bcde FGHJ", ctx.clone()).unwrap();
+        let act = IambAction::Recovery(RecoveryAction::Restore("EsTa bcde FGHJ".into()));
+        assert_eq!(res, vec![(act.into(), ctx.clone())]);
+
+        // Invalid invocations.
+        let res = cmds.input_cmd("recovery", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("recovery restore", ctx.clone());
+        assert_eq!(res, Err(CommandError::InvalidArgument));
+
+        let res = cmds.input_cmd("recovery setup now", ctx.clone());
         assert_eq!(res, Err(CommandError::InvalidArgument));
     }
 }
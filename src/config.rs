@@ -1,7 +1,7 @@
 //! # Logic for loading and validating application configuration
 use std::borrow::Cow;
 use std::collections::hash_map::DefaultHasher;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::fs::File;
 use std::hash::{Hash, Hasher};
@@ -9,6 +9,7 @@ use std::io::{BufReader, BufWriter};
 use std::path::{Path, PathBuf};
 use std::process;
 
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, TimeZone};
 use clap::Parser;
 use matrix_sdk::matrix_auth::MatrixSession;
 use matrix_sdk::ruma::{OwnedDeviceId, OwnedRoomAliasId, OwnedRoomId, OwnedUserId, UserId};
@@ -25,10 +26,12 @@ use super::base::{
     IambError,
     IambId,
     RoomInfo,
+    ScrollPosition,
     SortColumn,
     SortFieldRoom,
     SortFieldUser,
     SortOrder,
+    MAX_RECENT_REACTIONS,
 };
 
 type Macros = HashMap<VimModes, HashMap<Keys, Keys>>;
@@ -54,6 +57,27 @@ const DEFAULT_ROOM_SORT: [SortColumn<SortFieldRoom>; 4] = [
 
 const DEFAULT_REQ_TIMEOUT: u64 = 120;
 
+const DEFAULT_AUTO_DOWNLOAD_SIZE: u64 = 10 * 1024 * 1024;
+
+const DEFAULT_DOWNLOAD_WARN_SIZE: u64 = 100 * 1024 * 1024;
+
+const DEFAULT_IMAGE_PREVIEW_CACHE_SIZE: u64 = 500 * 1024 * 1024;
+
+const DEFAULT_IMAGE_PREVIEW_MAX_FRAMES: usize = 20;
+
+const DEFAULT_MESSAGE_LOAD_BATCH: u32 = 50;
+
+const DEFAULT_MESSAGE_LOAD_INTERVAL: u64 = 2;
+
+/// The font size that image previews are sized for on Windows, where it can't be detected.
+pub const WINDOWS_FONT_SIZE: (u16, u16) = (10, 20);
+
+/// The range that `message_load_batch` gets clamped to.
+const MESSAGE_LOAD_BATCH_RANGE: (u32, u32) = (10, 1000);
+
+/// The narrowest user gutter that leaves room for the padding around usernames.
+const MIN_USER_GUTTER_WIDTH: usize = 2;
+
 const COLORS: [Color; 13] = [
     Color::Blue,
     Color::Cyan,
@@ -104,6 +128,23 @@ fn validate_profile_name(name: &str) -> bool {
     name.chars().all(is_profile_char)
 }
 
+/// Whether a program can be run, either from the given path or by searching `$PATH`.
+fn program_exists(program: &str) -> bool {
+    let path = Path::new(program);
+
+    if path.components().count() > 1 {
+        return path.is_file();
+    }
+
+    let Some(paths) = std::env::var_os("PATH") else {
+        return false;
+    };
+
+    std::env::split_paths(&paths).any(|dir| {
+        dir.join(program).is_file() || cfg!(windows) && dir.join(format!("{program}.exe")).is_file()
+    })
+}
+
 fn validate_profile_names(names: &HashMap<String, ProfileConfig>) {
     for name in names.keys() {
         if validate_profile_name(name.as_str()) {
@@ -133,8 +174,15 @@ pub struct Iamb {
 
     #[clap(short = 'C', long, value_parser)]
     pub config_directory: Option<PathBuf>,
+
+    /// Check the configuration for problems, and exit without starting.
+    #[clap(long)]
+    pub check_config: bool,
 }
 
+/// Held while identities.json is being updated by [ApplicationSettings::update_known_identities].
+static KNOWN_IDENTITIES_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
 #[derive(thiserror::Error, Debug)]
 pub enum ConfigError {
     #[error("Error reading configuration file: {0}")]
@@ -352,6 +400,36 @@ pub struct UserDisplayTunables {
 
 pub type UserOverrides = HashMap<OwnedUserId, UserDisplayTunables>;
 
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+pub struct RoomTunables {
+    pub send_template: Option<String>,
+
+    /// How many seconds to wait after sending before allowing another message to be sent.
+    pub send_cooldown: Option<u64>,
+
+    /// Whether to render sent messages as Markdown, overriding the global setting.
+    pub send_markdown: Option<bool>,
+}
+
+impl RoomTunables {
+    /// Wrap a message being sent in this room's template, if it has one.
+    ///
+    /// Templates without a `{message}` placeholder are added as a signature on their own line.
+    pub fn apply_send_template(&self, text: &str) -> Option<String> {
+        let template = self.send_template.as_ref()?;
+
+        if template.contains("{message}") {
+            Some(template.replace("{message}", text))
+        } else {
+            Some(format!("{text}\n{template}"))
+        }
+    }
+}
+
+pub type RoomOverrides = HashMap<OwnedRoomId, RoomTunables>;
+
+pub type CustomEventTemplates = HashMap<String, String>;
+
 fn merge_sorts(a: SortOverrides, b: SortOverrides) -> SortOverrides {
     SortOverrides {
         chats: b.chats.or(a.chats),
@@ -398,6 +476,134 @@ pub enum UserDisplayStyle {
     DisplayName,
 }
 
+/// What pressing Escape in Normal mode does while replying to or editing a message.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EscapeAction {
+    /// Nothing beyond the usual Vim behaviour; only leaving Insert mode takes focus off the draft.
+    #[default]
+    Blur,
+    /// Stop replying to or editing the message.
+    Cancel,
+}
+
+/// Where the scrollback starts out when a room is opened.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RoomOpenPosition {
+    /// Start at the most recent message.
+    Bottom,
+    /// Start at the first unread message, or at the most recent one when everything's been read.
+    #[default]
+    Unread,
+    /// Start at the message that was selected when the room was last looked at, even in an
+    /// earlier session, or else like `unread`.
+    Last,
+}
+
+/// The timezone that timestamps and day separators are shown in.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Timezone {
+    /// The system's local timezone.
+    #[default]
+    Local,
+    /// A fixed offset from UTC.
+    Fixed(FixedOffset),
+}
+
+impl Timezone {
+    /// Convert a UTC time into this timezone.
+    pub fn convert_utc(&self, utc: &NaiveDateTime) -> DateTime<FixedOffset> {
+        match self {
+            Timezone::Local => Local.from_utc_datetime(utc).fixed_offset(),
+            Timezone::Fixed(offset) => offset.from_utc_datetime(utc),
+        }
+    }
+}
+
+/// Parse an offset like "+02:00", "-0530" or "+9", optionally after "UTC" or "GMT".
+fn parse_utc_offset(value: &str) -> Option<FixedOffset> {
+    let upper = value.trim().to_ascii_uppercase();
+    let offset = upper
+        .strip_prefix("UTC")
+        .or_else(|| upper.strip_prefix("GMT"))
+        .unwrap_or(&upper);
+
+    if offset.is_empty() || offset == "Z" {
+        return FixedOffset::east_opt(0);
+    }
+
+    let (sign, offset) = if let Some(rest) = offset.strip_prefix('+') {
+        (1, rest)
+    } else if let Some(rest) = offset.strip_prefix('-') {
+        (-1, rest)
+    } else {
+        return None;
+    };
+
+    // Only ASCII is allowed past here, so that splitting at byte indices is safe.
+    if !offset.bytes().all(|b| b.is_ascii_digit() || b == b':') {
+        return None;
+    }
+
+    let (hours, minutes) = match offset.split_once(':') {
+        Some((h, m)) => (h, m),
+        None if offset.len() > 2 => offset.split_at(offset.len() - 2),
+        None => (offset, "0"),
+    };
+
+    if hours.is_empty() || !(hours.len() <= 2 && minutes.len() <= 2) {
+        return None;
+    }
+
+    let hours: i32 = hours.parse().ok()?;
+    let minutes: i32 = minutes.parse().ok()?;
+
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+pub struct TimezoneVisitor;
+
+impl<'de> Visitor<'de> for TimezoneVisitor {
+    type Value = Timezone;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("\"local\", \"utc\", or an offset from UTC like \"+05:30\"")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: SerdeError,
+    {
+        if value.eq_ignore_ascii_case("local") {
+            return Ok(Timezone::Local);
+        }
+
+        match parse_utc_offset(value) {
+            Some(offset) => Ok(Timezone::Fixed(offset)),
+            None => {
+                let msg = format!(
+                    "Invalid timezone {value:?}; expected \"local\", \"utc\", or an offset like \"+05:30\""
+                );
+                Err(E::custom(msg))
+            },
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Timezone {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(TimezoneVisitor)
+    }
+}
+
 #[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum NotifyVia {
@@ -422,12 +628,65 @@ pub struct Notifications {
 pub struct ImagePreviewValues {
     pub size: ImagePreviewSize,
     pub protocol: Option<ImagePreviewProtocolValues>,
+    pub max_frames: usize,
+    pub max_fps: u32,
+}
+
+/// How often the Megolm session used in newly encrypted rooms should be replaced.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+pub struct RoomKeyRotation {
+    /// Rotate after this many milliseconds.
+    pub period_ms: Option<u64>,
+
+    /// Rotate after this many messages.
+    pub messages: Option<u64>,
+}
+
+/// The kinds of attachments that can be downloaded automatically.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+    Audio,
+    File,
+    Image,
+    Video,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AutoDownloadValues {
+    pub types: Vec<MediaKind>,
+    pub max_size: u64,
+}
+
+impl AutoDownloadValues {
+    /// Whether an attachment should be fetched as soon as it arrives. Attachments that don't
+    /// state their size are skipped, since there's no way to respect the limit for them.
+    pub fn allows(&self, kind: MediaKind, size: Option<u64>) -> bool {
+        self.types.contains(&kind) && size.is_some_and(|size| size <= self.max_size)
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+pub struct AutoDownload {
+    pub types: Option<Vec<MediaKind>>,
+    pub max_size: Option<u64>,
+}
+
+impl AutoDownload {
+    fn values(self) -> AutoDownloadValues {
+        AutoDownloadValues {
+            types: self.types.unwrap_or_else(|| vec![MediaKind::Image]),
+            max_size: self.max_size.unwrap_or(DEFAULT_AUTO_DOWNLOAD_SIZE),
+        }
+    }
 }
 
 #[derive(Clone, Default, Deserialize)]
 pub struct ImagePreview {
     pub size: Option<ImagePreviewSize>,
     pub protocol: Option<ImagePreviewProtocolValues>,
+    pub max_frames: Option<usize>,
+    pub max_fps: Option<u32>,
 }
 
 impl ImagePreview {
@@ -435,6 +694,8 @@ impl ImagePreview {
         ImagePreviewValues {
             size: self.size.unwrap_or_default(),
             protocol: self.protocol,
+            max_frames: self.max_frames.unwrap_or(DEFAULT_IMAGE_PREVIEW_MAX_FRAMES),
+            max_fps: self.max_fps.unwrap_or(10).clamp(1, 1000),
         }
     }
 }
@@ -451,6 +712,19 @@ impl Default for ImagePreviewSize {
     }
 }
 
+/// Overrides for what would otherwise be detected from the terminal.
+///
+/// When `type` is set it's always used, even if the terminal seems to support something else. It
+/// takes the lowercase name of a [ProtocolType]:
+///
+/// - `"halfblocks"`: Unicode half blocks, which work in any terminal with colors
+/// - `"sixel"`: [Sixel] graphics
+/// - `"kitty"`: the [Kitty graphics protocol]
+/// - `"iterm2"`: iTerm2's [inline images protocol]
+///
+/// [Sixel]: https://en.wikipedia.org/wiki/Sixel
+/// [Kitty graphics protocol]: https://sw.kovidgoyal.net/kitty/graphics-protocol/
+/// [inline images protocol]: https://iterm2.com/documentation-images.html
 #[derive(Clone, Deserialize)]
 pub struct ImagePreviewProtocolValues {
     pub r#type: Option<ProtocolType>,
@@ -489,34 +763,67 @@ impl SortOverrides {
 
 #[derive(Clone)]
 pub struct TunableValues {
+    pub debug_event_log: bool,
     pub log_level: Level,
     pub message_shortcode_display: bool,
+    pub message_shortcode_expand: bool,
     pub reaction_display: bool,
     pub reaction_shortcode_display: bool,
     pub read_receipt_send: bool,
+    pub read_receipt_send_unfocused: bool,
     pub read_receipt_display: bool,
     pub request_timeout: u64,
     pub sort: SortValues,
     pub typing_notice_send: bool,
     pub typing_notice_display: bool,
     pub users: UserOverrides,
+    pub rooms: RoomOverrides,
     pub username_display: UserDisplayStyle,
     pub message_user_color: bool,
     pub default_room: Option<String>,
     pub open_command: Option<Vec<String>>,
     pub notifications: Notifications,
     pub image_preview: Option<ImagePreviewValues>,
+    pub auto_download: Option<AutoDownloadValues>,
+    pub download_warn_size: u64,
+    pub image_preview_cache_size: u64,
     pub user_gutter_width: usize,
     pub external_edit_file_suffix: String,
+    pub send_markdown: bool,
+    pub send_on_enter: bool,
+    pub member_count_display: bool,
+    pub membership_display: bool,
+    pub membership_summary_threshold: usize,
+    pub custom_events: CustomEventTemplates,
+    pub room_version_warning: bool,
+    pub confirm_room_name: bool,
+    pub upload_pasted_paths: bool,
+    pub url_preview: bool,
+    pub verification_auto_accept: bool,
+    pub unsend_grace_period: u64,
+    pub unverified_session_warning: bool,
+    pub command_history_size: usize,
+    pub sent_history_size: usize,
+    pub save_drafts: bool,
+    pub room_key_rotation: RoomKeyRotation,
+    pub room_list_group_by_space: bool,
+    pub room_open_position: RoomOpenPosition,
+    pub escape_action: EscapeAction,
+    pub timezone: Timezone,
+    pub message_load_batch: u32,
+    pub message_load_interval: u64,
 }
 
 #[derive(Clone, Default, Deserialize)]
 pub struct Tunables {
+    pub debug_event_log: Option<bool>,
     pub log_level: Option<LogLevel>,
     pub message_shortcode_display: Option<bool>,
+    pub message_shortcode_expand: Option<bool>,
     pub reaction_display: Option<bool>,
     pub reaction_shortcode_display: Option<bool>,
     pub read_receipt_send: Option<bool>,
+    pub read_receipt_send_unfocused: Option<bool>,
     pub read_receipt_display: Option<bool>,
     pub request_timeout: Option<u64>,
     #[serde(default)]
@@ -524,70 +831,179 @@ pub struct Tunables {
     pub typing_notice_send: Option<bool>,
     pub typing_notice_display: Option<bool>,
     pub users: Option<UserOverrides>,
+    pub rooms: Option<RoomOverrides>,
     pub username_display: Option<UserDisplayStyle>,
     pub message_user_color: Option<bool>,
     pub default_room: Option<String>,
     pub open_command: Option<Vec<String>>,
     pub notifications: Option<Notifications>,
     pub image_preview: Option<ImagePreview>,
+    pub auto_download: Option<AutoDownload>,
+    pub download_warn_size: Option<u64>,
+    pub image_preview_cache_size: Option<u64>,
     pub user_gutter_width: Option<usize>,
     pub external_edit_file_suffix: Option<String>,
+    pub send_markdown: Option<bool>,
+    pub send_on_enter: Option<bool>,
+    pub member_count_display: Option<bool>,
+    pub membership_display: Option<bool>,
+    pub membership_summary_threshold: Option<usize>,
+    pub custom_events: Option<CustomEventTemplates>,
+    pub room_version_warning: Option<bool>,
+    pub confirm_room_name: Option<bool>,
+    pub upload_pasted_paths: Option<bool>,
+    pub url_preview: Option<bool>,
+    pub verification_auto_accept: Option<bool>,
+    pub unsend_grace_period: Option<u64>,
+    pub unverified_session_warning: Option<bool>,
+    pub command_history_size: Option<usize>,
+    pub sent_history_size: Option<usize>,
+    pub save_drafts: Option<bool>,
+    pub room_key_rotation: Option<RoomKeyRotation>,
+    pub room_list_group_by_space: Option<bool>,
+    pub room_open_position: Option<RoomOpenPosition>,
+    pub escape_action: Option<EscapeAction>,
+    pub timezone: Option<Timezone>,
+    pub message_load_batch: Option<u32>,
+    pub message_load_interval: Option<u64>,
 }
 
 impl Tunables {
     fn merge(self, other: Self) -> Self {
         Tunables {
+            debug_event_log: self.debug_event_log.or(other.debug_event_log),
             log_level: self.log_level.or(other.log_level),
             message_shortcode_display: self
                 .message_shortcode_display
                 .or(other.message_shortcode_display),
+            message_shortcode_expand: self
+                .message_shortcode_expand
+                .or(other.message_shortcode_expand),
             reaction_display: self.reaction_display.or(other.reaction_display),
             reaction_shortcode_display: self
                 .reaction_shortcode_display
                 .or(other.reaction_shortcode_display),
             read_receipt_send: self.read_receipt_send.or(other.read_receipt_send),
+            read_receipt_send_unfocused: self
+                .read_receipt_send_unfocused
+                .or(other.read_receipt_send_unfocused),
             read_receipt_display: self.read_receipt_display.or(other.read_receipt_display),
             request_timeout: self.request_timeout.or(other.request_timeout),
             sort: merge_sorts(self.sort, other.sort),
             typing_notice_send: self.typing_notice_send.or(other.typing_notice_send),
             typing_notice_display: self.typing_notice_display.or(other.typing_notice_display),
             users: merge_maps(self.users, other.users),
+            rooms: merge_maps(self.rooms, other.rooms),
             username_display: self.username_display.or(other.username_display),
             message_user_color: self.message_user_color.or(other.message_user_color),
             default_room: self.default_room.or(other.default_room),
             open_command: self.open_command.or(other.open_command),
             notifications: self.notifications.or(other.notifications),
             image_preview: self.image_preview.or(other.image_preview),
+            auto_download: self.auto_download.or(other.auto_download),
+            download_warn_size: self.download_warn_size.or(other.download_warn_size),
+            image_preview_cache_size: self
+                .image_preview_cache_size
+                .or(other.image_preview_cache_size),
             user_gutter_width: self.user_gutter_width.or(other.user_gutter_width),
             external_edit_file_suffix: self
                 .external_edit_file_suffix
                 .or(other.external_edit_file_suffix),
+            send_markdown: self.send_markdown.or(other.send_markdown),
+            send_on_enter: self.send_on_enter.or(other.send_on_enter),
+            member_count_display: self.member_count_display.or(other.member_count_display),
+            membership_display: self.membership_display.or(other.membership_display),
+            membership_summary_threshold: self
+                .membership_summary_threshold
+                .or(other.membership_summary_threshold),
+            custom_events: merge_maps(self.custom_events, other.custom_events),
+            room_version_warning: self.room_version_warning.or(other.room_version_warning),
+            confirm_room_name: self.confirm_room_name.or(other.confirm_room_name),
+            upload_pasted_paths: self.upload_pasted_paths.or(other.upload_pasted_paths),
+            url_preview: self.url_preview.or(other.url_preview),
+            verification_auto_accept: self
+                .verification_auto_accept
+                .or(other.verification_auto_accept),
+            unsend_grace_period: self.unsend_grace_period.or(other.unsend_grace_period),
+            unverified_session_warning: self
+                .unverified_session_warning
+                .or(other.unverified_session_warning),
+            command_history_size: self.command_history_size.or(other.command_history_size),
+            sent_history_size: self.sent_history_size.or(other.sent_history_size),
+            save_drafts: self.save_drafts.or(other.save_drafts),
+            room_key_rotation: self.room_key_rotation.or(other.room_key_rotation),
+            room_list_group_by_space: self
+                .room_list_group_by_space
+                .or(other.room_list_group_by_space),
+            room_open_position: self.room_open_position.or(other.room_open_position),
+            escape_action: self.escape_action.or(other.escape_action),
+            timezone: self.timezone.or(other.timezone),
+            message_load_batch: self.message_load_batch.or(other.message_load_batch),
+            message_load_interval: self.message_load_interval.or(other.message_load_interval),
         }
     }
 
     fn values(self) -> TunableValues {
         TunableValues {
+            debug_event_log: self.debug_event_log.unwrap_or(false),
             log_level: self.log_level.map(Level::from).unwrap_or(Level::INFO),
             message_shortcode_display: self.message_shortcode_display.unwrap_or(false),
+            message_shortcode_expand: self.message_shortcode_expand.unwrap_or(true),
             reaction_display: self.reaction_display.unwrap_or(true),
             reaction_shortcode_display: self.reaction_shortcode_display.unwrap_or(false),
             read_receipt_send: self.read_receipt_send.unwrap_or(true),
+            read_receipt_send_unfocused: self.read_receipt_send_unfocused.unwrap_or(true),
             read_receipt_display: self.read_receipt_display.unwrap_or(true),
             request_timeout: self.request_timeout.unwrap_or(DEFAULT_REQ_TIMEOUT),
             sort: self.sort.values(),
             typing_notice_send: self.typing_notice_send.unwrap_or(true),
             typing_notice_display: self.typing_notice_display.unwrap_or(true),
             users: self.users.unwrap_or_default(),
+            rooms: self.rooms.unwrap_or_default(),
             username_display: self.username_display.unwrap_or_default(),
             message_user_color: self.message_user_color.unwrap_or(false),
             default_room: self.default_room,
             open_command: self.open_command,
             notifications: self.notifications.unwrap_or_default(),
             image_preview: self.image_preview.map(ImagePreview::values),
+            auto_download: self.auto_download.map(AutoDownload::values),
+            download_warn_size: self.download_warn_size.unwrap_or(DEFAULT_DOWNLOAD_WARN_SIZE),
+            image_preview_cache_size: self
+                .image_preview_cache_size
+                .unwrap_or(DEFAULT_IMAGE_PREVIEW_CACHE_SIZE),
             user_gutter_width: self.user_gutter_width.unwrap_or(30),
             external_edit_file_suffix: self
                 .external_edit_file_suffix
                 .unwrap_or_else(|| ".md".to_string()),
+            send_markdown: self.send_markdown.unwrap_or(true),
+            send_on_enter: self.send_on_enter.unwrap_or(true),
+            member_count_display: self.member_count_display.unwrap_or(true),
+            membership_display: self.membership_display.unwrap_or(false),
+            membership_summary_threshold: self.membership_summary_threshold.unwrap_or(3),
+            custom_events: self.custom_events.unwrap_or_default(),
+            room_version_warning: self.room_version_warning.unwrap_or(true),
+            confirm_room_name: self.confirm_room_name.unwrap_or(true),
+            upload_pasted_paths: self.upload_pasted_paths.unwrap_or(false),
+            url_preview: self.url_preview.unwrap_or(false),
+            verification_auto_accept: self.verification_auto_accept.unwrap_or(true),
+            unsend_grace_period: self.unsend_grace_period.unwrap_or(0),
+            unverified_session_warning: self.unverified_session_warning.unwrap_or(true),
+            command_history_size: self.command_history_size.unwrap_or(100),
+            sent_history_size: self.sent_history_size.unwrap_or(0),
+            save_drafts: self.save_drafts.unwrap_or(false),
+            room_key_rotation: self.room_key_rotation.unwrap_or_default(),
+            room_list_group_by_space: self.room_list_group_by_space.unwrap_or(false),
+            room_open_position: self.room_open_position.unwrap_or_default(),
+            escape_action: self.escape_action.unwrap_or_default(),
+            timezone: self.timezone.unwrap_or_default(),
+            message_load_batch: self
+                .message_load_batch
+                .unwrap_or(DEFAULT_MESSAGE_LOAD_BATCH)
+                .clamp(MESSAGE_LOAD_BATCH_RANGE.0, MESSAGE_LOAD_BATCH_RANGE.1),
+            message_load_interval: self
+                .message_load_interval
+                .unwrap_or(DEFAULT_MESSAGE_LOAD_INTERVAL)
+                .max(1),
         }
     }
 }
@@ -603,7 +1019,12 @@ pub struct DirectoryValues {
 
 impl DirectoryValues {
     fn create_dir_all(&self) -> std::io::Result<()> {
-        use std::fs::create_dir_all;
+        let create_dir_all = |dir: &PathBuf| {
+            std::fs::create_dir_all(dir).map_err(|e| {
+                let msg = format!("Couldn't create directory {}: {e}", dir.display());
+                std::io::Error::new(e.kind(), msg)
+            })
+        };
 
         let Self { cache, data, logs, downloads, image_previews } = self;
 
@@ -727,7 +1148,129 @@ pub struct IambConfig {
     pub macros: Option<Macros>,
 }
 
+fn config_dir(dir: Option<PathBuf>) -> PathBuf {
+    let mut config_dir = dir.or_else(dirs::config_dir).unwrap_or_else(|| {
+        usage!(
+            "No user configuration directory found;\
+            please specify one via -C.\n\n
+            For more information try '--help'"
+        );
+    });
+
+    config_dir.push("iamb");
+    config_dir
+}
+
+/// Try to load a single entry of the configuration, placed into an otherwise empty file by
+/// `wrap`. If it doesn't load, then each of its entries up to `depth` levels down gets tried on
+/// its own, so that the problem is reported for the exact setting that caused it.
+fn entry_problems(
+    name: String,
+    value: &serde_json::Value,
+    depth: usize,
+    wrap: &dyn Fn(serde_json::Value) -> serde_json::Value,
+    problems: &mut Vec<String>,
+) {
+    let Err(err) = serde_json::from_value::<IambConfig>(wrap(value.clone())) else {
+        return;
+    };
+
+    let found = problems.len();
+
+    if let (serde_json::Value::Object(entries), 1..) = (value, depth) {
+        for (key, entry) in entries {
+            let wrap = |entry| wrap(serde_json::json!({ key.as_str(): entry }));
+            entry_problems(format!("{name}.{key}"), entry, depth - 1, &wrap, problems);
+        }
+    }
+
+    if problems.len() == found {
+        problems.push(format!("{name}: {err}"));
+    }
+}
+
+/// How many levels of a section can be checked one entry at a time.
+fn section_depth(section: &str) -> usize {
+    match section {
+        "settings" | "dirs" => 1,
+        "macros" => 2,
+        _ => 0,
+    }
+}
+
+/// Find every entry in a parsed configuration file that can't be loaded.
+fn config_problems(config: &serde_json::Value) -> Vec<String> {
+    use serde_json::{json, Value};
+
+    let mut problems = vec![];
+
+    let Value::Object(config) = config else {
+        return vec!["the configuration needs to be a table of settings".into()];
+    };
+
+    for (key, value) in config.iter().filter(|(key, _)| *key != "profiles") {
+        let wrap = |value| json!({ "profiles": {}, key.as_str(): value });
+        entry_problems(key.clone(), value, section_depth(key), &wrap, &mut problems);
+    }
+
+    match config.get("profiles") {
+        None => problems.push("profiles: no profiles are configured".into()),
+        Some(Value::Object(profiles)) => {
+            for (name, profile) in profiles {
+                let Value::Object(profile) = profile else {
+                    problems.push(format!("profiles.{name}: needs to be a table of settings"));
+                    continue;
+                };
+
+                if !profile.contains_key("user_id") {
+                    problems.push(format!("profiles.{name}: missing field `user_id`"));
+                }
+
+                // Every other entry needs a valid user_id beside it to load.
+                for (key, value) in profile {
+                    let wrap = |value| {
+                        let mut profile = serde_json::Map::new();
+                        profile.insert("user_id".into(), "@user:example.com".into());
+                        profile.insert(key.clone(), value);
+                        json!({ "profiles": { name.as_str(): profile } })
+                    };
+                    let key_name = format!("profiles.{name}.{key}");
+                    entry_problems(key_name, value, section_depth(key), &wrap, &mut problems);
+                }
+            }
+        },
+        Some(profiles) => {
+            entry_problems(
+                "profiles".into(),
+                profiles,
+                0,
+                &|v| json!({ "profiles": v }),
+                &mut problems,
+            );
+        },
+    }
+
+    problems
+}
+
 impl IambConfig {
+    /// Find every entry in a configuration file that can't be loaded, instead of stopping at
+    /// the first one like [IambConfig::load_toml] and [IambConfig::load_json] do.
+    pub fn problems(path: &Path) -> Vec<String> {
+        let config = std::fs::read_to_string(path).map_err(ConfigError::from).and_then(|s| {
+            if path.extension().is_some_and(|ext| ext == "json") {
+                serde_json::from_str(&s).map_err(ConfigError::from)
+            } else {
+                toml::from_str(&s).map_err(ConfigError::from)
+            }
+        });
+
+        match config {
+            Ok(config) => config_problems(&config),
+            Err(e) => vec![e.to_string()],
+        }
+    }
+
     pub fn load_toml(path: &Path) -> Result<Self, ConfigError> {
         let s = std::fs::read_to_string(path)?;
         let config = toml::from_str(&s)?;
@@ -745,6 +1288,13 @@ impl IambConfig {
 
 #[derive(Clone)]
 pub struct ApplicationSettings {
+    pub command_history: PathBuf,
+    pub sent_history: PathBuf,
+    pub recent_reactions: PathBuf,
+    pub identities_json: PathBuf,
+    pub scroll_positions_json: PathBuf,
+    pub version_warnings_json: PathBuf,
+    pub drafts_json: PathBuf,
     pub layout_json: PathBuf,
     pub session_json: PathBuf,
     pub session_json_old: PathBuf,
@@ -760,15 +1310,7 @@ pub struct ApplicationSettings {
 
 impl ApplicationSettings {
     pub fn load(cli: Iamb) -> Result<Self, Box<dyn std::error::Error>> {
-        let mut config_dir = cli.config_directory.or_else(dirs::config_dir).unwrap_or_else(|| {
-            usage!(
-                "No user configuration directory found;\
-                please specify one via -C.\n\n
-                For more information try '--help'"
-            );
-        });
-
-        config_dir.push("iamb");
+        let config_dir = config_dir(cli.config_directory);
         let config_json = config_dir.join("config.json");
         let config_toml = config_dir.join("config.toml");
 
@@ -848,6 +1390,27 @@ impl ApplicationSettings {
         let mut session_json_old = profile_dir;
         session_json_old.push("session.json");
 
+        let mut command_history = profile_data_dir.clone();
+        command_history.push("command_history");
+
+        let mut sent_history = profile_data_dir.clone();
+        sent_history.push("sent_history.json");
+
+        let mut recent_reactions = profile_data_dir.clone();
+        recent_reactions.push("recent_reactions.json");
+
+        let mut identities_json = profile_data_dir.clone();
+        identities_json.push("identities.json");
+
+        let mut scroll_positions_json = profile_data_dir.clone();
+        scroll_positions_json.push("scroll_positions.json");
+
+        let mut version_warnings_json = profile_data_dir.clone();
+        version_warnings_json.push("version_warnings.json");
+
+        let mut drafts_json = profile_data_dir.clone();
+        drafts_json.push("drafts.json");
+
         // Set up paths that live inside the profile's cache directory.
         let mut cache_dir = dirs.cache.clone();
         cache_dir.push("profiles");
@@ -857,6 +1420,13 @@ impl ApplicationSettings {
         layout_json.push("layout.json");
 
         let settings = ApplicationSettings {
+            command_history,
+            sent_history,
+            recent_reactions,
+            identities_json,
+            scroll_positions_json,
+            version_warnings_json,
+            drafts_json,
             sled_dir,
             layout_json,
             session_json,
@@ -873,6 +1443,130 @@ impl ApplicationSettings {
         Ok(settings)
     }
 
+    /// Check the configuration file the way [ApplicationSettings::load] would find it, and
+    /// report every problem in it: entries that can't be loaded, and then, if there were none,
+    /// the ones found by [ApplicationSettings::validate].
+    pub fn check(cli: Iamb) -> Vec<String> {
+        let config_dir = config_dir(cli.config_directory.clone());
+        let config_toml = config_dir.join("config.toml");
+        let config_json = config_dir.join("config.json");
+
+        let config = if config_toml.is_file() {
+            config_toml
+        } else {
+            config_json
+        };
+
+        if config.is_file() {
+            let problems = IambConfig::problems(&config);
+
+            if !problems.is_empty() {
+                return problems;
+            }
+        }
+
+        match ApplicationSettings::load(cli) {
+            Ok(settings) => settings.validate(),
+            Err(e) => vec![e.to_string()],
+        }
+    }
+
+    /// Look for settings that parse fine, but won't work the way they were meant to.
+    ///
+    /// Every problem found gets returned, so that they can all be fixed in one go.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        let tunables = &self.tunables;
+
+        if tunables.user_gutter_width < MIN_USER_GUTTER_WIDTH {
+            problems.push(format!(
+                "user_gutter_width is {}, but needs to be at least {MIN_USER_GUTTER_WIDTH}",
+                tunables.user_gutter_width
+            ));
+        }
+
+        if tunables.request_timeout == 0 {
+            problems.push("request_timeout is 0, so every request would time out".into());
+        }
+
+        let suffix = &tunables.external_edit_file_suffix;
+        if !suffix.is_empty() && !suffix.starts_with('.') {
+            problems
+                .push(format!("external_edit_file_suffix {suffix:?} should start with a \".\""));
+        }
+
+        if let Some(preview) = &tunables.image_preview {
+            if preview.size.width == 0 || preview.size.height == 0 {
+                problems.push("image_preview.size needs a non-zero width and height".into());
+            }
+
+            let protocol = preview.protocol.as_ref();
+
+            if matches!(protocol, Some(p) if matches!(p.font_size, Some((0, _) | (_, 0)))) {
+                problems.push("image_preview.protocol.font_size can't contain a 0".into());
+            }
+
+            let font_size = protocol.and_then(|p| p.font_size);
+            if cfg!(windows) && font_size.is_none() {
+                let (w, h) = WINDOWS_FONT_SIZE;
+                problems.push(format!(
+                    "image_preview.protocol.font_size can't be detected on Windows, so previews \
+                     are sized for a {w}x{h} font until it's set"
+                ));
+            }
+        }
+
+        match tunables.open_command.as_deref() {
+            Some([]) => problems.push("open_command can't be an empty list".into()),
+            Some([program, ..]) if !program_exists(program) => {
+                problems.push(format!("open_command program {program:?} couldn't be found"));
+            },
+            _ => {},
+        }
+
+        for event_type in tunables.custom_events.keys() {
+            if crate::message::is_native_event_type(event_type) {
+                problems.push(format!(
+                    "custom_events can't change how {event_type} is shown, since iamb already \
+                     handles it"
+                ));
+            }
+        }
+
+        let DirectoryValues { cache, data, logs, downloads, image_previews } = &self.dirs;
+        let dirs = [
+            ("cache", Some(cache)),
+            ("data", Some(data)),
+            ("logs", Some(logs)),
+            ("downloads", downloads.as_ref()),
+            ("image_previews", Some(image_previews)),
+        ];
+
+        for (name, dir) in dirs {
+            let Some(dir) = dir else { continue };
+
+            match std::fs::metadata(dir) {
+                Ok(meta) if !meta.is_dir() => {
+                    problems.push(format!("dirs.{name} {} isn't a directory", dir.display()));
+                },
+                Ok(meta) if meta.permissions().readonly() => {
+                    problems.push(format!("dirs.{name} {} isn't writable", dir.display()));
+                },
+                Ok(_) | Err(_) => {},
+            }
+        }
+
+        for keys in self.macros.values() {
+            for Keys(input, raw) in keys.keys() {
+                if input.is_empty() {
+                    problems.push(format!("macros can't map the empty key sequence {raw:?}"));
+                }
+            }
+        }
+
+        problems
+    }
+
     pub fn read_session(&self, path: impl AsRef<Path>) -> Result<Session, IambError> {
         let file = File::open(path)?;
         let reader = BufReader::new(file);
@@ -888,6 +1582,212 @@ impl ApplicationSettings {
         Ok(())
     }
 
+    /// Load the `:` commands saved by [ApplicationSettings::write_command_history].
+    pub fn read_command_history(&self) -> Vec<String> {
+        let Ok(text) = std::fs::read_to_string(&self.command_history) else {
+            return vec![];
+        };
+
+        let size = self.tunables.command_history_size;
+
+        history_entries(text.lines().map(String::from), size)
+    }
+
+    /// Save the most recent `:` commands, so that they can be recalled in the next session.
+    pub fn write_command_history<I>(&self, cmds: I) -> std::io::Result<()>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let size = self.tunables.command_history_size;
+        let cmds = cmds.into_iter().filter(|cmd| !cmd.contains('\n') && !has_secret(cmd));
+        let mut text = history_entries(cmds, size).join("\n");
+        text.push('\n');
+
+        if let Some(parent) = self.command_history.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        std::fs::write(&self.command_history, text)
+    }
+
+    /// Load the messages saved by [ApplicationSettings::write_sent_history].
+    pub fn read_sent_history(&self) -> HashMap<OwnedRoomId, Vec<String>> {
+        let Ok(text) = std::fs::read_to_string(&self.sent_history) else {
+            return HashMap::new();
+        };
+
+        // Older versions kept a single list for all rooms, which can't be sorted out now.
+        let Ok(history) = serde_json::from_str::<HashMap<OwnedRoomId, Vec<String>>>(&text) else {
+            return HashMap::new();
+        };
+
+        sent_history_entries(history, self.tunables.sent_history_size)
+    }
+
+    /// Save the most recently sent messages in each room. Unlike `:` commands, these can span
+    /// several lines, so they get stored as JSON lists.
+    pub fn write_sent_history(
+        &self,
+        history: HashMap<OwnedRoomId, Vec<String>>,
+    ) -> Result<(), IambError> {
+        let msgs = sent_history_entries(history, self.tunables.sent_history_size);
+
+        if let Some(parent) = self.sent_history.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let file = File::create(&self.sent_history)?;
+        let writer = BufWriter::new(file);
+        serde_json::to_writer(writer, &msgs).map_err(IambError::from)?;
+
+        Ok(())
+    }
+
+    /// Load the reactions saved by [ApplicationSettings::write_recent_reactions].
+    pub fn read_recent_reactions(&self) -> Vec<String> {
+        let Ok(text) = std::fs::read_to_string(&self.recent_reactions) else {
+            return vec![];
+        };
+
+        let mut recent = serde_json::from_str::<Vec<String>>(&text).unwrap_or_default();
+        recent.truncate(MAX_RECENT_REACTIONS);
+        recent
+    }
+
+    /// Save the most recently used reactions, so that `:react recent` still has them after
+    /// restarting.
+    pub fn write_recent_reactions(&self, recent: &[String]) -> Result<(), IambError> {
+        if let Some(parent) = self.recent_reactions.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let file = File::create(&self.recent_reactions)?;
+        let writer = BufWriter::new(file);
+        serde_json::to_writer(writer, recent).map_err(IambError::from)?;
+
+        Ok(())
+    }
+
+    /// Load the scrollback positions saved by [ApplicationSettings::write_scroll_positions].
+    pub fn read_scroll_positions(&self) -> HashMap<OwnedRoomId, ScrollPosition> {
+        let Ok(text) = std::fs::read_to_string(&self.scroll_positions_json) else {
+            return HashMap::new();
+        };
+
+        serde_json::from_str(&text).unwrap_or_default()
+    }
+
+    /// Save where each room's scrollback was left, so that rooms can be reopened there.
+    pub fn write_scroll_positions(
+        &self,
+        positions: &HashMap<OwnedRoomId, ScrollPosition>,
+    ) -> Result<(), IambError> {
+        if let Some(parent) = self.scroll_positions_json.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let file = File::create(&self.scroll_positions_json)?;
+        let writer = BufWriter::new(file);
+        serde_json::to_writer(writer, positions).map_err(IambError::from)?;
+
+        Ok(())
+    }
+
+    /// Load the rooms saved by [ApplicationSettings::write_version_warnings].
+    pub fn read_version_warnings(&self) -> HashSet<OwnedRoomId> {
+        let Ok(text) = std::fs::read_to_string(&self.version_warnings_json) else {
+            return HashSet::new();
+        };
+
+        serde_json::from_str(&text).unwrap_or_default()
+    }
+
+    /// Save which rooms the user has dismissed the outdated room version warning for.
+    pub fn write_version_warnings(
+        &self,
+        dismissed: &HashSet<OwnedRoomId>,
+    ) -> Result<(), IambError> {
+        if let Some(parent) = self.version_warnings_json.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let file = File::create(&self.version_warnings_json)?;
+        let writer = BufWriter::new(file);
+        serde_json::to_writer(writer, dismissed).map_err(IambError::from)?;
+
+        Ok(())
+    }
+
+    /// Load the unsent messages saved by [ApplicationSettings::write_drafts].
+    pub fn read_drafts(&self) -> HashMap<OwnedRoomId, String> {
+        let Ok(text) = std::fs::read_to_string(&self.drafts_json) else {
+            return HashMap::new();
+        };
+
+        serde_json::from_str(&text).unwrap_or_default()
+    }
+
+    /// Save the text left in each room's message bar, so that it's still there after a restart.
+    pub fn write_drafts(&self, drafts: &HashMap<OwnedRoomId, String>) -> Result<(), IambError> {
+        if let Some(parent) = self.drafts_json.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let file = File::create(&self.drafts_json)?;
+        let writer = BufWriter::new(file);
+        serde_json::to_writer(writer, drafts).map_err(IambError::from)?;
+
+        Ok(())
+    }
+
+    /// Load the master keys first seen for other users, which are used to notice when their
+    /// identities change.
+    pub fn read_known_identities(&self) -> HashMap<OwnedUserId, String> {
+        let Ok(text) = std::fs::read_to_string(&self.identities_json) else {
+            return HashMap::new();
+        };
+
+        serde_json::from_str(&text).unwrap_or_default()
+    }
+
+    /// Change the master keys first seen for other users, saving them if `f` modified them.
+    ///
+    /// Several tasks record identities, so each update happens under a lock to keep them from
+    /// overwriting entries that another one just added. Failing to save is only logged, since
+    /// the identities seen this session are still worth acting on.
+    pub fn update_known_identities<T>(
+        &self,
+        f: impl FnOnce(&mut HashMap<OwnedUserId, String>) -> T,
+    ) -> T {
+        let _guard = KNOWN_IDENTITIES_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut known = self.read_known_identities();
+        let prev = known.clone();
+        let res = f(&mut known);
+
+        if known != prev {
+            if let Err(e) = self.write_known_identities(&known) {
+                tracing::error!("Failed to record user identities: {e}");
+            }
+        }
+
+        res
+    }
+
+    fn write_known_identities(
+        &self,
+        known: &HashMap<OwnedUserId, String>,
+    ) -> Result<(), IambError> {
+        if let Some(parent) = self.identities_json.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let file = File::create(&self.identities_json)?;
+        let writer = BufWriter::new(file);
+        serde_json::to_writer(writer, known).map_err(IambError::from)?;
+
+        Ok(())
+    }
+
     pub fn get_user_char_span<'a>(&self, user_id: &'a UserId) -> Span<'a> {
         let (color, c) = self
             .tunables
@@ -954,12 +1854,139 @@ impl ApplicationSettings {
     }
 }
 
+/// Whether a `:` command contains a recovery key or a key export passphrase, which shouldn't be
+/// written to the unencrypted command history.
+fn has_secret(cmd: &str) -> bool {
+    cmd.split('|').any(|cmd| {
+        let mut words = cmd.split_whitespace();
+
+        matches!(
+            (words.next(), words.next()),
+            (Some("recovery"), Some("restore")) | (Some("keys"), Some("import" | "export"))
+        )
+    })
+}
+
+/// Drop blank and repeated entries from a history list, and keep only the newest `size`.
+fn history_entries<I>(cmds: I, size: usize) -> Vec<String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut entries: Vec<String> = vec![];
+
+    for cmd in cmds {
+        if cmd.trim().is_empty() {
+            continue;
+        }
+
+        if entries.last() != Some(&cmd) {
+            entries.push(cmd);
+        }
+    }
+
+    let skip = entries.len().saturating_sub(size);
+    entries.split_off(skip)
+}
+
+/// Clean up the sent message history of each room, dropping rooms that have none left.
+fn sent_history_entries(
+    history: HashMap<OwnedRoomId, Vec<String>>,
+    size: usize,
+) -> HashMap<OwnedRoomId, Vec<String>> {
+    history
+        .into_iter()
+        .map(|(room_id, msgs)| (room_id, history_entries(msgs, size)))
+        .filter(|(_, msgs)| !msgs.is_empty())
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use matrix_sdk::ruma::user_id;
+    use matrix_sdk::ruma::{room_id, user_id};
     use std::convert::TryFrom;
 
+    #[test]
+    fn test_history_entries() {
+        let cmds = ["join #a", "join #a", "", "dms", "join #a", "  ", "rooms"];
+        let cmds = cmds.iter().map(|s| s.to_string());
+        assert_eq!(history_entries(cmds.clone(), 10), vec!["join #a", "dms", "join #a", "rooms"]);
+        assert_eq!(history_entries(cmds.clone(), 2), vec!["join #a", "rooms"]);
+        assert_eq!(history_entries(cmds, 0), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_command_history_secrets() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let mut settings = crate::tests::mock_settings();
+        settings.command_history = dir.child("command_history");
+
+        let cmds = [
+            "recovery restore EsTc 8JgN",
+            "dms",
+            "keys export /tmp/keys.txt hunter2",
+            "  keys import /tmp/keys.txt hunter2",
+            "rooms | recovery restore EsTc 8JgN",
+            "recovery setup",
+            "keys",
+        ];
+        settings.write_command_history(cmds.iter().map(|s| s.to_string())).unwrap();
+
+        let text = std::fs::read_to_string(&settings.command_history).unwrap();
+        assert_eq!(text, "dms\nrecovery setup\nkeys\n");
+        assert!(!text.contains("EsTc") && !text.contains("hunter2"));
+    }
+
+    #[test]
+    fn test_drafts_round_trip() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let mut settings = crate::tests::mock_settings();
+        settings.drafts_json = dir.child("profile").join("drafts.json");
+
+        // Nothing has been saved yet.
+        assert!(settings.read_drafts().is_empty());
+
+        let drafts = HashMap::from([
+            (room_id!("!a:example.com").to_owned(), "unfinished\nthought".to_string()),
+            (room_id!("!b:example.com").to_owned(), "hello".to_string()),
+        ]);
+        settings.write_drafts(&drafts).unwrap();
+        assert_eq!(settings.read_drafts(), drafts);
+    }
+
+    #[test]
+    fn test_update_known_identities() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let mut settings = crate::tests::mock_settings();
+        settings.identities_json = dir.child("profile").join("identities.json");
+
+        // Updates from several tasks at once all get kept.
+        std::thread::scope(|s| {
+            for i in 0..8 {
+                let settings = &settings;
+                s.spawn(move || {
+                    let user_id = OwnedUserId::try_from(format!("@u{i}:example.com")).unwrap();
+                    settings.update_known_identities(|known| known.insert(user_id, i.to_string()));
+                });
+            }
+        });
+
+        let known = settings.read_known_identities();
+        assert_eq!(known.len(), 8);
+        assert_eq!(known[&OwnedUserId::try_from("@u3:example.com").unwrap()], "3");
+    }
+
+    #[test]
+    fn test_sent_history_entries() {
+        let history = HashMap::from([
+            (room_id!("!a:example.com").to_owned(), vec!["hi".into(), "".into(), "bye".into()]),
+            (room_id!("!b:example.com").to_owned(), vec![" ".into()]),
+        ]);
+        let res = sent_history_entries(history, 1);
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[room_id!("!a:example.com")], vec!["bye"]);
+    }
+
     #[test]
     fn test_profile_name_invalid() {
         assert_eq!(validate_profile_name(""), false);
@@ -1053,6 +2080,41 @@ mod tests {
         assert_eq!(res.users, Some(users.into_iter().collect()));
     }
 
+    #[test]
+    fn test_message_load_tunables() {
+        let res: Tunables = serde_json::from_str("{}").unwrap();
+        let values = res.values();
+        assert_eq!(values.message_load_batch, 50);
+        assert_eq!(values.message_load_interval, 2);
+
+        let res: Tunables =
+            serde_json::from_str("{\"message_load_batch\": 5000, \"message_load_interval\": 0}")
+                .unwrap();
+        let values = res.values();
+        assert_eq!(values.message_load_batch, 1000);
+        assert_eq!(values.message_load_interval, 1);
+
+        let res: Tunables = serde_json::from_str("{\"message_load_batch\": 1}").unwrap();
+        assert_eq!(res.values().message_load_batch, 10);
+    }
+
+    #[test]
+    fn test_parse_image_preview_protocol() {
+        let parse = |ty: &str| {
+            let json = format!("{{\"protocol\": {{\"type\": \"{ty}\"}}}}");
+            let preview: ImagePreview = serde_json::from_str(&json).unwrap();
+            preview.values().protocol.and_then(|p| p.r#type)
+        };
+
+        assert_eq!(parse("halfblocks"), Some(ProtocolType::Halfblocks));
+        assert_eq!(parse("sixel"), Some(ProtocolType::Sixel));
+        assert_eq!(parse("kitty"), Some(ProtocolType::Kitty));
+        assert_eq!(parse("iterm2"), Some(ProtocolType::Iterm2));
+
+        let res = serde_json::from_str::<ImagePreview>("{\"protocol\": {\"type\": \"png\"}}");
+        assert!(res.is_err());
+    }
+
     #[test]
     fn test_parse_tunables_username_display() {
         let res: Tunables = serde_json::from_str("{\"username_display\": \"username\"}").unwrap();
@@ -1066,6 +2128,167 @@ mod tests {
         assert_eq!(res.username_display, Some(UserDisplayStyle::DisplayName));
     }
 
+    #[test]
+    fn test_validate() {
+        let mut settings = crate::tests::mock_settings();
+        assert_eq!(settings.validate(), Vec::<String>::new());
+
+        settings.tunables.user_gutter_width = 1;
+        settings.tunables.request_timeout = 0;
+        settings.tunables.open_command = Some(vec!["/nonexistent/iamb-opener".into()]);
+        settings.tunables.custom_events.insert("m.room.message".into(), "".into());
+
+        // Every problem gets reported, not just the first one.
+        let problems = settings.validate();
+        assert_eq!(problems.len(), 4);
+        assert!(problems[0].starts_with("user_gutter_width is 1"));
+        assert!(problems[1].starts_with("request_timeout"));
+        assert!(problems[2].contains("/nonexistent/iamb-opener"));
+        assert!(problems[3].contains("m.room.message"));
+    }
+
+    #[test]
+    fn test_config_problems() {
+        let config = serde_json::json!({
+            "default_profile": "user",
+            "settings": {
+                "request_timeout": "soon",
+                "user_gutter_width": 30,
+                "users": { "@a:example.com": { "color": "not-a-color" } },
+            },
+            "macros": { "i": { "jj": "<Esc>" } },
+            "layout": { "style": "sideways" },
+            "profiles": {
+                "user": {
+                    "user_id": "not-a-user-id",
+                    "dirs": { "cache": 5 },
+                },
+                "other": { "url": "https://example.com" },
+            },
+        });
+
+        let mut problems = config_problems(&config);
+        problems.sort();
+
+        // Every bad entry gets reported on its own, and the good ones don't show up.
+        assert_eq!(problems.len(), 6, "{problems:?}");
+        assert!(problems[0].starts_with("layout: "));
+        assert!(problems[1].starts_with("profiles.other: missing field `user_id`"));
+        assert!(problems[2].starts_with("profiles.user.dirs.cache: "));
+        assert!(problems[3].starts_with("profiles.user.user_id: "));
+        assert!(problems[4].starts_with("settings.request_timeout: "));
+        assert!(problems[5].starts_with("settings.users: "));
+
+        let config = serde_json::json!({ "profiles": { "user": { "user_id": "@a:b.c" } } });
+        assert_eq!(config_problems(&config), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_parse_tunables_timezone() {
+        let res: Tunables = serde_json::from_str("{}").unwrap();
+        assert_eq!(res.values().timezone, Timezone::Local);
+
+        let parse = |tz: &str| {
+            let json = format!(r#"{{"timezone": "{tz}"}}"#);
+            serde_json::from_str::<Tunables>(&json).map(|t| t.values().timezone)
+        };
+        let offset = |secs| Timezone::Fixed(FixedOffset::east_opt(secs).unwrap());
+
+        assert_eq!(parse("local").unwrap(), Timezone::Local);
+        assert_eq!(parse("UTC").unwrap(), offset(0));
+        assert_eq!(parse("z").unwrap(), offset(0));
+        assert_eq!(parse("+05:30").unwrap(), offset(5 * 3600 + 30 * 60));
+        assert_eq!(parse("-0800").unwrap(), offset(-8 * 3600));
+        assert_eq!(parse("UTC+2").unwrap(), offset(2 * 3600));
+        assert_eq!(parse("gmt-3:45").unwrap(), offset(-(3 * 3600 + 45 * 60)));
+
+        // Named zones aren't supported, and offsets have to be in range.
+        assert!(parse("Europe/Berlin").is_err());
+        assert!(parse("+24:00").is_err());
+        assert!(parse("+05:60").is_err());
+        assert!(parse("5").is_err());
+        assert!(parse("+").is_err());
+        assert!(parse("é").is_err());
+        assert!(parse("+é1").is_err());
+        assert!(parse("+1é").is_err());
+    }
+
+    #[test]
+    fn test_parse_tunables_rooms() {
+        let res: Tunables = serde_json::from_str(
+            r#"{"rooms": {"!a:example.com": {"send_template": "{message}\n-- support"}}}"#,
+        )
+        .unwrap();
+        let rooms = res.values().rooms;
+        let room = rooms.get(room_id!("!a:example.com")).unwrap();
+        assert_eq!(room.apply_send_template("hi").unwrap(), "hi\n-- support");
+
+        let room = RoomTunables {
+            send_template: Some("[helpdesk] {message}".into()),
+            ..Default::default()
+        };
+        assert_eq!(room.apply_send_template("hi").unwrap(), "[helpdesk] hi");
+
+        let room = RoomTunables {
+            send_template: Some("-- support".into()),
+            ..Default::default()
+        };
+        assert_eq!(room.apply_send_template("hi").unwrap(), "hi\n-- support");
+
+        assert_eq!(RoomTunables::default().apply_send_template("hi"), None);
+
+        let res: Tunables =
+            serde_json::from_str(r#"{"rooms": {"!a:example.com": {"send_cooldown": 30}}}"#)
+                .unwrap();
+        let rooms = res.values().rooms;
+        let room = rooms.get(room_id!("!a:example.com")).unwrap();
+        assert_eq!(room.send_cooldown, Some(30));
+        assert_eq!(room.send_template, None);
+        assert_eq!(room.send_markdown, None);
+
+        let res: Tunables =
+            serde_json::from_str(r#"{"rooms": {"!a:example.com": {"send_markdown": false}}}"#)
+                .unwrap();
+        let values = res.values();
+        assert!(values.send_markdown);
+        let room = values.rooms.get(room_id!("!a:example.com")).unwrap();
+        assert_eq!(room.send_markdown, Some(false));
+    }
+
+    #[test]
+    fn test_parse_tunables_auto_download() {
+        let res: Tunables = serde_json::from_str("{}").unwrap();
+        assert_eq!(res.values().auto_download, None);
+
+        let res: Tunables = serde_json::from_str(r#"{"auto_download": {}}"#).unwrap();
+        let auto = res.values().auto_download.unwrap();
+        assert_eq!(auto.types, vec![MediaKind::Image]);
+        assert_eq!(auto.max_size, DEFAULT_AUTO_DOWNLOAD_SIZE);
+
+        let res: Tunables = serde_json::from_str(
+            r#"{"auto_download": {"types": ["file", "video"], "max_size": 1000}}"#,
+        )
+        .unwrap();
+        let auto = res.values().auto_download.unwrap();
+        assert!(auto.allows(MediaKind::File, Some(1000)));
+        assert!(auto.allows(MediaKind::Video, Some(10)));
+        assert!(!auto.allows(MediaKind::File, Some(1001)));
+        assert!(!auto.allows(MediaKind::File, None));
+        assert!(!auto.allows(MediaKind::Image, Some(10)));
+    }
+
+    #[test]
+    fn test_parse_tunables_room_key_rotation() {
+        let res: Tunables =
+            serde_json::from_str(r#"{"room_key_rotation": {"messages": 10}}"#).unwrap();
+        let rotation = RoomKeyRotation { period_ms: None, messages: Some(10) };
+        assert_eq!(res.room_key_rotation, Some(rotation));
+
+        let res = res.values();
+        assert_eq!(res.room_key_rotation.period_ms, None);
+        assert_eq!(res.room_key_rotation.messages, Some(10));
+    }
+
     #[test]
     fn test_parse_tunables_sort() {
         let res: Tunables = serde_json::from_str(
@@ -0,0 +1,116 @@
+//! # Emoji shortcodes
+//!
+//! Shortcodes like `:thumbsup:` get looked up in the table of GitHub-style names that comes with
+//! the [emojis] crate, which is also what tab completion and `message_shortcode_display` use.
+
+/// Look up the Emoji for a shortcode name, written without the surrounding colons.
+pub fn shortcode_emoji(name: &str) -> Option<&'static str> {
+    emojis::get_by_shortcode(name).map(|emoji| emoji.as_str())
+}
+
+/// Characters that can appear in a shortcode name, like `+1` or `woman_technologist`.
+fn is_shortcode_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '+' | '-')
+}
+
+/// Characters that a shortcode can't be attached to, so that things like `12:30:45` and
+/// `foo:bar:baz` are left alone.
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn expand_span(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut copied = 0;
+    let mut search = 0;
+
+    while let Some(off) = text[search..].find(':') {
+        let open = search + off;
+        search = open + 1;
+
+        if matches!(text[..open].chars().next_back(), Some(c) if is_word_char(c)) {
+            continue;
+        }
+
+        let name_start = open + 1;
+        let name_len = text[name_start..]
+            .find(|c| !is_shortcode_char(c))
+            .unwrap_or(text.len() - name_start);
+        let close = name_start + name_len;
+
+        if name_len == 0 || !text[close..].starts_with(':') {
+            continue;
+        }
+
+        if matches!(text[close + 1..].chars().next(), Some(c) if is_word_char(c)) {
+            continue;
+        }
+
+        if let Some(emoji) = shortcode_emoji(&text[name_start..close]) {
+            out.push_str(&text[copied..open]);
+            out.push_str(emoji);
+            copied = close + 1;
+            search = close + 1;
+        }
+    }
+
+    out.push_str(&text[copied..]);
+    out
+}
+
+/// Replace each `:name:` shortcode in some text with its Emoji.
+///
+/// Shortcodes have to stand on their own, so colons in URLs and times aren't touched, and any
+/// colon-wrapped word that isn't a known shortcode is kept as written. Text between backticks is
+/// left alone, so that code can still be quoted.
+pub fn expand_shortcodes(text: &str) -> String {
+    text.split('`')
+        .enumerate()
+        .map(|(i, span)| {
+            if i % 2 == 0 {
+                expand_span(span)
+            } else {
+                span.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("`")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_shortcodes() {
+        assert_eq!(expand_shortcodes(":thumbsup:"), "👍");
+        assert_eq!(expand_shortcodes(":+1: and :tada:!"), "👍 and 🎉!");
+        assert_eq!(expand_shortcodes("(:smile:)"), "(😄)");
+        assert_eq!(expand_shortcodes(":smile::smile:"), "😄😄");
+
+        // Unknown shortcodes round-trip untouched.
+        assert_eq!(expand_shortcodes("a :notanemoji: word"), "a :notanemoji: word");
+        assert_eq!(expand_shortcodes(":notanemoji::smile:"), ":notanemoji:😄");
+
+        // Shortcodes attached to other words aren't expanded.
+        assert_eq!(expand_shortcodes("foo:smile:"), "foo:smile:");
+        assert_eq!(expand_shortcodes(":smile:s"), ":smile:s");
+
+        // Other uses of colons are left alone.
+        assert_eq!(expand_shortcodes("https://example.com:8448/"), "https://example.com:8448/");
+        assert_eq!(expand_shortcodes("at 12:30:45"), "at 12:30:45");
+        assert_eq!(expand_shortcodes("key: value :"), "key: value :");
+        assert_eq!(expand_shortcodes("::"), "::");
+
+        // Code spans are kept as written.
+        assert_eq!(expand_shortcodes("`:smile:` :smile:"), "`:smile:` 😄");
+        assert_eq!(expand_shortcodes("```\n:smile:\n```"), "```\n:smile:\n```");
+    }
+
+    #[test]
+    fn test_shortcode_emoji() {
+        assert_eq!(shortcode_emoji("thumbsup"), Some("👍"));
+        assert_eq!(shortcode_emoji(":thumbsup:"), None);
+        assert_eq!(shortcode_emoji("notanemoji"), None);
+    }
+}
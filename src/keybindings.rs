@@ -3,17 +3,25 @@
 //! The keybindings are set up here. We define some iamb-specific keybindings, but the default Vim
 //! keys come from [modalkit::env::vim::keybindings].
 use modalkit::{
-    actions::{MacroAction, WindowAction},
+    actions::{Action, InsertTextAction, MacroAction, PromptAction, WindowAction},
     env::vim::keybindings::{InputStep, VimBindings},
     env::vim::VimMode,
     env::CommonKeyClass,
     key::TerminalKey,
     keybindings::{EdgeEvent, EdgeRepeat, InputBindings},
-    prelude::Count,
+    prelude::{Char, Count, MoveDir1D, OpenTarget, Specifier},
 };
 
-use crate::base::{IambAction, IambInfo, Keybindings, MATRIX_ID_WORD};
-use crate::config::{ApplicationSettings, Keys};
+use crate::base::{
+    IambAction,
+    IambId,
+    IambInfo,
+    Keybindings,
+    MessageAction,
+    SendAction,
+    MATRIX_ID_WORD,
+};
+use crate::config::{ApplicationSettings, EscapeAction, Keys};
 
 pub type IambStep = InputStep<IambInfo>;
 
@@ -34,6 +42,8 @@ pub fn setup_keybindings() -> Keybindings {
     let ctrl_w = "<C-W>".parse::<TerminalKey>().unwrap();
     let ctrl_m = "<C-M>".parse::<TerminalKey>().unwrap();
     let ctrl_z = "<C-Z>".parse::<TerminalKey>().unwrap();
+    let key_b_lc = "b".parse::<TerminalKey>().unwrap();
+    let key_g_lc = "g".parse::<TerminalKey>().unwrap();
     let key_m_lc = "m".parse::<TerminalKey>().unwrap();
     let key_z_lc = "z".parse::<TerminalKey>().unwrap();
 
@@ -57,11 +67,70 @@ pub fn setup_keybindings() -> Keybindings {
     ism.add_mapping(VimMode::Visual, &cwm, &stoggle);
     ism.add_mapping(VimMode::Normal, &cwcm, &stoggle);
     ism.add_mapping(VimMode::Visual, &cwcm, &stoggle);
+
+    let gb = vec![once(&key_g_lc), once(&key_b_lc)];
+    let breadcrumbs = OpenTarget::Application(IambId::BreadcrumbList);
+    let breadcrumbs = IambStep::new()
+        .actions(vec![WindowAction::Switch(breadcrumbs).into()])
+        .goto(VimMode::Normal);
+    ism.add_mapping(VimMode::Normal, &gb, &breadcrumbs);
+
+    let alt_r = "<A-r>".parse::<TerminalKey>().unwrap();
+    let search = IambStep::new().actions(vec![IambAction::from(SendAction::SearchHistory).into()]);
+    ism.add_mapping(VimMode::Normal, &[once(&alt_r)], &search);
+    ism.add_mapping(VimMode::Insert, &[once(&alt_r)], &search);
+
     ism
 }
 
+/// Map `<Enter>` in Insert mode to either send the message or insert a newline, and bind the
+/// other behaviour to `<A-Enter>` and `<S-Enter>`.
+fn setup_enter(bindings: &mut Keybindings, send_on_enter: bool) {
+    let enter = "<Enter>".parse::<TerminalKey>().unwrap();
+    let alt_enter = "<A-Enter>".parse::<TerminalKey>().unwrap();
+    let shift_enter = "<S-Enter>".parse::<TerminalKey>().unwrap();
+
+    let newline = InsertTextAction::Type(
+        Specifier::Exact(Char::Single('\n')),
+        MoveDir1D::Previous,
+        Count::Exact(1),
+    );
+    let newline = IambStep::new().actions(vec![Action::from(newline)]);
+    let submit = IambStep::new()
+        .actions(vec![Action::Prompt(PromptAction::Submit)])
+        .goto(VimMode::Insert);
+
+    let (plain, modified) = if send_on_enter {
+        (submit, newline)
+    } else {
+        (newline, submit)
+    };
+
+    bindings.add_mapping(VimMode::Insert, &[once(&enter)], &plain);
+    bindings.add_mapping(VimMode::Insert, &[once(&alt_enter)], &modified);
+    bindings.add_mapping(VimMode::Insert, &[once(&shift_enter)], &modified);
+}
+
+/// Map `<Esc>` in Normal mode to stop replying to or editing a message.
+fn setup_escape(bindings: &mut Keybindings, action: EscapeAction) {
+    if action != EscapeAction::Cancel {
+        return;
+    }
+
+    let esc = "<Esc>".parse::<TerminalKey>().unwrap();
+    let cancel = IambStep::new().actions(vec![
+        IambAction::from(MessageAction::CancelEdit).into(),
+        IambAction::from(MessageAction::CancelReply).into(),
+    ]);
+
+    bindings.add_mapping(VimMode::Normal, &[once(&esc)], &cancel);
+}
+
 impl InputBindings<TerminalKey, IambStep> for ApplicationSettings {
     fn setup(&self, bindings: &mut Keybindings) {
+        setup_enter(bindings, self.tunables.send_on_enter);
+        setup_escape(bindings, self.tunables.escape_action);
+
         for (modes, keys) in &self.macros {
             for (Keys(input, _), Keys(_, run)) in keys {
                 let act = MacroAction::Run(run.clone(), Count::Contextual);
@@ -15,7 +15,8 @@
 #![allow(clippy::needless_return)]
 #![allow(clippy::result_large_err)]
 #![allow(clippy::bool_assert_comparison)]
-use std::collections::VecDeque;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
 use std::convert::TryFrom;
 use std::fmt::Display;
 use std::fs::{create_dir_all, File};
@@ -29,7 +30,7 @@ use std::time::{Duration, Instant};
 use clap::Parser;
 use matrix_sdk::crypto::encrypt_room_key_export;
 use matrix_sdk::ruma::api::client::error::ErrorKind;
-use matrix_sdk::ruma::OwnedUserId;
+use matrix_sdk::ruma::{OwnedRoomId, OwnedUserId};
 use modalkit::keybindings::InputBindings;
 use rand::{distributions::Alphanumeric, Rng};
 use temp_dir::TempDir;
@@ -65,6 +66,7 @@ use ratatui::{
 mod base;
 mod commands;
 mod config;
+mod emoji;
 mod keybindings;
 mod message;
 mod notifications;
@@ -79,10 +81,13 @@ mod tests;
 
 use crate::{
     base::{
+        room_version_outdated,
         AsyncProgramStore,
         ChatStore,
+        DebugAction,
         HomeserverAction,
         IambAction,
+        IambBufferId,
         IambError,
         IambId,
         IambInfo,
@@ -91,10 +96,13 @@ use crate::{
         ProgramAction,
         ProgramContext,
         ProgramStore,
+        RecoveryAction,
+        RoomFocus,
     },
     config::{ApplicationSettings, Iamb},
+    preview::format_size,
     windows::IambWindow,
-    worker::{create_room, ClientWorker, LoginStyle, Requester},
+    worker::{create_room, create_space, restore_room_keys, ClientWorker, LoginStyle, Requester},
 };
 
 use modalkit::{
@@ -113,7 +121,13 @@ use modalkit::{
         WindowAction,
         WindowContainer,
     },
-    editing::{context::Resolve, key::KeyManager, store::Store},
+    editing::{
+        context::Resolve,
+        history::HistoryList,
+        key::KeyManager,
+        rope::EditRope,
+        store::Store,
+    },
     errors::{EditError, UIError},
     key::TerminalKey,
     keybindings::{
@@ -235,9 +249,6 @@ struct Application {
     /// Pending actions to run.
     actstack: VecDeque<(ProgramAction, ProgramContext)>,
 
-    /// Whether or not the terminal is currently focused.
-    focused: bool,
-
     /// The tab layout before the last executed [TabAction].
     last_layout: Option<TabLayoutDescription<IambInfo>>,
 
@@ -267,6 +278,41 @@ impl Application {
         let bindings = KeyManager::new(bindings);
 
         let mut locked = store.lock().await;
+
+        let history_size = settings.tunables.command_history_size;
+
+        if history_size > 0 {
+            locked.commands = HistoryList::new(EditRope::from(""), history_size);
+
+            for cmd in settings.read_command_history() {
+                locked.commands.select(EditRope::from(cmd));
+            }
+        }
+
+        let sent_size = settings.tunables.sent_history_size;
+
+        if sent_size > 0 {
+            for (room_id, msgs) in settings.read_sent_history() {
+                let sent = locked.application.sent_history.get(&room_id);
+
+                for msg in msgs {
+                    sent.select(EditRope::from(msg));
+                }
+            }
+        }
+
+        locked.application.recent_reactions = settings.read_recent_reactions();
+        locked.application.scroll_positions = settings.read_scroll_positions();
+        locked.application.version_warnings_dismissed = settings.read_version_warnings();
+
+        // Put unsent messages back into their message bars before any rooms get opened.
+        if settings.tunables.save_drafts {
+            for (room_id, text) in settings.read_drafts() {
+                let id = IambBufferId::Room(room_id, None, RoomFocus::MessageBar);
+                locked.buffers.load_str(id, &text);
+            }
+        }
+
         let screen = setup_screen(settings, locked.deref_mut())?;
 
         let worker = locked.application.worker.clone();
@@ -282,7 +328,6 @@ impl Application {
             bindings,
             actstack,
             screen,
-            focused: true,
             last_layout: None,
             dirty: true,
         })
@@ -290,7 +335,7 @@ impl Application {
 
     fn redraw(&mut self, full: bool, store: &mut ProgramStore) -> Result<(), std::io::Error> {
         let bindings = &mut self.bindings;
-        let focused = self.focused;
+        let focused = store.application.terminal_focused;
         let sstate = &mut self.screen;
         let term = &mut self.terminal;
 
@@ -306,6 +351,43 @@ impl Application {
             let area = f.size();
 
             let modestr = bindings.show_mode();
+            let worker = &store.application.worker;
+            let modestr = if worker.is_online() {
+                modestr
+            } else {
+                // Make it obvious that messages are being queued instead of sent.
+                let offline = if worker.is_reconnecting() {
+                    "[reconnecting]"
+                } else {
+                    "[offline]"
+                };
+                let modestr = modestr.map(|m| format!("{m} {offline}"));
+                modestr.or_else(|| offline.to_string().into())
+            };
+            let alerts = &store.application.identity_alerts;
+            let modestr = if alerts.is_empty() {
+                modestr
+            } else {
+                let alert = match alerts.keys().next() {
+                    Some(user_id) if alerts.len() == 1 => {
+                        format!("[identity changed: {user_id}]")
+                    },
+                    _ => format!("[{} identities changed]", alerts.len()),
+                };
+                let modestr = modestr.map(|m| format!("{m} {alert}"));
+                modestr.or(Some(alert))
+            };
+            let sessions = store.application.unverified_sessions.len();
+            let modestr = if !store.application.session_warning || sessions == 0 {
+                modestr
+            } else {
+                let warning = match sessions {
+                    1 => "[1 unverified session]".to_string(),
+                    n => format!("[{n} unverified sessions]"),
+                };
+                let modestr = modestr.map(|m| format!("{m} {warning}"));
+                modestr.or(Some(warning))
+            };
             let cursor = bindings.get_cursor_indicator();
             let dialogstr = bindings.show_dialog(area.height as usize, area.width as usize);
 
@@ -339,12 +421,73 @@ impl Application {
         Ok(())
     }
 
+    /// Warn about outdated versions of rooms joined during this session, once they're known.
+    fn check_room_versions(&mut self, store: &mut ProgramStore) {
+        let ChatStore {
+            version_checks,
+            version_warnings_dismissed,
+            worker,
+            settings,
+            rooms,
+            ..
+        } = &mut store.application;
+
+        version_checks.retain(|room_id| {
+            let Some(room) = worker.client.get_room(room_id) else {
+                return true;
+            };
+            let Some(version) = room.clone_info().room_version().cloned() else {
+                return true;
+            };
+
+            let warn = settings.tunables.room_version_warning &&
+                !version_warnings_dismissed.contains(room_id);
+
+            if warn && room_version_outdated(&version) {
+                let name = rooms
+                    .get(room_id)
+                    .and_then(|info| info.name.clone())
+                    .or_else(|| room.name())
+                    .unwrap_or_else(|| room_id.to_string());
+                let msg = format!(
+                    "{name} uses version {version}, which is outdated and has known security issues; consider :room version upgrade"
+                );
+                self.screen.push_info(msg);
+            }
+
+            false
+        });
+    }
+
     async fn step(&mut self) -> Result<TerminalKey, std::io::Error> {
         loop {
-            self.redraw(self.dirty, self.store.clone().lock().await.deref_mut())?;
+            let timeout = {
+                let store = self.store.clone();
+                let mut locked = store.lock().await;
+                self.check_room_versions(locked.deref_mut());
+
+                for msg in std::mem::take(&mut locked.application.send_errors) {
+                    self.screen.push_error(UIError::<IambInfo>::Failure(msg));
+                }
+
+                for msg in std::mem::take(&mut locked.application.notices) {
+                    self.screen.push_info(msg);
+                }
+
+                self.redraw(self.dirty, locked.deref_mut())?;
+
+                // Keep animated previews moving while they're on screen.
+                let fps = locked.application.settings.tunables.image_preview.as_ref();
+                match fps.map(|p| p.max_fps) {
+                    Some(fps) if preview::take_animation_drawn() => {
+                        Duration::from_millis(1000 / u64::from(fps))
+                    },
+                    _ => Duration::from_secs(1),
+                }
+            };
             self.dirty = false;
 
-            if !poll(Duration::from_secs(1))? {
+            if !poll(timeout)? {
                 // Redraw in case there's new messages to show.
                 continue;
             }
@@ -361,10 +504,10 @@ impl Application {
                     // Do nothing for now.
                 },
                 Event::FocusGained => {
-                    self.focused = true;
+                    self.store.lock().await.application.terminal_focused = true;
                 },
                 Event::FocusLost => {
-                    self.focused = false;
+                    self.store.lock().await.application.terminal_focused = false;
                 },
                 Event::Resize(_, _) => {
                     // We'll redraw for the new size next time step() is called.
@@ -523,6 +666,16 @@ impl Application {
 
                 None
             },
+            IambAction::ToggleRoomGrouping => {
+                let grouped = !store.application.room_list_grouped;
+                store.application.room_list_grouped = grouped;
+
+                if grouped {
+                    Some(InfoMessage::from("Grouping the room list by space"))
+                } else {
+                    Some(InfoMessage::from("No longer grouping the room list by space"))
+                }
+            },
 
             IambAction::Homeserver(act) => {
                 let acts = self.homeserver_command(act, ctx, store).await?;
@@ -531,6 +684,23 @@ impl Application {
                 None
             },
             IambAction::Keys(act) => self.keys_command(act, ctx, store).await?,
+            IambAction::Recovery(RecoveryAction::Setup) => {
+                let key = self.worker.recovery_setup()?;
+                let msg = format!(
+                    "Recovery is set up. Your recovery key is:\n\n    {key}\n\n\
+                     Keep it somewhere safe, like a password manager. It's needed to restore \
+                     your encryption keys on a new device, and iamb doesn't save a copy."
+                );
+
+                Some(InfoMessage::Pager(msg))
+            },
+            IambAction::Recovery(RecoveryAction::Restore(key)) => {
+                self.worker.recovery_restore(key)?;
+                let client = store.application.worker.client.clone();
+                tokio::spawn(restore_room_keys(client, self.store.clone()));
+
+                Some(InfoMessage::from("Recovered; fetching the backed up room keys"))
+            },
             IambAction::Message(act) => {
                 self.screen.current_window_mut()?.message_command(act, ctx, store).await?
             },
@@ -544,6 +714,69 @@ impl Application {
                 self.screen.current_window_mut()?.send_command(act, ctx, store).await?
             },
 
+            IambAction::Debug(act) => {
+                let log = &mut store.application.debug_events;
+
+                match act {
+                    DebugAction::Filter(filter) => {
+                        let msg = match &filter {
+                            Some(filter) => format!("Only showing events matching {filter:?}"),
+                            None => "Showing all events".into(),
+                        };
+                        log.filter = filter;
+
+                        Some(InfoMessage::from(msg))
+                    },
+                    DebugAction::TogglePause => {
+                        log.paused = !log.paused;
+
+                        if log.paused {
+                            Some(InfoMessage::from("Paused recording events"))
+                        } else {
+                            Some(InfoMessage::from("Resumed recording events"))
+                        }
+                    },
+                }
+            },
+
+            IambAction::CheckConfig => {
+                let problems = store.application.settings.validate();
+
+                if problems.is_empty() {
+                    Some(InfoMessage::from("No problems found in the configuration"))
+                } else {
+                    let problems = problems.iter().map(|p| format!("* {p}")).collect::<Vec<_>>();
+                    Some(InfoMessage::Pager(problems.join("\n")))
+                }
+            },
+
+            IambAction::SetPresence(presence, status_msg) => {
+                self.worker.set_presence(presence, status_msg)?
+            },
+
+            IambAction::SetIgnored(user_id, ignore) => {
+                let info = self.worker.set_ignored(user_id.clone(), ignore)?;
+                let ignored = &mut store.application.ignored_users;
+
+                if ignore {
+                    ignored.insert(user_id);
+                } else {
+                    ignored.remove(&user_id);
+                }
+
+                info
+            },
+
+            IambAction::CleanCache => {
+                let settings = &store.application.settings;
+                let dir = &settings.dirs.image_previews;
+                let limit = settings.tunables.image_preview_cache_size;
+                let freed = preview::clean_cache(dir, limit).map_err(IambError::from)?;
+                let msg = format!("Freed {} from the image preview cache", format_size(freed));
+
+                Some(InfoMessage::from(msg))
+            },
+
             IambAction::OpenLink(url) => {
                 tokio::task::spawn_blocking(move || {
                     return open::that(url);
@@ -566,6 +799,24 @@ impl Application {
                     return Err(IambError::InvalidUserId(user_id).into());
                 }
             },
+            IambAction::VerifyAcknowledge(user_id) => {
+                let Ok(user_id) = OwnedUserId::try_from(user_id.as_str()) else {
+                    return Err(IambError::InvalidUserId(user_id).into());
+                };
+                let Some(key) = store.application.identity_alerts.remove(&user_id) else {
+                    return Err(IambError::NoIdentityAlert(user_id).into());
+                };
+
+                let settings = &store.application.settings;
+                settings.update_known_identities(|known| known.insert(user_id.clone(), key));
+
+                let msg = format!("Accepted the new identity for {user_id}");
+                Some(InfoMessage::from(msg))
+            },
+            IambAction::VerifyDismiss => {
+                store.application.session_warning = false;
+                None
+            },
         };
 
         Ok(info)
@@ -580,28 +831,92 @@ impl Application {
         match action {
             HomeserverAction::CreateRoom(alias, vis, flags) => {
                 let client = &store.application.worker.client;
-                let room_id = create_room(client, alias, vis, flags).await?;
+                let rotation = &store.application.settings.tunables.room_key_rotation;
+                let room_id = create_room(client, alias, None, vis, flags, rotation).await?;
                 let room = IambId::Room(room_id, None);
                 let target = OpenTarget::Application(room);
                 let action = WindowAction::Switch(target);
 
                 Ok(vec![(action.into(), ctx)])
             },
-            HomeserverAction::Logout(user, true) => {
-                self.worker.logout(user)?;
+            HomeserverAction::CreateSpace(name, children) => {
+                let client = &store.application.worker.client;
+                let rotation = &store.application.settings.tunables.room_key_rotation;
+                let msg = match children.len() {
+                    0 => format!("Created space {name}"),
+                    1 => format!("Created space {name} with 1 room"),
+                    n => format!("Created space {name} with {n} rooms"),
+                };
+                let room_id = create_space(client, name, children, rotation).await?;
+                let room = IambId::Room(room_id, None);
+                let target = OpenTarget::Application(room);
+                let action = WindowAction::Switch(target);
+                let info = Action::ShowInfoMessage(msg.into());
+
+                Ok(vec![(action.into(), ctx.clone()), (info, ctx)])
+            },
+            HomeserverAction::Logout(user, erase, true) => {
+                self.worker.logout(user, erase)?;
                 let flags = CloseFlags::QUIT | CloseFlags::FORCE;
                 let act = TabAction::Close(TabTarget::All, flags);
 
                 Ok(vec![(act.into(), ctx)])
             },
-            HomeserverAction::Logout(user, false) => {
-                let msg = "Would you like to logout?";
-                let act = IambAction::from(HomeserverAction::Logout(user, true));
+            HomeserverAction::Logout(user, erase, false) => {
+                let msg = if erase {
+                    "Would you like to logout, and erase the local store and encryption keys?"
+                } else {
+                    "Would you like to logout?"
+                };
+                let act = IambAction::from(HomeserverAction::Logout(user, erase, true));
                 let prompt = PromptYesNo::new(msg, vec![Action::from(act)]);
                 let prompt = Box::new(prompt);
 
                 Err(UIError::NeedConfirm(prompt))
             },
+            HomeserverAction::ClearCache(true) => {
+                self.worker.clear_cache()?;
+                let flags = CloseFlags::QUIT | CloseFlags::FORCE;
+                let act = TabAction::Close(TabTarget::All, flags);
+
+                Ok(vec![(act.into(), ctx)])
+            },
+            HomeserverAction::ClearCache(false) => {
+                let msg = "Clearing the cache will quit iamb, and sync everything again on the \
+                           next start. Continue?";
+                let act = IambAction::from(HomeserverAction::ClearCache(true));
+                let prompt = PromptYesNo::new(msg, vec![Action::from(act)]);
+                let prompt = Box::new(prompt);
+
+                Err(UIError::NeedConfirm(prompt))
+            },
+            HomeserverAction::LeaveRooms(rooms) => {
+                let results = self.worker.leave_rooms(rooms);
+                let mut left = 0;
+                let mut report = vec![];
+
+                for (room_id, res) in results.iter() {
+                    let name = self
+                        .worker
+                        .client
+                        .get_room(room_id)
+                        .and_then(|room| room.name())
+                        .unwrap_or_else(|| room_id.to_string());
+
+                    match res {
+                        Ok(()) => {
+                            left += 1;
+                            report.push(format!("✓ {name}"));
+                        },
+                        Err(e) => report.push(format!("✗ {name}: {e}")),
+                    }
+                }
+
+                let summary = format!("Left {left} of {} rooms:\n", results.len());
+                let info = InfoMessage::Pager(summary + &report.join("\n"));
+
+                Ok(vec![(Action::ShowInfoMessage(info), ctx)])
+            },
         }
     }
 
@@ -690,6 +1005,53 @@ impl Application {
             }
         }
 
+        {
+            let mut locked = self.store.lock().await;
+            let drafts = unsent_drafts(locked.deref_mut());
+            let settings = &locked.application.settings;
+
+            if settings.tunables.command_history_size > 0 {
+                let cmds = locked.commands.iter().map(ToString::to_string);
+
+                if let Err(e) = settings.write_command_history(cmds) {
+                    tracing::error!("Failed to save command history while exiting: {}", e);
+                }
+            }
+
+            if settings.tunables.sent_history_size > 0 {
+                let history = locked.application.sent_history.entries();
+
+                if let Err(e) = settings.write_sent_history(history) {
+                    tracing::error!("Failed to save sent message history while exiting: {}", e);
+                }
+            }
+
+            if let Err(e) = settings.write_recent_reactions(&locked.application.recent_reactions) {
+                tracing::error!("Failed to save recent reactions while exiting: {}", e);
+            }
+
+            let positions = &locked.application.scroll_positions;
+
+            if let Err(e) = settings.write_scroll_positions(positions) {
+                tracing::error!("Failed to save scrollback positions while exiting: {}", e);
+            }
+
+            let dismissed = &locked.application.version_warnings_dismissed;
+
+            if let Err(e) = settings.write_version_warnings(dismissed) {
+                tracing::error!(
+                    "Failed to save dismissed room version warnings while exiting: {}",
+                    e
+                );
+            }
+
+            if settings.tunables.save_drafts {
+                if let Err(e) = settings.write_drafts(&drafts) {
+                    tracing::error!("Failed to save unsent messages while exiting: {}", e);
+                }
+            }
+        }
+
         if let Some(ref layout) = self.last_layout {
             let locked = self.store.lock().await;
             let path = locked.application.settings.layout_json.as_path();
@@ -711,6 +1073,26 @@ impl Application {
     }
 }
 
+/// Gather the text left in each joined room's message bar, skipping any that are empty.
+fn unsent_drafts(store: &mut ProgramStore) -> HashMap<OwnedRoomId, String> {
+    let mut drafts = HashMap::new();
+
+    for room in store.application.worker.client.joined_rooms() {
+        let room_id = room.room_id().to_owned();
+        let id = IambBufferId::Room(room_id.clone(), None, RoomFocus::MessageBar);
+
+        if let Entry::Occupied(buffer) = store.buffers.entry(id) {
+            let buffer = buffer.get().read().unwrap();
+
+            if !buffer.is_blank() {
+                drafts.insert(room_id, buffer.get_text());
+            }
+        }
+    }
+
+    drafts
+}
+
 fn gen_passphrase() -> String {
     rand::thread_rng()
         .sample_iter(&Alphanumeric)
@@ -909,6 +1291,9 @@ async fn run(settings: ApplicationSettings) -> IambResult<()> {
     // Get old keys the first time we run w/ the upgraded SDK.
     let import_keys = check_import_keys(&settings).await?;
 
+    // Finish clearing the store if it couldn't be done when we last exited.
+    worker::remove_marked_stores(&settings)?;
+
     // Set up client state.
     create_dir_all(settings.sqlite_dir.as_path())?;
     let client = worker::create_client(&settings).await;
@@ -967,6 +1352,21 @@ fn main() -> IambResult<()> {
     // Parse command-line flags.
     let iamb = Iamb::parse();
 
+    if iamb.check_config {
+        let problems = ApplicationSettings::check(iamb);
+
+        if problems.is_empty() {
+            println!("No problems found in the configuration");
+            process::exit(0);
+        }
+
+        for problem in problems {
+            eprintln!("* {problem}");
+        }
+
+        process::exit(1);
+    }
+
     // Load configuration and set up the Matrix SDK.
     let settings = ApplicationSettings::load(iamb).unwrap_or_else(print_exit);
 
@@ -1000,8 +1400,17 @@ fn main() -> IambResult<()> {
         .build()
         .unwrap();
 
+    let store_settings = settings.clone();
     rt.block_on(async move { run(settings).await })?;
 
+    // Shutting down the runtime closes the store, so that anything marked by :clearcache or
+    // :logout! can now be removed.
+    drop(rt);
+
+    if let Err(e) = worker::remove_marked_stores(&store_settings) {
+        eprintln!("Failed to remove the local store, trying again on the next start: {e}");
+    }
+
     drop(guard);
     process::exit(0);
 }
@@ -0,0 +1,160 @@
+//! # Rendering for custom event types
+//!
+//! Bots and other clients sometimes send message-like events with types that iamb doesn't
+//! understand, which would normally never show up in the scrollback. Users can list the ones they
+//! care about in the `custom_events` setting, mapping each event type to a template like
+//! `"{question}"`, where each `{field}` gets filled in from the event's content. Dotted names
+//! like `{answers.0.text}` reach into nested objects and arrays.
+//!
+//! An empty template falls back to printing the content as a list of `key: value` pairs.
+use matrix_sdk::ruma::{MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedUserId};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+/// Event types that already have dedicated handling, and therefore can't be mapped to a template.
+const NATIVE_EVENT_TYPES: [&str; 4] = [
+    "m.reaction",
+    "m.room.encrypted",
+    "m.room.message",
+    "m.room.redaction",
+];
+
+/// A message-like event of a type that iamb doesn't natively understand.
+#[derive(Clone, Debug, Deserialize)]
+pub struct CustomEvent {
+    pub event_id: OwnedEventId,
+    pub sender: OwnedUserId,
+    pub origin_server_ts: MilliSecondsSinceUnixEpoch,
+
+    #[serde(rename = "type")]
+    pub event_type: String,
+
+    #[serde(default)]
+    pub content: Map<String, Value>,
+
+    /// The text to show in the scrollback, produced by [CustomEvent::render].
+    #[serde(skip)]
+    pub body: String,
+}
+
+impl CustomEvent {
+    /// Fill in [CustomEvent::body] using the given template.
+    pub fn render(mut self, template: &str) -> Self {
+        self.body = if template.is_empty() {
+            render_fields(&self.event_type, &self.content)
+        } else {
+            render_template(template, &self.content)
+        };
+
+        self
+    }
+
+    /// Drop the event's content after it has been redacted.
+    pub fn redact(&mut self) {
+        self.content.clear();
+        self.body = "[Redacted]".into();
+    }
+}
+
+/// Whether an event type is one that iamb already knows how to display.
+pub fn is_native(event_type: &str) -> bool {
+    NATIVE_EVENT_TYPES.contains(&event_type)
+}
+
+fn value_text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+fn lookup<'a>(content: &'a Map<String, Value>, path: &str) -> Option<&'a Value> {
+    let mut parts = path.split('.');
+    let mut value = content.get(parts.next()?)?;
+
+    for part in parts {
+        value = match value {
+            Value::Object(map) => map.get(part)?,
+            Value::Array(items) => items.get(part.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+
+    Some(value)
+}
+
+fn render_fields(event_type: &str, content: &Map<String, Value>) -> String {
+    let fields = content
+        .iter()
+        .map(|(k, v)| format!("{k}: {}", value_text(v)))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    if fields.is_empty() {
+        format!("[{event_type}]")
+    } else {
+        format!("[{event_type}] {fields}")
+    }
+}
+
+fn render_template(template: &str, content: &Map<String, Value>) -> String {
+    let mut out = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+
+        out.push_str(&rest[..start]);
+
+        let field = &rest[start + 1..start + len];
+        let value = lookup(content, field).map(value_text).unwrap_or_default();
+        out.push_str(&value);
+
+        rest = &rest[start + len + 1..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn content() -> Map<String, Value> {
+        let value = json!({
+            "question": "Lunch?",
+            "answers": [{"text": "Yes"}, {"text": "No"}],
+            "count": 2,
+        });
+
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn test_render_template() {
+        let content = content();
+
+        assert_eq!(render_template("{question}", &content), "Lunch?");
+        assert_eq!(
+            render_template("Poll: {question} ({answers.0.text}/{answers.1.text})", &content),
+            "Poll: Lunch? (Yes/No)"
+        );
+        assert_eq!(render_template("{count} votes", &content), "2 votes");
+        assert_eq!(render_template("{missing}!", &content), "!");
+        assert_eq!(render_template("open {question", &content), "open {question");
+    }
+
+    #[test]
+    fn test_render_fields() {
+        assert_eq!(
+            render_fields("org.example.poll", &content()),
+            "[org.example.poll] answers: [{\"text\":\"Yes\"},{\"text\":\"No\"}], count: 2, question: Lunch?"
+        );
+        assert_eq!(render_fields("org.example.ping", &Map::new()), "[org.example.ping]");
+    }
+}
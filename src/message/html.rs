@@ -268,6 +268,7 @@ pub enum StyleTreeNode {
     Pre(Box<StyleTreeNode>),
     Reply(Box<StyleTreeNode>),
     Ruler,
+    Spoiler(Box<StyleTreeNode>, Option<String>),
     Style(Box<StyleTreeNode>, Style),
     Table(Table),
     Text(String),
@@ -293,6 +294,7 @@ impl StyleTreeNode {
             StyleTreeNode::Paragraph(child) |
             StyleTreeNode::Pre(child) |
             StyleTreeNode::Reply(child) |
+            StyleTreeNode::Spoiler(child, _) |
             StyleTreeNode::Style(child, _) => {
                 child.gather_links(urls);
             },
@@ -439,6 +441,19 @@ impl StyleTreeNode {
                 printer.push_str(s.as_str(), style);
             },
 
+            StyleTreeNode::Spoiler(child, reason) => {
+                if printer.spoilers_revealed() {
+                    child.print(printer, style);
+                    return;
+                }
+
+                let label = match reason {
+                    Some(reason) => format!("[Spoiler: {reason}]"),
+                    None => "[Spoiler]".into(),
+                };
+                let hidden = style.add_modifier(StyleModifier::REVERSED);
+                printer.push_span_nobreak(Span::styled(label, hidden));
+            },
             StyleTreeNode::Style(child, patch) => child.print(printer, style.patch(*patch)),
             StyleTreeNode::Sequence(children) => {
                 for child in children {
@@ -472,8 +487,13 @@ impl StyleTree {
         hide_reply: bool,
         emoji_shortcodes: bool,
     ) -> Text<'_> {
-        let mut printer = TextPrinter::new(width, style, hide_reply, emoji_shortcodes);
+        let printer = TextPrinter::new(width, style, hide_reply, emoji_shortcodes);
 
+        self.print_with(printer, style)
+    }
+
+    /// Render the document using an already configured printer.
+    pub fn print_with<'a>(&'a self, mut printer: TextPrinter<'a>, style: Style) -> Text<'a> {
         for child in self.children.iter() {
             child.print(&mut printer, style);
         }
@@ -603,6 +623,14 @@ fn attrs_to_alt(attrs: &[Attribute]) -> Option<String> {
     return None;
 }
 
+/// Check whether a span is a spoiler, returning the reason given for hiding it, if any.
+fn attrs_to_spoiler(attrs: &[Attribute]) -> Option<Option<String>> {
+    let attr = attrs.iter().find(|attr| attr.name.local.as_ref() == "data-mx-spoiler")?;
+    let reason = attr.value.trim();
+
+    Some((!reason.is_empty()).then(|| reason.to_string()))
+}
+
 fn attrs_to_href(attrs: &[Attribute]) -> Option<String> {
     for attr in attrs {
         if attr.name.local.as_ref() != "href" {
@@ -703,9 +731,13 @@ fn h2t(hdl: &Handle, state: &mut TreeGenState) -> StyleTreeChildren {
                 },
                 "span" => {
                     let c = c2t(&node.children.borrow(), state);
-                    let s = attrs_to_style(&attrs.borrow());
+                    let attrs = attrs.borrow();
 
-                    StyleTreeNode::Style(c, s)
+                    if let Some(reason) = attrs_to_spoiler(&attrs) {
+                        StyleTreeNode::Spoiler(c, reason)
+                    } else {
+                        StyleTreeNode::Style(c, attrs_to_style(&attrs))
+                    }
                 },
                 "del" | "strike" => {
                     let c = c2t(&node.children.borrow(), state);
@@ -1024,6 +1056,31 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn test_spoiler() {
+        let line = |text: &Text| {
+            text.lines[0]
+                .spans
+                .iter()
+                .map(|span| span.content.as_ref())
+                .collect::<String>()
+        };
+
+        let s = "Hi <span data-mx-spoiler>secret</span>";
+        let tree = parse_matrix_html(s);
+        let text = tree.to_text(25, Style::default(), false, false);
+        assert_eq!(line(&text).trim_end(), "Hi [Spoiler]");
+
+        let printer = TextPrinter::new(25, Style::default(), false, false).reveal_spoilers(true);
+        let text = tree.print_with(printer, Style::default());
+        assert_eq!(line(&text).trim_end(), "Hi secret");
+
+        let s = "<span data-mx-spoiler=\"the end\">secret</span>";
+        let tree = parse_matrix_html(s);
+        let text = tree.to_text(25, Style::default(), false, false);
+        assert_eq!(line(&text).trim_end(), "[Spoiler: the end]");
+    }
+
     #[test]
     fn test_blockquote() {
         let s = "<blockquote>Hello world!</blockquote>";
@@ -0,0 +1,245 @@
+//! # Summaries of membership changes
+//!
+//! When `membership_display` is enabled, joins, leaves and other `m.room.member` changes get shown
+//! in the scrollback. Runs of consecutive changes are kept together in a single
+//! [MembershipGroup], so that a busy room doesn't get buried under them; once a group reaches the
+//! `membership_summary_threshold` it's shown as a summary like "3 users joined, 1 left", and only
+//! lists each change when selected.
+use matrix_sdk::ruma::{
+    events::room::member::{MembershipChange, OriginalRoomMemberEvent},
+    MilliSecondsSinceUnixEpoch,
+    OwnedEventId,
+    OwnedUserId,
+};
+
+/// The kinds of membership change that get shown, with similar changes folded together.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MembershipKind {
+    Joined,
+    Left,
+    Invited,
+    Removed,
+    Banned,
+    Unbanned,
+    Knocked,
+    ProfileChanged,
+}
+
+impl MembershipKind {
+    fn from_change(change: MembershipChange<'_>) -> Option<Self> {
+        let kind = match change {
+            MembershipChange::Joined | MembershipChange::InvitationAccepted => Self::Joined,
+            MembershipChange::Left |
+            MembershipChange::InvitationRejected |
+            MembershipChange::KnockRetracted => Self::Left,
+            MembershipChange::Invited | MembershipChange::KnockAccepted => Self::Invited,
+            MembershipChange::Kicked |
+            MembershipChange::InvitationRevoked |
+            MembershipChange::KnockDenied => Self::Removed,
+            MembershipChange::Banned | MembershipChange::KickedAndBanned => Self::Banned,
+            MembershipChange::Unbanned => Self::Unbanned,
+            MembershipChange::Knocked => Self::Knocked,
+            MembershipChange::ProfileChanged { .. } => Self::ProfileChanged,
+            _ => return None,
+        };
+
+        Some(kind)
+    }
+
+    fn verb(&self, plural: bool) -> &'static str {
+        match (self, plural) {
+            (Self::Joined, _) => "joined",
+            (Self::Left, _) => "left",
+            (Self::Invited, false) => "was invited",
+            (Self::Invited, true) => "were invited",
+            (Self::Removed, false) => "was removed",
+            (Self::Removed, true) => "were removed",
+            (Self::Banned, false) => "was banned",
+            (Self::Banned, true) => "were banned",
+            (Self::Unbanned, false) => "was unbanned",
+            (Self::Unbanned, true) => "were unbanned",
+            (Self::Knocked, _) => "asked to join",
+            (Self::ProfileChanged, _) => "changed their profile",
+        }
+    }
+}
+
+/// A single membership change.
+#[derive(Clone, Debug)]
+pub struct MembershipEvent {
+    pub event_id: OwnedEventId,
+    pub sender: OwnedUserId,
+    pub origin_server_ts: MilliSecondsSinceUnixEpoch,
+
+    /// The name of the member whose membership changed, as of this event.
+    pub name: String,
+    pub kind: MembershipKind,
+}
+
+impl MembershipEvent {
+    /// Describe an `m.room.member` event, if it changes anything worth showing.
+    pub fn new(ev: &OriginalRoomMemberEvent) -> Option<Self> {
+        let kind = MembershipKind::from_change(ev.membership_change())?;
+        let prev = ev.unsigned.prev_content.as_ref();
+        let name = ev
+            .content
+            .displayname
+            .as_deref()
+            .or_else(|| prev.and_then(|p| p.displayname.as_deref()))
+            .unwrap_or(ev.state_key.as_str())
+            .to_string();
+
+        Some(MembershipEvent {
+            event_id: ev.event_id.clone(),
+            sender: ev.sender.clone(),
+            origin_server_ts: ev.origin_server_ts,
+            name,
+            kind,
+        })
+    }
+
+    pub fn key(&self) -> (MilliSecondsSinceUnixEpoch, &OwnedEventId) {
+        (self.origin_server_ts, &self.event_id)
+    }
+
+    fn describe(&self) -> String {
+        format!("{} {}", self.name, self.kind.verb(false))
+    }
+}
+
+/// A run of consecutive membership changes, in the order they happened.
+#[derive(Clone, Debug)]
+pub struct MembershipGroup {
+    events: Vec<MembershipEvent>,
+}
+
+impl MembershipGroup {
+    pub fn new(ev: MembershipEvent) -> Self {
+        MembershipGroup { events: vec![ev] }
+    }
+
+    pub fn events(&self) -> &[MembershipEvent] {
+        &self.events
+    }
+
+    /// The most recent change, which the group is stored under.
+    pub fn last(&self) -> &MembershipEvent {
+        self.events.last().expect("membership groups are never empty")
+    }
+
+    /// Add a change to the group, keeping it in order.
+    pub fn insert(&mut self, ev: MembershipEvent) {
+        if self.events.iter().any(|e| e.event_id == ev.event_id) {
+            return;
+        }
+
+        let idx = self.events.partition_point(|e| e.key() < ev.key());
+        self.events.insert(idx, ev);
+    }
+
+    /// Move all of the changes in another group into this one.
+    pub fn extend(&mut self, other: MembershipGroup) {
+        for ev in other.events {
+            self.insert(ev);
+        }
+    }
+
+    /// Count the changes of each kind, like "3 users joined, 1 left".
+    pub fn summary(&self) -> String {
+        let mut counts: Vec<(MembershipKind, usize)> = vec![];
+
+        for ev in self.events.iter() {
+            match counts.iter_mut().find(|(kind, _)| *kind == ev.kind) {
+                Some((_, n)) => *n += 1,
+                None => counts.push((ev.kind, 1)),
+            }
+        }
+
+        counts
+            .into_iter()
+            .enumerate()
+            .map(|(i, (kind, n))| {
+                let verb = kind.verb(n > 1);
+
+                match (i, n) {
+                    (0, 1) => format!("1 user {verb}"),
+                    (0, n) => format!("{n} users {verb}"),
+                    (_, n) => format!("{n} {verb}"),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// The text to use when the group is quoted or searched.
+    pub fn body(&self) -> String {
+        match self.events.as_slice() {
+            [ev] => ev.describe(),
+            _ => self.summary(),
+        }
+    }
+
+    /// The text to show in the scrollback.
+    ///
+    /// Groups smaller than `threshold` list each change, while larger ones are summarized unless
+    /// `expanded` is set.
+    pub fn show(&self, threshold: usize, expanded: bool) -> String {
+        let details = self.events.iter().map(MembershipEvent::describe);
+
+        if self.events.len() < threshold {
+            details.collect::<Vec<_>>().join("\n")
+        } else if expanded {
+            let mut lines = vec![self.summary() + ":"];
+            lines.extend(details.map(|line| format!("  {line}")));
+            lines.join("\n")
+        } else {
+            self.summary()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use matrix_sdk::ruma::{event_id, user_id, EventId, UInt};
+
+    fn mock_membership(id: &str, ts: u64, name: &str, kind: MembershipKind) -> MembershipEvent {
+        MembershipEvent {
+            event_id: EventId::parse(id).unwrap(),
+            sender: user_id!("@user1:example.com").to_owned(),
+            origin_server_ts: MilliSecondsSinceUnixEpoch(UInt::new(ts).unwrap()),
+            name: name.into(),
+            kind,
+        }
+    }
+
+    #[test]
+    fn test_membership_summary() {
+        let mut group =
+            MembershipGroup::new(mock_membership("$b:example.com", 2, "Bob", MembershipKind::Left));
+        assert_eq!(group.body(), "Bob left");
+        assert_eq!(group.show(3, false), "Bob left");
+
+        group.insert(mock_membership("$a:example.com", 1, "Alice", MembershipKind::Joined));
+        group.insert(mock_membership("$c:example.com", 3, "Carol", MembershipKind::Joined));
+        group.insert(mock_membership("$d:example.com", 4, "Dave", MembershipKind::Invited));
+
+        // Changes stay in order, even when they arrive out of order.
+        assert_eq!(group.last().event_id, event_id!("$d:example.com"));
+        assert_eq!(group.summary(), "2 users joined, 1 left, 1 was invited");
+        assert_eq!(group.body(), group.summary());
+
+        // Large groups only list each change when expanded.
+        assert_eq!(group.show(4, false), group.summary());
+        assert_eq!(
+            group.show(4, true),
+            "2 users joined, 1 left, 1 was invited:\n  \
+             Alice joined\n  Bob left\n  Carol joined\n  Dave was invited"
+        );
+        assert_eq!(group.show(5, false), "Alice joined\nBob left\nCarol joined\nDave was invited");
+
+        // Seeing the same event again doesn't count it twice.
+        group.insert(mock_membership("$d:example.com", 4, "Dave", MembershipKind::Invited));
+        assert_eq!(group.events().len(), 4);
+    }
+}
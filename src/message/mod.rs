@@ -2,16 +2,18 @@
 use std::borrow::Cow;
 use std::cmp::{Ord, Ordering, PartialOrd};
 use std::collections::hash_map::DefaultHasher;
-use std::collections::hash_set;
 use std::collections::BTreeMap;
+use std::collections::HashSet;
 use std::convert::TryFrom;
+use std::fmt::Write;
 use std::hash::{Hash, Hasher};
 use std::ops::{Deref, DerefMut};
 
-use chrono::{DateTime, Local as LocalTz, NaiveDateTime, TimeZone};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Utc};
 use comrak::{markdown_to_html, ComrakOptions};
 use serde_json::json;
 use unicode_width::UnicodeWidthStr;
+use url::Url;
 
 use matrix_sdk::ruma::{
     events::{
@@ -23,9 +25,12 @@ use matrix_sdk::ruma::{
                 RoomEncryptedEvent,
             },
             message::{
+                sanitize::remove_plain_reply_fallback,
+                EmoteMessageEventContent,
                 FormattedBody,
                 MessageFormat,
                 MessageType,
+                NoticeMessageEventContent,
                 OriginalRoomMessageEvent,
                 RedactedRoomMessageEvent,
                 Relation,
@@ -47,7 +52,7 @@ use matrix_sdk::ruma::{
 };
 
 use ratatui::{
-    style::{Modifier as StyleModifier, Style},
+    style::{Color, Modifier as StyleModifier, Style},
     symbols::line::THICK_VERTICAL,
     text::{Line, Span, Text},
 };
@@ -56,17 +61,25 @@ use modalkit::editing::cursor::Cursor;
 use modalkit::prelude::*;
 use ratatui_image::protocol::Protocol;
 
-use crate::config::ImagePreviewSize;
+use crate::config::{ImagePreviewSize, Timezone};
 use crate::{
     base::RoomInfo,
     config::ApplicationSettings,
     message::html::{parse_matrix_html, StyleTree},
-    util::{replace_emojis_in_str, space, space_span, take_width, wrapped_text},
+    util::{replace_emojis_in_str, space, space_span, take_width, wrap, wrapped_text},
 };
 
+mod custom;
 mod html;
+mod membership;
 mod printer;
 
+pub use self::custom::{is_native as is_native_event_type, CustomEvent};
+pub use self::membership::{MembershipEvent, MembershipGroup};
+
+#[cfg(test)]
+pub use self::membership::MembershipKind;
+
 pub type MessageKey = (MessageTimeStamp, OwnedEventId);
 
 #[derive(Default)]
@@ -122,27 +135,116 @@ const BOLD_STYLE: Style = Style {
 
 const TIME_GUTTER: usize = 12;
 const READ_GUTTER: usize = 5;
+const READ_GUTTER_USERS: usize = READ_GUTTER - 2;
 const MIN_MSG_LEN: usize = 30;
 
 const TIME_GUTTER_EMPTY: &str = "            ";
 const TIME_GUTTER_EMPTY_SPAN: Span<'static> = span_static(TIME_GUTTER_EMPTY);
 
+/// Pick which readers to show next to a message, in a stable order.
+///
+/// At most [READ_GUTTER_USERS] readers are returned, with the last slot going to a `+` when there
+/// are too many to show. Only a partial selection is done, so that rooms with hundreds of
+/// members don't require sorting every receipt on each draw.
+fn read_receipt_spans<'a>(
+    readers: &'a HashSet<OwnedUserId>,
+    settings: &ApplicationSettings,
+) -> Vec<Span<'a>> {
+    let mut users = readers.iter().collect::<Vec<_>>();
+    let overflow = users.len() > READ_GUTTER_USERS;
+
+    if overflow {
+        let n = READ_GUTTER_USERS - 1;
+        users.select_nth_unstable(n);
+        users.truncate(n);
+    }
+
+    users.sort_unstable();
+
+    let mut spans = users
+        .into_iter()
+        .map(|user| settings.get_user_char_span(user))
+        .collect::<Vec<_>>();
+
+    if overflow {
+        spans.push(Span::styled("+", BOLD_STYLE));
+    }
+
+    spans
+}
+
+/// Render a timestamp format for a date with long day and month names, so that every timestamp
+/// rendered with the format fits in the result's width. Formats that chrono can't render for
+/// the offset-aware times shown in the scrollback give [None].
+fn sample_timestamp(format: &str) -> Option<String> {
+    let sample = NaiveDate::from_ymd_opt(2000, 9, 27)
+        .and_then(|d| d.and_hms_opt(23, 59, 59))
+        .expect("valid sample date");
+    let sample = FixedOffset::east_opt(0).expect("valid offset").from_utc_datetime(&sample);
+
+    let mut time = String::new();
+    write!(time, "{}", sample.format(format)).ok()?;
+
+    Some(time)
+}
+
+/// Check that a strftime-style format only uses specifiers that chrono understands, since
+/// formatting with an unknown one fails at render time.
+pub fn valid_timestamp_format(format: &str) -> bool {
+    sample_timestamp(format).is_some()
+}
+
+/// How wide the time column needs to be for a room's timestamp format.
+///
+/// An empty format hides the column entirely.
+fn time_gutter_width(format: Option<&str>) -> usize {
+    match format {
+        None => TIME_GUTTER,
+        Some("") => 0,
+        Some(format) => {
+            let time = sample_timestamp(format).unwrap_or_default();
+
+            UnicodeWidthStr::width(time.as_str()) + 4
+        },
+    }
+}
+
 fn text_to_message_content(input: String) -> TextMessageEventContent {
     let mut options = ComrakOptions::default();
     options.extension.autolink = true;
     options.extension.shortcodes = true;
     options.extension.strikethrough = true;
+    options.extension.table = true;
+    options.extension.tasklist = true;
     options.render.hardbreaks = true;
-    let html = markdown_to_html(input.as_str(), &options);
+
+    // Checkboxes aren't in the HTML subset that Matrix clients accept, so use characters that
+    // will survive sanitization instead.
+    let html = markdown_to_html(input.as_str(), &options)
+        .replace("<input type=\"checkbox\" checked=\"\" disabled=\"\" />", "\u{2611}")
+        .replace("<input type=\"checkbox\" disabled=\"\" />", "\u{2610}");
 
     TextMessageEventContent::html(input, html)
 }
 
-pub fn text_to_message(input: String) -> RoomMessageEventContent {
-    let msg = MessageType::Text(text_to_message_content(input));
+/// Build the content for a text message, rendering it as Markdown unless it should be sent as
+/// written.
+pub fn text_to_message(input: String, markdown: bool) -> RoomMessageEventContent {
+    let content = if markdown {
+        text_to_message_content(input)
+    } else {
+        TextMessageEventContent::plain(input)
+    };
+    let msg = MessageType::Text(content);
     RoomMessageEventContent::new(msg)
 }
 
+fn is_blank_text(text: &Text) -> bool {
+    text.lines
+        .iter()
+        .all(|line| line.spans.iter().all(|span| span.content.trim().is_empty()))
+}
+
 /// Before the image is loaded, already display a placeholder frame of the image size.
 fn placeholder_frame(
     text: Option<&str>,
@@ -173,11 +275,11 @@ fn placeholder_frame(
 }
 
 #[inline]
-fn millis_to_datetime(ms: UInt) -> DateTime<LocalTz> {
+pub fn millis_to_datetime(ms: UInt, tz: &Timezone) -> DateTime<FixedOffset> {
     let time = i64::from(ms) / 1000;
     let time = NaiveDateTime::from_timestamp_opt(time, 0).unwrap_or_default();
 
-    LocalTz.from_utc_datetime(&time)
+    tz.convert_utc(&time)
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -196,35 +298,37 @@ pub enum MessageTimeStamp {
 }
 
 impl MessageTimeStamp {
-    fn as_datetime(&self) -> DateTime<LocalTz> {
+    fn as_datetime(&self, tz: &Timezone) -> DateTime<FixedOffset> {
         match self {
-            MessageTimeStamp::OriginServer(ms) => millis_to_datetime(*ms),
-            MessageTimeStamp::LocalEcho => LocalTz::now(),
+            MessageTimeStamp::OriginServer(ms) => millis_to_datetime(*ms, tz),
+            MessageTimeStamp::LocalEcho => tz.convert_utc(&Utc::now().naive_utc()),
         }
     }
 
-    fn same_day(&self, other: &Self) -> bool {
-        let dt1 = self.as_datetime();
-        let dt2 = other.as_datetime();
+    fn same_day(&self, other: &Self, tz: &Timezone) -> bool {
+        let dt1 = self.as_datetime(tz);
+        let dt2 = other.as_datetime(tz);
 
         dt1.date_naive() == dt2.date_naive()
     }
 
-    fn show_date(&self) -> Option<Span> {
-        let time = self.as_datetime().format("%A, %B %d %Y").to_string();
+    fn show_date(&self, tz: &Timezone) -> Option<Span> {
+        let time = self.as_datetime(tz).format("%A, %B %d %Y").to_string();
 
         Span::styled(time, BOLD_STYLE).into()
     }
 
-    fn show_time(&self) -> Option<Span> {
-        match self {
-            MessageTimeStamp::OriginServer(ms) => {
-                let time = millis_to_datetime(*ms).format("%T");
-                let time = format!("  [{time}]");
+    fn show_time(&self, tz: &Timezone, format: Option<&str>, gutter: usize) -> Option<Span> {
+        match (self, format) {
+            (MessageTimeStamp::OriginServer(_), Some("")) => None,
+            (MessageTimeStamp::OriginServer(ms), format) => {
+                let time = millis_to_datetime(*ms, tz).format(format.unwrap_or("%T"));
+                let time = format!("[{time}]");
+                let time = format!("{time:>gutter$}");
 
                 Span::raw(time).into()
             },
-            MessageTimeStamp::LocalEcho => None,
+            (MessageTimeStamp::LocalEcho, _) => None,
         }
     }
 
@@ -428,6 +532,8 @@ pub enum MessageEvent {
     Original(Box<OriginalRoomMessageEvent>),
     Redacted(Box<RedactedRoomMessageEvent>),
     Local(OwnedEventId, Box<RoomMessageEventContent>),
+    Custom(Box<CustomEvent>),
+    Membership(Box<MembershipGroup>),
 }
 
 impl MessageEvent {
@@ -438,6 +544,8 @@ impl MessageEvent {
             MessageEvent::Original(ev) => ev.event_id.as_ref(),
             MessageEvent::Redacted(ev) => ev.event_id.as_ref(),
             MessageEvent::Local(event_id, _) => event_id.as_ref(),
+            MessageEvent::Custom(ev) => ev.event_id.as_ref(),
+            MessageEvent::Membership(group) => group.last().event_id.as_ref(),
         }
     }
 
@@ -448,9 +556,15 @@ impl MessageEvent {
             MessageEvent::EncryptedRedacted(_) => None,
             MessageEvent::Redacted(_) => None,
             MessageEvent::Local(_, content) => Some(content),
+            MessageEvent::Custom(_) => None,
+            MessageEvent::Membership(_) => None,
         }
     }
 
+    pub fn is_redacted(&self) -> bool {
+        matches!(self, MessageEvent::Redacted(_) | MessageEvent::EncryptedRedacted(_))
+    }
+
     pub fn is_emote(&self) -> bool {
         matches!(
             self.content(),
@@ -465,6 +579,8 @@ impl MessageEvent {
             MessageEvent::EncryptedRedacted(ev) => body_cow_reason(&ev.unsigned),
             MessageEvent::Redacted(ev) => body_cow_reason(&ev.unsigned),
             MessageEvent::Local(_, content) => body_cow_content(content),
+            MessageEvent::Custom(ev) => Cow::Borrowed(ev.body.as_str()),
+            MessageEvent::Membership(group) => Cow::Owned(group.body()),
         }
     }
 
@@ -475,11 +591,15 @@ impl MessageEvent {
             MessageEvent::Original(ev) => &ev.content,
             MessageEvent::Redacted(_) => return None,
             MessageEvent::Local(_, content) => content,
+            MessageEvent::Custom(_) => return None,
+            MessageEvent::Membership(_) => return None,
         };
 
         if let MessageType::Text(content) = &content.msgtype {
             if let Some(FormattedBody { format: MessageFormat::Html, body }) = &content.formatted {
                 Some(parse_matrix_html(body.as_str()))
+            } else if single_url(&content.body).is_none() {
+                linkify(&content.body).map(|html| parse_matrix_html(&html))
             } else {
                 None
             }
@@ -494,6 +614,8 @@ impl MessageEvent {
             MessageEvent::EncryptedRedacted(_) => return,
             MessageEvent::Redacted(_) => return,
             MessageEvent::Local(_, _) => return,
+            MessageEvent::Membership(_) => return,
+            MessageEvent::Custom(ev) => ev.redact(),
             MessageEvent::Original(ev) => {
                 let redacted = RedactedRoomMessageEvent {
                     content: ev.content.clone().redact(version),
@@ -589,11 +711,14 @@ struct MessageFormatter<'a> {
     /// The time the message was sent.
     time: Option<Span<'a>>,
 
+    /// The width of the time column.
+    time_gutter: usize,
+
     /// The date the message was sent.
     date: Option<Span<'a>>,
 
-    /// Iterator over the users who have read up to this message.
-    read: Option<hash_set::Iter<'a, OwnedUserId>>,
+    /// The users who have read up to this message, shown on its first line.
+    read: Option<&'a HashSet<OwnedUserId>>,
 }
 
 impl<'a> MessageFormatter<'a> {
@@ -619,25 +744,26 @@ impl<'a> MessageFormatter<'a> {
             MessageColumns::Four => {
                 let settings = self.settings;
                 let user = self.user.take().unwrap_or(user_gutter_empty_span);
-                let time = self.time.take().unwrap_or(TIME_GUTTER_EMPTY_SPAN);
+                let time = match self.time.take() {
+                    Some(time) => time,
+                    None if self.time_gutter == TIME_GUTTER => TIME_GUTTER_EMPTY_SPAN,
+                    None => space_span(self.time_gutter, Style::default()),
+                };
 
                 let mut line = vec![user];
                 line.extend(prev_line.spans);
                 line.push(time);
 
                 // Show read receipts.
-                let user_char =
-                    |user: &'a OwnedUserId| -> Span<'a> { settings.get_user_char_span(user) };
-                let mut read = self.read.iter_mut().flatten();
-
-                let a = read.next().map(user_char).unwrap_or_else(|| Span::raw(" "));
-                let b = read.next().map(user_char).unwrap_or_else(|| Span::raw(" "));
-                let c = read.next().map(user_char).unwrap_or_else(|| Span::raw(" "));
-
-                line.push(Span::raw(" "));
-                line.push(c);
-                line.push(b);
-                line.push(a);
+                let read = self
+                    .read
+                    .take()
+                    .map(|read| read_receipt_spans(read, settings))
+                    .unwrap_or_default();
+                let padding = READ_GUTTER_USERS - read.len();
+
+                line.push(space_span(padding + 1, Style::default()));
+                line.extend(read);
                 line.push(Span::raw(" "));
 
                 text.lines.push(Line::from(line))
@@ -689,17 +815,27 @@ impl<'a> MessageFormatter<'a> {
         let width = self.width();
         let w = width.saturating_sub(2);
         let shortcodes = self.settings.tunables.message_shortcode_display;
-        let (mut replied, _) = msg.show_msg(w, style, true, shortcodes);
+
+        // The quote is attributed using the original author's color, rather than the color of
+        // whoever sent the reply.
         let mut sender = msg.sender_span(info, self.settings);
+        let author = sender.style.fg.map(|color| style.fg(color)).unwrap_or(style);
+        let quoted = if self.settings.tunables.message_user_color {
+            author
+        } else {
+            style
+        };
+
+        let (mut replied, _) = msg.show_msg(w, quoted, true, shortcodes, false);
         let sender_width = UnicodeWidthStr::width(sender.content.as_ref());
         let trailing = w.saturating_sub(sender_width + 1);
 
-        sender.style = sender.style.patch(style);
+        sender.style = style.patch(sender.style);
 
         self.push_spans(
             Line::from(vec![
                 Span::styled(" ", style),
-                Span::styled(THICK_VERTICAL, style),
+                Span::styled(THICK_VERTICAL, author),
                 sender,
                 Span::styled(":", style),
                 space_span(trailing, style),
@@ -709,7 +845,7 @@ impl<'a> MessageFormatter<'a> {
         );
 
         for line in replied.lines.iter_mut() {
-            line.spans.insert(0, Span::styled(THICK_VERTICAL, style));
+            line.spans.insert(0, Span::styled(THICK_VERTICAL, author));
             line.spans.insert(0, Span::styled(" ", style));
         }
 
@@ -757,6 +893,33 @@ impl<'a> MessageFormatter<'a> {
         }
     }
 
+    fn push_pinned(&mut self, text: &mut Text<'a>) {
+        let style = Style::default();
+        let mut pinned = printer::TextPrinter::new(self.width(), style, false, false).literal(true);
+        pinned.push_str(" \u{1F4CC} ", style);
+        pinned.push_str("Pinned", style.add_modifier(StyleModifier::ITALIC));
+
+        self.push_text(pinned.finish(), style, text);
+    }
+
+    fn push_forwarded(&mut self, text: &mut Text<'a>) {
+        let style = Style::default().add_modifier(StyleModifier::DIM);
+        let mut fwd = printer::TextPrinter::new(self.width(), style, false, false).literal(true);
+        fwd.push_str(" \u{21AA} ", style);
+        fwd.push_str("Forwarded", style.add_modifier(StyleModifier::ITALIC));
+
+        self.push_text(fwd.finish(), style, text);
+    }
+
+    fn push_send_failed(&mut self, text: &mut Text<'a>) {
+        let style = Style::default().fg(Color::Red);
+        let mut failed = printer::TextPrinter::new(self.width(), style, false, false).literal(true);
+        failed.push_str(" \u{2717} ", style);
+        failed.push_str("Failed to send, use :resend to try again", style);
+
+        self.push_text(failed.finish(), style, text);
+    }
+
     fn push_thread_reply_count(&mut self, len: usize, text: &mut Text<'a>) {
         if len == 0 {
             return;
@@ -787,6 +950,119 @@ pub enum ImageStatus {
     Error(String),
 }
 
+/// What the homeserver's URL preview endpoint told us about a linked page.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct UrlPreview {
+    pub title: Option<String>,
+    pub description: Option<String>,
+}
+
+/// Get the link out of a message body that consists of nothing but a single web URL.
+pub fn single_url(body: &str) -> Option<Url> {
+    let body = body.trim();
+
+    if body.is_empty() || body.contains(char::is_whitespace) {
+        return None;
+    }
+
+    let url = Url::parse(body).ok()?;
+
+    matches!(url.scheme(), "http" | "https").then_some(url)
+}
+
+/// Find a web or Matrix content link within a word of a plain-text message, returning the byte
+/// range that it spans, without any punctuation surrounding it.
+fn word_url(word: &str) -> Option<(usize, usize, Url)> {
+    let punct =
+        |c: char| matches!(c, '(' | ')' | '<' | '>' | '"' | '\'' | ',' | '.' | ';' | '!' | '?');
+    let trimmed = word.trim_start_matches(punct);
+    let start = word.len() - trimmed.len();
+    let end = start + trimmed.trim_end_matches(punct).len();
+
+    let url = Url::parse(&word[start..end]).ok()?;
+
+    matches!(url.scheme(), "http" | "https" | "mxc").then_some((start, end, url))
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Turn a plain-text message body into HTML where the URLs in it are links, so that they get
+/// labeled and can be opened like those in formatted messages. Bodies without any URLs are left
+/// alone.
+fn linkify(body: &str) -> Option<String> {
+    let mut html = String::new();
+    let mut found = false;
+
+    for (i, line) in body.split('\n').enumerate() {
+        if i > 0 {
+            html.push_str("<br>");
+        }
+
+        for (j, word) in line.split(' ').enumerate() {
+            if j > 0 {
+                html.push(' ');
+            }
+
+            let Some((start, end, url)) = word_url(word) else {
+                html.push_str(&escape_html(word));
+                continue;
+            };
+
+            found = true;
+            html.push_str(&escape_html(&word[..start]));
+            html.push_str(&format!("<a href=\"{}\">", escape_html(url.as_str())));
+            html.push_str(&escape_html(&word[start..end]));
+            html.push_str("</a>");
+            html.push_str(&escape_html(&word[end..]));
+        }
+    }
+
+    found.then_some(html)
+}
+
+/// Remove the quote of the replied-to message that clients put at the start of a reply, so that
+/// only what was actually said is left.
+pub fn strip_reply_fallback(msgtype: &mut MessageType) {
+    if let MessageType::Emote(EmoteMessageEventContent { body, formatted, .. }) |
+    MessageType::Notice(NoticeMessageEventContent { body, formatted, .. }) |
+    MessageType::Text(TextMessageEventContent { body, formatted, .. }) = msgtype
+    {
+        *body = remove_plain_reply_fallback(body).to_string();
+
+        if let Some(formatted) = formatted {
+            if let Some((_, rest)) = formatted.body.split_once("</mx-reply>") {
+                formatted.body = rest.to_string();
+            }
+        }
+    }
+}
+
+/// Swap the body of an attachment for a new caption, keeping the media that it points at, so
+/// that it can be edited without uploading it again. Returns [None] for anything that isn't an
+/// attachment.
+pub fn recaption(msgtype: &MessageType, caption: String) -> Option<MessageType> {
+    let mut msgtype = msgtype.clone();
+
+    match &mut msgtype {
+        MessageType::Audio(content) => content.body = caption,
+        MessageType::File(content) => {
+            // Files can say what they're called separately, so the old name isn't lost.
+            let name = std::mem::replace(&mut content.body, caption);
+            content.filename.get_or_insert(name);
+        },
+        MessageType::Image(content) => content.body = caption,
+        MessageType::Video(content) => content.body = caption,
+        _ => return None,
+    }
+
+    Some(msgtype)
+}
+
 pub struct Message {
     pub event: MessageEvent,
     pub sender: OwnedUserId,
@@ -794,6 +1070,13 @@ pub struct Message {
     pub downloaded: bool,
     pub html: Option<StyleTree>,
     pub image_preview: ImageStatus,
+    pub url_preview: Option<UrlPreview>,
+
+    /// Whether this is a local echo for a message that the server rejected.
+    pub send_failed: bool,
+
+    /// Whether this message was forwarded here from another room.
+    pub forwarded: bool,
 }
 
 impl Message {
@@ -808,6 +1091,30 @@ impl Message {
             downloaded,
             html,
             image_preview: ImageStatus::None,
+            url_preview: None,
+            send_failed: false,
+            forwarded: false,
+        }
+    }
+
+    /// The link, if this is a text message whose body is only a URL.
+    pub fn link_url(&self) -> Option<Url> {
+        match self.event.content() {
+            Some(RoomMessageEventContent { msgtype: MessageType::Text(content), .. }) => {
+                single_url(&content.body)
+            },
+            _ => None,
+        }
+    }
+
+    /// The links in this message, along with the labels shown after them.
+    pub fn links(&self) -> Vec<(char, Url)> {
+        if let Some(url) = self.link_url() {
+            vec![('0', url)]
+        } else if let Some(html) = &self.html {
+            html.get_links()
+        } else {
+            vec![]
         }
     }
 
@@ -818,6 +1125,8 @@ impl Message {
             MessageEvent::Local(_, content) => content,
             MessageEvent::Original(ev) => &ev.content,
             MessageEvent::Redacted(_) => return None,
+            MessageEvent::Custom(_) => return None,
+            MessageEvent::Membership(_) => return None,
         };
 
         match &content.relates_to {
@@ -838,6 +1147,8 @@ impl Message {
             MessageEvent::Local(_, content) => content,
             MessageEvent::Original(ev) => &ev.content,
             MessageEvent::Redacted(_) => return None,
+            MessageEvent::Custom(_) => return None,
+            MessageEvent::Membership(_) => return None,
         };
 
         match &content.relates_to {
@@ -878,30 +1189,53 @@ impl Message {
         settings: &'a ApplicationSettings,
     ) -> MessageFormatter<'a> {
         let orig = width;
+        let tz = &settings.tunables.timezone;
         let date = match &prev {
-            Some(prev) if prev.timestamp.same_day(&self.timestamp) => None,
-            _ => self.timestamp.show_date(),
+            Some(prev) if prev.timestamp.same_day(&self.timestamp, tz) => None,
+            _ => self.timestamp.show_date(tz),
         };
         let user_gutter = settings.tunables.user_gutter_width;
+        let time_format = info.timestamp_format.as_deref();
+        let time_gutter = time_gutter_width(time_format);
 
-        if user_gutter + TIME_GUTTER + READ_GUTTER + MIN_MSG_LEN <= width &&
+        if user_gutter + time_gutter + READ_GUTTER + MIN_MSG_LEN <= width &&
             settings.tunables.read_receipt_display
         {
             let cols = MessageColumns::Four;
-            let fill = width - user_gutter - TIME_GUTTER - READ_GUTTER;
+            let fill = width - user_gutter - time_gutter - READ_GUTTER;
             let user = self.show_sender(prev, true, info, settings);
-            let time = self.timestamp.show_time();
-            let read = info.event_receipts.get(self.event.event_id()).map(|read| read.iter());
-
-            MessageFormatter { settings, cols, orig, fill, user, date, time, read }
-        } else if user_gutter + TIME_GUTTER + MIN_MSG_LEN <= width {
+            let time = self.timestamp.show_time(tz, time_format, time_gutter);
+            let read = info.event_receipts.get(self.event.event_id());
+
+            MessageFormatter {
+                settings,
+                cols,
+                orig,
+                fill,
+                user,
+                date,
+                time,
+                time_gutter,
+                read,
+            }
+        } else if user_gutter + time_gutter + MIN_MSG_LEN <= width {
             let cols = MessageColumns::Three;
-            let fill = width - user_gutter - TIME_GUTTER;
+            let fill = width - user_gutter - time_gutter;
             let user = self.show_sender(prev, true, info, settings);
-            let time = self.timestamp.show_time();
+            let time = self.timestamp.show_time(tz, time_format, time_gutter);
             let read = None;
 
-            MessageFormatter { settings, cols, orig, fill, user, date, time, read }
+            MessageFormatter {
+                settings,
+                cols,
+                orig,
+                fill,
+                user,
+                date,
+                time,
+                time_gutter,
+                read,
+            }
         } else if user_gutter + MIN_MSG_LEN <= width {
             let cols = MessageColumns::Two;
             let fill = width - user_gutter;
@@ -909,7 +1243,17 @@ impl Message {
             let time = None;
             let read = None;
 
-            MessageFormatter { settings, cols, orig, fill, user, date, time, read }
+            MessageFormatter {
+                settings,
+                cols,
+                orig,
+                fill,
+                user,
+                date,
+                time,
+                time_gutter,
+                read,
+            }
         } else {
             let cols = MessageColumns::One;
             let fill = width.saturating_sub(2);
@@ -917,7 +1261,17 @@ impl Message {
             let time = None;
             let read = None;
 
-            MessageFormatter { settings, cols, orig, fill, user, date, time, read }
+            MessageFormatter {
+                settings,
+                cols,
+                orig,
+                fill,
+                user,
+                date,
+                time,
+                time_gutter,
+                read,
+            }
         }
     }
 
@@ -949,13 +1303,27 @@ impl Message {
             fmt.push_in_reply(r, style, &mut text, info);
         }
 
+        if self.forwarded {
+            fmt.push_forwarded(&mut text);
+        }
+
         // Now show the message contents, and the inlined reply if we couldn't find it above.
-        let (msg, proto) = self.show_msg(
-            width,
-            style,
-            reply.is_some(),
-            settings.tunables.message_shortcode_display,
-        );
+        let (msg, proto) = if let MessageEvent::Membership(group) = &self.event {
+            let threshold = settings.tunables.membership_summary_threshold;
+            let body = group.show(threshold, selected);
+
+            (wrapped_text(body, width, style), None)
+        } else if let Some(body) = self.show_verification_request(settings) {
+            (wrapped_text(body, width, style), None)
+        } else {
+            self.show_msg(
+                width,
+                style,
+                reply.is_some(),
+                settings.tunables.message_shortcode_display,
+                selected,
+            )
+        };
 
         // Given our text so far, determine the image offset.
         let proto = proto.map(|p| {
@@ -978,6 +1346,14 @@ impl Message {
             fmt.push_reactions(reactions, style, &mut text);
         }
 
+        if info.is_pinned(self.event.event_id()) {
+            fmt.push_pinned(&mut text);
+        }
+
+        if self.send_failed {
+            fmt.push_send_failed(&mut text);
+        }
+
         if let Some(thread) = info.get_thread(Some(self.event.event_id())) {
             fmt.push_thread_reply_count(thread.len(), &mut text);
         }
@@ -1002,38 +1378,104 @@ impl Message {
         style: Style,
         hide_reply: bool,
         emoji_shortcodes: bool,
+        reveal_spoilers: bool,
     ) -> (Text, Option<&dyn Protocol>) {
+        if let Some(url) = self.link_url() {
+            return (self.show_link(url, width, style), None);
+        }
+
         if let Some(html) = &self.html {
-            (html.to_text(width, style, hide_reply, emoji_shortcodes), None)
-        } else {
-            let mut msg = self.event.body();
-            if emoji_shortcodes {
-                msg = Cow::Owned(replace_emojis_in_str(msg.as_ref()));
-            }
+            let printer = printer::TextPrinter::new(width, style, hide_reply, emoji_shortcodes)
+                .reveal_spoilers(reveal_spoilers);
+            let text = html.print_with(printer, style);
 
-            if self.downloaded {
-                msg.to_mut().push_str(" \u{2705}");
+            // Malformed HTML can leave nothing to show, so use the plain body instead.
+            if !is_blank_text(&text) {
+                return (text, None);
             }
+        }
 
-            let mut proto = None;
-            let placeholder = match &self.image_preview {
-                ImageStatus::None => None,
-                ImageStatus::Downloading(image_preview_size) => {
-                    placeholder_frame(Some("Downloading..."), width, image_preview_size)
-                },
-                ImageStatus::Loaded(backend) => {
-                    proto = Some(backend.as_ref());
-                    placeholder_frame(None, width, &backend.rect().into())
-                },
-                ImageStatus::Error(err) => Some(format!("[Image error: {err}]\n")),
-            };
+        let mut msg = self.event.body();
+        if emoji_shortcodes {
+            msg = Cow::Owned(replace_emojis_in_str(msg.as_ref()));
+        }
 
-            if let Some(placeholder) = placeholder {
-                msg.to_mut().insert_str(0, &placeholder);
-            }
+        if self.downloaded {
+            msg.to_mut().push_str(" \u{2705}");
+        }
+
+        let mut proto = None;
+        let placeholder = match &self.image_preview {
+            ImageStatus::None => None,
+            ImageStatus::Downloading(image_preview_size) => {
+                placeholder_frame(Some("Downloading..."), width, image_preview_size)
+            },
+            ImageStatus::Loaded(backend) => {
+                proto = Some(backend.as_ref());
+                placeholder_frame(None, width, &backend.rect().into())
+            },
+            ImageStatus::Error(err) => Some(format!("[Preview failed: {err}]\n")),
+        };
 
-            (wrapped_text(msg, width, style), proto)
+        if let Some(placeholder) = placeholder {
+            msg.to_mut().insert_str(0, &placeholder);
         }
+
+        (wrapped_text(msg, width, style), proto)
+    }
+
+    /// Show a link-only message as the styled link, followed by its preview card if one has been
+    /// fetched.
+    fn show_link(&self, url: Url, width: usize, style: Style) -> Text<'_> {
+        let mut text = Text::default();
+        let link = style.add_modifier(StyleModifier::UNDERLINED);
+
+        for (line, w) in wrap(String::from(url), width) {
+            let pad = space_span(width.saturating_sub(w), style);
+            text.lines.push(Line::from(vec![Span::styled(line, link), pad]));
+        }
+
+        let Some(preview) = &self.url_preview else {
+            return text;
+        };
+
+        let inner = width.saturating_sub(2);
+        let title = preview
+            .title
+            .as_deref()
+            .map(|t| (t, style.add_modifier(StyleModifier::BOLD)));
+        let desc = preview.description.as_deref().map(|d| (d, style));
+
+        for (s, line_style) in title.into_iter().chain(desc) {
+            for (line, w) in wrap(s, inner).take(3) {
+                let bar = Span::styled(format!("{THICK_VERTICAL} "), style);
+                let pad = space_span(inner.saturating_sub(w), style);
+                text.lines.push(Line::from(vec![bar, Span::styled(line, line_style), pad]));
+            }
+        }
+
+        text
+    }
+
+    /// Describe an in-room verification request, and how to answer it when it's been sent to the
+    /// user and isn't accepted automatically.
+    fn show_verification_request(&self, settings: &ApplicationSettings) -> Option<String> {
+        let MessageEvent::Original(ev) = &self.event else {
+            return None;
+        };
+        let MessageType::VerificationRequest(request) = &ev.content.msgtype else {
+            return None;
+        };
+
+        let text = if request.to != settings.profile.user_id {
+            format!("[Verification requested from {}]", request.to)
+        } else if settings.tunables.verification_auto_accept {
+            "[Verification requested, and accepted automatically]".into()
+        } else {
+            "[Verification requested: answer with :verify accept or :verify decline]".into()
+        };
+
+        Some(text)
     }
 
     fn sender_span<'a>(
@@ -1051,9 +1493,15 @@ impl Message {
         info: &'a RoomInfo,
         settings: &'a ApplicationSettings,
     ) -> Option<Span<'a>> {
+        if let MessageEvent::Membership(_) = &self.event {
+            // Membership changes name everyone involved in their own text.
+            return None;
+        }
+
         if let Some(prev) = prev {
             if self.sender == prev.sender &&
-                self.timestamp.same_day(&prev.timestamp) &&
+                !matches!(prev.event, MessageEvent::Membership(_)) &&
+                self.timestamp.same_day(&prev.timestamp, &settings.tunables.timezone) &&
                 !self.event.is_emote()
             {
                 return None;
@@ -1093,6 +1541,27 @@ impl From<RoomEncryptedEvent> for Message {
     }
 }
 
+impl From<MembershipGroup> for Message {
+    fn from(group: MembershipGroup) -> Self {
+        let last = group.last();
+        let timestamp = last.origin_server_ts.into();
+        let sender = last.sender.clone();
+        let content = MessageEvent::Membership(group.into());
+
+        Message::new(content, sender, timestamp)
+    }
+}
+
+impl From<CustomEvent> for Message {
+    fn from(event: CustomEvent) -> Self {
+        let timestamp = event.origin_server_ts.into();
+        let user_id = event.sender.clone();
+        let content = MessageEvent::Custom(event.into());
+
+        Message::new(content, user_id, timestamp)
+    }
+}
+
 impl From<OriginalRoomMessageEvent> for Message {
     fn from(event: OriginalRoomMessageEvent) -> Self {
         let timestamp = event.origin_server_ts.into();
@@ -1132,6 +1601,45 @@ impl ToString for Message {
 pub mod tests {
     use super::*;
     use crate::tests::*;
+    use matrix_sdk::ruma::{
+        events::relation::InReplyTo,
+        events::room::message::{FileMessageEventContent, ImageMessageEventContent},
+        events::room::MediaSource,
+        owned_mxc_uri,
+        server_name,
+    };
+
+    #[test]
+    fn test_read_receipt_spans() {
+        let settings = mock_settings();
+        let readers = |users: &[&str]| -> HashSet<OwnedUserId> {
+            users.iter().map(|u| OwnedUserId::try_from(*u).unwrap()).collect()
+        };
+        let chars = |spans: Vec<Span>| -> String {
+            spans.into_iter().map(|span| span.content.into_owned()).collect()
+        };
+
+        let read = readers(&[]);
+        assert_eq!(chars(read_receipt_spans(&read, &settings)), "");
+
+        // Readers are sorted so that they don't shuffle between draws.
+        let read = readers(&[
+            "@charlie:example.com",
+            "@alice:example.com",
+            "@bob:example.com",
+        ]);
+        assert_eq!(chars(read_receipt_spans(&read, &settings)), "abc");
+
+        // Too many readers to show get summarized.
+        let read = readers(&[
+            "@dave:example.com",
+            "@charlie:example.com",
+            "@bob:example.com",
+            "@alice:example.com",
+            "@eve:example.com",
+        ]);
+        assert_eq!(chars(read_receipt_spans(&read, &settings)), "ab+");
+    }
 
     #[test]
     fn test_mc_cmp() {
@@ -1238,6 +1746,46 @@ pub mod tests {
         assert_eq!(identity(&mc6), mc1);
     }
 
+    #[test]
+    fn test_markdown_table() {
+        let input = "| a | b |\n|:--|--:|\n| 1 | 2 |\n";
+        let content = text_to_message_content(input.into());
+        assert_eq!(content.body, input);
+        assert_eq!(
+            content.formatted.unwrap().body,
+            "<table>\n<thead>\n<tr>\n<th align=\"left\">a</th>\n<th align=\"right\">b</th>\n\
+             </tr>\n</thead>\n<tbody>\n<tr>\n<td align=\"left\">1</td>\n\
+             <td align=\"right\">2</td>\n</tr>\n</tbody>\n</table>\n"
+        );
+    }
+
+    #[test]
+    fn test_markdown_lists() {
+        let input = "- one\n  - nested\n- two\n";
+        let content = text_to_message_content(input.into());
+        assert_eq!(content.body, input);
+        assert_eq!(
+            content.formatted.unwrap().body,
+            "<ul>\n<li>one\n<ul>\n<li>nested</li>\n</ul>\n</li>\n<li>two</li>\n</ul>\n"
+        );
+
+        let input = "1. first\n2. second\n   - nested\n";
+        let content = text_to_message_content(input.into());
+        assert_eq!(content.body, input);
+        assert_eq!(
+            content.formatted.unwrap().body,
+            "<ol>\n<li>first</li>\n<li>second\n<ul>\n<li>nested</li>\n</ul>\n</li>\n</ol>\n"
+        );
+
+        let input = "- [ ] todo\n- [x] done\n";
+        let content = text_to_message_content(input.into());
+        assert_eq!(content.body, input);
+        assert_eq!(
+            content.formatted.unwrap().body,
+            "<ul>\n<li>\u{2610} todo</li>\n<li>\u{2611} done</li>\n</ul>\n"
+        );
+    }
+
     #[test]
     fn test_markdown_autolink() {
         let input = "http://example.com\n";
@@ -1265,6 +1813,23 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn test_text_to_message_plain() {
+        let content = text_to_message("*hello*".into(), false);
+        let MessageType::Text(text) = content.msgtype else {
+            panic!("expected a text message");
+        };
+        assert_eq!(text.body, "*hello*");
+        assert!(text.formatted.is_none());
+
+        let content = text_to_message("*hello*".into(), true);
+        let MessageType::Text(text) = content.msgtype else {
+            panic!("expected a text message");
+        };
+        assert_eq!(text.body, "*hello*");
+        assert_eq!(text.formatted.unwrap().body, "<p><em>hello</em></p>\n");
+    }
+
     #[test]
     fn test_markdown_message() {
         let input = "**bold**\n";
@@ -1314,6 +1879,230 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn test_timestamp_timezone() {
+        let utc = Timezone::Fixed(FixedOffset::east_opt(0).unwrap());
+        let ahead = Timezone::Fixed(FixedOffset::east_opt(2 * 3600).unwrap());
+
+        // 23:00 and 23:30 UTC on the first day of 1970 are a day later two hours east.
+        let ts1 = MessageTimeStamp::OriginServer(UInt::new(23 * 3600 * 1000).unwrap());
+        let ts2 =
+            MessageTimeStamp::OriginServer(UInt::new(23 * 3600 * 1000 + 1800 * 1000).unwrap());
+        let ts0 = MessageTimeStamp::OriginServer(UInt::new(3600 * 1000).unwrap());
+
+        assert_eq!(ts1.show_time(&utc, None, TIME_GUTTER).unwrap().content, "  [23:00:00]");
+        assert_eq!(ts1.show_time(&ahead, None, TIME_GUTTER).unwrap().content, "  [01:00:00]");
+        assert_eq!(ts1.show_date(&utc).unwrap().content, "Thursday, January 01 1970");
+        assert_eq!(ts1.show_date(&ahead).unwrap().content, "Friday, January 02 1970");
+
+        assert!(ts0.same_day(&ts1, &utc));
+        assert!(!ts0.same_day(&ts1, &ahead));
+        assert!(ts1.same_day(&ts2, &ahead));
+    }
+
+    #[test]
+    fn test_timestamp_format() {
+        let utc = Timezone::Fixed(FixedOffset::east_opt(0).unwrap());
+        let ts = MessageTimeStamp::OriginServer(UInt::new(23 * 3600 * 1000).unwrap());
+
+        let gutter = time_gutter_width(Some("%H:%M"));
+        assert_eq!(gutter, 9);
+        assert_eq!(ts.show_time(&utc, Some("%H:%M"), gutter).unwrap().content, "  [23:00]");
+
+        // Gutters fit the longest day and month names, and shorter times are padded to match.
+        let gutter = time_gutter_width(Some("%A %R"));
+        assert_eq!(gutter, 19);
+        assert_eq!(
+            ts.show_time(&utc, Some("%A %R"), gutter).unwrap().content,
+            "   [Thursday 23:00]"
+        );
+
+        // An empty format hides the timestamp.
+        assert_eq!(time_gutter_width(Some("")), 0);
+        assert_eq!(ts.show_time(&utc, Some(""), 0), None);
+        assert_eq!(time_gutter_width(None), TIME_GUTTER);
+
+        assert!(valid_timestamp_format("%b %d %T"));
+        assert!(!valid_timestamp_format("%Q"));
+
+        // Formats with offsets and timezone names work, since timestamps are offset-aware.
+        assert!(valid_timestamp_format("%R %Z"));
+        assert!(valid_timestamp_format("%R %z"));
+        assert!(valid_timestamp_format("%R %:z"));
+        assert_eq!(time_gutter_width(Some("%R %:z")), 16);
+        assert_eq!(ts.show_time(&utc, Some("%R %:z"), 16).unwrap().content, "  [23:00 +00:00]");
+    }
+
+    #[test]
+    fn test_reply_author_color() {
+        let mut settings = mock_settings();
+        settings.tunables.message_user_color = true;
+        let info = mock_room();
+
+        let mut content = RoomMessageEventContent::text_plain("agreed");
+        content.relates_to =
+            Some(Relation::Reply { in_reply_to: InReplyTo::new(MSG2_EVID.clone()) });
+        let key = (
+            MessageTimeStamp::OriginServer(UInt::new(10).unwrap()),
+            EventId::new(server_name!("example.com")),
+        );
+        let reply = mock_room1_message(content, TEST_USER5.clone(), key);
+
+        let vwctx = ViewportContext { dimensions: (60, 10), ..Default::default() };
+
+        let text = reply.show(None, false, &vwctx, &info, &settings);
+        let author = settings.get_user_color(&TEST_USER2);
+        let replier = settings.get_user_color(&TEST_USER5);
+        assert_ne!(author, replier);
+
+        // The quoted author keeps their own color inside the reply's colored message.
+        let span = |needle: &str| {
+            text.lines
+                .iter()
+                .flat_map(|line| line.spans.iter())
+                .find(|span| span.content.contains(needle))
+                .unwrap()
+                .style
+                .fg
+        };
+        assert_eq!(span(TEST_USER2.as_str()), Some(author));
+        assert_eq!(span("┃"), Some(author));
+        assert_eq!(span("helium"), Some(author));
+        assert_eq!(span("agreed"), Some(replier));
+    }
+
+    #[test]
+    fn test_verification_request() {
+        use matrix_sdk::ruma::{
+            device_id,
+            events::{
+                key::verification::VerificationMethod,
+                room::message::KeyVerificationRequestEventContent,
+            },
+            UserId,
+        };
+
+        let mut settings = mock_settings();
+        let info = mock_room();
+        let vwctx = ViewportContext { dimensions: (200, 10), ..Default::default() };
+        let key = (
+            MessageTimeStamp::OriginServer(UInt::new(10).unwrap()),
+            EventId::new(server_name!("example.com")),
+        );
+        let request = |to: &UserId| {
+            let content = KeyVerificationRequestEventContent::new(
+                "verify me".into(),
+                vec![VerificationMethod::SasV1],
+                device_id!("FOOBAR").to_owned(),
+                to.to_owned(),
+            );
+            let content = RoomMessageEventContent::new(MessageType::VerificationRequest(content));
+
+            mock_room1_message(content, TEST_USER2.clone(), key.clone())
+        };
+        let show = |msg: &Message, settings: &ApplicationSettings| {
+            let text = msg.show(None, false, &vwctx, &info, settings);
+            text.lines
+                .iter()
+                .flat_map(|line| line.spans.iter())
+                .map(|span| span.content.as_ref())
+                .collect::<String>()
+        };
+
+        let msg = request(&settings.profile.user_id);
+        assert!(show(&msg, &settings).contains("accepted automatically"));
+
+        settings.tunables.verification_auto_accept = false;
+        assert!(show(&msg, &settings).contains(":verify accept or :verify decline"));
+
+        let msg = request(&TEST_USER3);
+        let text = format!("[Verification requested from {}]", TEST_USER3.as_str());
+        assert!(show(&msg, &settings).contains(&text));
+    }
+
+    #[test]
+    fn test_single_url() {
+        let url = single_url("https://example.com/page?q=1").unwrap();
+        assert_eq!(url.as_str(), "https://example.com/page?q=1");
+
+        let url = single_url("  http://example.com\n").unwrap();
+        assert_eq!(url.as_str(), "http://example.com/");
+
+        assert_eq!(single_url("see https://example.com"), None);
+        assert_eq!(single_url("https://example.com https://example.org"), None);
+        assert_eq!(single_url("mailto:user@example.com"), None);
+        assert_eq!(single_url("example.com"), None);
+        assert_eq!(single_url(""), None);
+    }
+
+    #[test]
+    fn test_linkify() {
+        assert_eq!(linkify("no links here"), None);
+        assert_eq!(linkify("example.com"), None);
+
+        let html = linkify("see (https://example.com/a?b=1&c=2), ok").unwrap();
+        assert_eq!(
+            html,
+            "see (<a href=\"https://example.com/a?b=1&amp;c=2\">https://example.com/a?b=1&amp;c=2</a>), ok"
+        );
+
+        let html = linkify("<b>\nmxc://example.com/abc").unwrap();
+        assert_eq!(
+            html,
+            "&lt;b&gt;<br><a href=\"mxc://example.com/abc\">mxc://example.com/abc</a>"
+        );
+
+        let tree = parse_matrix_html(&linkify("a https://a.com b https://b.com").unwrap());
+        let links = tree.get_links();
+        assert_eq!(links.len(), 2);
+        assert_eq!(links[0], ('0', Url::parse("https://a.com").unwrap()));
+        assert_eq!(links[1], ('1', Url::parse("https://b.com").unwrap()));
+    }
+
+    #[test]
+    fn test_strip_reply_fallback() {
+        let mut msgtype = MessageType::Text(TextMessageEventContent::html(
+            "> <@user1:example.com> original\n\nforwarded text",
+            "<mx-reply><blockquote>original</blockquote></mx-reply><b>forwarded</b> text",
+        ));
+        strip_reply_fallback(&mut msgtype);
+
+        let MessageType::Text(content) = msgtype else {
+            panic!("Expected a text message");
+        };
+        assert_eq!(content.body, "forwarded text");
+        assert_eq!(content.formatted.unwrap().body, "<b>forwarded</b> text");
+
+        // Messages without a fallback are left alone.
+        let mut msgtype = MessageType::Text(TextMessageEventContent::plain("just text"));
+        strip_reply_fallback(&mut msgtype);
+        assert_eq!(msgtype.body(), "just text");
+    }
+
+    #[test]
+    fn test_recaption() {
+        let url = owned_mxc_uri!("mxc://example.com/abc");
+        let image = ImageMessageEventContent::plain("cat.png".into(), url.clone());
+        let recaptioned = recaption(&MessageType::Image(image), "My cat".into()).unwrap();
+        let MessageType::Image(image) = recaptioned else {
+            panic!("Expected an image");
+        };
+        assert_eq!(image.body, "My cat");
+        assert!(matches!(image.source, MediaSource::Plain(ref u) if u == &url));
+
+        // Files keep their original name around.
+        let file = FileMessageEventContent::plain("notes.txt".into(), url.clone());
+        let MessageType::File(file) = recaption(&MessageType::File(file), "Notes".into()).unwrap()
+        else {
+            panic!("Expected a file");
+        };
+        assert_eq!(file.body, "Notes");
+        assert_eq!(file.filename.as_deref(), Some("notes.txt"));
+
+        let text = MessageType::Text(TextMessageEventContent::plain("hello"));
+        assert!(recaption(&text, "bye".into()).is_none());
+    }
+
     #[test]
     fn test_placeholder_frame() {
         fn pretty_frame_test(str: &str) -> Option<String> {
@@ -26,6 +26,7 @@ pub struct TextPrinter<'a> {
     base_style: Style,
     hide_reply: bool,
     emoji_shortcodes: bool,
+    reveal_spoilers: bool,
 
     alignment: Alignment,
     curr_spans: Vec<Span<'a>>,
@@ -42,6 +43,7 @@ impl<'a> TextPrinter<'a> {
             base_style,
             hide_reply,
             emoji_shortcodes,
+            reveal_spoilers: false,
 
             alignment: Alignment::Left,
             curr_spans: vec![],
@@ -62,6 +64,17 @@ impl<'a> TextPrinter<'a> {
         self
     }
 
+    /// Set whether text marked as a spoiler should be shown, instead of a placeholder.
+    pub fn reveal_spoilers(mut self, reveal: bool) -> Self {
+        self.reveal_spoilers = reveal;
+        self
+    }
+
+    /// Indicates whether spoilers should be shown.
+    pub fn spoilers_revealed(&self) -> bool {
+        self.reveal_spoilers
+    }
+
     /// Indicates whether replies should be pushed to the printer.
     pub fn hide_reply(&self) -> bool {
         self.hide_reply
@@ -85,6 +98,7 @@ impl<'a> TextPrinter<'a> {
             base_style: self.base_style,
             hide_reply: self.hide_reply,
             emoji_shortcodes: self.emoji_shortcodes,
+            reveal_spoilers: self.reveal_spoilers,
 
             alignment: self.alignment,
             curr_spans: vec![],
@@ -14,7 +14,7 @@ use matrix_sdk::{
 use unicode_segmentation::UnicodeSegmentation;
 
 use crate::{
-    base::{AsyncProgramStore, IambError, IambResult},
+    base::{AsyncProgramStore, IambError, IambResult, ProgramStore},
     config::{ApplicationSettings, NotifyVia},
 };
 
@@ -59,7 +59,11 @@ pub async fn register_notifications(
                         }
 
                         match notify_via {
-                            NotifyVia::Desktop => send_notification_desktop(summary, body),
+                            NotifyVia::Desktop => {
+                                if let Err(err) = send_notification_desktop(summary, body) {
+                                    tracing::error!("Failed to send notification: {err}")
+                                }
+                            },
                             NotifyVia::Bell => send_notification_bell(&store).await,
                         }
                     },
@@ -72,12 +76,58 @@ pub async fn register_notifications(
         .await;
 }
 
+/// Send a sample notification for a room the same way that real ones are delivered, and
+/// describe what happened.
+pub async fn send_test_notification(
+    client: &Client,
+    room: &MatrixRoom,
+    settings: &ApplicationSettings,
+    store: &mut ProgramStore,
+) -> IambResult<String> {
+    let notifications = &settings.tunables.notifications;
+
+    if !notifications.enabled {
+        return Err(IambError::NotificationsDisabled.into());
+    }
+
+    let summary = "iamb".to_string();
+    let body = notifications.show_message.then(|| {
+        let name = store.application.get_room_title(room.room_id());
+        format!("This is a test notification for {name}.")
+    });
+
+    let mut msg = match notifications.via {
+        NotifyVia::Desktop => {
+            send_notification_desktop(summary, body)?;
+            "Sent a test desktop notification".to_string()
+        },
+        NotifyVia::Bell => {
+            store.application.ring_bell = true;
+            "Rang the terminal bell".to_string()
+        },
+    };
+
+    let server_settings = client.notification_settings().await;
+
+    match global_or_room_mode(&server_settings, room).await {
+        RoomNotificationMode::Mute => {
+            msg.push_str(", but this room is muted, so its messages won't notify");
+        },
+        RoomNotificationMode::MentionsAndKeywordsOnly => {
+            msg.push_str("; messages here only notify when they mention you");
+        },
+        RoomNotificationMode::AllMessages => {},
+    }
+
+    Ok(msg)
+}
+
 async fn send_notification_bell(store: &AsyncProgramStore) {
     let mut locked = store.lock().await;
     locked.application.ring_bell = true;
 }
 
-fn send_notification_desktop(summary: String, body: Option<String>) {
+fn send_notification_desktop(summary: String, body: Option<String>) -> IambResult<()> {
     let mut desktop_notification = notify_rust::Notification::new();
     desktop_notification
         .summary(&summary)
@@ -89,9 +139,11 @@ fn send_notification_desktop(summary: String, body: Option<String>) {
         desktop_notification.body(&body);
     }
 
-    if let Err(err) = desktop_notification.show() {
-        tracing::error!("Failed to send notification: {err}")
-    }
+    desktop_notification
+        .show()
+        .map_err(|err| IambError::Notification(err.to_string()))?;
+
+    Ok(())
 }
 
 async fn global_or_room_mode(
@@ -1,12 +1,18 @@
 use std::{
+    ffi::OsStr,
     fs::File,
-    io::{Read, Write},
+    io::{Cursor, Read, Write},
     path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant, SystemTime},
 };
 
+use image::{codecs::gif::GifDecoder, AnimationDecoder, DynamicImage, ImageFormat};
+
 use matrix_sdk::{
     media::{MediaFormat, MediaRequest},
     ruma::{
+        api::client::media::get_media_preview,
         events::{
             room::{
                 message::{MessageType, RoomMessageEventContent},
@@ -14,29 +20,150 @@ use matrix_sdk::{
             },
             MessageLikeEvent,
         },
+        EventId,
+        MilliSecondsSinceUnixEpoch,
         OwnedEventId,
         OwnedRoomId,
     },
+    Client,
     Media,
 };
-use ratatui::layout::Rect;
-use ratatui_image::Resize;
+use ratatui::{buffer::Buffer, layout::Rect};
+use ratatui_image::{
+    picker::{Picker, ProtocolType},
+    protocol::Protocol,
+    Resize,
+};
+use url::Url;
 
 use crate::{
     base::{AsyncProgramStore, ChatStore, IambError},
-    config::ImagePreviewSize,
-    message::ImageStatus,
+    config::{ImagePreviewSize, ImagePreviewValues, MediaKind},
+    message::{ImageStatus, UrlPreview},
 };
 
+/// The downloadable file attached to a message.
+pub struct Attachment<'a> {
+    pub kind: MediaKind,
+    pub source: MediaSource,
+    pub filename: &'a str,
+
+    /// The MIME type, if the sender included it.
+    pub mimetype: Option<&'a str>,
+
+    /// The size in bytes, if the sender included it.
+    pub size: Option<u64>,
+}
+
+impl<'a> Attachment<'a> {
+    pub fn from_msgtype(msgtype: &'a MessageType) -> Option<Self> {
+        let (kind, source, filename, mimetype, size) = match msgtype {
+            MessageType::Audio(c) => {
+                let info = c.info.as_ref();
+                let mimetype = info.and_then(|i| i.mimetype.as_deref());
+                let size = info.and_then(|i| i.size);
+                (MediaKind::Audio, &c.source, c.body.as_str(), mimetype, size)
+            },
+            MessageType::File(c) => {
+                let info = c.info.as_ref();
+                let mimetype = info.and_then(|i| i.mimetype.as_deref());
+                let size = info.and_then(|i| i.size);
+                let filename = c.filename.as_deref().unwrap_or(c.body.as_str());
+                (MediaKind::File, &c.source, filename, mimetype, size)
+            },
+            MessageType::Image(c) => {
+                let info = c.info.as_ref();
+                let mimetype = info.and_then(|i| i.mimetype.as_deref());
+                let size = info.and_then(|i| i.size);
+                (MediaKind::Image, &c.source, c.body.as_str(), mimetype, size)
+            },
+            MessageType::Video(c) => {
+                let info = c.info.as_ref();
+                let mimetype = info.and_then(|i| i.mimetype.as_deref());
+                let size = info.and_then(|i| i.size);
+                (MediaKind::Video, &c.source, c.body.as_str(), mimetype, size)
+            },
+            _ => return None,
+        };
+
+        let source = source.clone();
+        let size = size.map(u64::from);
+
+        Some(Attachment { kind, source, filename, mimetype, size })
+    }
+
+    /// A short summary of the type and size, like "image/png, 1.5 MiB".
+    pub fn details(&self) -> String {
+        let mimetype = self.mimetype.unwrap_or("unknown type");
+        let size = self.size.map(format_size).unwrap_or_else(|| "unknown size".into());
+
+        format!("{mimetype}, {size}")
+    }
+}
+
+/// Format a number of bytes using the largest binary unit that keeps it at or above 1.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+/// Find an incrementally suffixed filename that isn't in use yet, e.g. image-2.jpg -> image-3.jpg
+pub fn unused_path(filename: PathBuf) -> PathBuf {
+    if !filename.exists() {
+        return filename;
+    }
+
+    let Some(stem) = filename.file_stem().and_then(OsStr::to_str) else {
+        return filename;
+    };
+
+    let ext = filename.extension().and_then(OsStr::to_str);
+    let mut filename_incr = filename.clone();
+
+    for n in 1..=1000 {
+        if let Some(ext) = ext {
+            filename_incr.set_file_name(format!("{}-{}.{}", stem, n, ext));
+        } else {
+            filename_incr.set_file_name(format!("{}-{}", stem, n));
+        }
+
+        if !filename_incr.exists() {
+            return filename_incr;
+        }
+    }
+
+    filename
+}
+
+/// Find the image to preview for a message: the image itself, or the thumbnail that the sender
+/// attached to a video.
 pub fn source_from_event(
     ev: &MessageLikeEvent<RoomMessageEventContent>,
 ) -> Option<(OwnedEventId, MediaSource)> {
-    if let MessageLikeEvent::Original(ev) = &ev {
-        if let MessageType::Image(c) = &ev.content.msgtype {
-            return Some((ev.event_id.clone(), c.source.clone()));
-        }
-    }
-    None
+    let MessageLikeEvent::Original(ev) = &ev else {
+        return None;
+    };
+
+    let source = match &ev.content.msgtype {
+        MessageType::Image(c) => c.source.clone(),
+        MessageType::Video(c) => c.info.as_ref()?.thumbnail_source.clone()?,
+        _ => return None,
+    };
+
+    Some((ev.event_id.clone(), source))
 }
 
 impl From<ImagePreviewSize> for Rect {
@@ -50,6 +177,134 @@ impl From<Rect> for ImagePreviewSize {
     }
 }
 
+/// Whether an animated preview has been drawn since the last call to [take_animation_drawn].
+static ANIMATION_DRAWN: AtomicBool = AtomicBool::new(false);
+
+/// Check whether an animated preview was drawn, so that the screen can be redrawn again soon to
+/// show its next frame.
+pub fn take_animation_drawn() -> bool {
+    ANIMATION_DRAWN.swap(false, Ordering::Relaxed)
+}
+
+/// A preview that cycles through the frames of an animated image each time it gets drawn.
+struct AnimatedProtocol {
+    frames: Vec<(Box<dyn Protocol>, Duration)>,
+    start: Instant,
+}
+
+impl AnimatedProtocol {
+    fn current(&self) -> &dyn Protocol {
+        let delays = self.frames.iter().map(|(_, delay)| *delay);
+        let idx = frame_at(delays, self.start.elapsed());
+
+        self.frames[idx].0.as_ref()
+    }
+}
+
+impl Protocol for AnimatedProtocol {
+    fn render(&self, area: Rect, buf: &mut Buffer) {
+        ANIMATION_DRAWN.store(true, Ordering::Relaxed);
+        self.current().render(area, buf)
+    }
+
+    fn rect(&self) -> Rect {
+        self.frames[0].0.rect()
+    }
+}
+
+/// Find which frame to show once `elapsed` has passed since an animation started looping.
+fn frame_at(delays: impl Iterator<Item = Duration> + Clone, elapsed: Duration) -> usize {
+    let total = delays.clone().sum::<Duration>().as_millis();
+
+    if total == 0 {
+        return 0;
+    }
+
+    let mut offset = elapsed.as_millis() % total;
+
+    for (idx, delay) in delays.enumerate() {
+        if offset < delay.as_millis() {
+            return idx;
+        }
+
+        offset -= delay.as_millis();
+    }
+
+    0
+}
+
+/// Decode an image into the frames to preview, each with how long to show it for.
+///
+/// Animated GIFs get up to `max_frames` frames, shown no faster than `max_fps`. Everything else,
+/// including GIFs with a single frame, gets decoded into one still frame.
+fn decode_frames(
+    bytes: Vec<u8>,
+    image_preview: &ImagePreviewValues,
+) -> Result<Vec<(DynamicImage, Duration)>, IambError> {
+    let reader = image::io::Reader::new(Cursor::new(bytes)).with_guessed_format()?;
+
+    if reader.format() == Some(ImageFormat::Gif) && image_preview.max_frames > 1 {
+        let min_delay = Duration::from_millis(1000 / u64::from(image_preview.max_fps));
+        let bytes = reader.into_inner().into_inner();
+        let frames = GifDecoder::new(Cursor::new(&bytes)).and_then(|gif| {
+            gif.into_frames()
+                .take(image_preview.max_frames)
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+
+        if frames.len() > 1 {
+            let frames = frames
+                .into_iter()
+                .map(|frame| {
+                    let delay = Duration::from(frame.delay()).max(min_delay);
+                    (DynamicImage::ImageRgba8(frame.into_buffer()), delay)
+                })
+                .collect();
+
+            return Ok(frames);
+        }
+
+        let img = image::load_from_memory_with_format(&bytes, ImageFormat::Gif)?;
+
+        return Ok(vec![(img, Duration::ZERO)]);
+    }
+
+    Ok(vec![(reader.decode()?, Duration::ZERO)])
+}
+
+/// Encode the decoded frames for the terminal, animating them if there's more than one.
+fn new_preview(
+    picker: &mut Picker,
+    frames: Vec<(DynamicImage, Duration)>,
+    size: Rect,
+) -> Result<Box<dyn Protocol>, IambError> {
+    let mut frames = frames
+        .into_iter()
+        .map(|(img, delay)| {
+            picker
+                .new_protocol(img, size, Resize::Fit(None))
+                .map(|backend| (backend, delay))
+                .map_err(|err| IambError::Preview(format!("{err:?}")))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match frames.len() {
+        0 => Err(IambError::Preview("Image has no frames".into())),
+        1 => Ok(frames.remove(0).0),
+        _ => Ok(Box::new(AnimatedProtocol { frames, start: Instant::now() })),
+    }
+}
+
+/// Previews get encoded one at a time, so that each one gets its own Kitty image identifiers
+/// from the picker without holding the store's lock while encoding.
+static ENCODING: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Whether a protocol can show animated previews. Halfblocks would have to redraw every cell of
+/// the preview for each frame, so it only shows the first one.
+fn can_animate(protocol: ProtocolType) -> bool {
+    !matches!(protocol, ProtocolType::Halfblocks)
+}
+
 /// Download and prepare the preview, and then lock the store to insert it.
 pub fn spawn_insert_preview(
     store: AsyncProgramStore,
@@ -58,60 +313,174 @@ pub fn spawn_insert_preview(
     source: MediaSource,
     media: Media,
     cache_dir: PathBuf,
+    mut image_preview: ImagePreviewValues,
 ) {
     tokio::spawn(async move {
-        let img = download_or_load(event_id.to_owned(), source, media, cache_dir)
+        let bytes = download_or_load(event_id.to_owned(), source, media, cache_dir)
             .await
-            .map(std::io::Cursor::new)
-            .map(image::io::Reader::new)
-            .map_err(IambError::Matrix)
-            .and_then(|reader| reader.with_guessed_format().map_err(IambError::IOError))
-            .and_then(|reader| reader.decode().map_err(IambError::Image));
+            .map_err(IambError::Matrix);
 
-        match img {
-            Err(err) => {
-                try_set_msg_preview_error(
-                    &mut store.lock().await.application,
-                    room_id,
-                    event_id,
-                    err,
-                );
+        let encoding = ENCODING.lock().await;
+        let picker = store.lock().await.application.picker;
+
+        let res = tokio::task::spawn_blocking(move || {
+            let mut picker =
+                picker.ok_or_else(|| IambError::Preview("Picker is empty".to_string()))?;
+
+            if !can_animate(picker.protocol_type) {
+                image_preview.max_frames = 1;
+            }
+
+            let frames = decode_frames(bytes?, &image_preview)?;
+            let backend = new_preview(&mut picker, frames, image_preview.size.into())?;
+
+            Ok((backend, picker))
+        })
+        .await
+        .unwrap_or_else(|err| Err(IambError::Preview(err.to_string())));
+
+        let mut locked = store.lock().await;
+        let backend = res.map(|(backend, picker)| {
+            locked.application.picker = Some(picker);
+            backend
+        });
+        drop(encoding);
+
+        let msg = locked
+            .application
+            .rooms
+            .get_or_default(room_id.clone())
+            .get_event_mut(&event_id);
+
+        match (backend, msg) {
+            (Ok(backend), Some(msg)) => {
+                msg.image_preview = ImageStatus::Loaded(backend);
             },
-            Ok(img) => {
+            (Ok(_), None) => {
+                let err = IambError::Preview("Message not found".to_string());
+                try_set_msg_preview_error(&mut locked.application, room_id, event_id, err);
+            },
+            (Err(err), _) => {
+                try_set_msg_preview_error(&mut locked.application, room_id, event_id, err);
+            },
+        }
+    });
+}
+
+/// Save a newly arrived attachment into the download directory, and then lock the store to mark
+/// its message as downloaded.
+pub fn spawn_auto_download(
+    store: AsyncProgramStore,
+    room_id: OwnedRoomId,
+    event_id: OwnedEventId,
+    source: MediaSource,
+    media: Media,
+    filename: PathBuf,
+) {
+    tokio::spawn(async move {
+        let req = MediaRequest { source, format: MediaFormat::File };
+        let res = media
+            .get_media_content(&req, true)
+            .await
+            .map_err(IambError::from)
+            .and_then(|bytes| {
+                if let Some(dir) = filename.parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+
+                let filename = unused_path(filename);
+                std::fs::write(&filename, bytes)?;
+                Ok(filename)
+            });
+
+        match res {
+            Ok(filename) => {
                 let mut locked = store.lock().await;
-                let ChatStore { rooms, picker, settings, .. } = &mut locked.application;
-
-                match picker
-                    .as_mut()
-                    .ok_or_else(|| IambError::Preview("Picker is empty".to_string()))
-                    .and_then(|picker| {
-                        Ok((
-                            picker,
-                            rooms
-                                .get_or_default(room_id.clone())
-                                .get_event_mut(&event_id)
-                                .ok_or_else(|| {
-                                    IambError::Preview("Message not found".to_string())
-                                })?,
-                            settings.tunables.image_preview.clone().ok_or_else(|| {
-                                IambError::Preview("image_preview settings not found".to_string())
-                            })?,
-                        ))
-                    })
-                    .and_then(|(picker, msg, image_preview)| {
-                        picker
-                            .new_protocol(img, image_preview.size.into(), Resize::Fit(None))
-                            .map_err(|err| IambError::Preview(format!("{err:?}")))
-                            .map(|backend| (backend, msg))
-                    }) {
-                    Err(err) => {
-                        try_set_msg_preview_error(&mut locked.application, room_id, event_id, err);
-                    },
-                    Ok((backend, msg)) => {
-                        msg.image_preview = ImageStatus::Loaded(backend);
-                    },
+                let info = locked.application.rooms.get_or_default(room_id);
+
+                if let Some(msg) = info.get_event_mut(&event_id) {
+                    msg.downloaded = true;
                 }
+
+                tracing::info!(
+                    event_id = event_id.as_str(),
+                    "Downloaded to {}",
+                    filename.display()
+                );
+            },
+            Err(err) => {
+                tracing::warn!(
+                    event_id = event_id.as_str(),
+                    "Failed to download attachment: {err}"
+                );
+            },
+        }
+    });
+}
+
+/// Pull the title and description out of the OpenGraph data returned by the homeserver.
+fn parse_url_preview(data: &str) -> Option<UrlPreview> {
+    let data = serde_json::from_str::<serde_json::Value>(data).ok()?;
+    let field = |name: &str| {
+        data.get(name)
+            .and_then(serde_json::Value::as_str)
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+    };
+
+    let preview = UrlPreview {
+        title: field("og:title"),
+        description: field("og:description"),
+    };
+
+    if preview == UrlPreview::default() {
+        None
+    } else {
+        Some(preview)
+    }
+}
+
+/// Ask the homeserver to preview a linked page, and then lock the store to attach the preview to
+/// its message.
+///
+/// Links in encrypted rooms are never sent to the homeserver, since that would reveal them.
+pub fn spawn_url_preview(
+    store: AsyncProgramStore,
+    room_id: OwnedRoomId,
+    event_id: OwnedEventId,
+    url: Url,
+    client: Client,
+) {
+    tokio::spawn(async move {
+        let Some(room) = client.get_room(&room_id) else {
+            return;
+        };
+
+        if room.is_encrypted().await.unwrap_or(true) {
+            return;
+        }
+
+        let req =
+            get_media_preview::v3::Request::new(url.into(), MilliSecondsSinceUnixEpoch::now());
+
+        let preview = match client.send(req, None).await {
+            Ok(resp) => resp.data.and_then(|data| parse_url_preview(data.get())),
+            Err(err) => {
+                tracing::warn!(event_id = event_id.as_str(), "Failed to preview link: {err}");
+                return;
             },
+        };
+
+        let Some(preview) = preview else {
+            return;
+        };
+
+        let mut locked = store.lock().await;
+        let info = locked.application.rooms.get_or_default(room_id);
+
+        if let Some(msg) = info.get_event_mut(&event_id) {
+            msg.url_preview = Some(preview);
         }
     });
 }
@@ -129,7 +498,16 @@ fn try_set_msg_preview_error(
         .get_event_mut(&event_id)
         .ok_or_else(|| IambError::Preview("Message not found".to_string()))
     {
-        Ok(msg) => msg.image_preview = ImageStatus::Error(format!("{err:?}")),
+        Ok(msg) => {
+            // Show the reason the same way as other errors, without repeating that it's about
+            // the preview.
+            let reason = match err {
+                IambError::Preview(reason) => reason,
+                err => err.to_string(),
+            };
+
+            msg.image_preview = ImageStatus::Error(reason);
+        },
         Err(err) => {
             tracing::error!(
                 "Failed to set error on msg.image_backend for event {}, room {}: {}",
@@ -141,6 +519,69 @@ fn try_set_msg_preview_error(
     }
 }
 
+/// Whether a file in the preview cache is named the way [download_or_load] names previews,
+/// after the localpart of the event they're for.
+///
+/// Event IDs are opaque, so this only checks that the name is the localpart of some event ID.
+/// iamb never writes hidden files or files with extensions there, so those are left alone too.
+fn is_preview_file(name: &OsStr) -> bool {
+    let Some(name) = name.to_str() else {
+        return false;
+    };
+
+    if name.contains('.') {
+        return false;
+    }
+
+    EventId::parse(format!("${name}")).is_ok_and(|id| id.localpart() == name)
+}
+
+/// Remove the least recently written previews in the preview cache until they fit in `limit`
+/// bytes, and return how many bytes were freed.
+///
+/// Previews may be downloading while this runs, so files that vanish or can't be removed are
+/// skipped. A removed preview just gets downloaded again the next time it's shown.
+pub fn clean_cache(dir: &Path, limit: u64) -> std::io::Result<u64> {
+    let mut files = vec![];
+
+    for entry in std::fs::read_dir(dir)? {
+        let Ok((path, meta)) = entry.and_then(|entry| Ok((entry.path(), entry.metadata()?))) else {
+            continue;
+        };
+
+        if meta.is_file() && path.file_name().is_some_and(is_preview_file) {
+            let modified = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            files.push((modified, meta.len(), path));
+        }
+    }
+
+    files.sort();
+
+    let mut total = files.iter().map(|(_, len, _)| len).sum::<u64>();
+    let mut freed = 0;
+
+    for (_, len, path) in files {
+        if total <= limit {
+            break;
+        }
+
+        match std::fs::remove_file(&path) {
+            Ok(()) => {
+                freed += len;
+                total -= len;
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                total -= len;
+            },
+            Err(e) => {
+                tracing::warn!(path = path.display().to_string(), "Failed to remove preview: {e}");
+            },
+        }
+    }
+
+    Ok(freed)
+}
+
 async fn download_or_load(
     event_id: OwnedEventId,
     source: MediaSource,
@@ -170,3 +611,213 @@ async fn download_or_load(
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1024), "1.0 KiB");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(10 * 1024 * 1024), "10.0 MiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+
+    fn mock_gif(frames: usize) -> Vec<u8> {
+        use image::{codecs::gif::GifEncoder, Delay, Frame, RgbaImage};
+
+        let mut bytes = vec![];
+        let mut encoder = GifEncoder::new(&mut bytes);
+        let frames = (0..frames).map(|n| {
+            let img = RgbaImage::from_pixel(2, 2, image::Rgba([n as u8 * 40, 0, 0, 255]));
+            Frame::from_parts(img, 0, 0, Delay::from_numer_denom_ms(50, 1))
+        });
+        encoder.encode_frames(frames).unwrap();
+        drop(encoder);
+
+        bytes
+    }
+
+    #[test]
+    fn test_source_from_event() {
+        let event = |content: serde_json::Value| -> MessageLikeEvent<RoomMessageEventContent> {
+            serde_json::from_value(serde_json::json!({
+                "type": "m.room.message",
+                "event_id": "$event:example.com",
+                "room_id": "!room:example.com",
+                "sender": "@user:example.com",
+                "origin_server_ts": 0,
+                "content": content,
+            }))
+            .unwrap()
+        };
+        let source = |content| {
+            match source_from_event(&event(content)) {
+                Some((_, MediaSource::Plain(uri))) => Some(uri.to_string()),
+                Some((_, MediaSource::Encrypted(file))) => Some(file.url.to_string()),
+                None => None,
+            }
+        };
+
+        let image = serde_json::json!({
+            "msgtype": "m.image",
+            "body": "image.png",
+            "url": "mxc://example.com/image",
+        });
+        assert_eq!(source(image), Some("mxc://example.com/image".into()));
+
+        // Videos are previewed with their thumbnail, if they have one.
+        let video = serde_json::json!({
+            "msgtype": "m.video",
+            "body": "video.mp4",
+            "url": "mxc://example.com/video",
+            "info": { "thumbnail_url": "mxc://example.com/thumb" },
+        });
+        assert_eq!(source(video), Some("mxc://example.com/thumb".into()));
+
+        let video = serde_json::json!({
+            "msgtype": "m.video",
+            "body": "video.mp4",
+            "url": "mxc://example.com/video",
+        });
+        assert_eq!(source(video), None);
+
+        let text = serde_json::json!({ "msgtype": "m.text", "body": "hello" });
+        assert_eq!(source(text), None);
+    }
+
+    #[test]
+    fn test_clean_cache() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let previews = [
+            ("Fl8VQJ6iZrhXOn4vq0PyKSuGJeAC7oE9RwwHdZEBxKc", 300),
+            ("1583267486111hZrSr", 200),
+            ("x_uvLuZUwbQfCVDPUqMjgr6qi35aR9XW0nLVJjhhQbM", 100),
+        ];
+
+        // Write the previews oldest first, waiting for the filesystem's clock to move on
+        // between them so that each one is newer than the last.
+        let mut last = SystemTime::UNIX_EPOCH;
+
+        for (name, len) in previews {
+            loop {
+                std::fs::write(dir.child(name), vec![0u8; len]).unwrap();
+
+                let modified = dir.child(name).metadata().unwrap().modified().unwrap();
+
+                if modified > last {
+                    last = modified;
+                    break;
+                }
+
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+
+        // Files that iamb didn't name after an event are never removed.
+        std::fs::write(dir.child("notes.txt"), vec![0u8; 1000]).unwrap();
+
+        // Nothing needs removing when the cache already fits.
+        assert_eq!(clean_cache(dir.path(), 600).unwrap(), 0);
+
+        // The oldest previews go first.
+        assert_eq!(clean_cache(dir.path(), 350).unwrap(), 300);
+        assert!(!dir.child(previews[0].0).exists());
+        assert!(dir.child(previews[1].0).exists());
+
+        assert_eq!(clean_cache(dir.path(), 0).unwrap(), 300);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+        assert!(dir.child("notes.txt").exists());
+    }
+
+    #[test]
+    fn test_frame_at() {
+        let delays = [100, 200, 100].map(Duration::from_millis);
+        let frame = |ms| frame_at(delays.iter().copied(), Duration::from_millis(ms));
+
+        assert_eq!(frame(0), 0);
+        assert_eq!(frame(99), 0);
+        assert_eq!(frame(100), 1);
+        assert_eq!(frame(299), 1);
+        assert_eq!(frame(300), 2);
+
+        // Animations loop.
+        assert_eq!(frame(400), 0);
+        assert_eq!(frame(550), 1);
+
+        // Frames without any delay can't be timed.
+        assert_eq!(frame_at([Duration::ZERO; 2].iter().copied(), Duration::from_secs(1)), 0);
+    }
+
+    #[test]
+    fn test_decode_frames() {
+        let mut settings = ImagePreviewValues {
+            size: ImagePreviewSize::default(),
+            protocol: None,
+            max_frames: 3,
+            max_fps: 10,
+        };
+
+        // Frames are capped, and shown no faster than the max FPS.
+        let frames = decode_frames(mock_gif(5), &settings).unwrap();
+        assert_eq!(frames.len(), 3);
+        assert!(frames.iter().all(|(_, delay)| *delay == Duration::from_millis(100)));
+
+        // A single frame is shown as a still image.
+        let frames = decode_frames(mock_gif(1), &settings).unwrap();
+        assert_eq!(frames.len(), 1);
+
+        // Animation can be turned off.
+        settings.max_frames = 1;
+        let frames = decode_frames(mock_gif(5), &settings).unwrap();
+        assert_eq!(frames.len(), 1);
+
+        assert!(decode_frames(b"not an image".to_vec(), &settings).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_preview_error() {
+        let mut store = mock_store().await;
+        let room_id = TEST_ROOM1_ID.clone();
+        let err = IambError::Preview("Picker is empty".into());
+        try_set_msg_preview_error(&mut store.application, room_id.clone(), MSG1_EVID.clone(), err);
+
+        let info = store.application.rooms.get_or_default(room_id.clone());
+        let msg = info.get_event(&MSG1_EVID).unwrap();
+        assert!(matches!(&msg.image_preview, ImageStatus::Error(e) if e == "Picker is empty"));
+
+        let err = std::io::Error::new(std::io::ErrorKind::Other, "bad file");
+        try_set_msg_preview_error(
+            &mut store.application,
+            room_id.clone(),
+            MSG1_EVID.clone(),
+            err.into(),
+        );
+
+        let info = store.application.rooms.get_or_default(room_id);
+        let msg = info.get_event(&MSG1_EVID).unwrap();
+        assert!(matches!(&msg.image_preview, ImageStatus::Error(e) if e.ends_with("bad file")));
+    }
+
+    #[test]
+    fn test_parse_url_preview() {
+        let data = r#"{"og:title": "Example", "og:description": " A page ", "og:type": "website"}"#;
+        let preview = parse_url_preview(data).unwrap();
+        assert_eq!(preview.title.as_deref(), Some("Example"));
+        assert_eq!(preview.description.as_deref(), Some("A page"));
+
+        let data = r#"{"og:title": "Example"}"#;
+        let preview = parse_url_preview(data).unwrap();
+        assert_eq!(preview.title.as_deref(), Some("Example"));
+        assert_eq!(preview.description, None);
+
+        // Nothing worth showing.
+        assert_eq!(parse_url_preview(r#"{"og:title": " ", "og:image": "mxc://a/b"}"#), None);
+        assert_eq!(parse_url_preview("{}"), None);
+        assert_eq!(parse_url_preview("not json"), None);
+    }
+}
@@ -1,5 +1,7 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32};
+use std::sync::Arc;
 
 use matrix_sdk::ruma::{
     event_id,
@@ -27,10 +29,13 @@ use crate::{
         user_style_from_color,
         ApplicationSettings,
         DirectoryValues,
+        EscapeAction,
         Notifications,
         NotifyVia,
         ProfileConfig,
+        RoomOpenPosition,
         SortOverrides,
+        Timezone,
         TunableValues,
         UserColor,
         UserDisplayStyle,
@@ -169,11 +174,14 @@ pub fn mock_dirs() -> DirectoryValues {
 pub fn mock_tunables() -> TunableValues {
     TunableValues {
         default_room: None,
+        debug_event_log: false,
         log_level: Level::INFO,
         message_shortcode_display: false,
+        message_shortcode_expand: true,
         reaction_display: true,
         reaction_shortcode_display: false,
         read_receipt_send: true,
+        read_receipt_send_unfocused: true,
         read_receipt_display: true,
         request_timeout: 120,
         sort: SortOverrides::default().values(),
@@ -185,6 +193,7 @@ pub fn mock_tunables() -> TunableValues {
         })]
         .into_iter()
         .collect::<HashMap<_, _>>(),
+        rooms: HashMap::new(),
         open_command: None,
         external_edit_file_suffix: String::from(".md"),
         username_display: UserDisplayStyle::Username,
@@ -195,13 +204,46 @@ pub fn mock_tunables() -> TunableValues {
             show_message: true,
         },
         image_preview: None,
+        auto_download: None,
+        download_warn_size: 100 * 1024 * 1024,
+        image_preview_cache_size: 500 * 1024 * 1024,
         user_gutter_width: 30,
+        send_markdown: true,
+        send_on_enter: true,
+        member_count_display: true,
+        membership_display: false,
+        membership_summary_threshold: 3,
+        custom_events: HashMap::new(),
+        room_version_warning: true,
+        confirm_room_name: true,
+        upload_pasted_paths: false,
+        url_preview: false,
+        verification_auto_accept: true,
+        unsend_grace_period: 0,
+        unverified_session_warning: true,
+        command_history_size: 100,
+        sent_history_size: 100,
+        save_drafts: false,
+        room_key_rotation: Default::default(),
+        room_list_group_by_space: false,
+        room_open_position: RoomOpenPosition::Unread,
+        escape_action: EscapeAction::Blur,
+        timezone: Timezone::Local,
+        message_load_batch: 50,
+        message_load_interval: 2,
     }
 }
 
 pub fn mock_settings() -> ApplicationSettings {
     ApplicationSettings {
+        command_history: PathBuf::new(),
+        sent_history: PathBuf::new(),
+        recent_reactions: PathBuf::new(),
+        identities_json: PathBuf::new(),
         layout_json: PathBuf::new(),
+        scroll_positions_json: PathBuf::new(),
+        version_warnings_json: PathBuf::new(),
+        drafts_json: PathBuf::new(),
         session_json: PathBuf::new(),
         session_json_old: PathBuf::new(),
         sled_dir: PathBuf::new(),
@@ -227,7 +269,9 @@ pub async fn mock_store() -> ProgramStore {
     let (tx, _) = unbounded_channel();
     let homeserver = Url::parse("https://localhost").unwrap();
     let client = matrix_sdk::Client::new(homeserver).await.unwrap();
-    let worker = Requester { tx, client };
+    let online = Arc::new(AtomicBool::new(true));
+    let sync_failures = Arc::new(AtomicU32::new(0));
+    let worker = Requester { tx, client, online, sync_failures };
 
     let mut store = ChatStore::new(worker, mock_settings());
 
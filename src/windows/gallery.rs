@@ -0,0 +1,358 @@
+//! Image Gallery Window
+use std::fmt::{self, Display};
+use std::ops::{Deref, DerefMut};
+
+use matrix_sdk::ruma::{events::room::message::MessageType, OwnedEventId, OwnedRoomId};
+
+use ratatui::{
+    buffer::Buffer,
+    layout::{Alignment, Rect},
+    text::{Line, Text},
+    widgets::{Paragraph, Widget},
+};
+use ratatui_image::Image;
+
+use modalkit::{
+    actions::{Action, PromptAction, Promptable, WindowAction},
+    editing::completion::CompletionList,
+    errors::{EditError, EditResult},
+    prelude::*,
+};
+
+use modalkit_ratatui::{
+    list::{ListCursor, ListItem, ListState},
+    TermOffset,
+    TerminalCursor,
+    WindowOps,
+};
+
+use crate::base::{
+    IambAction,
+    IambBufferId,
+    IambId,
+    IambInfo,
+    IambResult,
+    MessageAction,
+    ProgramAction,
+    ProgramContext,
+    ProgramStore,
+    RoomInfo,
+};
+use crate::config::ImagePreviewSize;
+use crate::message::{ImageStatus, MessageEvent};
+
+use super::selected_style;
+
+/// An image message in the loaded scrollback.
+#[derive(Clone)]
+pub struct GalleryItem {
+    room_id: OwnedRoomId,
+    event_id: OwnedEventId,
+    caption: String,
+
+    /// Where this item sits in the gallery, so that the grid can find the selected cell.
+    index: usize,
+}
+
+impl Display for GalleryItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.caption)
+    }
+}
+
+impl ListItem<IambInfo> for GalleryItem {
+    fn show(
+        &self,
+        selected: bool,
+        _: &ViewportContext<ListCursor>,
+        store: &mut ProgramStore,
+    ) -> Text<'_> {
+        // The gallery draws its own grid, but the list still uses this to size each item when
+        // scrolling, so keep the height the same as a cell.
+        let size = preview_size(store);
+        let mut lines = vec![Line::styled(self.caption.clone(), selected_style(selected))];
+        lines.resize(size.height + 1, Line::default());
+
+        Text::from(lines)
+    }
+
+    fn get_word(&self) -> Option<String> {
+        self.event_id.to_string().into()
+    }
+}
+
+impl Promptable<ProgramContext, ProgramStore, IambInfo> for GalleryItem {
+    fn prompt(
+        &mut self,
+        act: &PromptAction,
+        ctx: &ProgramContext,
+        _: &mut ProgramStore,
+    ) -> EditResult<Vec<(ProgramAction, ProgramContext)>, IambInfo> {
+        match act {
+            PromptAction::Submit => {
+                let room = IambId::Room(self.room_id.clone(), None);
+                let open = WindowAction::Switch(OpenTarget::Application(room));
+                let jump = IambAction::from(MessageAction::Jump(self.event_id.clone()));
+
+                Ok(vec![
+                    (open.into(), ctx.clone()),
+                    (Action::from(jump), ctx.clone()),
+                ])
+            },
+            PromptAction::Abort(_) => {
+                let msg = "Cannot abort entry inside a list";
+                let err = EditError::Failure(msg.into());
+
+                Err(err)
+            },
+            PromptAction::Recall(..) => {
+                let msg = "Cannot recall history inside a list";
+                let err = EditError::Failure(msg.into());
+
+                Err(err)
+            },
+        }
+    }
+}
+
+/// Collect the image messages in a room's loaded timeline, newest first.
+pub fn gallery_items(room_id: &OwnedRoomId, info: &RoomInfo) -> Vec<GalleryItem> {
+    let Some(messages) = info.get_thread(None) else {
+        return vec![];
+    };
+
+    messages
+        .values()
+        .rev()
+        .filter_map(|msg| {
+            let MessageEvent::Original(ev) = &msg.event else {
+                return None;
+            };
+            let MessageType::Image(content) = &ev.content.msgtype else {
+                return None;
+            };
+
+            let sender = match info.display_names.get(&msg.sender) {
+                Some(name) => name.as_str(),
+                None => msg.sender.as_str(),
+            };
+            let caption = format!("{sender}: {}", content.body);
+
+            Some((ev.event_id.clone(), caption))
+        })
+        .enumerate()
+        .map(|(index, (event_id, caption))| {
+            GalleryItem { room_id: room_id.clone(), event_id, caption, index }
+        })
+        .collect()
+}
+
+fn preview_size(store: &ProgramStore) -> ImagePreviewSize {
+    let image_preview = store.application.settings.tunables.image_preview.as_ref();
+
+    image_preview.map(|p| p.size.clone()).unwrap_or_default()
+}
+
+/// State for the `:gallery` window, which shows a room's images as a grid of previews.
+///
+/// Moving through the grid uses the usual list movements, in reading order.
+pub struct GalleryState {
+    list: ListState<GalleryItem, IambInfo>,
+    room_id: OwnedRoomId,
+
+    /// The first row of cells currently on screen.
+    top: usize,
+}
+
+impl GalleryState {
+    pub fn new(room_id: OwnedRoomId) -> Self {
+        let list = ListState::new(IambBufferId::Gallery(room_id.clone()), vec![]);
+
+        GalleryState { list, room_id, top: 0 }
+    }
+
+    pub fn room_id(&self) -> &OwnedRoomId {
+        &self.room_id
+    }
+
+    /// The image message currently under the cursor.
+    pub fn selected(&self) -> Option<&OwnedEventId> {
+        self.list.get().map(|item| &item.event_id)
+    }
+}
+
+impl Deref for GalleryState {
+    type Target = ListState<GalleryItem, IambInfo>;
+
+    fn deref(&self) -> &Self::Target {
+        return &self.list;
+    }
+}
+
+impl DerefMut for GalleryState {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        return &mut self.list;
+    }
+}
+
+impl TerminalCursor for GalleryState {
+    fn get_term_cursor(&self) -> Option<TermOffset> {
+        self.list.get_term_cursor()
+    }
+}
+
+impl WindowOps<IambInfo> for GalleryState {
+    fn draw(&mut self, area: Rect, buf: &mut Buffer, focused: bool, store: &mut ProgramStore) {
+        let size = preview_size(store);
+        let previews = store.application.settings.tunables.image_preview.is_some();
+        let info = store.application.rooms.get_or_default(self.room_id.clone());
+        let items = gallery_items(&self.room_id, info);
+        self.list.set(items.clone());
+        self.list.set_term_info(area);
+
+        if self.list.is_empty() {
+            Paragraph::new("No images in the loaded messages yet")
+                .alignment(Alignment::Center)
+                .render(area, buf);
+            return;
+        }
+
+        let cell_width = size.width + 2;
+        let cell_height = size.height + 2;
+        let cols = (area.width as usize / cell_width).max(1);
+        let rows = (area.height as usize / cell_height).max(1);
+
+        // Scroll just far enough to keep the selected cell on screen.
+        let selected = self.list.get().map(|item| item.index).unwrap_or(0);
+        let row = selected / cols;
+
+        if row < self.top {
+            self.top = row;
+        } else if row >= self.top + rows {
+            self.top = row + 1 - rows;
+        }
+
+        let visible = items.iter().skip(self.top * cols).take(rows * cols);
+
+        for (n, item) in visible.enumerate() {
+            let x = area.x + ((n % cols) * cell_width) as u16;
+            let y = area.y + ((n / cols) * cell_height) as u16;
+            let width = size.width.min((area.right() - x) as usize);
+
+            let style = selected_style(focused && item.index == selected);
+            let _ = buf.set_stringn(x, y, &item.caption, width, style);
+
+            let Some(msg) = info.get_event(&item.event_id) else {
+                continue;
+            };
+
+            let status = match &msg.image_preview {
+                ImageStatus::Loaded(backend) => {
+                    let mut rect = backend.rect();
+                    rect.x = x;
+                    rect.y = y + 1;
+
+                    // Only draw previews that fit completely inside the window.
+                    if rect.bottom() <= area.bottom() && rect.right() <= area.right() {
+                        Image::new(backend.as_ref()).render(rect, buf);
+                    }
+
+                    continue;
+                },
+                ImageStatus::Downloading(_) => "Downloading...".into(),
+                ImageStatus::Error(err) => format!("[Preview failed: {err}]"),
+                ImageStatus::None if previews => "Waiting for preview...".into(),
+                ImageStatus::None => "[Enable image_preview to see this image]".into(),
+            };
+
+            if y + 1 < area.bottom() {
+                let _ = buf.set_stringn(x, y + 1, status, width, Default::default());
+            }
+        }
+    }
+
+    fn dup(&self, store: &mut ProgramStore) -> Self {
+        GalleryState {
+            list: self.list.dup(store),
+            room_id: self.room_id.clone(),
+            top: self.top,
+        }
+    }
+
+    fn close(&mut self, flags: CloseFlags, store: &mut ProgramStore) -> bool {
+        self.list.close(flags, store)
+    }
+
+    fn write(
+        &mut self,
+        path: Option<&str>,
+        flags: WriteFlags,
+        store: &mut ProgramStore,
+    ) -> IambResult<EditInfo> {
+        self.list.write(path, flags, store)
+    }
+
+    fn get_completions(&self) -> Option<CompletionList> {
+        self.list.get_completions()
+    }
+
+    fn get_cursor_word(&self, style: &WordStyle) -> Option<String> {
+        self.list.get_cursor_word(style)
+    }
+
+    fn get_selected_word(&self) -> Option<String> {
+        self.list.get_selected_word()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::EventLocation;
+    use crate::message::{Message, MessageKey, MessageTimeStamp::OriginServer};
+    use crate::tests::*;
+    use matrix_sdk::ruma::{
+        events::room::message::{ImageMessageEventContent, RoomMessageEventContent},
+        mxc_uri,
+        server_name,
+        EventId,
+        UInt,
+    };
+
+    fn mock_image(name: &str, ts: u64) -> (MessageKey, Message) {
+        let uri = mxc_uri!("mxc://example.com/image").to_owned();
+        let image = ImageMessageEventContent::plain(name.into(), uri);
+        let content = RoomMessageEventContent::new(MessageType::Image(image));
+        let event_id = EventId::new(server_name!("example.com"));
+        let key = (OriginServer(UInt::new(ts).unwrap()), event_id);
+        let msg = mock_room1_message(content, TEST_USER1.clone(), key.clone());
+
+        (key, msg)
+    }
+
+    #[test]
+    fn test_gallery_items() {
+        let mut info = mock_room();
+        assert!(gallery_items(&TEST_ROOM1_ID, &info).is_empty());
+
+        let (key1, img1) = mock_image("cat.png", 3);
+        let (key2, img2) = mock_image("dog.png", 5);
+        info.keys
+            .insert(key1.1.clone(), EventLocation::Message(None, key1.clone()));
+        info.keys
+            .insert(key2.1.clone(), EventLocation::Message(None, key2.clone()));
+        info.get_thread_mut(None).insert_message(key1.clone(), img1);
+        info.get_thread_mut(None).insert_message(key2.clone(), img2);
+        info.display_names.insert(TEST_USER1.clone(), "User 1".into());
+
+        // Only the images show up, and the newest comes first.
+        let items = gallery_items(&TEST_ROOM1_ID, &info);
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].event_id, key2.1);
+        assert_eq!(items[0].caption, "User 1: dog.png");
+        assert_eq!(items[0].index, 0);
+        assert_eq!(items[1].event_id, key1.1);
+        assert_eq!(items[1].caption, "User 1: cat.png");
+        assert_eq!(items[1].index, 1);
+    }
+}
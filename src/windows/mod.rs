@@ -7,18 +7,23 @@
 //! example, [sending messages][crate::base::SendAction] delegate to the [room window][RoomState],
 //! where we have the message bar and room ID easily accesible and resetable.
 use std::cmp::{Ord, Ordering, PartialOrd};
+use std::collections::HashMap;
+use std::fmt::{self, Display};
 use std::ops::Deref;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::Instant;
 
 use matrix_sdk::{
-    encryption::verification::{format_emojis, SasVerification},
+    encryption::verification::{format_emojis, SasState, SasVerification},
+    notification_settings::RoomNotificationMode,
     room::{Room as MatrixRoom, RoomMember},
     ruma::{
         events::room::member::MembershipState,
         events::tag::{TagName, Tags},
+        OwnedEventId,
         OwnedRoomAliasId,
         OwnedRoomId,
+        OwnedUserId,
         RoomAliasId,
         RoomId,
     },
@@ -27,7 +32,7 @@ use matrix_sdk::{
 use ratatui::{
     buffer::Buffer,
     layout::{Alignment, Rect},
-    style::{Modifier as StyleModifier, Style},
+    style::{Color, Modifier as StyleModifier, Style},
     text::{Line, Span, Text},
     widgets::StatefulWidget,
 };
@@ -58,18 +63,25 @@ use modalkit_ratatui::{
 
 use crate::base::{
     ChatStore,
+    DebugEvent,
+    IambAction,
     IambBufferId,
     IambError,
     IambId,
     IambInfo,
     IambResult,
+    IdentityInfo,
+    IdentityStatus,
     MessageAction,
     Need,
     ProgramAction,
     ProgramContext,
     ProgramStore,
     RoomAction,
+    RoomInfo,
+    SearchHit,
     SendAction,
+    SessionInfo,
     SortColumn,
     SortFieldRoom,
     SortFieldUser,
@@ -77,16 +89,19 @@ use crate::base::{
     UnreadInfo,
 };
 
-use self::{room::RoomState, welcome::WelcomeState};
+use self::{
+    gallery::GalleryState,
+    room::{download_attachment, RoomState},
+    welcome::WelcomeState,
+};
 use crate::message::MessageTimeStamp;
 
+pub mod gallery;
 pub mod room;
 pub mod welcome;
 
 type MatrixRoomInfo = Arc<(MatrixRoom, Option<Tags>)>;
 
-const MEMBER_FETCH_DEBOUNCE: Duration = Duration::from_secs(5);
-
 #[inline]
 fn bold_style() -> Style {
     Style::default().add_modifier(StyleModifier::BOLD)
@@ -121,7 +136,12 @@ fn selected_text(s: &str, selected: bool) -> Text {
     Text::from(selected_span(s, selected))
 }
 
-fn name_and_labels(name: &str, unread: bool, style: Style) -> (Span<'_>, Vec<Vec<Span<'_>>>) {
+fn name_and_labels(
+    name: &str,
+    unread: bool,
+    muted: bool,
+    style: Style,
+) -> (Span<'_>, Vec<Vec<Span<'_>>>) {
     let name_style = if unread {
         style.add_modifier(StyleModifier::BOLD)
     } else {
@@ -129,11 +149,15 @@ fn name_and_labels(name: &str, unread: bool, style: Style) -> (Span<'_>, Vec<Vec
     };
 
     let name = Span::styled(name, name_style);
-    let labels = if unread {
-        vec![vec![Span::styled("Unread", style)]]
-    } else {
-        vec![]
-    };
+    let mut labels = vec![];
+
+    if unread {
+        labels.push(vec![Span::styled("Unread", style)]);
+    }
+
+    if muted {
+        labels.push(vec![Span::styled("Muted", style)]);
+    }
 
     (name, labels)
 }
@@ -194,6 +218,13 @@ fn room_cmp<T: RoomLikeItem>(a: &T, b: &T, field: &SortFieldRoom) -> Ordering {
             // sort larger timestamps towards the top.
             some_cmp(a.recent_ts(), b.recent_ts(), |a, b| b.cmp(a))
         },
+        SortFieldRoom::Breadcrumbs => {
+            // Sort earlier breadcrumbs towards the top, and use activity for everything else.
+            match (a.breadcrumb(), b.breadcrumb()) {
+                (None, None) => room_cmp(a, b, &SortFieldRoom::Recent),
+                (a, b) => some_cmp(a, b, Ord::cmp),
+            }
+        },
     }
 }
 
@@ -272,6 +303,49 @@ trait RoomLikeItem {
     fn recent_ts(&self) -> Option<&MessageTimeStamp>;
     fn alias(&self) -> Option<&RoomAliasId>;
     fn name(&self) -> &str;
+    fn breadcrumb(&self) -> Option<usize>;
+}
+
+/// Find where a room is in the list of recently visited rooms from the account data.
+fn breadcrumb(room_id: &RoomId, store: &ProgramStore) -> Option<usize> {
+    store.application.sync_info.breadcrumbs.iter().position(|r| r == room_id)
+}
+
+/// Group rooms under the spaces that list them as children, in the order the spaces are given,
+/// followed by the rooms that aren't in any of them. A room in several spaces shows up under each
+/// one, and spaces without any of the rooms are left out.
+fn group_by_space<T: RoomLikeItem + Clone>(
+    rooms: &[T],
+    spaces: &[(OwnedRoomId, String)],
+    children: &HashMap<OwnedRoomId, Vec<OwnedRoomId>>,
+) -> Vec<(Option<OwnedRoomId>, String, Vec<T>)> {
+    let in_space = |room: &T, space: &OwnedRoomId| {
+        children
+            .get(space)
+            .is_some_and(|c| c.iter().any(|id| id == room.room_id()))
+    };
+
+    let mut groups = vec![];
+
+    for (space, name) in spaces {
+        let rooms = rooms.iter().filter(|r| in_space(r, space)).cloned().collect::<Vec<_>>();
+
+        if !rooms.is_empty() {
+            groups.push((Some(space.clone()), name.clone(), rooms));
+        }
+    }
+
+    let orphans = rooms
+        .iter()
+        .filter(|r| !spaces.iter().any(|(space, _)| in_space(r, space)))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    if !orphans.is_empty() {
+        groups.push((None, "Orphans".into(), orphans));
+    }
+
+    groups
 }
 
 #[inline]
@@ -309,11 +383,16 @@ macro_rules! delegate {
             IambWindow::Room($id) => $e,
             IambWindow::DirectList($id) => $e,
             IambWindow::MemberList($id, _, _) => $e,
+            IambWindow::Gallery($id) => $e,
+            IambWindow::PinList($id, _) => $e,
+            IambWindow::SearchResults($id, _) => $e,
             IambWindow::RoomList($id) => $e,
             IambWindow::SpaceList($id) => $e,
             IambWindow::VerifyList($id) => $e,
             IambWindow::Welcome($id) => $e,
             IambWindow::ChatList($id) => $e,
+            IambWindow::BreadcrumbList($id) => $e,
+            IambWindow::DebugEvents($id) => $e,
         }
     };
 }
@@ -321,12 +400,17 @@ macro_rules! delegate {
 pub enum IambWindow {
     DirectList(DirectListState),
     MemberList(MemberListState, OwnedRoomId, Option<Instant>),
+    Gallery(GalleryState),
+    PinList(PinListState, OwnedRoomId),
+    SearchResults(SearchResultsState, OwnedRoomId),
     Room(RoomState),
     VerifyList(VerifyListState),
     RoomList(RoomListState),
     SpaceList(SpaceListState),
     Welcome(WelcomeState),
     ChatList(ChatListState),
+    BreadcrumbList(BreadcrumbListState),
+    DebugEvents(DebugEventListState),
 }
 
 impl IambWindow {
@@ -344,10 +428,23 @@ impl IambWindow {
         ctx: ProgramContext,
         store: &mut ProgramStore,
     ) -> IambResult<EditInfo> {
-        if let IambWindow::Room(w) = self {
-            w.message_command(act, ctx, store).await
-        } else {
-            return Err(IambError::NoSelectedRoom.into());
+        match self {
+            IambWindow::Room(w) => w.message_command(act, ctx, store).await,
+            IambWindow::Gallery(w) => {
+                let MessageAction::Download(filename, flags) = act else {
+                    return Err(IambError::NoSelectedRoom.into());
+                };
+
+                let event_id = w.selected().ok_or(IambError::NoSelectedMessage)?;
+                let ChatStore { rooms, settings, worker, .. } = &mut store.application;
+                let info = rooms.get_or_default(w.room_id().clone());
+                let msg = info.get_event_mut(event_id).ok_or(IambError::NoSelectedMessage)?;
+
+                download_attachment(msg, filename, flags, &worker.client, settings).await
+            },
+            // There's never a reply or edit to cancel outside of a room.
+            _ if act.is_cancel() => Ok(None),
+            _ => Err(IambError::NoSelectedRoom.into()),
         }
     }
 
@@ -380,8 +477,12 @@ impl IambWindow {
 
 pub type DirectListState = ListState<DirectItem, IambInfo>;
 pub type MemberListState = ListState<MemberItem, IambInfo>;
-pub type RoomListState = ListState<RoomItem, IambInfo>;
+pub type PinListState = ListState<PinItem, IambInfo>;
+pub type SearchResultsState = ListState<SearchItem, IambInfo>;
+pub type RoomListState = ListState<RoomListItem, IambInfo>;
 pub type ChatListState = ListState<GenericChatItem, IambInfo>;
+pub type BreadcrumbListState = ListState<GenericChatItem, IambInfo>;
+pub type DebugEventListState = ListState<DebugEventItem, IambInfo>;
 pub type SpaceListState = ListState<SpaceItem, IambInfo>;
 pub type VerifyListState = ListState<VerifyItem, IambInfo>;
 
@@ -397,6 +498,12 @@ impl From<RoomState> for IambWindow {
     }
 }
 
+impl From<GalleryState> for IambWindow {
+    fn from(gallery: GalleryState) -> Self {
+        IambWindow::Gallery(gallery)
+    }
+}
+
 impl From<VerifyListState> for IambWindow {
     fn from(list: VerifyListState) -> Self {
         IambWindow::VerifyList(list)
@@ -503,26 +610,87 @@ impl WindowOps<IambInfo> for IambWindow {
                     .render(area, buf, state);
             },
             IambWindow::MemberList(state, room_id, last_fetch) => {
-                let need_fetch = match last_fetch {
-                    Some(i) => i.elapsed() >= MEMBER_FETCH_DEBOUNCE,
-                    None => true,
-                };
+                // Members get fetched in the background, so that big rooms don't hold up drawing.
+                let info = store.application.rooms.get_or_default(room_id.clone());
+
+                if !info.members_recently_fetched() {
+                    store.application.need_load.insert(room_id.clone(), Need::MEMBER_LIST);
+                }
+
+                let loaded = info.member_list.is_some();
 
-                if need_fetch {
-                    if let Ok(mems) = store.application.worker.members(room_id.clone()) {
+                // Only rebuild the list when there's a newer fetch to show.
+                if let Some((fetched, mems)) = &info.member_list {
+                    if *last_fetch != Some(*fetched) {
                         let mut items = mems
-                            .into_iter()
-                            .map(|m| MemberItem::new(m, room_id.clone()))
+                            .iter()
+                            .map(|m| MemberItem::new(m.clone(), room_id.clone()))
                             .collect::<Vec<_>>();
                         let fields = &store.application.settings.tunables.sort.members;
                         items.sort_by(|a, b| user_fields_cmp(a, b, fields));
                         state.set(items);
-                        *last_fetch = Some(Instant::now());
+                        *last_fetch = Some(*fetched);
                     }
                 }
 
+                let empty = if loaded {
+                    "No users here yet!"
+                } else {
+                    "Loading members..."
+                };
+
+                List::new(store)
+                    .empty_message(empty)
+                    .empty_alignment(Alignment::Center)
+                    .focus(focused)
+                    .render(area, buf, state);
+            },
+            IambWindow::Gallery(state) => state.draw(area, buf, focused, store),
+            IambWindow::PinList(state, room_id) => {
+                // Pinned events get fetched in the background, so that drawing doesn't wait.
+                let info = store.application.rooms.get_or_default(room_id.clone());
+
+                if !info.pinned_recently_fetched() {
+                    store.application.need_load.insert(room_id.clone(), Need::PINNED);
+                }
+
+                let info = store.application.rooms.get_or_default(room_id.clone());
+                let empty = if info.pinned_fetched.is_some() {
+                    "No pinned messages in this room"
+                } else {
+                    "Loading pinned messages..."
+                };
+                let items = info
+                    .pinned
+                    .iter()
+                    .map(|event_id| PinItem::new(room_id.clone(), event_id.clone(), info))
+                    .collect::<Vec<_>>();
+                state.set(items);
+
                 List::new(store)
-                    .empty_message("No users here yet!")
+                    .empty_message(empty)
+                    .empty_alignment(Alignment::Center)
+                    .focus(focused)
+                    .render(area, buf, state);
+            },
+            IambWindow::SearchResults(state, room_id) => {
+                let info = store.application.rooms.get_or_default(room_id.clone());
+                let items = info
+                    .search_results
+                    .iter()
+                    .flat_map(|(_, hits)| hits.iter())
+                    .map(|hit| SearchItem::new(room_id.clone(), hit))
+                    .collect::<Vec<_>>();
+                state.set(items);
+
+                let empty = if info.searching {
+                    "Searching..."
+                } else {
+                    "No messages found"
+                };
+
+                List::new(store)
+                    .empty_message(empty)
                     .empty_alignment(Alignment::Center)
                     .focus(focused)
                     .render(area, buf, state);
@@ -539,6 +707,12 @@ impl WindowOps<IambInfo> for IambWindow {
                 let fields = &store.application.settings.tunables.sort.rooms;
                 items.sort_by(|a, b| room_fields_cmp(a, b, fields));
 
+                let items = if store.application.room_list_grouped {
+                    group_room_items(items, store)
+                } else {
+                    items.into_iter().map(RoomListItem::Room).collect()
+                };
+
                 state.set(items);
 
                 List::new(store)
@@ -578,6 +752,53 @@ impl WindowOps<IambInfo> for IambWindow {
                     .focus(focused)
                     .render(area, buf, state);
             },
+            IambWindow::BreadcrumbList(state) => {
+                let sync_info = &store.application.sync_info;
+                let rooms = sync_info.rooms.clone().into_iter().map(|r| (r, false));
+                let dms = sync_info.dms.clone().into_iter().map(|r| (r, true));
+                let mut items = rooms
+                    .chain(dms)
+                    .map(|(room_info, is_dm)| GenericChatItem::new(room_info, store, is_dm))
+                    .filter(|item| item.breadcrumb.is_some())
+                    .collect::<Vec<_>>();
+                items.sort_by_key(|item| item.breadcrumb);
+
+                state.set(items);
+
+                List::new(store)
+                    .empty_message("No recently visited rooms")
+                    .empty_alignment(Alignment::Center)
+                    .focus(focused)
+                    .render(area, buf, state);
+            },
+            IambWindow::DebugEvents(state) => {
+                // Show the newest events at the top, so that they're in view as they arrive.
+                let events = store
+                    .application
+                    .debug_events
+                    .filtered()
+                    .rev()
+                    .cloned()
+                    .collect::<Vec<_>>();
+                let items = events
+                    .into_iter()
+                    .map(|ev| DebugEventItem::new(ev, store))
+                    .collect::<Vec<_>>();
+
+                state.set(items);
+
+                let empty = if store.application.settings.tunables.debug_event_log {
+                    "No events received yet"
+                } else {
+                    "Set debug_event_log to true in the settings to record events"
+                };
+
+                List::new(store)
+                    .empty_message(empty)
+                    .empty_alignment(Alignment::Center)
+                    .focus(focused)
+                    .render(area, buf, state);
+            },
             IambWindow::SpaceList(state) => {
                 let mut items = store
                     .application
@@ -600,7 +821,19 @@ impl WindowOps<IambInfo> for IambWindow {
             },
             IambWindow::VerifyList(state) => {
                 let verifications = &store.application.verifications;
-                let mut items = verifications.iter().map(VerifyItem::from).collect::<Vec<_>>();
+                let identities = &store.application.identities;
+                let user_id = &store.application.settings.profile.user_id;
+                let sessions = store
+                    .application
+                    .unverified_sessions
+                    .iter()
+                    .map(|s| VerifyItem::Session(user_id.clone(), s.clone()));
+                let mut items = verifications
+                    .iter()
+                    .map(VerifyItem::from)
+                    .chain(sessions)
+                    .chain(identities.iter().map(VerifyItem::from))
+                    .collect::<Vec<_>>();
 
                 // Sort the active verifications towards the top.
                 items.sort();
@@ -608,7 +841,7 @@ impl WindowOps<IambInfo> for IambWindow {
                 state.set(items);
 
                 List::new(store)
-                    .empty_message("No in-progress verifications")
+                    .empty_message("No verifications or encrypted rooms yet")
                     .empty_alignment(Alignment::Center)
                     .focus(focused)
                     .render(area, buf, state);
@@ -624,11 +857,18 @@ impl WindowOps<IambInfo> for IambWindow {
             IambWindow::MemberList(w, room_id, last_fetch) => {
                 IambWindow::MemberList(w.dup(store), room_id.clone(), *last_fetch)
             },
+            IambWindow::PinList(w, room_id) => IambWindow::PinList(w.dup(store), room_id.clone()),
+            IambWindow::SearchResults(w, room_id) => {
+                IambWindow::SearchResults(w.dup(store), room_id.clone())
+            },
+            IambWindow::Gallery(w) => w.dup(store).into(),
             IambWindow::RoomList(w) => w.dup(store).into(),
             IambWindow::SpaceList(w) => w.dup(store).into(),
-            IambWindow::VerifyList(w) => w.dup(store).into(),
+            IambWindow::VerifyList(w) => IambWindow::VerifyList(w.dup(store)),
             IambWindow::Welcome(w) => w.dup(store).into(),
             IambWindow::ChatList(w) => w.dup(store).into(),
+            IambWindow::BreadcrumbList(w) => IambWindow::BreadcrumbList(w.dup(store)),
+            IambWindow::DebugEvents(w) => IambWindow::DebugEvents(w.dup(store)),
         }
     }
 
@@ -664,11 +904,16 @@ impl Window<IambInfo> for IambWindow {
             IambWindow::Room(room) => IambId::Room(room.id().to_owned(), room.thread().cloned()),
             IambWindow::DirectList(_) => IambId::DirectList,
             IambWindow::MemberList(_, room_id, _) => IambId::MemberList(room_id.clone()),
+            IambWindow::Gallery(w) => IambId::Gallery(w.room_id().clone()),
+            IambWindow::PinList(_, room_id) => IambId::PinList(room_id.clone()),
+            IambWindow::SearchResults(_, room_id) => IambId::SearchResults(room_id.clone()),
             IambWindow::RoomList(_) => IambId::RoomList,
             IambWindow::SpaceList(_) => IambId::SpaceList,
             IambWindow::VerifyList(_) => IambId::VerifyList,
             IambWindow::Welcome(_) => IambId::Welcome,
             IambWindow::ChatList(_) => IambId::ChatList,
+            IambWindow::BreadcrumbList(_) => IambId::BreadcrumbList,
+            IambWindow::DebugEvents(_) => IambId::DebugEvents,
         }
     }
 
@@ -680,6 +925,8 @@ impl Window<IambInfo> for IambWindow {
             IambWindow::VerifyList(_) => bold_spans("Verifications"),
             IambWindow::Welcome(_) => bold_spans("Welcome to iamb"),
             IambWindow::ChatList(_) => bold_spans("DMs & Rooms"),
+            IambWindow::BreadcrumbList(_) => bold_spans("Recently Visited"),
+            IambWindow::DebugEvents(_) => debug_events_title(store),
 
             IambWindow::Room(w) => {
                 let title = store.application.get_room_title(w.id());
@@ -696,6 +943,29 @@ impl Window<IambInfo> for IambWindow {
                 ];
                 Line::from(v)
             },
+            IambWindow::Gallery(state) => {
+                let title = store.application.get_room_title(state.room_id().as_ref());
+                let n = state.len();
+                let v = vec![
+                    bold_span("Gallery "),
+                    Span::styled(format!("({n}): "), bold_style()),
+                    title.into(),
+                ];
+                Line::from(v)
+            },
+            IambWindow::PinList(state, room_id) => {
+                let title = store.application.get_room_title(room_id.as_ref());
+                let n = state.len();
+                let v = vec![
+                    bold_span("Pinned Messages "),
+                    Span::styled(format!("({n}): "), bold_style()),
+                    title.into(),
+                ];
+                Line::from(v)
+            },
+            IambWindow::SearchResults(state, room_id) => {
+                search_results_title(state, room_id, store)
+            },
         }
     }
 
@@ -707,6 +977,8 @@ impl Window<IambInfo> for IambWindow {
             IambWindow::VerifyList(_) => bold_spans("Verifications"),
             IambWindow::Welcome(_) => bold_spans("Welcome to iamb"),
             IambWindow::ChatList(_) => bold_spans("DMs & Rooms"),
+            IambWindow::BreadcrumbList(_) => bold_spans("Recently Visited"),
+            IambWindow::DebugEvents(_) => debug_events_title(store),
 
             IambWindow::Room(w) => w.get_title(store),
             IambWindow::MemberList(state, room_id, _) => {
@@ -719,6 +991,29 @@ impl Window<IambInfo> for IambWindow {
                 ];
                 Line::from(v)
             },
+            IambWindow::Gallery(state) => {
+                let title = store.application.get_room_title(state.room_id().as_ref());
+                let n = state.len();
+                let v = vec![
+                    bold_span("Gallery "),
+                    Span::styled(format!("({n}): "), bold_style()),
+                    title.into(),
+                ];
+                Line::from(v)
+            },
+            IambWindow::PinList(state, room_id) => {
+                let title = store.application.get_room_title(room_id.as_ref());
+                let n = state.len();
+                let v = vec![
+                    bold_span("Pinned Messages "),
+                    Span::styled(format!("({n}): "), bold_style()),
+                    title.into(),
+                ];
+                Line::from(v)
+            },
+            IambWindow::SearchResults(state, room_id) => {
+                search_results_title(state, room_id, store)
+            },
         }
     }
 
@@ -743,6 +1038,25 @@ impl Window<IambInfo> for IambWindow {
 
                 return Ok(win);
             },
+            IambId::Gallery(room_id) => {
+                let gallery = GalleryState::new(room_id);
+
+                return Ok(gallery.into());
+            },
+            IambId::PinList(room_id) => {
+                let id = IambBufferId::PinList(room_id.clone());
+                let list = PinListState::new(id, vec![]);
+                let win = IambWindow::PinList(list, room_id);
+
+                return Ok(win);
+            },
+            IambId::SearchResults(room_id) => {
+                let id = IambBufferId::SearchResults(room_id.clone());
+                let list = SearchResultsState::new(id, vec![]);
+                let win = IambWindow::SearchResults(list, room_id);
+
+                return Ok(win);
+            },
             IambId::RoomList => {
                 let list = RoomListState::new(IambBufferId::RoomList, vec![]);
 
@@ -768,6 +1082,16 @@ impl Window<IambInfo> for IambWindow {
 
                 Ok(list.into())
             },
+            IambId::BreadcrumbList => {
+                let list = BreadcrumbListState::new(IambBufferId::BreadcrumbList, vec![]);
+
+                Ok(IambWindow::BreadcrumbList(list))
+            },
+            IambId::DebugEvents => {
+                let list = DebugEventListState::new(IambBufferId::DebugEvents, vec![]);
+
+                Ok(IambWindow::DebugEvents(list))
+            },
         }
     }
 
@@ -781,6 +1105,7 @@ impl Window<IambInfo> for IambWindow {
         } else {
             let room_id = worker.join_room(name.clone())?;
             names.insert(name, room_id.clone());
+            store.application.version_checks.insert(room_id.clone());
 
             let (room, name, tags) = store.application.worker.get_room(room_id)?;
             let room = RoomState::new(room, None, name, tags, store);
@@ -808,6 +1133,8 @@ pub struct GenericChatItem {
     name: String,
     alias: Option<OwnedRoomAliasId>,
     unread: UnreadInfo,
+    breadcrumb: Option<usize>,
+    muted: bool,
     is_dm: bool,
 }
 
@@ -820,13 +1147,23 @@ impl GenericChatItem {
         let name = info.name.clone().unwrap_or_default();
         let alias = room.canonical_alias();
         let unread = info.unreads(&store.application.settings);
+        let muted = info.notification_mode == Some(RoomNotificationMode::Mute);
         info.tags = room_info.deref().1.clone();
+        let breadcrumb = breadcrumb(room_id, store);
 
         if let Some(alias) = &alias {
             store.application.names.insert(alias.to_string(), room_id.to_owned());
         }
 
-        GenericChatItem { room_info, name, alias, is_dm, unread }
+        GenericChatItem {
+            room_info,
+            name,
+            alias,
+            is_dm,
+            unread,
+            breadcrumb,
+            muted,
+        }
     }
 
     #[inline]
@@ -868,6 +1205,10 @@ impl RoomLikeItem for GenericChatItem {
     fn is_unread(&self) -> bool {
         self.unread.is_unread()
     }
+
+    fn breadcrumb(&self) -> Option<usize> {
+        self.breadcrumb
+    }
 }
 
 impl ToString for GenericChatItem {
@@ -877,10 +1218,15 @@ impl ToString for GenericChatItem {
 }
 
 impl ListItem<IambInfo> for GenericChatItem {
-    fn show(&self, selected: bool, _: &ViewportContext<ListCursor>, _: &mut ProgramStore) -> Text {
+    fn show(
+        &self,
+        selected: bool,
+        _: &ViewportContext<ListCursor>,
+        _: &mut ProgramStore,
+    ) -> Text<'_> {
         let unread = self.unread.is_unread();
         let style = selected_style(selected);
-        let (name, mut labels) = name_and_labels(&self.name, unread, style);
+        let (name, mut labels) = name_and_labels(&self.name, unread, self.muted, style);
         let mut spans = vec![name];
 
         labels.push(if self.is_dm {
@@ -919,6 +1265,8 @@ pub struct RoomItem {
     name: String,
     alias: Option<OwnedRoomAliasId>,
     unread: UnreadInfo,
+    breadcrumb: Option<usize>,
+    muted: bool,
 }
 
 impl RoomItem {
@@ -930,13 +1278,15 @@ impl RoomItem {
         let name = info.name.clone().unwrap_or_default();
         let alias = room.canonical_alias();
         let unread = info.unreads(&store.application.settings);
+        let muted = info.notification_mode == Some(RoomNotificationMode::Mute);
         info.tags = room_info.deref().1.clone();
+        let breadcrumb = breadcrumb(room_id, store);
 
         if let Some(alias) = &alias {
             store.application.names.insert(alias.to_string(), room_id.to_owned());
         }
 
-        RoomItem { room_info, name, alias, unread }
+        RoomItem { room_info, name, alias, unread, breadcrumb, muted }
     }
 
     #[inline]
@@ -978,6 +1328,10 @@ impl RoomLikeItem for RoomItem {
     fn is_unread(&self) -> bool {
         self.unread.is_unread()
     }
+
+    fn breadcrumb(&self) -> Option<usize> {
+        self.breadcrumb
+    }
 }
 
 impl ToString for RoomItem {
@@ -990,7 +1344,7 @@ impl ListItem<IambInfo> for RoomItem {
     fn show(&self, selected: bool, _: &ViewportContext<ListCursor>, _: &mut ProgramStore) -> Text {
         let unread = self.unread.is_unread();
         let style = selected_style(selected);
-        let (name, mut labels) = name_and_labels(&self.name, unread, style);
+        let (name, mut labels) = name_and_labels(&self.name, unread, self.muted, style);
         let mut spans = vec![name];
 
         if let Some(tags) = &self.tags() {
@@ -1018,12 +1372,150 @@ impl Promptable<ProgramContext, ProgramStore, IambInfo> for RoomItem {
     }
 }
 
+/// Lay out the room list's groups, with a header for each one, and its rooms unless it's been
+/// collapsed.
+fn group_room_items(rooms: Vec<RoomItem>, store: &mut ProgramStore) -> Vec<RoomListItem> {
+    let mut spaces = store
+        .application
+        .sync_info
+        .spaces
+        .clone()
+        .into_iter()
+        .map(|room_info| SpaceItem::new(room_info, store))
+        .collect::<Vec<_>>();
+    let fields = &store.application.settings.tunables.sort.spaces;
+    spaces.sort_by(|a, b| room_fields_cmp(a, b, fields));
+
+    let spaces = spaces
+        .into_iter()
+        .map(|space| (space.room_id().to_owned(), space.name))
+        .collect::<Vec<_>>();
+    let children = &store.application.sync_info.space_children;
+    let mut items = vec![];
+
+    for (space, name, rooms) in group_by_space(&rooms, &spaces, children) {
+        let collapsed = store.application.collapsed_groups.contains(&space);
+        let unread = rooms.iter().any(RoomLikeItem::is_unread);
+        let header = RoomGroupItem { space, name, rooms: rooms.len(), unread, collapsed };
+        items.push(RoomListItem::Group(header));
+
+        if !collapsed {
+            items.extend(rooms.into_iter().map(RoomListItem::Room));
+        }
+    }
+
+    items
+}
+
+/// The header for a group of rooms in the room list.
+#[derive(Clone)]
+pub struct RoomGroupItem {
+    /// The space that the rooms are in, or [None] for rooms outside of any space.
+    space: Option<OwnedRoomId>,
+    name: String,
+    rooms: usize,
+    unread: bool,
+    collapsed: bool,
+}
+
+impl RoomGroupItem {
+    fn show(&self, selected: bool) -> Text<'_> {
+        let style = selected_style(selected).add_modifier(StyleModifier::BOLD);
+        let marker = if self.collapsed { "▸ " } else { "▾ " };
+        let mut spans = vec![
+            Span::styled(marker, style),
+            Span::styled(self.name.as_str(), style),
+        ];
+        let mut labels = vec![vec![Span::styled(self.rooms.to_string(), style)]];
+
+        if self.unread {
+            labels.push(vec![Span::styled("Unread", style)]);
+        }
+
+        append_tags(labels, &mut spans, style);
+
+        Text::from(Line::from(spans))
+    }
+
+    fn toggle(&self, store: &mut ProgramStore) {
+        let collapsed = &mut store.application.collapsed_groups;
+
+        if !collapsed.remove(&self.space) {
+            collapsed.insert(self.space.clone());
+        }
+    }
+}
+
+/// An entry in the room list, which is either a room or, when grouping by space, the header for
+/// a group of rooms.
+#[derive(Clone)]
+pub enum RoomListItem {
+    Group(RoomGroupItem),
+    Room(RoomItem),
+}
+
+impl Display for RoomListItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RoomListItem::Group(group) => write!(f, "{}", group.name),
+            RoomListItem::Room(room) => write!(f, "{}", room.name),
+        }
+    }
+}
+
+impl ListItem<IambInfo> for RoomListItem {
+    fn show(
+        &self,
+        selected: bool,
+        viewport: &ViewportContext<ListCursor>,
+        store: &mut ProgramStore,
+    ) -> Text<'_> {
+        match self {
+            RoomListItem::Group(group) => group.show(selected),
+            RoomListItem::Room(room) => room.show(selected, viewport, store),
+        }
+    }
+
+    fn get_word(&self) -> Option<String> {
+        match self {
+            RoomListItem::Group(group) => group.space.as_ref().map(ToString::to_string),
+            RoomListItem::Room(room) => room.get_word(),
+        }
+    }
+}
+
+impl Promptable<ProgramContext, ProgramStore, IambInfo> for RoomListItem {
+    fn prompt(
+        &mut self,
+        act: &PromptAction,
+        ctx: &ProgramContext,
+        store: &mut ProgramStore,
+    ) -> EditResult<Vec<(ProgramAction, ProgramContext)>, IambInfo> {
+        match (self, act) {
+            (RoomListItem::Room(room), act) => room.prompt(act, ctx, store),
+            (RoomListItem::Group(group), PromptAction::Submit) => {
+                group.toggle(store);
+
+                Ok(vec![])
+            },
+            (RoomListItem::Group(_), _) => {
+                let msg = "Space headers can only be expanded or collapsed";
+                let err = EditError::Failure(msg.into());
+
+                Err(err)
+            },
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct DirectItem {
     room_info: MatrixRoomInfo,
     name: String,
     alias: Option<OwnedRoomAliasId>,
     unread: UnreadInfo,
+    breadcrumb: Option<usize>,
+    muted: bool,
 }
 
 impl DirectItem {
@@ -1031,12 +1523,14 @@ impl DirectItem {
         let room_id = room_info.0.room_id().to_owned();
         let alias = room_info.0.canonical_alias();
 
-        let info = store.application.rooms.get_or_default(room_id);
+        let info = store.application.rooms.get_or_default(room_id.clone());
         let name = info.name.clone().unwrap_or_default();
         let unread = info.unreads(&store.application.settings);
+        let muted = info.notification_mode == Some(RoomNotificationMode::Mute);
         info.tags = room_info.deref().1.clone();
+        let breadcrumb = breadcrumb(&room_id, store);
 
-        DirectItem { room_info, name, alias, unread }
+        DirectItem { room_info, name, alias, unread, breadcrumb, muted }
     }
 
     #[inline]
@@ -1078,6 +1572,10 @@ impl RoomLikeItem for DirectItem {
     fn is_unread(&self) -> bool {
         self.unread.is_unread()
     }
+
+    fn breadcrumb(&self) -> Option<usize> {
+        self.breadcrumb
+    }
 }
 
 impl ToString for DirectItem {
@@ -1090,7 +1588,7 @@ impl ListItem<IambInfo> for DirectItem {
     fn show(&self, selected: bool, _: &ViewportContext<ListCursor>, _: &mut ProgramStore) -> Text {
         let unread = self.unread.is_unread();
         let style = selected_style(selected);
-        let (name, mut labels) = name_and_labels(&self.name, unread, style);
+        let (name, mut labels) = name_and_labels(&self.name, unread, self.muted, style);
         let mut spans = vec![name];
 
         if let Some(tags) = &self.tags() {
@@ -1123,6 +1621,7 @@ pub struct SpaceItem {
     room_info: MatrixRoomInfo,
     name: String,
     alias: Option<OwnedRoomAliasId>,
+    breadcrumb: Option<usize>,
 }
 
 impl SpaceItem {
@@ -1135,12 +1634,13 @@ impl SpaceItem {
             .clone()
             .unwrap_or_default();
         let alias = room_info.0.canonical_alias();
+        let breadcrumb = breadcrumb(room_id, store);
 
         if let Some(alias) = &alias {
             store.application.names.insert(alias.to_string(), room_id.to_owned());
         }
 
-        SpaceItem { room_info, name, alias }
+        SpaceItem { room_info, name, alias, breadcrumb }
     }
 
     #[inline]
@@ -1177,6 +1677,10 @@ impl RoomLikeItem for SpaceItem {
         // XXX: this needs to check whether the space contains rooms with unread messages
         false
     }
+
+    fn breadcrumb(&self) -> Option<usize> {
+        self.breadcrumb
+    }
 }
 
 impl ToString for SpaceItem {
@@ -1207,29 +1711,57 @@ impl Promptable<ProgramContext, ProgramStore, IambInfo> for SpaceItem {
 }
 
 #[derive(Clone)]
-pub struct VerifyItem {
-    user_dev: String,
-    sasv1: SasVerification,
+pub enum VerifyItem {
+    /// An in-progress or finished verification, keyed by `user/device`.
+    Sas(String, Box<SasVerification>),
+
+    /// One of the user's own sessions that hasn't been verified, along with the user's ID.
+    Session(OwnedUserId, SessionInfo),
+
+    /// The trust status of someone we share encrypted rooms with.
+    Identity(IdentityInfo),
 }
 
 impl VerifyItem {
-    fn new(user_dev: String, sasv1: SasVerification) -> Self {
-        VerifyItem { user_dev, sasv1 }
-    }
-
     fn show_item(&self) -> String {
-        let state = if self.sasv1.is_done() {
-            "done"
-        } else if self.sasv1.is_cancelled() {
-            "cancelled"
-        } else if self.sasv1.emoji().is_some() {
-            "accepted"
-        } else {
-            "not accepted"
+        let sasv1 = match self {
+            VerifyItem::Sas(_, sasv1) => sasv1,
+            VerifyItem::Session(_, session) => {
+                return match &session.display_name {
+                    Some(name) => {
+                        format!("Your session {name} ({}, not verified)", session.device_id)
+                    },
+                    None => format!("Your session {} (not verified)", session.device_id),
+                };
+            },
+            VerifyItem::Identity(identity) => {
+                let state = match identity.status {
+                    IdentityStatus::Changed => "identity changed",
+                    IdentityStatus::Unverified => "not verified",
+                    IdentityStatus::Missing => "no cross-signing keys",
+                    IdentityStatus::Verified => "verified",
+                };
+                let rooms = match identity.rooms {
+                    1 => "1 encrypted room".to_string(),
+                    n => format!("{n} encrypted rooms"),
+                };
+
+                return format!("{} ({state}, {rooms})", identity.user_id);
+            },
         };
 
-        if self.sasv1.is_self_verification() {
-            let device = self.sasv1.other_device();
+        let state = match sasv1.state() {
+            SasState::Done { .. } => "done",
+            SasState::Cancelled(_) => "cancelled",
+            SasState::Started { .. } if sasv1.we_started() => "waiting",
+            SasState::Started { .. } => "not accepted",
+            SasState::Accepted { .. } => "waiting",
+            SasState::KeysExchanged { .. } => "accepted",
+            SasState::Confirmed => "confirmed",
+        };
+
+        if sasv1.is_self_verification() {
+            let device = sasv1.other_device();
 
             if let Some(display_name) = device.display_name() {
                 format!("Device verification with {display_name} ({state})")
@@ -1237,14 +1769,22 @@ impl VerifyItem {
                 format!("Device verification with device {} ({})", device.device_id(), state)
             }
         } else {
-            format!("User Verification with {} ({})", self.sasv1.other_user_id(), state)
+            format!("User Verification with {} ({})", sasv1.other_user_id(), state)
+        }
+    }
+
+    fn key(&self) -> &str {
+        match self {
+            VerifyItem::Sas(user_dev, _) => user_dev.as_str(),
+            VerifyItem::Session(_, session) => session.device_id.as_str(),
+            VerifyItem::Identity(identity) => identity.user_id.as_str(),
         }
     }
 }
 
 impl PartialEq for VerifyItem {
     fn eq(&self, other: &Self) -> bool {
-        self.user_dev == other.user_dev
+        self.key() == other.key()
     }
 }
 
@@ -1270,18 +1810,33 @@ impl Ord for VerifyItem {
             }
         }
 
-        let state1 = state_val(&self.sasv1);
-        let state2 = state_val(&other.sasv1);
+        let (sas1, sas2) = match (self, other) {
+            (VerifyItem::Sas(_, sas1), VerifyItem::Sas(_, sas2)) => (sas1, sas2),
+            (VerifyItem::Sas(..), _) => return Ordering::Less,
+            (_, VerifyItem::Sas(..)) => return Ordering::Greater,
+            (VerifyItem::Session(_, s1), VerifyItem::Session(_, s2)) => {
+                return s1.device_id.cmp(&s2.device_id);
+            },
+            (VerifyItem::Session(..), VerifyItem::Identity(_)) => return Ordering::Less,
+            (VerifyItem::Identity(_), VerifyItem::Session(..)) => return Ordering::Greater,
+            (VerifyItem::Identity(id1), VerifyItem::Identity(id2)) => {
+                // Put the identities that need attention first.
+                return id1.status.cmp(&id2.status).then_with(|| id1.user_id.cmp(&id2.user_id));
+            },
+        };
+
+        let state1 = state_val(sas1);
+        let state2 = state_val(sas2);
 
-        let dev1 = device_val(&self.sasv1);
-        let dev2 = device_val(&other.sasv1);
+        let dev1 = device_val(sas1);
+        let dev2 = device_val(sas2);
 
         let scmp = state1.cmp(&state2);
         let dcmp = dev1.cmp(&dev2);
 
         scmp.then(dcmp).then_with(|| {
-            let did1 = self.sasv1.other_device().device_id();
-            let did2 = other.sasv1.other_device().device_id();
+            let did1 = sas1.other_device().device_id();
+            let did2 = sas2.other_device().device_id();
 
             did1.cmp(did2)
         })
@@ -1296,24 +1851,47 @@ impl PartialOrd for VerifyItem {
 
 impl From<(&String, &SasVerification)> for VerifyItem {
     fn from((user_dev, sasv1): (&String, &SasVerification)) -> Self {
-        VerifyItem::new(user_dev.clone(), sasv1.clone())
+        VerifyItem::Sas(user_dev.clone(), Box::new(sasv1.clone()))
+    }
+}
+
+impl From<&IdentityInfo> for VerifyItem {
+    fn from(identity: &IdentityInfo) -> Self {
+        VerifyItem::Identity(identity.clone())
     }
 }
 
 impl ToString for VerifyItem {
     fn to_string(&self) -> String {
-        if self.sasv1.is_done() {
-            String::new()
-        } else if self.sasv1.is_cancelled() {
-            format!(":verify request {}", self.sasv1.other_user_id())
-        } else if self.sasv1.emoji().is_some() {
-            format!(":verify confirm {}", self.user_dev)
-        } else {
-            format!(":verify accept {}", self.user_dev)
+        let (user_dev, sasv1) = match self {
+            VerifyItem::Sas(user_dev, sasv1) => (user_dev, sasv1),
+            VerifyItem::Session(user_id, _) => return format!(":verify request {user_id}"),
+            VerifyItem::Identity(identity) => {
+                return match identity.status {
+                    IdentityStatus::Changed | IdentityStatus::Unverified => {
+                        format!(":verify request {}", identity.user_id)
+                    },
+                    IdentityStatus::Missing | IdentityStatus::Verified => String::new(),
+                };
+            },
+        };
+
+        match sasv1.state() {
+            SasState::Cancelled(_) => format!(":verify request {}", sasv1.other_user_id()),
+            SasState::KeysExchanged { .. } => format!(":verify confirm {}", user_dev),
+            SasState::Started { .. } if !sasv1.we_started() => {
+                format!(":verify accept {}", user_dev)
+            },
+            _ => String::new(),
         }
     }
 }
 
+/// Format the decimal short authentication string the way other clients show it.
+fn format_decimals((a, b, c): (u16, u16, u16)) -> String {
+    format!("{a} {b} {c}")
+}
+
 impl ListItem<IambInfo> for VerifyItem {
     fn show(&self, selected: bool, _: &ViewportContext<ListCursor>, _: &mut ProgramStore) -> Text {
         let mut lines = vec![];
@@ -1322,36 +1900,105 @@ impl ListItem<IambInfo> for VerifyItem {
         let item = Span::styled(self.show_item(), selected_style(selected));
         lines.push(Line::from(item));
 
-        if self.sasv1.is_done() {
-            // Print nothing.
-        } else if self.sasv1.is_cancelled() {
-            if let Some(info) = self.sasv1.cancel_info() {
-                lines.push(Line::from(format!("    Cancelled: {}", info.reason())));
-                lines.push(Line::from(""));
-            }
-
-            lines.push(Line::from("    You can start a new verification request with:"));
-        } else if let Some(emoji) = self.sasv1.emoji() {
-            lines.push(Line::from(
-                "    Both devices should see the following Emoji sequence:".to_string(),
-            ));
-            lines.push(Line::from(""));
-
-            for line in format_emojis(emoji).lines() {
-                lines.push(Line::from(format!("    {line}")));
-            }
-
-            lines.push(Line::from(""));
-            lines.push(Line::from("    If they don't match, run:"));
-            lines.push(Line::from(""));
-            lines.push(Line::from(Span::styled(
-                format!(":verify mismatch {}", self.user_dev),
-                bold,
-            )));
-            lines.push(Line::from(""));
-            lines.push(Line::from("    If everything looks right, you can confirm with:"));
-        } else {
-            lines.push(Line::from("    To accept this request, run:"));
+        match self {
+            VerifyItem::Session(..) => {
+                lines.push(Line::from(
+                    "    Messages from this session can't be trusted until it's verified. To send a",
+                ));
+                lines.push(Line::from("    verification request to your other sessions, run:"));
+            },
+            VerifyItem::Identity(identity) => {
+                match identity.status {
+                    IdentityStatus::Changed => {
+                        let alert =
+                            Style::default().fg(Color::Red).add_modifier(StyleModifier::BOLD);
+                        lines.push(Line::from(Span::styled(
+                            "    WARNING: this user's identity has changed since you first saw it!",
+                            alert,
+                        )));
+                        lines.push(Line::from(
+                            "    Their messages may not really be from them until you verify them again with the",
+                        ));
+                        lines.push(Line::from(
+                            "    command below, or accept the change with :verify ack.",
+                        ));
+                    },
+                    IdentityStatus::Unverified => {
+                        lines.push(Line::from("    To verify this user, run:"));
+                    },
+                    IdentityStatus::Missing => {
+                        lines.push(Line::from("    This user hasn't set up cross-signing yet."));
+                    },
+                    IdentityStatus::Verified => {},
+                }
+            },
+            VerifyItem::Sas(user_dev, sasv1) => {
+                match sasv1.state() {
+                    SasState::Done { .. } => {
+                        // Print nothing.
+                    },
+                    SasState::Cancelled(info) => {
+                        lines.push(Line::from(format!("    Cancelled: {}", info.reason())));
+                        lines.push(Line::from(""));
+                        lines
+                            .push(Line::from("    You can start a new verification request with:"));
+                    },
+                    SasState::Started { .. } if sasv1.we_started() => {
+                        lines.push(Line::from(
+                            "    Waiting for the other device to accept the request...",
+                        ));
+                    },
+                    SasState::Started { .. } => {
+                        lines.push(Line::from("    To accept this request, run:"));
+                    },
+                    SasState::Accepted { .. } => {
+                        lines.push(Line::from(
+                            "    Waiting for the devices to exchange keys before showing the codes to compare...",
+                        ));
+                    },
+                    SasState::Confirmed => {
+                        lines.push(Line::from(
+                            "    Waiting for the other device to confirm that the codes matched...",
+                        ));
+                    },
+                    SasState::KeysExchanged { decimals, .. } => {
+                        if let Some(emoji) = sasv1.emoji() {
+                            lines.push(Line::from(
+                                "    Both devices should see the following Emoji sequence:",
+                            ));
+                            lines.push(Line::from(""));
+
+                            for line in format_emojis(emoji).lines() {
+                                lines.push(Line::from(format!("    {line}")));
+                            }
+
+                            lines.push(Line::from(""));
+                            lines.push(Line::from("    Or, if the other device shows numbers:"));
+                        } else {
+                            lines.push(Line::from(
+                                "    Both devices should see the following numbers:",
+                            ));
+                        }
+
+                        lines.push(Line::from(""));
+                        lines.push(Line::from(Span::styled(
+                            format!("    {}", format_decimals(decimals)),
+                            bold,
+                        )));
+                        lines.push(Line::from(""));
+                        lines.push(Line::from("    If they don't match, run:"));
+                        lines.push(Line::from(""));
+                        lines.push(Line::from(Span::styled(
+                            format!(":verify mismatch {}", user_dev),
+                            bold,
+                        )));
+                        lines.push(Line::from(""));
+                        lines.push(Line::from(
+                            "    If everything looks right, you can confirm with:",
+                        ));
+                    },
+                }
+            },
         }
 
         let cmd = self.to_string();
@@ -1380,11 +2027,25 @@ impl Promptable<ProgramContext, ProgramStore, IambInfo> for VerifyItem {
     fn prompt(
         &mut self,
         act: &PromptAction,
-        _: &ProgramContext,
+        ctx: &ProgramContext,
         _: &mut ProgramStore,
     ) -> EditResult<Vec<(ProgramAction, ProgramContext)>, IambInfo> {
         match act {
-            PromptAction::Submit => Ok(vec![]),
+            PromptAction::Submit => {
+                match self {
+                    VerifyItem::Identity(IdentityInfo {
+                        user_id,
+                        status: IdentityStatus::Changed | IdentityStatus::Unverified,
+                        ..
+                    }) |
+                    VerifyItem::Session(user_id, _) => {
+                        let act = IambAction::VerifyRequest(user_id.to_string());
+
+                        Ok(vec![(act.into(), ctx.clone())])
+                    },
+                    _ => Ok(vec![]),
+                }
+            },
             PromptAction::Abort(_) => {
                 let msg = "Cannot abort entry inside a list";
                 let err = EditError::Failure(msg.into());
@@ -1457,7 +2118,14 @@ impl ListItem<IambInfo> for MemberItem {
             MembershipState::Invite => Span::raw(" (invited)").into(),
             MembershipState::Knock => Span::raw(" (wants to join)").into(),
             MembershipState::Leave => Span::raw(" (left)").into(),
-            MembershipState::Join => None,
+            MembershipState::Join => {
+                store
+                    .application
+                    .presences
+                    .get(user_id)
+                    .and_then(Option::as_ref)
+                    .map(|presence| Span::raw(format!(" ({})", presence.describe())))
+            },
             _ => None,
         };
 
@@ -1496,6 +2164,266 @@ impl Promptable<ProgramContext, ProgramStore, IambInfo> for MemberItem {
     }
 }
 
+fn debug_events_title(store: &ProgramStore) -> Line<'static> {
+    let log = &store.application.debug_events;
+    let mut spans = vec![bold_span("Debug Events")];
+
+    if let Some(filter) = &log.filter {
+        spans.push(Span::from(format!(" (matching {filter:?})")));
+    }
+
+    if log.paused {
+        spans.push(Span::from(" [paused]"));
+    }
+
+    Line::from(spans)
+}
+
+/// An incoming event listed in the `:debug events` window.
+#[derive(Clone)]
+pub struct DebugEventItem {
+    event: DebugEvent,
+    summary: String,
+}
+
+impl DebugEventItem {
+    fn new(event: DebugEvent, store: &mut ProgramStore) -> Self {
+        let room = store.application.get_room_title(event.room_id.as_ref());
+        let sender = event.sender.as_ref().map(|s| s.as_str()).unwrap_or("-");
+        let summary = format!("{}  {}  {}", event.event_type, room, sender);
+
+        DebugEventItem { event, summary }
+    }
+}
+
+impl Display for DebugEventItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.summary)
+    }
+}
+
+impl ListItem<IambInfo> for DebugEventItem {
+    fn show(
+        &self,
+        selected: bool,
+        _: &ViewportContext<ListCursor>,
+        _: &mut ProgramStore,
+    ) -> Text<'_> {
+        selected_text(self.summary.as_str(), selected)
+    }
+
+    fn get_word(&self) -> Option<String> {
+        self.event.event_type.clone().into()
+    }
+}
+
+impl Promptable<ProgramContext, ProgramStore, IambInfo> for DebugEventItem {
+    fn prompt(
+        &mut self,
+        act: &PromptAction,
+        ctx: &ProgramContext,
+        _: &mut ProgramStore,
+    ) -> EditResult<Vec<(ProgramAction, ProgramContext)>, IambInfo> {
+        match act {
+            PromptAction::Submit => {
+                let room = IambId::Room(self.event.room_id.clone(), None);
+                let open = WindowAction::Switch(OpenTarget::Application(room));
+
+                Ok(vec![(open.into(), ctx.clone())])
+            },
+            PromptAction::Abort(_) => {
+                let msg = "Cannot abort entry inside a list";
+                let err = EditError::Failure(msg.into());
+
+                Err(err)
+            },
+            PromptAction::Recall(..) => {
+                let msg = "Cannot recall history inside a list";
+                let err = EditError::Failure(msg.into());
+
+                Err(err)
+            },
+        }
+    }
+}
+
+/// Open a room and select one of its messages. Jumping fetches the surrounding history first if
+/// the message isn't loaded.
+fn jump_to_event(
+    room_id: &OwnedRoomId,
+    event_id: &OwnedEventId,
+    ctx: &ProgramContext,
+) -> Vec<(ProgramAction, ProgramContext)> {
+    let room = IambId::Room(room_id.clone(), None);
+    let open = WindowAction::Switch(OpenTarget::Application(room));
+    let jump = IambAction::from(MessageAction::Jump(event_id.clone()));
+
+    vec![
+        (open.into(), ctx.clone()),
+        (Action::from(jump), ctx.clone()),
+    ]
+}
+
+/// A message listed in the `:room pins` window.
+#[derive(Clone)]
+pub struct PinItem {
+    room_id: OwnedRoomId,
+    event_id: OwnedEventId,
+    preview: String,
+}
+
+impl PinItem {
+    fn new(room_id: OwnedRoomId, event_id: OwnedEventId, info: &RoomInfo) -> Self {
+        let preview = pin_preview(&event_id, info);
+
+        PinItem { room_id, event_id, preview }
+    }
+}
+
+/// Summarize a pinned message by its sender and the first line of its body. Messages that
+/// haven't been loaded into the scrollback yet are shown by their identifier instead.
+fn pin_preview(event_id: &OwnedEventId, info: &RoomInfo) -> String {
+    let Some(msg) = info.get_event(event_id) else {
+        return format!("{event_id} (not loaded yet)");
+    };
+
+    let sender = match info.display_names.get(&msg.sender) {
+        Some(name) => name.as_str(),
+        None => msg.sender.as_str(),
+    };
+    let body = msg.event.body();
+    let line = body.lines().find(|l| !l.trim().is_empty()).unwrap_or_default();
+
+    format!("{sender}: {}", line.trim())
+}
+
+impl Display for PinItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.preview)
+    }
+}
+
+impl ListItem<IambInfo> for PinItem {
+    fn show(&self, selected: bool, _: &ViewportContext<ListCursor>, _: &mut ProgramStore) -> Text {
+        selected_text(self.preview.as_str(), selected)
+    }
+
+    fn get_word(&self) -> Option<String> {
+        self.event_id.to_string().into()
+    }
+}
+
+impl Promptable<ProgramContext, ProgramStore, IambInfo> for PinItem {
+    fn prompt(
+        &mut self,
+        act: &PromptAction,
+        ctx: &ProgramContext,
+        _: &mut ProgramStore,
+    ) -> EditResult<Vec<(ProgramAction, ProgramContext)>, IambInfo> {
+        match act {
+            PromptAction::Submit => Ok(jump_to_event(&self.room_id, &self.event_id, ctx)),
+            PromptAction::Abort(_) => {
+                let msg = "Cannot abort entry inside a list";
+                let err = EditError::Failure(msg.into());
+
+                Err(err)
+            },
+            PromptAction::Recall(..) => {
+                let msg = "Cannot recall history inside a list";
+                let err = EditError::Failure(msg.into());
+
+                Err(err)
+            },
+        }
+    }
+}
+
+fn search_results_title<'a>(
+    state: &SearchResultsState,
+    room_id: &OwnedRoomId,
+    store: &mut ProgramStore,
+) -> Line<'a> {
+    let title = store.application.get_room_title(room_id.as_ref());
+    let info = store.application.rooms.get_or_default(room_id.clone());
+    let query = info
+        .search_results
+        .as_ref()
+        .map(|(query, _)| query.as_str())
+        .unwrap_or_default();
+    let n = state.len();
+    let v = vec![
+        bold_span("Search Results "),
+        Span::styled(format!("({n}) for {query:?}: "), bold_style()),
+        title.into(),
+    ];
+
+    Line::from(v)
+}
+
+/// A message listed in the `:room search` window.
+#[derive(Clone)]
+pub struct SearchItem {
+    room_id: OwnedRoomId,
+    event_id: OwnedEventId,
+    preview: String,
+}
+
+impl SearchItem {
+    fn new(room_id: OwnedRoomId, hit: &SearchHit) -> Self {
+        let sender = hit.sender_name.as_deref().unwrap_or(hit.sender.as_str());
+        let line = hit.body.lines().find(|l| !l.trim().is_empty()).unwrap_or_default();
+        let preview = format!("{sender}: {}", line.trim());
+
+        SearchItem { room_id, event_id: hit.event_id.clone(), preview }
+    }
+}
+
+impl Display for SearchItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.preview)
+    }
+}
+
+impl ListItem<IambInfo> for SearchItem {
+    fn show(
+        &self,
+        selected: bool,
+        _: &ViewportContext<ListCursor>,
+        _: &mut ProgramStore,
+    ) -> Text<'_> {
+        selected_text(self.preview.as_str(), selected)
+    }
+
+    fn get_word(&self) -> Option<String> {
+        self.event_id.to_string().into()
+    }
+}
+
+impl Promptable<ProgramContext, ProgramStore, IambInfo> for SearchItem {
+    fn prompt(
+        &mut self,
+        act: &PromptAction,
+        ctx: &ProgramContext,
+        _: &mut ProgramStore,
+    ) -> EditResult<Vec<(ProgramAction, ProgramContext)>, IambInfo> {
+        match act {
+            PromptAction::Submit => Ok(jump_to_event(&self.room_id, &self.event_id, ctx)),
+            PromptAction::Abort(_) => {
+                let msg = "Cannot abort entry inside a list";
+                let err = EditError::Failure(msg.into());
+
+                Err(err)
+            },
+            PromptAction::Recall(..) => {
+                let msg = "Cannot recall history inside a list";
+                let err = EditError::Failure(msg.into());
+
+                Err(err)
+            },
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1508,6 +2436,7 @@ mod tests {
         alias: Option<OwnedRoomAliasId>,
         name: &'static str,
         unread: UnreadInfo,
+        breadcrumb: Option<usize>,
     }
 
     impl RoomLikeItem for &TestRoomItem {
@@ -1534,6 +2463,58 @@ mod tests {
         fn is_unread(&self) -> bool {
             self.unread.is_unread()
         }
+
+        fn breadcrumb(&self) -> Option<usize> {
+            self.breadcrumb
+        }
+    }
+
+    #[test]
+    fn test_group_by_space() {
+        let server = server_name!("example.com");
+        let room = |name| {
+            TestRoomItem {
+                room_id: RoomId::new(server).to_owned(),
+                tags: vec![],
+                alias: None,
+                name,
+                unread: UnreadInfo::default(),
+                breadcrumb: None,
+            }
+        };
+
+        let room1 = room("A");
+        let room2 = room("B");
+        let room3 = room("C");
+        let space1 = RoomId::new(server).to_owned();
+        let space2 = RoomId::new(server).to_owned();
+        let space3 = RoomId::new(server).to_owned();
+
+        let spaces = vec![
+            (space1.clone(), "Space 1".to_string()),
+            (space2.clone(), "Space 2".to_string()),
+            (space3.clone(), "Space 3".to_string()),
+        ];
+        let children = HashMap::from([
+            (space1.clone(), vec![room1.room_id.clone(), room2.room_id.clone()]),
+            (space2.clone(), vec![room2.room_id.clone()]),
+            (space3.clone(), vec![RoomId::new(server).to_owned()]),
+        ]);
+
+        let rooms = vec![&room1, &room2, &room3];
+        let groups = group_by_space(&rooms, &spaces, &children);
+
+        // Rooms keep their order within each group, and can be in several of them, while spaces
+        // without any of the rooms are skipped.
+        assert_eq!(groups, vec![
+            (Some(space1), "Space 1".to_string(), vec![&room1, &room2]),
+            (Some(space2), "Space 2".to_string(), vec![&room2]),
+            (None, "Orphans".to_string(), vec![&room3]),
+        ]);
+
+        // Without any spaces, everything is an orphan.
+        let groups = group_by_space(&rooms, &[], &children);
+        assert_eq!(groups, vec![(None, "Orphans".to_string(), rooms)]);
     }
 
     #[test]
@@ -1546,6 +2527,7 @@ mod tests {
             alias: Some(room_alias_id!("#room1:example.com").to_owned()),
             name: "Z",
             unread: UnreadInfo::default(),
+            breadcrumb: None,
         };
 
         let room2 = TestRoomItem {
@@ -1554,6 +2536,7 @@ mod tests {
             alias: Some(room_alias_id!("#a:example.com").to_owned()),
             name: "Unnamed Room",
             unread: UnreadInfo::default(),
+            breadcrumb: None,
         };
 
         let room3 = TestRoomItem {
@@ -1562,6 +2545,7 @@ mod tests {
             alias: None,
             name: "Cool Room",
             unread: UnreadInfo::default(),
+            breadcrumb: None,
         };
 
         // Sort by Name ascending.
@@ -1607,6 +2591,7 @@ mod tests {
             alias: None,
             name: "Room 1",
             unread: UnreadInfo { unread: false, latest: None },
+            breadcrumb: None,
         };
 
         let room2 = TestRoomItem {
@@ -1618,6 +2603,7 @@ mod tests {
                 unread: false,
                 latest: Some(MessageTimeStamp::OriginServer(40u32.into())),
             },
+            breadcrumb: None,
         };
 
         let room3 = TestRoomItem {
@@ -1629,6 +2615,7 @@ mod tests {
                 unread: false,
                 latest: Some(MessageTimeStamp::OriginServer(20u32.into())),
             },
+            breadcrumb: None,
         };
 
         // Sort by Recent ascending.
@@ -1643,4 +2630,117 @@ mod tests {
         rooms.sort_by(|a, b| room_fields_cmp(a, b, fields));
         assert_eq!(rooms, vec![&room1, &room3, &room2]);
     }
+
+    #[test]
+    fn test_sort_room_breadcrumbs() {
+        let server = server_name!("example.com");
+
+        let room1 = TestRoomItem {
+            room_id: RoomId::new(server).to_owned(),
+            tags: vec![],
+            alias: None,
+            name: "Room 1",
+            unread: UnreadInfo {
+                unread: false,
+                latest: Some(MessageTimeStamp::OriginServer(10u32.into())),
+            },
+            breadcrumb: Some(1),
+        };
+
+        let room2 = TestRoomItem {
+            room_id: RoomId::new(server).to_owned(),
+            tags: vec![],
+            alias: None,
+            name: "Room 2",
+            unread: UnreadInfo {
+                unread: false,
+                latest: Some(MessageTimeStamp::OriginServer(20u32.into())),
+            },
+            breadcrumb: None,
+        };
+
+        let room3 = TestRoomItem {
+            room_id: RoomId::new(server).to_owned(),
+            tags: vec![],
+            alias: None,
+            name: "Room 3",
+            unread: UnreadInfo {
+                unread: false,
+                latest: Some(MessageTimeStamp::OriginServer(30u32.into())),
+            },
+            breadcrumb: None,
+        };
+
+        let room4 = TestRoomItem {
+            room_id: RoomId::new(server).to_owned(),
+            tags: vec![],
+            alias: None,
+            name: "Room 4",
+            unread: UnreadInfo::default(),
+            breadcrumb: Some(0),
+        };
+
+        // Breadcrumbs come first, and then the remaining rooms sort by activity.
+        let mut rooms = vec![&room1, &room2, &room3, &room4];
+        let fields = &[SortColumn(SortFieldRoom::Breadcrumbs, SortOrder::Ascending)];
+        rooms.sort_by(|a, b| room_fields_cmp(a, b, fields));
+        assert_eq!(rooms, vec![&room4, &room1, &room3, &room2]);
+    }
+
+    #[test]
+    fn test_pin_preview() {
+        let mut info = crate::tests::mock_room();
+        let user = crate::tests::TEST_USER2.clone();
+        info.display_names.insert(user, "User 2".into());
+
+        let msg2 = crate::tests::MSG2_EVID.clone();
+        assert_eq!(pin_preview(&msg2, &info), "User 2: helium");
+
+        // Multi-line messages only show their first line.
+        let msg3 = crate::tests::MSG3_EVID.clone();
+        assert_eq!(pin_preview(&msg3, &info), "User 2: this");
+
+        let unknown = matrix_sdk::ruma::owned_event_id!("$unknown:example.com");
+        assert_eq!(pin_preview(&unknown, &info), "$unknown:example.com (not loaded yet)");
+    }
+
+    #[test]
+    fn test_format_decimals() {
+        assert_eq!(format_decimals((1234, 5678, 9012)), "1234 5678 9012");
+        assert_eq!(format_decimals((1000, 8191, 4097)), "1000 8191 4097");
+    }
+
+    #[test]
+    fn test_verify_item_sessions() {
+        let user_id = crate::tests::TEST_USER1.clone();
+        let session = |device_id: &str, display_name: Option<&str>| {
+            let session = SessionInfo {
+                device_id: device_id.into(),
+                display_name: display_name.map(String::from),
+            };
+            VerifyItem::Session(user_id.clone(), session)
+        };
+        let identity = VerifyItem::Identity(IdentityInfo {
+            user_id: crate::tests::TEST_USER2.clone(),
+            status: IdentityStatus::Changed,
+            rooms: 1,
+        });
+
+        // Own sessions come before other users' identities, ordered by device.
+        let mut items = [
+            identity,
+            session("BBBB", None),
+            session("AAAA", Some("Phone")),
+        ];
+        items.sort();
+        let keys = items.iter().map(VerifyItem::key).collect::<Vec<_>>();
+        assert_eq!(keys, vec!["AAAA", "BBBB", crate::tests::TEST_USER2.as_str()]);
+
+        assert_eq!(
+            session("AAAA", Some("Phone")).show_item(),
+            "Your session Phone (AAAA, not verified)"
+        );
+        assert_eq!(session("AAAA", None).show_item(), "Your session AAAA (not verified)");
+        assert_eq!(session("AAAA", None).to_string(), format!(":verify request {user_id}"));
+    }
 }
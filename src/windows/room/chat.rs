@@ -1,13 +1,16 @@
 //! Window for Matrix rooms
 use std::borrow::Cow;
+use std::convert::TryFrom;
 use std::ffi::{OsStr, OsString};
 use std::fs;
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use edit::edit_with_builder as external_edit;
 use edit::Builder;
 use modalkit::editing::store::RegisterError;
+use regex::Regex;
 use std::process::Command;
 use tokio;
 use url::Url;
@@ -15,30 +18,46 @@ use url::Url;
 use matrix_sdk::{
     attachment::AttachmentConfig,
     media::{MediaFormat, MediaRequest},
-    room::Room as MatrixRoom,
+    room::{Receipts, Room as MatrixRoom},
     ruma::{
+        api::client::error::ErrorKind,
         events::reaction::ReactionEventContent,
-        events::relation::{Annotation, Replacement},
+        events::relation::{Annotation, Replacement, Thread},
         events::room::message::{
             AddMentions,
+            AudioInfo,
+            AudioMessageEventContent,
+            FileInfo,
+            FileMessageEventContent,
             ForwardThread,
+            ImageMessageEventContent,
             MessageType,
             OriginalRoomMessageEvent,
             Relation,
             ReplyWithinThread,
             RoomMessageEventContent,
             TextMessageEventContent,
+            VideoInfo,
+            VideoMessageEventContent,
         },
+        events::room::{ImageInfo, MediaSource},
+        EventId,
         OwnedEventId,
+        OwnedMxcUri,
         OwnedRoomId,
+        OwnedRoomOrAliasId,
         RoomId,
+        TransactionId,
+        UInt,
+        UserId,
     },
+    Client,
     RoomState,
 };
 
 use ratatui::{
     buffer::Buffer,
-    layout::Rect,
+    layout::{Alignment, Rect},
     text::{Line, Span},
     widgets::{Paragraph, StatefulWidget, Widget},
 };
@@ -61,73 +80,164 @@ use modalkit::actions::{
     Promptable,
     Scrollable,
 };
-use modalkit::editing::{
-    completion::CompletionList,
-    context::Resolve,
-    history::{self, HistoryList},
-    rope::EditRope,
-};
+use modalkit::editing::{completion::CompletionList, context::Resolve, history, rope::EditRope};
 use modalkit::errors::{EditError, EditResult, UIError};
 use modalkit::prelude::*;
 
 use crate::base::{
+    ChatStore,
     DownloadFlags,
+    DraftTargets,
     IambAction,
     IambBufferId,
     IambError,
     IambInfo,
     IambResult,
     MessageAction,
+    MessageTarget,
+    Need,
+    PendingMessage,
     ProgramAction,
     ProgramContext,
     ProgramStore,
+    RoomAction,
+    RoomFetchStatus,
     RoomFocus,
     RoomInfo,
     SendAction,
+    FORWARDED_FIELD,
 };
 
-use crate::message::{text_to_message, Message, MessageEvent, MessageKey, MessageTimeStamp};
-use crate::worker::Requester;
+use crate::config::{EscapeAction, RoomOpenPosition};
+use crate::emoji::expand_shortcodes;
+use crate::message::{
+    millis_to_datetime,
+    recaption,
+    strip_reply_fallback,
+    text_to_message,
+    Message,
+    MessageEvent,
+    MessageKey,
+    MessageTimeStamp,
+};
+use crate::preview::{unused_path, Attachment};
+use crate::worker::{decrypt_loaded, fetch_event_token, megolm_session_id, Requester};
+use crate::ApplicationSettings;
 
 use super::scrollback::{Scrollback, ScrollbackState};
 
+/// How often to tell the room that we're still typing. Notices expire after four seconds, so this
+/// refreshes them a little before then.
+const TYPING_NOTICE_RESEND: Duration = Duration::from_secs(3);
+
 /// State needed for rendering [Chat].
 pub struct ChatState {
     room_id: OwnedRoomId,
     room: MatrixRoom,
 
     tbox: TextBoxState<IambInfo>,
-    sent: HistoryList<EditRope>,
+    tbox_top: usize,
     sent_scrollback: history::ScrollbackState,
+    sent_search: Option<(String, usize)>,
 
     scrollback: ScrollbackState,
     focus: RoomFocus,
 
     reply_to: Option<MessageKey>,
     editing: Option<MessageKey>,
+
+    /// A reply target that stays selected after sending, until cancelled.
+    sticky_reply: Option<MessageKey>,
+
+    /// The root of a thread to reply in from the main timeline, set by `:thread`.
+    reply_thread: Option<MessageKey>,
+
+    /// When the room's rate limit allows sending again, after a recent send.
+    send_cooldown: Option<Instant>,
+
+    /// When we last told the room that we're typing, if we haven't since said that we stopped.
+    typing_sent: Option<Instant>,
 }
 
 impl ChatState {
     pub fn new(room: MatrixRoom, thread: Option<OwnedEventId>, store: &mut ProgramStore) -> Self {
         let room_id = room.room_id().to_owned();
-        let scrollback = ScrollbackState::new(room_id.clone(), thread.clone());
+        let mut scrollback = ScrollbackState::new(room_id.clone(), thread.clone());
+
+        let open_position = store.application.settings.tunables.room_open_position;
+        let saved = match open_position {
+            RoomOpenPosition::Last => store.application.scroll_positions.get(&room_id).cloned(),
+            RoomOpenPosition::Bottom | RoomOpenPosition::Unread => None,
+        };
+
+        if thread.is_none() && open_position != RoomOpenPosition::Bottom {
+            let user_id = &store.application.settings.profile.user_id;
+            let info = store.application.rooms.get_or_default(room_id.clone());
+
+            if let Some(pos) = saved {
+                scrollback.restore_position(info, pos);
+            } else if let Some((_, event_id)) = info.first_unread(user_id) {
+                scrollback.select_event(info, event_id);
+            }
+        }
+
         let id = IambBufferId::Room(room_id.clone(), thread, RoomFocus::MessageBar);
         let ebuf = store.load_buffer(id);
         let tbox = TextBoxState::new(ebuf);
 
-        ChatState {
-            room_id,
+        let mut state = ChatState {
+            room_id: room_id.clone(),
             room,
 
             tbox,
-            sent: HistoryList::new(EditRope::from(""), 100),
+            tbox_top: 0,
             sent_scrollback: history::ScrollbackState::Pending,
+            sent_search: None,
 
             scrollback,
             focus: RoomFocus::MessageBar,
 
             reply_to: None,
             editing: None,
+            sticky_reply: None,
+            reply_thread: None,
+            send_cooldown: None,
+            typing_sent: None,
+        };
+
+        state.restore_targets(store.application.rooms.get_or_default(room_id));
+        state
+    }
+
+    /// Pick back up whatever this message bar was last replying to or editing, skipping any
+    /// messages that have been redacted since.
+    fn restore_targets(&mut self, info: &RoomInfo) {
+        let Some(targets) = info.draft_targets.get(&self.thread().cloned()) else {
+            return;
+        };
+        let restore = |id: &Option<OwnedEventId>| info.get_draft_target(id.as_ref()?);
+
+        self.reply_to = restore(&targets.reply_to);
+        self.sticky_reply = restore(&targets.sticky_reply);
+        self.reply_thread = restore(&targets.reply_thread);
+        self.editing = restore(&targets.editing);
+    }
+
+    /// Remember what this message bar is replying to or editing, so that the targets can be
+    /// restored along with the draft after switching away from the room.
+    fn save_targets(&self, info: &mut RoomInfo) {
+        let targets = DraftTargets {
+            reply_to: self.reply_to.as_ref().map(|key| key.1.clone()),
+            sticky_reply: self.sticky_reply.as_ref().map(|key| key.1.clone()),
+            reply_thread: self.reply_thread.as_ref().map(|key| key.1.clone()),
+            editing: self.editing.as_ref().map(|key| key.1.clone()),
+        };
+        let thread = self.thread().cloned();
+
+        if targets == DraftTargets::default() {
+            info.draft_targets.remove(&thread);
+        } else {
+            info.draft_targets.insert(thread, targets);
         }
     }
 
@@ -135,6 +245,44 @@ impl ChatState {
         self.scrollback.thread()
     }
 
+    /// Mark everything up to the selected message as read, returning whether the read receipt
+    /// moved.
+    pub fn mark_read_here(&self, info: &mut RoomInfo, user_id: &UserId) -> IambResult<bool> {
+        let key = self.scrollback.get_key(info).ok_or(IambError::NoSelectedMessage)?;
+
+        if let Some(root) = self.thread() {
+            let moved = info.get_thread_receipt(root, user_id) != Some(&key.1);
+            info.set_thread_receipt(root.clone(), user_id.to_owned(), key.1);
+
+            return Ok(moved);
+        }
+
+        Ok(info.mark_read_until(user_id, &key))
+    }
+
+    /// How long is left before another message can be sent, if the room's rate limit is still
+    /// cooling down from the last one.
+    fn send_cooldown_remaining(&self) -> Option<Duration> {
+        let until = self.send_cooldown?;
+        let remaining = until.saturating_duration_since(Instant::now());
+
+        (!remaining.is_zero()).then_some(remaining)
+    }
+
+    /// Map a failure to send something, starting a cooldown if the server says that we're
+    /// sending too quickly.
+    fn send_error(&mut self, err: matrix_sdk::Error) -> IambError {
+        if let Some(ErrorKind::LimitExceeded { retry_after_ms: Some(wait) }) =
+            err.client_api_error_kind()
+        {
+            self.send_cooldown = Some(Instant::now() + *wait);
+
+            return IambError::SendCooldown(cooldown_secs(*wait));
+        }
+
+        IambError::from(err)
+    }
+
     fn get_joined(&self, worker: &Requester) -> Result<MatrixRoom, IambError> {
         let Some(room) = worker.client.get_room(self.id()) else {
             return Err(IambError::NotJoined);
@@ -147,6 +295,27 @@ impl ChatState {
         }
     }
 
+    /// Like [ChatState::get_joined], but if the user has only been invited to this room, then
+    /// ask whether to accept the invite first, and afterwards try the action from `retry` again.
+    fn get_joined_or_accept(
+        &self,
+        worker: &Requester,
+        retry: impl FnOnce() -> IambAction,
+    ) -> IambResult<MatrixRoom> {
+        match worker.client.get_room(self.id()) {
+            Some(room) if room.state() == RoomState::Invited => {
+                let msg = "You haven't joined this room yet. Accept the invite and continue?";
+                let accept = IambAction::Room(RoomAction::InviteAccept);
+                let acts = vec![Action::from(accept), Action::from(retry())];
+                let prompt = PromptYesNo::new(msg, acts);
+                let prompt = Box::new(prompt);
+
+                Err(UIError::NeedConfirm(prompt))
+            },
+            _ => self.get_joined(worker).map_err(UIError::from),
+        }
+    }
+
     fn get_reply_to<'a>(&self, info: &'a RoomInfo) -> Option<&'a OriginalRoomMessageEvent> {
         let thread = self.scrollback.get_thread(info)?;
         let key = self.reply_to.as_ref()?;
@@ -160,8 +329,10 @@ impl ChatState {
     }
 
     fn reset(&mut self) -> EditRope {
-        self.reply_to = None;
+        self.reply_to = self.sticky_reply.clone();
+        self.reply_thread = None;
         self.editing = None;
+        self.sent_search = None;
         self.tbox.reset()
     }
 
@@ -171,158 +342,218 @@ impl ChatState {
         }
     }
 
+    /// Start loading the room's history around a message that hasn't been fetched yet, and
+    /// select it once it arrives.
+    async fn jump_unloaded(
+        &mut self,
+        event_id: OwnedEventId,
+        store: &mut ProgramStore,
+    ) -> IambResult<EditInfo> {
+        let client = &store.application.worker.client;
+        let token = fetch_event_token(client, self.id(), &event_id).await?;
+
+        let info = store.application.rooms.get_or_default(self.room_id.clone());
+        info.open_gap(token);
+        store
+            .application
+            .need_load
+            .insert(self.room_id.clone(), Need::MESSAGES | Need::GAP);
+        self.scrollback.select_event_later(event_id);
+
+        Ok(Some("Loading older messages...".into()))
+    }
+
+    /// Select the closest message in a direction that contains some text, ignoring case.
+    ///
+    /// The search also becomes the last search, so that `n` and `N` can find more matches.
+    pub fn search(
+        &mut self,
+        text: &str,
+        dir: MoveDir1D,
+        store: &mut ProgramStore,
+    ) -> IambResult<EditInfo> {
+        let pattern = format!("(?i){}", regex::escape(text));
+        let needle = Regex::new(&pattern).map_err(|e| UIError::Failure(e.to_string()))?;
+        store.set_last_search(pattern);
+
+        let info = store.application.rooms.get_or_default(self.room_id.clone());
+        let (found, needs_load) = self.scrollback.find_text(&needle, dir, info);
+
+        if found {
+            return Ok(None);
+        }
+
+        if needs_load && !matches!(info.fetch_id, RoomFetchStatus::Done) {
+            store.application.need_load.insert(self.room_id.clone(), Need::MESSAGES);
+
+            return Ok(Some("No matches yet, loading older messages...".into()));
+        }
+
+        let msg = format!("No messages contain {text:?}");
+
+        Err(UIError::Failure(msg))
+    }
+
     pub async fn message_command(
         &mut self,
         act: MessageAction,
         _: ProgramContext,
         store: &mut ProgramStore,
     ) -> IambResult<EditInfo> {
+        // Anything that has already gone out can only be taken back by redacting it.
+        let act = match act {
+            MessageAction::Unsend => {
+                if let Some(info) = self.unsend(store)? {
+                    return Ok(info);
+                }
+
+                MessageAction::Redact(None, false, MessageTarget::LastOwn)
+            },
+            MessageAction::GotoParent => {
+                let info = store.application.rooms.get_or_default(self.room_id.clone());
+                let msg = self.scrollback.get_mut(info).ok_or(IambError::NoSelectedMessage)?;
+                let parent = msg.reply_to().ok_or(IambError::NotAReply)?;
+
+                MessageAction::Jump(parent)
+            },
+            act => act,
+        };
+
         let client = &store.application.worker.client;
 
         let settings = &store.application.settings;
         let info = store.application.rooms.get_or_default(self.room_id.clone());
 
-        let msg = self.scrollback.get_mut(info).ok_or(IambError::NoSelectedMessage)?;
+        match act.target() {
+            MessageTarget::Selected => {},
+            MessageTarget::LastOwn => {
+                if !self.scrollback.select_last_from(info, &settings.profile.user_id) {
+                    return Err(IambError::NoOwnMessages.into());
+                }
+            },
+            MessageTarget::Event(event_id) => {
+                if !self.scrollback.select_event(info, &event_id) {
+                    if matches!(act, MessageAction::Jump(_)) && self.thread().is_none() {
+                        return self.jump_unloaded(event_id, store).await;
+                    }
+
+                    return Err(IambError::MessageNotLoaded(event_id).into());
+                }
+            },
+        }
 
+        // These don't need a selected message.
         match act {
-            MessageAction::Cancel(skip_confirm) => {
-                if skip_confirm {
+            MessageAction::CancelEdit => {
+                if self.editing.is_some() {
                     self.reset();
-
-                    return Ok(None);
+                    self.typing_stopped(&store.application.worker);
                 }
 
+                return Ok(None);
+            },
+            MessageAction::CancelReply => {
                 self.reply_to = None;
-                self.editing = None;
+                self.sticky_reply = None;
+                self.reply_thread = None;
 
-                let msg = "Would you like to clear the message bar?";
-                let act = PromptAction::Abort(false);
-                let prompt = PromptYesNo::new(msg, vec![Action::from(act)]);
-                let prompt = Box::new(prompt);
+                return Ok(None);
+            },
+            MessageAction::SelectClear => {
+                self.scrollback.clear_selected();
 
-                Err(UIError::NeedConfirm(prompt))
+                return Ok(None);
             },
-            MessageAction::Download(filename, flags) => {
-                if let MessageEvent::Original(ev) = &msg.event {
-                    let media = client.media();
+            MessageAction::Download(..) | MessageAction::Redact(..)
+                if act.target() == MessageTarget::Selected && self.scrollback.has_selected() =>
+            {
+                return self.batch_command(act, store).await;
+            },
+            _ => {},
+        }
 
-                    let mut filename = match (filename, &settings.dirs.downloads) {
-                        (Some(f), _) => PathBuf::from(f),
-                        (None, Some(downloads)) => downloads.clone(),
-                        (None, None) => return Err(IambError::NoDownloadDir.into()),
-                    };
+        let msg = self.scrollback.get_mut(info).ok_or(IambError::NoSelectedMessage)?;
 
-                    let (source, msg_filename) = match &ev.content.msgtype {
-                        MessageType::Audio(c) => (c.source.clone(), c.body.as_str()),
-                        MessageType::File(c) => {
-                            (c.source.clone(), c.filename.as_deref().unwrap_or(c.body.as_str()))
-                        },
-                        MessageType::Image(c) => (c.source.clone(), c.body.as_str()),
-                        MessageType::Video(c) => (c.source.clone(), c.body.as_str()),
-                        _ => {
-                            if !flags.contains(DownloadFlags::OPEN) {
-                                return Err(IambError::NoAttachment.into());
-                            }
-
-                            let links = if let Some(html) = &msg.html {
-                                html.get_links()
-                            } else if let Ok(url) = Url::parse(&msg.event.body()) {
-                                vec![('0', url)]
-                            } else {
-                                vec![]
-                            };
-
-                            if links.is_empty() {
-                                return Err(IambError::NoAttachment.into());
-                            }
-
-                            let choices = links
-                                .into_iter()
-                                .map(|l| {
-                                    let url = l.1.to_string();
-                                    let act = IambAction::OpenLink(url.clone()).into();
-                                    MultiChoiceItem::new(l.0, url, vec![act])
-                                })
-                                .collect();
-                            let dialog = MultiChoice::new(choices);
-                            let err = UIError::NeedConfirm(Box::new(dialog));
-
-                            return Err(err);
-                        },
-                    };
+        match act {
+            MessageAction::AnswerVerification(accept) => {
+                let request = match &msg.event {
+                    MessageEvent::Original(ev) => {
+                        match &ev.content.msgtype {
+                            MessageType::VerificationRequest(_) => {
+                                client
+                                    .encryption()
+                                    .get_verification_request(&ev.sender, &ev.event_id)
+                                    .await
+                            },
+                            _ => None,
+                        }
+                    },
+                    _ => None,
+                };
 
-                    if filename.is_dir() {
-                        filename.push(msg_filename);
-                    }
+                let Some(request) = request else {
+                    let msg = "The selected message isn't a pending verification request";
+                    let err = UIError::Failure(msg.into());
 
-                    if filename.exists() && !flags.contains(DownloadFlags::FORCE) {
-                        // Find an incrementally suffixed filename, e.g. image-2.jpg -> image-3.jpg
-                        if let Some(stem) = filename.file_stem().and_then(OsStr::to_str) {
-                            let ext = filename.extension();
-                            let mut filename_incr = filename.clone();
-                            for n in 1..=1000 {
-                                if let Some(ext) = ext.and_then(OsStr::to_str) {
-                                    filename_incr.set_file_name(format!("{}-{}.{}", stem, n, ext));
-                                } else {
-                                    filename_incr.set_file_name(format!("{}-{}", stem, n));
-                                }
-
-                                if !filename_incr.exists() {
-                                    filename = filename_incr;
-                                    break;
-                                }
-                            }
-                        }
-                    }
+                    return Err(err);
+                };
+
+                if request.we_started() {
+                    let msg = "Cannot answer your own verification request";
+                    let err = UIError::Failure(msg.into());
+
+                    return Err(err);
+                }
 
-                    if !filename.exists() || flags.contains(DownloadFlags::FORCE) {
-                        let req = MediaRequest { source, format: MediaFormat::File };
+                if request.is_done() || request.is_cancelled() {
+                    let msg = "This verification request has already finished";
+                    let err = UIError::Failure(msg.into());
 
-                        let bytes =
-                            media.get_media_content(&req, true).await.map_err(IambError::from)?;
+                    return Err(err);
+                }
 
-                        fs::write(filename.as_path(), bytes.as_slice())?;
+                let user_id = request.other_user_id().to_owned();
 
-                        msg.downloaded = true;
-                    } else if !flags.contains(DownloadFlags::OPEN) {
-                        let msg = format!(
-                            "The file {} already exists; add ! to end of command to overwrite it.",
-                            filename.display()
-                        );
-                        let err = UIError::Failure(msg);
+                if accept {
+                    request.accept().await.map_err(IambError::from)?;
 
-                        return Err(err);
-                    }
+                    let msg = format!("Accepted verification request from {user_id}");
 
-                    let info = if flags.contains(DownloadFlags::OPEN) {
-                        let target = filename.clone().into_os_string();
-                        match open_command(
-                            store.application.settings.tunables.open_command.as_ref(),
-                            target,
-                        ) {
-                            Ok(_) => {
-                                InfoMessage::from(format!(
-                                    "Attachment downloaded to {} and opened",
-                                    filename.display()
-                                ))
-                            },
-                            Err(err) => {
-                                return Err(err);
-                            },
-                        }
-                    } else {
-                        InfoMessage::from(format!(
-                            "Attachment downloaded to {}",
-                            filename.display()
-                        ))
-                    };
+                    Ok(Some(InfoMessage::from(msg)))
+                } else {
+                    request.cancel().await.map_err(IambError::from)?;
+
+                    let msg = format!("Declined verification request from {user_id}");
+
+                    Ok(Some(InfoMessage::from(msg)))
+                }
+            },
+            MessageAction::CancelEdit | MessageAction::CancelReply => Ok(None),
+            MessageAction::SelectClear | MessageAction::Unsend => Ok(None),
+            MessageAction::Cancel(skip_confirm) => {
+                if skip_confirm {
+                    self.sticky_reply = None;
+                    self.reset();
+                    self.typing_stopped(&store.application.worker);
 
-                    return Ok(info.into());
+                    return Ok(None);
                 }
 
-                Err(IambError::NoAttachment.into())
+                // Nothing changes until it's confirmed, so that answering no keeps the reply.
+                let msg = "Would you like to clear the message bar?";
+                let detach = IambAction::from(MessageAction::CancelReply);
+                let abort = PromptAction::Abort(false);
+                let prompt = PromptYesNo::new(msg, vec![Action::from(detach), Action::from(abort)]);
+                let prompt = Box::new(prompt);
+
+                Err(UIError::NeedConfirm(prompt))
             },
-            MessageAction::Edit => {
+            MessageAction::Download(filename, flags) => {
+                download_attachment(msg, filename, flags, client, settings).await
+            },
+            MessageAction::Jump(_) | MessageAction::GotoParent => Ok(None),
+            MessageAction::OpenLink(label) => open_link(msg, label, client, settings).await,
+            MessageAction::Edit(_) => {
                 if msg.sender != settings.profile.user_id {
                     let msg = "Cannot edit messages sent by someone else";
                     let err = UIError::Failure(msg.into());
@@ -333,6 +564,18 @@ impl ChatState {
                 let ev = match &msg.event {
                     MessageEvent::Original(ev) => &ev.content,
                     MessageEvent::Local(_, ev) => ev.deref(),
+                    MessageEvent::Custom(_) => {
+                        let msg = "Cannot edit events of a custom type";
+                        let err = UIError::Failure(msg.into());
+
+                        return Err(err);
+                    },
+                    MessageEvent::Membership(_) => {
+                        let msg = "Cannot edit membership changes";
+                        let err = UIError::Failure(msg.into());
+
+                        return Err(err);
+                    },
                     _ => {
                         let msg = "Cannot edit a redacted message";
                         let err = UIError::Failure(msg.into());
@@ -343,8 +586,12 @@ impl ChatState {
 
                 let text = match &ev.msgtype {
                     MessageType::Text(msg) => msg.body.as_str(),
+                    MessageType::Audio(msg) => msg.body.as_str(),
+                    MessageType::File(msg) => msg.body.as_str(),
+                    MessageType::Image(msg) => msg.body.as_str(),
+                    MessageType::Video(msg) => msg.body.as_str(),
                     _ => {
-                        let msg = "Cannot edit a non-text message";
+                        let msg = "Cannot edit this kind of message";
                         let err = UIError::Failure(msg.into());
 
                         return Err(err);
@@ -358,13 +605,67 @@ impl ChatState {
 
                 Ok(None)
             },
+            MessageAction::Forward(target) => {
+                let Some(content) = msg.event.content() else {
+                    let msg = "Only loaded, unredacted messages can be forwarded";
+                    return Err(UIError::Failure(msg.into()));
+                };
+
+                // Forward just what was said, without the original's reply or thread.
+                let mut content = RoomMessageEventContent::new(content.msgtype.clone());
+
+                if msg.reply_to().is_some() {
+                    strip_reply_fallback(&mut content.msgtype);
+                }
+
+                // Only mark the message as forwarded, without saying where it came from, since
+                // the original room may be private.
+                let mut json = serde_json::to_value(content).map_err(IambError::from)?;
+                json[FORWARDED_FIELD] = true.into();
+
+                let room_id = if let Some(room_id) = store.application.names.get_mut(&target) {
+                    room_id.clone()
+                } else {
+                    let id = OwnedRoomOrAliasId::try_from(target.as_str())
+                        .map_err(|_| IambError::InvalidRoomAlias(target.clone()))?;
+
+                    match OwnedRoomId::try_from(id) {
+                        Ok(room_id) => room_id,
+                        Err(alias) => {
+                            client
+                                .resolve_room_alias(&alias)
+                                .await
+                                .map_err(IambError::from)?
+                                .room_id
+                        },
+                    }
+                };
+
+                let room = store
+                    .application
+                    .get_joined_room(&room_id)
+                    .ok_or(UIError::Application(IambError::NotJoined))?;
+                let _ = room.send_raw("m.room.message", json).await.map_err(IambError::from)?;
+
+                Ok(Some(format!("Forwarded message to {target}").into()))
+            },
             MessageAction::React(emoji) => {
-                let room = self.get_joined(&store.application.worker)?;
+                let emoji = expand_shortcodes(&emoji);
+                let room = self.get_joined_or_accept(&store.application.worker, || {
+                    IambAction::from(MessageAction::React(emoji.clone()))
+                })?;
                 let event_id = match &msg.event {
                     MessageEvent::EncryptedOriginal(ev) => ev.event_id.clone(),
                     MessageEvent::EncryptedRedacted(ev) => ev.event_id.clone(),
                     MessageEvent::Original(ev) => ev.event_id.clone(),
                     MessageEvent::Local(event_id, _) => event_id.clone(),
+                    MessageEvent::Custom(ev) => ev.event_id.clone(),
+                    MessageEvent::Membership(_) => {
+                        let msg = "Cannot react to membership changes";
+                        let err = UIError::Failure(msg.into());
+
+                        return Err(err);
+                    },
                     MessageEvent::Redacted(_) => {
                         let msg = "Cannot react to a redacted message";
                         let err = UIError::Failure(msg.into());
@@ -380,16 +681,48 @@ impl ChatState {
                     return Err(err);
                 }
 
-                let reaction = Annotation::new(event_id, emoji);
+                let reaction = Annotation::new(event_id, emoji.clone());
                 let msg = ReactionEventContent::new(reaction);
                 let _ = room.send(msg).await.map_err(IambError::from)?;
 
+                store.application.record_reaction(&emoji);
+
                 Ok(None)
             },
-            MessageAction::Redact(reason, skip_confirm) => {
+            MessageAction::ReactRecent => {
+                let recent = &store.application.recent_reactions;
+
+                if recent.is_empty() {
+                    let msg = "You haven't reacted to any messages yet";
+                    let err = UIError::Failure(msg.into());
+
+                    return Err(err);
+                }
+
+                let choices = recent
+                    .iter()
+                    .zip('1'..='9')
+                    .map(|(emoji, key)| {
+                        let desc = match emojis::get(emoji).and_then(|e| e.shortcode()) {
+                            Some(shortcode) => format!("{emoji} :{shortcode}:"),
+                            None => emoji.clone(),
+                        };
+                        let act = IambAction::from(MessageAction::React(emoji.clone()));
+
+                        MultiChoiceItem::new(key, desc, vec![act.into()])
+                    })
+                    .collect();
+                let dialog = MultiChoice::new(choices);
+
+                Err(UIError::NeedConfirm(Box::new(dialog)))
+            },
+            MessageAction::Redact(reason, skip_confirm, _) => {
                 if !skip_confirm {
+                    // The cursor is already on the message now, so confirming shouldn't search
+                    // for it again.
                     let msg = "Are you sure you want to redact this message?";
-                    let act = IambAction::Message(MessageAction::Redact(reason, true));
+                    let act = MessageAction::Redact(reason, true, MessageTarget::Selected);
+                    let act = IambAction::Message(act);
                     let prompt = PromptYesNo::new(msg, vec![Action::from(act)]);
                     let prompt = Box::new(prompt);
 
@@ -402,6 +735,13 @@ impl ChatState {
                     MessageEvent::EncryptedRedacted(ev) => ev.event_id.clone(),
                     MessageEvent::Original(ev) => ev.event_id.clone(),
                     MessageEvent::Local(event_id, _) => event_id.clone(),
+                    MessageEvent::Custom(ev) => ev.event_id.clone(),
+                    MessageEvent::Membership(_) => {
+                        let msg = "Cannot redact membership changes";
+                        let err = UIError::Failure(msg.into());
+
+                        return Err(err);
+                    },
                     MessageEvent::Redacted(_) => {
                         let msg = "Cannot redact already redacted message";
                         let err = UIError::Failure(msg.into());
@@ -416,19 +756,165 @@ impl ChatState {
 
                 Ok(None)
             },
-            MessageAction::Reply => {
+            MessageAction::Reply(sticky) => {
                 self.reply_to = self.scrollback.get_key(info);
+                self.sticky_reply = self.reply_to.clone().filter(|_| sticky);
+                self.reply_thread = None;
+                self.focus = RoomFocus::MessageBar;
+
+                Ok(None)
+            },
+            MessageAction::ReplyThread => {
+                if self.thread().is_some() {
+                    let msg = "Already replying in this thread";
+                    return Err(UIError::Failure(msg.into()));
+                }
+
+                self.reply_thread = self.scrollback.get_key(info);
+                self.reply_to = None;
+                self.sticky_reply = None;
                 self.focus = RoomFocus::MessageBar;
 
                 Ok(None)
             },
+            MessageAction::Resend => {
+                if !msg.send_failed {
+                    let msg = "Only messages that failed to send can be resent";
+                    return Err(UIError::Failure(msg.into()));
+                }
+
+                let echo_id = msg.event.event_id();
+                let failed = &mut store.application.failed_sends;
+                let Some(idx) = failed.iter().position(|p| p.echo_id.as_deref() == Some(echo_id))
+                else {
+                    let msg = "The failed message is no longer available to resend";
+                    return Err(UIError::Failure(msg.into()));
+                };
+
+                let mut pending = failed.remove(idx);
+                pending.send_after = Instant::now();
+                store.application.send_queue.push(pending);
+                msg.send_failed = false;
+
+                Ok(Some("Resending message".into()))
+            },
+            MessageAction::MarkUnread => {
+                if self.thread().is_some() {
+                    let msg = "Only messages in the main timeline can be marked unread";
+                    return Err(UIError::Failure(msg.into()));
+                }
+
+                let key = self.scrollback.get_key(info).ok_or(IambError::NoSelectedMessage)?;
+                let room = self.get_joined(&store.application.worker)?;
+                let user_id = &settings.profile.user_id;
+
+                let Some(prev) = info.mark_unread_from(user_id, &key) else {
+                    let msg = "Load some older messages first to mark this one unread";
+                    return Err(UIError::Failure(msg.into()));
+                };
+
+                // Read receipts can't move backwards, but the fully read marker can.
+                let receipts = Receipts::new().fully_read_marker(prev);
+                room.send_multiple_receipts(receipts).await.map_err(IambError::from)?;
+
+                Ok(Some("Marked unread from the selected message".into()))
+            },
+            MessageAction::Receipts => {
+                let key = self.scrollback.get_key(info).ok_or(IambError::NoSelectedMessage)?;
+                let receipts = info.get_receipts_since(&key);
+
+                if receipts.is_empty() {
+                    return Ok(Some("Nobody has read this message yet".into()));
+                }
+
+                let mut text = String::from("Seen by:\n");
+
+                for (user_id, ts) in receipts {
+                    let name = match info.display_names.get(user_id) {
+                        Some(name) => format!("{name} ({user_id})"),
+                        None => user_id.to_string(),
+                    };
+                    let time = match ts {
+                        Some(ts) => {
+                            let tz = &settings.tunables.timezone;
+                            millis_to_datetime(ts.0, tz).format("%Y-%m-%d %H:%M").to_string()
+                        },
+                        None => "unknown time".into(),
+                    };
+
+                    text.push_str(&format!("\n{name} at {time}"));
+                }
+
+                Ok(Some(InfoMessage::Pager(text)))
+            },
+            MessageAction::RestoreKeys => {
+                let session_id = match &msg.event {
+                    MessageEvent::EncryptedOriginal(ev) => megolm_session_id(ev),
+                    MessageEvent::EncryptedRedacted(_) => {
+                        let msg = "The selected message has been redacted";
+                        return Err(UIError::Failure(msg.into()));
+                    },
+                    _ => {
+                        let msg = "The selected message has already been decrypted";
+                        return Err(UIError::Failure(msg.into()));
+                    },
+                };
+                let Some(session_id) = session_id.map(str::to_owned) else {
+                    let msg = "The selected message doesn't use a room key";
+                    return Err(UIError::Failure(msg.into()));
+                };
+
+                let room = self.get_joined(&store.application.worker)?;
+                let worker = &store.application.worker;
+                worker.restore_room_key(self.room_id.clone(), session_id.clone())?;
+
+                // The key may also unlock other loaded messages from the same session.
+                let mut decrypted = 0;
+
+                for ev in info.undecrypted() {
+                    if megolm_session_id(&ev) != Some(session_id.as_str()) {
+                        continue;
+                    }
+
+                    if let Some(msg) = decrypt_loaded(&room, &ev).await {
+                        info.insert_decrypted(msg);
+                        decrypted += 1;
+                    }
+                }
+
+                if decrypted == 0 {
+                    return Ok(Some("The key backup doesn't have this message's key".into()));
+                }
+
+                Ok(Some(format!("Decrypted {decrypted} message(s) from the key backup").into()))
+            },
+            MessageAction::Select => {
+                if matches!(msg.event, MessageEvent::Membership(_)) {
+                    let msg = "Cannot select membership changes";
+                    let err = UIError::Failure(msg.into());
+
+                    return Err(err);
+                }
+
+                self.scrollback.toggle_selected(msg.event.event_id().to_owned());
+
+                Ok(None)
+            },
             MessageAction::Unreact(emoji) => {
+                let emoji = emoji.as_deref().map(expand_shortcodes);
                 let room = self.get_joined(&store.application.worker)?;
                 let event_id = match &msg.event {
                     MessageEvent::EncryptedOriginal(ev) => ev.event_id.clone(),
                     MessageEvent::EncryptedRedacted(ev) => ev.event_id.clone(),
                     MessageEvent::Original(ev) => ev.event_id.clone(),
                     MessageEvent::Local(event_id, _) => event_id.clone(),
+                    MessageEvent::Custom(ev) => ev.event_id.clone(),
+                    MessageEvent::Membership(_) => {
+                        let msg = "Cannot unreact to membership changes";
+                        let err = UIError::Failure(msg.into());
+
+                        return Err(err);
+                    },
                     MessageEvent::Redacted(_) => {
                         let msg = "Cannot unreact to a redacted message";
                         let err = UIError::Failure(msg.into());
@@ -437,34 +923,271 @@ impl ChatState {
                     },
                 };
 
-                let reactions = match info.reactions.get(&event_id) {
-                    Some(r) => r,
-                    None => return Ok(None),
-                };
+                let reactions = match info.reactions.get(&event_id) {
+                    Some(r) => r,
+                    None => return Ok(None),
+                };
+
+                let reactions = reactions.iter().filter_map(|(event_id, (reaction, user_id))| {
+                    if user_id != &settings.profile.user_id {
+                        return None;
+                    }
+
+                    if let Some(emoji) = &emoji {
+                        if emoji == reaction {
+                            return Some(event_id);
+                        } else {
+                            return None;
+                        }
+                    } else {
+                        return Some(event_id);
+                    }
+                });
+
+                for reaction in reactions {
+                    let _ = room.redact(reaction, None, None).await.map_err(IambError::from)?;
+                }
+
+                Ok(None)
+            },
+        }
+    }
+
+    /// Perform a [MessageAction::Download] or [MessageAction::Redact] on every selected message,
+    /// reporting how it went for each one.
+    ///
+    /// Messages that the action succeeds on are removed from the selection, so that it can be
+    /// retried on whatever's left.
+    async fn batch_command(
+        &mut self,
+        act: MessageAction,
+        store: &mut ProgramStore,
+    ) -> IambResult<EditInfo> {
+        let client = &store.application.worker.client;
+        let settings = &store.application.settings;
+        let info = store.application.rooms.get_or_default(self.room_id.clone());
+        let keys = self.scrollback.selected_keys(info);
+
+        if keys.is_empty() {
+            let msg = "None of the selected messages are currently loaded";
+            let err = UIError::Failure(msg.into());
+
+            return Err(err);
+        }
+
+        let room = match &act {
+            MessageAction::Download(Some(filename), _) if !Path::new(filename).is_dir() => {
+                let msg = "Several attachments can only be downloaded into a directory";
+                let err = UIError::Failure(msg.into());
+
+                return Err(err);
+            },
+            MessageAction::Download(..) => None,
+            MessageAction::Redact(reason, false, _) => {
+                let msg = format!("Are you sure you want to redact {} messages?", keys.len());
+                let act = MessageAction::Redact(reason.clone(), true, MessageTarget::Selected);
+                let act = IambAction::Message(act);
+                let prompt = PromptYesNo::new(msg, vec![Action::from(act)]);
+                let prompt = Box::new(prompt);
+
+                return Err(UIError::NeedConfirm(prompt));
+            },
+            _ => Some(self.get_joined(&store.application.worker)?),
+        };
+
+        let thread = self.scrollback.get_thread_mut(info);
+        let mut done = 0;
+        let mut report = vec![];
+
+        for key in keys.iter() {
+            let Some(msg) = thread.get_mut(key) else {
+                continue;
+            };
+
+            let event_id = msg.event.event_id().to_owned();
+            let preview = target_preview(&msg.event.body());
+
+            let res = match (&act, &room) {
+                (MessageAction::Download(filename, flags), _) => {
+                    download_attachment(msg, filename.clone(), flags.clone(), client, settings)
+                        .await
+                        .map(|_| ())
+                },
+                (MessageAction::Redact(reason, _, _), Some(room)) => {
+                    match &msg.event {
+                        MessageEvent::Membership(_) => {
+                            Err(UIError::Failure("Cannot redact membership changes".into()))
+                        },
+                        MessageEvent::Redacted(_) => {
+                            Err(UIError::Failure("Already redacted".into()))
+                        },
+                        _ => {
+                            room.redact(&event_id, reason.as_deref(), None)
+                                .await
+                                .map(|_| ())
+                                .map_err(|e| IambError::from(e).into())
+                        },
+                    }
+                },
+                _ => continue,
+            };
+
+            match res {
+                Ok(()) => {
+                    done += 1;
+                    report.push(format!("✓ {preview}"));
+                    self.scrollback.deselect(&event_id);
+                },
+                Err(UIError::NeedConfirm(_)) => {
+                    report.push(format!("✗ {preview}: needs confirming, so run it on its own"));
+                },
+                Err(e) => {
+                    report.push(format!("✗ {preview}: {e}"));
+                },
+            }
+        }
+
+        let verb = match act {
+            MessageAction::Download(..) => "Downloaded attachments from",
+            _ => "Redacted",
+        };
+        let summary = format!("{verb} {done} of {} selected messages:\n", keys.len());
+
+        Ok(Some(InfoMessage::Pager(summary + &report.join("\n"))))
+    }
+
+    /// Step back through the sent message history, looking for messages that contain the text
+    /// that was in the message bar when the search began.
+    fn search_history(&mut self, store: &mut ProgramStore) -> IambResult<EditInfo> {
+        let entries = store
+            .application
+            .sent_history
+            .get(&self.room_id)
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        let text = self.tbox.get().to_string();
+
+        // Keep going from the previous match as long as it hasn't been edited since.
+        let (needle, start) = match self.sent_search.take() {
+            Some((needle, idx)) if entries.get(idx) == Some(&text) => (needle, idx),
+            _ => (text.trim().to_string(), entries.len()),
+        };
+
+        let Some(idx) = find_sent(&entries, &needle, start) else {
+            let msg = format!("No earlier sent message contains {needle:?}");
+            self.sent_search = (start < entries.len()).then_some((needle, start));
+
+            return Ok(Some(InfoMessage::from(msg)));
+        };
+
+        self.tbox.set_text(entries[idx].as_str());
+        self.sent_search = Some((needle, idx));
+
+        Ok(None)
+    }
+
+    /// Cancel the latest message from this message bar that's still waiting to be sent, and drop
+    /// its local echo. Its text goes back into the message bar if nothing else has been typed.
+    ///
+    /// This returns `None` if there's nothing left to cancel, and fails if the latest message is
+    /// already on its way to the server, since it's too late to stop it but not yet redactable.
+    fn unsend(&mut self, store: &mut ProgramStore) -> IambResult<Option<EditInfo>> {
+        let thread = self.thread();
+        let find = |queue: &Vec<PendingMessage>| {
+            queue
+                .iter()
+                .rposition(|p| p.room_id == self.room_id && p.thread.as_ref() == thread)
+        };
+
+        // Messages that the server rejected never went out either, so they can be dropped too.
+        let ChatStore { send_queue, failed_sends, .. } = &mut store.application;
+        let pending = if let Some(idx) = find(send_queue) {
+            if send_queue[idx].sending {
+                let msg = "The message is already being sent; try again once it's gone out";
+                return Err(UIError::Failure(msg.into()));
+            }
+
+            send_queue.remove(idx)
+        } else if let Some(idx) = find(failed_sends) {
+            failed_sends.remove(idx)
+        } else {
+            return Ok(None);
+        };
+
+        let info = store.application.rooms.get_or_default(self.room_id.clone());
 
-                let reactions = reactions.iter().filter_map(|(event_id, (reaction, user_id))| {
-                    if user_id != &settings.profile.user_id {
-                        return None;
-                    }
+        if let Some(echo_id) = &pending.echo_id {
+            let key = (MessageTimeStamp::LocalEcho, echo_id.clone());
+            self.scrollback.get_thread_mut(info).remove(&key);
+        }
 
-                    if let Some(emoji) = &emoji {
-                        if emoji == reaction {
-                            return Some(event_id);
-                        } else {
-                            return None;
-                        }
-                    } else {
-                        return Some(event_id);
-                    }
-                });
+        if self.tbox.get().is_blank() {
+            // Put back only what was typed, without any reply fallback, and pick the edit back up.
+            let msgtype = match pending.content.relates_to {
+                Some(Relation::Replacement(replacement)) => {
+                    let event_id = replacement.event_id;
+                    self.editing = info.get_message_key(&event_id).cloned();
+                    replacement.new_content.msgtype
+                },
+                Some(Relation::Reply { .. }) |
+                Some(Relation::Thread(Thread { is_falling_back: false, .. })) => {
+                    let mut msgtype = pending.content.msgtype;
+                    strip_reply_fallback(&mut msgtype);
+                    msgtype
+                },
+                _ => pending.content.msgtype,
+            };
+
+            self.tbox.set_text(msgtype.body());
+            self.focus = RoomFocus::MessageBar;
+        }
 
-                for reaction in reactions {
-                    let _ = room.redact(reaction, None, None).await.map_err(IambError::from)?;
-                }
+        Ok(Some(Some("Message unsent".into())))
+    }
 
-                Ok(None)
-            },
+    /// Build the content for a message from the message bar, relating it to the message being
+    /// edited or replied to. The flag is true for edits.
+    fn prepare_message(
+        &self,
+        text: String,
+        markdown: bool,
+        info: &RoomInfo,
+    ) -> (RoomMessageEventContent, bool) {
+        // Editing an attachment only changes its caption.
+        let attachment = self.editing.as_ref().and_then(|(_, event_id)| {
+            let content = info.get_event(event_id)?.event.content()?;
+            recaption(&content.msgtype, text.clone())
+        });
+        let mut msg = match attachment {
+            Some(msgtype) => RoomMessageEventContent::new(msgtype),
+            None => text_to_message(text, markdown),
+        };
+
+        if let Some((_, event_id)) = &self.editing {
+            msg.relates_to = Some(Relation::Replacement(Replacement::new(
+                event_id.clone(),
+                msg.msgtype.clone().into(),
+            )));
+
+            return (msg, true);
+        } else if let Some(thread_root) = self.scrollback.thread() {
+            if let Some(m) = self.get_reply_to(info) {
+                msg = msg.make_for_thread(m, ReplyWithinThread::Yes, AddMentions::No);
+            } else if let Some(m) = info.get_thread_last(thread_root) {
+                msg = msg.make_for_thread(m, ReplyWithinThread::No, AddMentions::No);
+            } else {
+                // Internal state is wonky?
+            }
+        } else if let Some((_, thread_root)) = &self.reply_thread {
+            if let Some(m) = info.get_thread_last(thread_root) {
+                msg = msg.make_for_thread(m, ReplyWithinThread::No, AddMentions::No);
+            }
+        } else if let Some(m) = self.get_reply_to(info) {
+            msg = msg.make_reply_to(m, ForwardThread::Yes, AddMentions::No);
         }
+
+        (msg, false)
     }
 
     pub async fn send_command(
@@ -473,12 +1196,22 @@ impl ChatState {
         _: ProgramContext,
         store: &mut ProgramStore,
     ) -> IambResult<EditInfo> {
-        let room = self.get_joined(&store.application.worker)?;
+        let room = self.get_joined_or_accept(&store.application.worker, || act.clone().into())?;
+        let online = store.application.worker.is_online();
+
+        if act != SendAction::SearchHistory {
+            if let Some(remaining) = self.send_cooldown_remaining() {
+                return Err(IambError::SendCooldown(cooldown_secs(remaining)).into());
+            }
+        }
+
         let info = store.application.rooms.get_or_default(self.id().to_owned());
         let mut show_echo = true;
+        let mut queued = None;
 
         let (event_id, msg) = match act {
-            SendAction::Submit | SendAction::SubmitFromEditor => {
+            SendAction::SearchHistory => return self.search_history(store),
+            SendAction::Submit | SendAction::SubmitText | SendAction::SubmitFromEditor => {
                 let msg = self.tbox.get();
 
                 let msg = if let SendAction::SubmitFromEditor = act {
@@ -491,40 +1224,92 @@ impl ChatState {
                     msg.trim_end().to_string()
                 };
 
-                let mut msg = text_to_message(msg);
+                // Check for pasted paths first, since they can contain text like a shortcode.
+                if act == SendAction::Submit &&
+                    self.editing.is_none() &&
+                    store.application.settings.tunables.upload_pasted_paths
+                {
+                    if let Some(paths) = pasted_paths(&msg) {
+                        return Err(upload_paths_dialog(paths));
+                    }
+                }
+
+                let tunables = &store.application.settings.tunables;
+                let msg = if tunables.message_shortcode_expand {
+                    expand_shortcodes(&msg)
+                } else {
+                    msg
+                };
+                let room_tunables = tunables.rooms.get(&self.room_id);
+                let markdown = room_tunables
+                    .and_then(|r| r.send_markdown)
+                    .unwrap_or(tunables.send_markdown);
+
+                // Per-room templates only wrap what gets sent, and never an edit.
+                let template = if self.editing.is_none() {
+                    room_tunables.and_then(|r| r.apply_send_template(&msg))
+                } else {
+                    None
+                };
+
+                let (echo, is_edit) = self.prepare_message(msg, markdown, info);
+                let msg = match template {
+                    Some(text) => self.prepare_message(text, markdown, info).0,
+                    None => echo.clone(),
+                };
 
-                if let Some((_, event_id)) = &self.editing {
-                    msg.relates_to = Some(Relation::Replacement(Replacement::new(
-                        event_id.clone(),
-                        msg.msgtype.clone().into(),
-                    )));
+                show_echo = !is_edit;
 
-                    show_echo = false;
-                } else if let Some(thread_root) = self.scrollback.thread() {
-                    if let Some(m) = self.get_reply_to(info) {
-                        msg = msg.make_for_thread(m, ReplyWithinThread::Yes, AddMentions::No);
-                    } else if let Some(m) = info.get_thread_last(thread_root) {
-                        msg = msg.make_for_thread(m, ReplyWithinThread::No, AddMentions::No);
-                    } else {
-                        // Internal state is wonky?
+                let grace = store.application.settings.tunables.unsend_grace_period;
+
+                // Sending directly while older messages are still queued would get ahead of them.
+                let queue = &store.application.send_queue;
+                let behind = queue.iter().any(|p| p.room_id == self.room_id);
+
+                let event_id = if online && grace == 0 && !behind {
+                    match room.send(msg.clone()).await {
+                        Ok(resp) => resp.event_id,
+                        Err(e) => return Err(self.send_error(e).into()),
                     }
-                } else if let Some(m) = self.get_reply_to(info) {
-                    msg = msg.make_reply_to(m, ForwardThread::Yes, AddMentions::No);
-                }
+                } else {
+                    // Hold onto the message until it can no longer be unsent and we can reach
+                    // the server, and give its local echo a placeholder identifier in the
+                    // meantime.
+                    let txn_id = TransactionId::new();
+                    let echo_id = format!("$iamb-queued-{txn_id}");
+                    let echo_id = EventId::parse(echo_id).expect("valid event ID");
+
+                    queued = Some(PendingMessage {
+                        room_id: self.room_id.clone(),
+                        thread: self.scrollback.thread().cloned(),
+                        echo_id: show_echo.then(|| echo_id.clone()),
+                        txn_id,
+                        content: msg.clone(),
+                        send_after: Instant::now() + Duration::from_secs(grace),
+                        sending: false,
+                    });
+
+                    echo_id
+                };
 
-                // XXX: second parameter can be a locally unique transaction id.
-                // Useful for doing retries.
-                let resp = room.send(msg.clone()).await.map_err(IambError::from)?;
-                let event_id = resp.event_id;
+                // Reset message bar state now that it's been sent, and remember what was sent.
+                let text = self.reset().trim();
+                self.typing_stopped(&store.application.worker);
 
-                // Reset message bar state now that it's been sent.
-                self.reset();
+                if !text.is_empty() {
+                    store.application.sent_history.get(&self.room_id).select(text);
+                }
 
-                (event_id, msg)
+                (event_id, echo)
             },
-            SendAction::Upload(file) => {
+            SendAction::Upload(..) | SendAction::UploadImage(..) if !online => {
+                return Err(IambError::Offline.into());
+            },
+            SendAction::Upload(file, caption, mime) => {
                 let path = Path::new(file.as_str());
-                let mime = mime_guess::from_path(path).first_or(mime::APPLICATION_OCTET_STREAM);
+                let mime = mime.unwrap_or_else(|| {
+                    mime_guess::from_path(path).first_or(mime::APPLICATION_OCTET_STREAM)
+                });
 
                 let bytes = fs::read(path)?;
                 let name = path
@@ -533,13 +1318,27 @@ impl ChatState {
                     .unwrap_or_else(|| Cow::from("Attachment"));
                 let config = AttachmentConfig::new();
 
-                let resp = room
-                    .send_attachment(name.as_ref(), &mime, bytes, config)
-                    .await
-                    .map_err(IambError::from)?;
+                let res = match &caption {
+                    Some(caption) => {
+                        match captioned_attachment(&room, &name, caption, &mime, bytes).await {
+                            Ok(json) => room.send_raw("m.room.message", json).await,
+                            Err(e) => Err(e),
+                        }
+                    },
+                    None => room.send_attachment(name.as_ref(), &mime, bytes, config).await,
+                };
+
+                let resp = match res {
+                    Ok(resp) => resp,
+                    Err(e) => return Err(self.send_error(e).into()),
+                };
 
                 // Mock up the local echo message for the scrollback.
-                let msg = TextMessageEventContent::plain(format!("[Attached File: {name}]"));
+                let echo = match &caption {
+                    Some(caption) => format!("[Attached File: {name}] {caption}"),
+                    None => format!("[Attached File: {name}]"),
+                };
+                let msg = TextMessageEventContent::plain(echo);
                 let msg = MessageType::Text(msg);
                 let msg = RoomMessageEventContent::new(msg);
 
@@ -563,10 +1362,10 @@ impl ChatState {
                 let name = "Clipboard.png";
                 let config = AttachmentConfig::new();
 
-                let resp = room
-                    .send_attachment(name.as_ref(), &mime, bytes, config)
-                    .await
-                    .map_err(IambError::from)?;
+                let resp = match room.send_attachment(name.as_ref(), &mime, bytes, config).await {
+                    Ok(resp) => resp,
+                    Err(e) => return Err(self.send_error(e).into()),
+                };
 
                 // Mock up the local echo message for the scrollback.
                 let msg = TextMessageEventContent::plain(format!("[Attached File: {name}]"));
@@ -586,6 +1385,16 @@ impl ChatState {
             thread.insert(key, msg);
         }
 
+        if let Some(pending) = queued {
+            store.application.send_queue.push(pending);
+        }
+
+        if online {
+            let rooms = &store.application.settings.tunables.rooms;
+            let cooldown = rooms.get(&self.room_id).and_then(|r| r.send_cooldown);
+            self.send_cooldown = cooldown.map(|secs| Instant::now() + Duration::from_secs(secs));
+        }
+
         // Jump to the end of the scrollback to show the message.
         self.scrollback.goto_latest();
 
@@ -608,7 +1417,7 @@ impl ChatState {
     }
 
     pub fn typing_notice(
-        &self,
+        &mut self,
         act: &EditorAction,
         ctx: &ProgramContext,
         store: &mut ProgramStore,
@@ -621,7 +1430,19 @@ impl ChatState {
             return;
         }
 
-        store.application.worker.typing_notice(self.room_id.clone());
+        let now = Instant::now();
+
+        if typing_notice_due(self.typing_sent, now) {
+            self.typing_sent = Some(now);
+            store.application.worker.typing_notice(self.room_id.clone(), true);
+        }
+    }
+
+    /// Tell the room that we've stopped typing, if we said that we were.
+    fn typing_stopped(&mut self, worker: &Requester) {
+        if self.typing_sent.take().is_some() {
+            worker.typing_notice(self.room_id.clone(), false);
+        }
     }
 }
 
@@ -656,20 +1477,28 @@ impl WindowOps<IambInfo> for ChatState {
         let ebuf = store.load_buffer(id);
         let tbox = TextBoxState::new(ebuf);
 
-        ChatState {
-            room_id,
+        let mut state = ChatState {
+            room_id: room_id.clone(),
             room: self.room.clone(),
 
             tbox,
-            sent: self.sent.clone(),
+            tbox_top: 0,
             sent_scrollback: history::ScrollbackState::Pending,
+            sent_search: None,
 
             scrollback: self.scrollback.dup(store),
             focus: self.focus,
 
             reply_to: None,
             editing: None,
-        }
+            sticky_reply: None,
+            reply_thread: None,
+            send_cooldown: self.send_cooldown,
+            typing_sent: None,
+        };
+
+        state.restore_targets(store.application.rooms.get_or_default(room_id));
+        state
     }
 
     fn close(&mut self, _: CloseFlags, _: &mut ProgramStore) -> bool {
@@ -786,7 +1615,7 @@ impl PromptActions<ProgramContext, ProgramStore, IambInfo> for ChatState {
         &mut self,
         empty: bool,
         _: &ProgramContext,
-        _: &mut ProgramStore,
+        store: &mut ProgramStore,
     ) -> EditResult<Vec<(ProgramAction, ProgramContext)>, IambInfo> {
         let text = self.tbox.get();
 
@@ -795,11 +1624,14 @@ impl PromptActions<ProgramContext, ProgramStore, IambInfo> for ChatState {
         }
 
         let text = self.reset().trim();
+        self.typing_stopped(&store.application.worker);
+
+        let sent = store.application.sent_history.get(&self.room_id);
 
         if text.is_empty() {
-            let _ = self.sent.end();
+            let _ = sent.end();
         } else {
-            self.sent.select(text);
+            sent.select(text);
         }
 
         return Ok(vec![]);
@@ -811,12 +1643,13 @@ impl PromptActions<ProgramContext, ProgramStore, IambInfo> for ChatState {
         count: &Count,
         prefixed: bool,
         ctx: &ProgramContext,
-        _: &mut ProgramStore,
+        store: &mut ProgramStore,
     ) -> EditResult<Vec<(ProgramAction, ProgramContext)>, IambInfo> {
         let count = ctx.resolve(count);
         let rope = self.tbox.get();
+        let sent = store.application.sent_history.get(&self.room_id);
 
-        let text = self.sent.recall(&rope, &mut self.sent_scrollback, *dir, prefixed, count);
+        let text = sent.recall(&rope, &mut self.sent_scrollback, *dir, prefixed, count);
 
         if let Some(text) = text {
             self.tbox.set_text(text);
@@ -869,11 +1702,44 @@ impl<'a> StatefulWidget for Chat<'a> {
 
     fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
         // Determine whether we have a description to show for the message bar.
+        let esc = self.store.application.settings.tunables.escape_action == EscapeAction::Cancel;
         let desc_spans = match (&state.editing, &state.reply_to, state.thread()) {
-            (None, None, None) => None,
+            (None, None, None) => {
+                let room = self.store.application.rooms.get(state.id());
+                state.reply_thread.as_ref().zip(room).and_then(|(key, room)| {
+                    let msg = state.scrollback.get_thread(room)?.get(key)?;
+                    let user = self.store.application.settings.get_user_span(&msg.sender, room);
+                    let preview = format!(": {}", target_preview(&msg.event.body()));
+                    let hint = cancel_hint(":thread cancel", false, esc);
+                    let spans = vec![
+                        "Replying in thread of ".into(),
+                        user,
+                        preview.into(),
+                        hint.into(),
+                    ];
+
+                    Some(Line::from(spans))
+                })
+            },
             (None, None, Some(_)) => Some(Line::from("Replying in thread")),
-            (Some(_), None, None) => Some(Line::from("Editing message")),
-            (Some(_), None, Some(_)) => Some(Line::from("Editing message in thread")),
+            (Some(key), None, thread) => {
+                let prefix = match thread {
+                    None => "Editing message",
+                    Some(_) => "Editing message in thread",
+                };
+                let mut spans = vec![Span::from(prefix)];
+
+                let room = self.store.application.rooms.get(state.id());
+                let msg = room.and_then(|room| state.scrollback.get_thread(room)?.get(key));
+
+                if let Some(msg) = msg {
+                    spans.push(format!(": {}", target_preview(&msg.event.body())).into());
+                }
+
+                spans.push(cancel_hint(":edit cancel", false, esc).into());
+
+                Some(Line::from(spans))
+            },
             (editing, Some(_), thread) => {
                 self.store.application.rooms.get(state.id()).and_then(|room| {
                     let msg = state.get_reply_to(room)?;
@@ -885,7 +1751,31 @@ impl<'a> StatefulWidget for Chat<'a> {
                         (false, false) => Span::from("Replying to "),
                         (false, true) => Span::from("Replying in thread to "),
                     };
-                    let spans = Line::from(vec![prefix, user]);
+
+                    let mut spans = vec![prefix];
+
+                    if let Some((kind, name)) = reply_kind(&msg.content.msgtype) {
+                        spans.push(format!("{kind} from ").into());
+                        spans.push(user);
+
+                        if let Some(name) = name.filter(|n| !n.is_empty()) {
+                            spans.push(format!(" ({name})").into());
+                        }
+                    } else {
+                        spans.push(user);
+                        spans.push(format!(": {}", target_preview(msg.content.body())).into());
+                    }
+
+                    let hint = match editing {
+                        Some(_) => cancel_hint(":edit cancel", false, esc),
+                        None => {
+                            let sticky = state.sticky_reply == state.reply_to;
+                            cancel_hint(":reply cancel", sticky, esc)
+                        },
+                    };
+                    spans.push(hint.into());
+
+                    let spans = Line::from(spans);
 
                     spans.into()
                 })
@@ -896,7 +1786,27 @@ impl<'a> StatefulWidget for Chat<'a> {
         let lines = state.tbox.has_lines(5).max(1) as u16;
         let drawh = area.height;
         let texth = lines.min(drawh).clamp(1, 5);
-        let desch = if desc_spans.is_some() {
+
+        // Track which lines of the draft are visible, the same way the TextBox scrolls.
+        let total = state.tbox.get_lines().max(1);
+        let cursor = state.tbox.get_cursor();
+        let height = texth as usize;
+
+        if cursor.y < state.tbox_top {
+            state.tbox_top = cursor.y;
+        } else if cursor.y >= state.tbox_top + height {
+            state.tbox_top = cursor.y + 1 - height;
+        }
+
+        let cooldown = state
+            .send_cooldown_remaining()
+            .map(|remaining| format!("Slow mode: {}s", cooldown_secs(remaining)));
+        let indicator = match (cooldown, draft_indicator(state.tbox_top, height, total)) {
+            (Some(cooldown), Some(draft)) => Some(format!("{cooldown}  {draft}")),
+            (cooldown, draft) => cooldown.or(draft),
+        };
+
+        let desch = if desc_spans.is_some() || indicator.is_some() {
             drawh.saturating_sub(texth).min(1)
         } else {
             0
@@ -912,6 +1822,10 @@ impl<'a> StatefulWidget for Chat<'a> {
             Paragraph::new(desc_spans).render(descarea, buf);
         }
 
+        if let Some(indicator) = indicator {
+            Paragraph::new(indicator).alignment(Alignment::Right).render(descarea, buf);
+        }
+
         let prompt = if self.focused { "> " } else { "  " };
 
         let tbox = TextBox::new().prompt(prompt);
@@ -923,7 +1837,422 @@ impl<'a> StatefulWidget for Chat<'a> {
             .focus(scrollback_focused)
             .room_focus(self.focused);
         scrollback.render(scrollarea, buf, &mut state.scrollback);
+
+        // Windows for rooms are replaced without notice when switching to another room, so keep
+        // the stored targets up to date whenever the message bar is shown.
+        let info = self.store.application.rooms.get_or_default(state.room_id.clone());
+        state.save_targets(info);
+    }
+}
+
+/// Upload a file and build the content for sending it with a caption. Following MSC2530, the
+/// caption goes in the body, and the file's name in a separate `filename` field.
+async fn captioned_attachment(
+    room: &MatrixRoom,
+    name: &str,
+    caption: &str,
+    mime: &mime::Mime,
+    bytes: Vec<u8>,
+) -> Result<serde_json::Value, matrix_sdk::Error> {
+    let client = room.client();
+    let size = UInt::new(bytes.len() as u64);
+
+    let source = if room.is_encrypted().await? {
+        let mut reader = std::io::Cursor::new(bytes);
+        let file = client.prepare_encrypted_file(mime, &mut reader).await?;
+        MediaSource::Encrypted(Box::new(file))
+    } else {
+        let resp = client.media().upload(mime, bytes).await?;
+        MediaSource::Plain(resp.content_uri)
+    };
+
+    let body = caption.to_string();
+    let mimetype = Some(mime.essence_str().to_string());
+
+    let msgtype = match mime.type_() {
+        mime::IMAGE => {
+            let mut info = ImageInfo::new();
+            info.mimetype = mimetype;
+            info.size = size;
+            MessageType::Image(ImageMessageEventContent::new(body, source).info(Box::new(info)))
+        },
+        mime::AUDIO => {
+            let mut info = AudioInfo::new();
+            info.mimetype = mimetype;
+            info.size = size;
+            MessageType::Audio(AudioMessageEventContent::new(body, source).info(Box::new(info)))
+        },
+        mime::VIDEO => {
+            let mut info = VideoInfo::new();
+            info.mimetype = mimetype;
+            info.size = size;
+            MessageType::Video(VideoMessageEventContent::new(body, source).info(Box::new(info)))
+        },
+        _ => {
+            let mut info = FileInfo::new();
+            info.mimetype = mimetype;
+            info.size = size;
+            MessageType::File(FileMessageEventContent::new(body, source).info(Box::new(info)))
+        },
+    };
+
+    let mut json = serde_json::to_value(RoomMessageEventContent::new(msgtype))?;
+    json["filename"] = name.into();
+
+    Ok(json)
+}
+
+/// Split the message bar's contents into shell-style words, and return them as paths if every
+/// one of them is a `file://` URL or an absolute path to an existing file.
+fn pasted_paths(text: &str) -> Option<Vec<PathBuf>> {
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut quote = None;
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('\\', q) if q != Some('\'') => word.push(chars.next()?),
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            (c, None) if c.is_whitespace() => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            },
+            (c, _) => word.push(c),
+        }
     }
+
+    if quote.is_some() {
+        return None;
+    }
+
+    if !word.is_empty() {
+        words.push(word);
+    }
+
+    if words.is_empty() {
+        return None;
+    }
+
+    words
+        .into_iter()
+        .map(|word| {
+            let path = if word.starts_with("file://") {
+                Url::parse(&word).ok()?.to_file_path().ok()?
+            } else if let Some(rest) = word.strip_prefix("~/") {
+                dirs::home_dir()?.join(rest)
+            } else {
+                PathBuf::from(word)
+            };
+
+            (path.is_absolute() && path.is_file()).then_some(path)
+        })
+        .collect()
+}
+
+/// Find the newest entry before `start` that contains `needle`, ignoring case.
+fn find_sent(entries: &[String], needle: &str, start: usize) -> Option<usize> {
+    let needle = needle.to_lowercase();
+
+    entries[..start.min(entries.len())]
+        .iter()
+        .rposition(|entry| !entry.trim().is_empty() && entry.to_lowercase().contains(&needle))
+}
+
+/// Ask whether pasted file paths should be uploaded as attachments or sent as text.
+fn upload_paths_dialog(paths: Vec<PathBuf>) -> UIError<IambInfo> {
+    let desc = match paths.as_slice() {
+        [path] => format!("Upload {}", path.display()),
+        paths => format!("Upload {} files", paths.len()),
+    };
+
+    let mut upload: Vec<ProgramAction> = paths
+        .into_iter()
+        .map(|path| {
+            let path = path.to_string_lossy().into_owned();
+            IambAction::from(SendAction::Upload(path, None, None)).into()
+        })
+        .collect();
+    upload.push(Action::from(PromptAction::Abort(false)));
+
+    let text = vec![IambAction::from(SendAction::SubmitText).into()];
+
+    let choices = vec![
+        MultiChoiceItem::new('u', desc, upload),
+        MultiChoiceItem::new('t', "Send as text", text),
+    ];
+
+    UIError::NeedConfirm(Box::new(MultiChoice::new(choices)))
+}
+
+/// Describe what kind of message is being replied to, along with the name of any attachment.
+///
+/// This returns `None` for text messages, since they don't need any further explanation.
+fn reply_kind(msgtype: &MessageType) -> Option<(&'static str, Option<&str>)> {
+    let kind = match msgtype {
+        MessageType::Text(_) | MessageType::Notice(_) | MessageType::Emote(_) => return None,
+        MessageType::Audio(c) => ("an audio clip", Some(c.body.as_str())),
+        MessageType::File(c) => ("a file", Some(c.body.as_str())),
+        MessageType::Image(c) => ("an image", Some(c.body.as_str())),
+        MessageType::Video(c) => ("a video", Some(c.body.as_str())),
+        MessageType::Location(_) => ("a location", None),
+        MessageType::ServerNotice(_) => ("a server notice", None),
+        MessageType::VerificationRequest(_) => ("a verification request", None),
+        _ => ("a message", None),
+    };
+
+    Some(kind)
+}
+
+/// Shorten a message into a one-line, quoted preview for the message bar description.
+fn target_preview(body: &str) -> String {
+    const MAX_PREVIEW: usize = 40;
+
+    let line = body.lines().map(str::trim).find(|l| !l.is_empty()).unwrap_or_default();
+
+    if line.chars().count() > MAX_PREVIEW {
+        let line = line.chars().take(MAX_PREVIEW).collect::<String>();
+        format!("\"{line}…\"")
+    } else {
+        format!("\"{line}\"")
+    }
+}
+
+/// Round up a cooldown to whole seconds, so that it never shows as 0s while still running.
+fn cooldown_secs(remaining: Duration) -> u64 {
+    ((remaining.as_millis() + 999) / 1000) as u64
+}
+
+/// Whether another typing notice should be sent, given when the last one was.
+fn typing_notice_due(sent: Option<Instant>, now: Instant) -> bool {
+    sent.map_or(true, |sent| now.saturating_duration_since(sent) >= TYPING_NOTICE_RESEND)
+}
+
+/// Describe how to stop replying to or editing a message.
+fn cancel_hint(cmd: &str, sticky: bool, esc: bool) -> String {
+    let how = if esc { "Esc" } else { cmd };
+
+    if sticky {
+        format!(" (sticky, stop with {how})")
+    } else {
+        format!(" (cancel with {how})")
+    }
+}
+
+/// Describe the size of a multi-line draft, and whether any of it is scrolled out of view.
+fn draft_indicator(top: usize, height: usize, lines: usize) -> Option<String> {
+    if lines <= 1 {
+        return None;
+    }
+
+    let above = top.min(lines);
+    let below = lines.saturating_sub(top + height);
+    let mut parts = vec![];
+
+    if above > 0 {
+        parts.push(format!("↑{above}"));
+    }
+
+    if below > 0 {
+        parts.push(format!("↓{below}"));
+    }
+
+    parts.push(format!("{lines} lines"));
+
+    Some(parts.join(" "))
+}
+
+/// Open one of the links in a message, asking which one to open when there are several and
+/// none was picked.
+pub async fn open_link(
+    msg: &Message,
+    label: Option<char>,
+    client: &Client,
+    settings: &ApplicationSettings,
+) -> IambResult<EditInfo> {
+    let links = msg.links();
+    let url = pick_link(&links, label)?;
+
+    if url.scheme() == "mxc" {
+        return open_media(url, client, settings).await;
+    }
+
+    open_command(settings.tunables.open_command.as_ref(), url.as_str().into())?;
+
+    Ok(Some(format!("Opened {url}").into()))
+}
+
+/// Download linked Matrix content into the download directory, and open the local copy.
+///
+/// Going through the client means the homeserver gets an authenticated request, which the
+/// media download endpoint requires on most servers now.
+async fn open_media(
+    url: Url,
+    client: &Client,
+    settings: &ApplicationSettings,
+) -> IambResult<EditInfo> {
+    let dir = settings.dirs.downloads.as_ref().ok_or(IambError::NoDownloadDir)?;
+    let mxc = OwnedMxcUri::from(url.as_str());
+    let Ok((_, media_id)) = mxc.parts() else {
+        return Err(UIError::Failure(format!("Invalid content link: {url}")));
+    };
+    let filename = unused_path(dir.join(media_id));
+
+    let req = MediaRequest {
+        source: MediaSource::Plain(mxc.clone()),
+        format: MediaFormat::File,
+    };
+    let bytes = client
+        .media()
+        .get_media_content(&req, true)
+        .await
+        .map_err(IambError::from)?;
+
+    fs::create_dir_all(dir)?;
+    fs::write(&filename, bytes)?;
+
+    let target = filename.clone().into_os_string();
+    open_command(settings.tunables.open_command.as_ref(), target)?;
+
+    Ok(Some(format!("Downloaded {url} to {} and opened it", filename.display()).into()))
+}
+
+/// Find the link with the given label, the only link when there's just one, or else ask which
+/// one to open.
+fn pick_link(links: &[(char, Url)], label: Option<char>) -> IambResult<Url> {
+    let url = match (label, links) {
+        (_, []) => return Err(IambError::NoLinks.into()),
+        (Some(label), links) => {
+            let Some((_, url)) = links.iter().find(|(c, _)| *c == label) else {
+                let msg = format!("Selected message does not have a link [{label}]");
+                return Err(UIError::Failure(msg));
+            };
+
+            url.clone()
+        },
+        (None, [(_, url)]) => url.clone(),
+        (None, links) => {
+            let choices = links
+                .iter()
+                .map(|(c, url)| {
+                    // Matrix content needs downloading first, so pick it again from the message.
+                    let act = if url.scheme() == "mxc" {
+                        IambAction::from(MessageAction::OpenLink(Some(*c)))
+                    } else {
+                        IambAction::OpenLink(url.to_string())
+                    };
+
+                    MultiChoiceItem::new(*c, url.to_string(), vec![act.into()])
+                })
+                .collect();
+            let dialog = MultiChoice::new(choices);
+
+            return Err(UIError::NeedConfirm(Box::new(dialog)));
+        },
+    };
+
+    Ok(url)
+}
+
+/// Download the attachment on a message, optionally opening it afterwards.
+pub async fn download_attachment(
+    msg: &mut Message,
+    filename: Option<String>,
+    flags: DownloadFlags,
+    client: &Client,
+    settings: &ApplicationSettings,
+) -> IambResult<EditInfo> {
+    if let MessageEvent::Original(ev) = &msg.event {
+        let media = client.media();
+
+        let requested = filename.clone();
+        let mut filename = match (filename, &settings.dirs.downloads) {
+            (Some(f), _) => PathBuf::from(f),
+            (None, Some(downloads)) => downloads.clone(),
+            (None, None) => return Err(IambError::NoDownloadDir.into()),
+        };
+
+        let attachment = match Attachment::from_msgtype(&ev.content.msgtype) {
+            Some(attachment) => attachment,
+            None => {
+                if !flags.contains(DownloadFlags::OPEN) || msg.links().is_empty() {
+                    return Err(IambError::NoAttachment.into());
+                }
+
+                return open_link(msg, None, client, settings).await;
+            },
+        };
+
+        let details = attachment.details();
+        let warn_size = settings.tunables.download_warn_size;
+
+        if !flags.contains(DownloadFlags::CONFIRMED) &&
+            matches!(attachment.size, Some(size) if size > warn_size)
+        {
+            let msg = format!("{} is large ({details}). Download it anyway?", attachment.filename);
+            let act = MessageAction::Download(requested, flags | DownloadFlags::CONFIRMED);
+            let act = IambAction::from(act);
+            let prompt = PromptYesNo::new(msg, vec![Action::from(act)]);
+            let prompt = Box::new(prompt);
+
+            return Err(UIError::NeedConfirm(prompt));
+        }
+
+        if filename.is_dir() {
+            filename.push(attachment.filename);
+        }
+
+        if !flags.contains(DownloadFlags::FORCE) {
+            filename = unused_path(filename);
+        }
+
+        if !filename.exists() || flags.contains(DownloadFlags::FORCE) {
+            let req = MediaRequest {
+                source: attachment.source,
+                format: MediaFormat::File,
+            };
+
+            let bytes = media.get_media_content(&req, true).await.map_err(IambError::from)?;
+
+            fs::write(filename.as_path(), bytes.as_slice())?;
+
+            msg.downloaded = true;
+        } else if !flags.contains(DownloadFlags::OPEN) {
+            let msg = format!(
+                "The file {} already exists; add ! to end of command to overwrite it.",
+                filename.display()
+            );
+            let err = UIError::Failure(msg);
+
+            return Err(err);
+        }
+
+        let info = if flags.contains(DownloadFlags::OPEN) {
+            let target = filename.clone().into_os_string();
+            match open_command(settings.tunables.open_command.as_ref(), target) {
+                Ok(_) => {
+                    InfoMessage::from(format!(
+                        "Attachment ({details}) downloaded to {} and opened",
+                        filename.display()
+                    ))
+                },
+                Err(err) => {
+                    return Err(err);
+                },
+            }
+        } else {
+            InfoMessage::from(format!(
+                "Attachment ({details}) downloaded to {}",
+                filename.display()
+            ))
+        };
+
+        return Ok(info.into());
+    }
+
+    Err(IambError::NoAttachment.into())
 }
 
 fn open_command(open_command: Option<&Vec<String>>, target: OsString) -> IambResult<()> {
@@ -948,3 +2277,154 @@ fn cmd(open_command: &Vec<String>) -> Option<Command> {
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use matrix_sdk::ruma::{
+        events::room::message::{
+            FileMessageEventContent,
+            ImageMessageEventContent,
+            LocationMessageEventContent,
+        },
+        owned_mxc_uri,
+    };
+
+    #[test]
+    fn test_pasted_paths() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let a = dir.child("a.png");
+        let b = dir.child("my file.txt");
+        fs::write(&a, "a").unwrap();
+        fs::write(&b, "b").unwrap();
+
+        let a_str = a.to_string_lossy();
+        let b_str = b.to_string_lossy();
+
+        // Bare paths, escaped or quoted paths, and file:// URLs are all understood.
+        assert_eq!(pasted_paths(&a_str), Some(vec![a.clone()]));
+        let escaped = b_str.replace(' ', "\\ ");
+        assert_eq!(pasted_paths(&escaped), Some(vec![b.clone()]));
+        let quoted = format!("'{a_str}' \"{b_str}\"");
+        assert_eq!(pasted_paths(&quoted), Some(vec![a.clone(), b.clone()]));
+        let url = Url::from_file_path(&b).unwrap();
+        assert_eq!(pasted_paths(url.as_str()), Some(vec![b.clone()]));
+        let lines = format!("{a_str}\n{escaped}\n");
+        assert_eq!(pasted_paths(&lines), Some(vec![a.clone(), b.clone()]));
+
+        // Anything else is just text.
+        assert_eq!(pasted_paths("hello world"), None);
+        assert_eq!(pasted_paths(&format!("see {a_str}")), None);
+        assert_eq!(pasted_paths(&dir.path().to_string_lossy()), None);
+        assert_eq!(pasted_paths("relative.txt"), None);
+        assert_eq!(pasted_paths(&format!("'{a_str}")), None);
+    }
+
+    #[test]
+    fn test_pick_link() {
+        let url = |n: usize| Url::parse(&format!("https://example.com/{n}")).unwrap();
+        let body = (0..12).map(|n| url(n).to_string()).collect::<Vec<_>>().join(" ");
+        let html = (0..12)
+            .map(|n| format!("<a href=\"{}\">link {n}</a>", url(n)))
+            .collect::<String>();
+        let content = RoomMessageEventContent::text_html(body, html);
+        let msg = crate::tests::mock_room1_message(
+            content,
+            crate::tests::TEST_USER1.clone(),
+            crate::tests::MSG2_KEY.clone(),
+        );
+        let links = msg.links();
+        assert_eq!(links.len(), 12);
+
+        // Links are picked by their labels, which continue with letters after 9.
+        assert_eq!(pick_link(&links, Some('1')).unwrap(), url(1));
+        assert_eq!(pick_link(&links, Some('9')).unwrap(), url(9));
+        assert_eq!(pick_link(&links, Some('a')).unwrap(), url(10));
+        assert_eq!(pick_link(&links, Some('b')).unwrap(), url(11));
+        assert!(pick_link(&links, Some('c')).is_err());
+
+        // Without a label, the only link gets opened, or else there's a choice to make.
+        assert_eq!(pick_link(&links[..1], None).unwrap(), url(0));
+        assert!(pick_link(&links, None).is_err());
+        assert!(pick_link(&[], Some('0')).is_err());
+    }
+
+    #[test]
+    fn test_find_sent() {
+        let entries = ["hello world", "lunch?", "", "Hello again", "bye"];
+        let entries = entries.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(find_sent(&entries, "hello", entries.len()), Some(3));
+        assert_eq!(find_sent(&entries, "hello", 3), Some(0));
+        assert_eq!(find_sent(&entries, "hello", 0), None);
+        assert_eq!(find_sent(&entries, "", entries.len()), Some(4));
+        assert_eq!(find_sent(&entries, "", 3), Some(1));
+        assert_eq!(find_sent(&entries, "dinner", entries.len()), None);
+    }
+
+    #[test]
+    fn test_reply_kind() {
+        let text = MessageType::text_plain("hello");
+        assert_eq!(reply_kind(&text), None);
+
+        let url = owned_mxc_uri!("mxc://example.org/abc");
+        let image = ImageMessageEventContent::plain("cat.png".into(), url.clone());
+        let image = MessageType::Image(image);
+        assert_eq!(reply_kind(&image), Some(("an image", Some("cat.png"))));
+
+        let file = FileMessageEventContent::plain("notes.txt".into(), url);
+        let file = MessageType::File(file);
+        assert_eq!(reply_kind(&file), Some(("a file", Some("notes.txt"))));
+
+        let location = LocationMessageEventContent::new("Home".into(), "geo:0,0".into());
+        let location = MessageType::Location(location);
+        assert_eq!(reply_kind(&location), Some(("a location", None)));
+    }
+
+    #[test]
+    fn test_target_preview() {
+        assert_eq!(target_preview("hello"), "\"hello\"");
+        assert_eq!(target_preview("\n  first line  \nsecond line"), "\"first line\"");
+
+        let long = "a".repeat(50);
+        assert_eq!(target_preview(&long), format!("\"{}…\"", "a".repeat(40)));
+    }
+
+    #[test]
+    fn test_typing_notice_due() {
+        let now = Instant::now();
+        assert!(typing_notice_due(None, now));
+        assert!(!typing_notice_due(Some(now), now));
+        assert!(!typing_notice_due(Some(now), now + Duration::from_millis(2999)));
+        assert!(typing_notice_due(Some(now), now + TYPING_NOTICE_RESEND));
+    }
+
+    #[test]
+    fn test_cancel_hint() {
+        assert_eq!(cancel_hint(":reply cancel", false, false), " (cancel with :reply cancel)");
+        assert_eq!(cancel_hint(":reply cancel", true, false), " (sticky, stop with :reply cancel)");
+        assert_eq!(cancel_hint(":edit cancel", false, true), " (cancel with Esc)");
+    }
+
+    #[test]
+    fn test_cooldown_secs() {
+        assert_eq!(cooldown_secs(Duration::from_millis(1)), 1);
+        assert_eq!(cooldown_secs(Duration::from_millis(1000)), 1);
+        assert_eq!(cooldown_secs(Duration::from_millis(29_001)), 30);
+    }
+
+    #[test]
+    fn test_draft_indicator() {
+        // Single-line drafts don't need an indicator.
+        assert_eq!(draft_indicator(0, 5, 0), None);
+        assert_eq!(draft_indicator(0, 5, 1), None);
+
+        // Multi-line drafts that fit only show the line count.
+        assert_eq!(draft_indicator(0, 5, 3).unwrap(), "3 lines");
+
+        // Show how many lines are hidden above and below the visible window.
+        assert_eq!(draft_indicator(0, 5, 8).unwrap(), "↓3 8 lines");
+        assert_eq!(draft_indicator(2, 5, 8).unwrap(), "↑2 ↓1 8 lines");
+        assert_eq!(draft_indicator(3, 5, 8).unwrap(), "↑3 8 lines");
+    }
+}
@@ -1,13 +1,28 @@
 //! # Windows for Matrix rooms and spaces
+use std::path::Path;
+
+use edit::edit_with_builder as external_edit;
+use edit::Builder;
 use matrix_sdk::{
     room::Room as MatrixRoom,
     ruma::{
+        api::client::{error::ErrorBody, room::upgrade_room::v3::Request as UpgradeRoomRequest},
         events::{
-            room::{name::RoomNameEventContent, topic::RoomTopicEventContent},
+            room::{
+                canonical_alias::RoomCanonicalAliasEventContent,
+                join_rules::JoinRule,
+                member::MembershipState,
+                name::RoomNameEventContent,
+                topic::RoomTopicEventContent,
+            },
             tag::{TagInfo, Tags},
+            StateEventType,
         },
         OwnedEventId,
+        OwnedRoomAliasId,
+        RoomAliasId,
         RoomId,
+        UserId,
     },
     DisplayName,
     RoomState as MatrixRoomState,
@@ -16,7 +31,7 @@ use matrix_sdk::{
 use ratatui::{
     buffer::Buffer,
     layout::{Alignment, Rect},
-    style::{Modifier as StyleModifier, Style},
+    style::{Color, Modifier as StyleModifier, Style},
     text::{Line, Span, Text},
     widgets::{Paragraph, StatefulWidget, Widget},
 };
@@ -29,19 +44,27 @@ use modalkit::actions::{
     PromptAction,
     Promptable,
     Scrollable,
+    WindowAction,
 };
-use modalkit::errors::{EditResult, UIError};
+use modalkit::editing::store::{RegisterCell, RegisterPutFlags};
+use modalkit::errors::{EditError, EditResult, UIError};
 use modalkit::prelude::*;
 use modalkit::{editing::completion::CompletionList, keybindings::dialog::PromptYesNo};
 use modalkit_ratatui::{TermOffset, TerminalCursor, WindowOps};
 
 use crate::base::{
+    notification_mode_name,
+    parse_notification_mode,
+    room_version_outdated,
+    ChatStore,
+    HomeserverAction,
     IambAction,
     IambError,
     IambId,
     IambInfo,
     IambResult,
     MessageAction,
+    Need,
     ProgramAction,
     ProgramContext,
     ProgramStore,
@@ -49,7 +72,9 @@ use crate::base::{
     RoomField,
     SendAction,
 };
+use crate::notifications::send_test_notification;
 
+pub use self::chat::download_attachment;
 use self::chat::ChatState;
 use self::space::{Space, SpaceState};
 
@@ -66,6 +91,45 @@ macro_rules! delegate {
     };
 }
 
+fn member_count_text((members, online): (usize, usize)) -> String {
+    let noun = if members == 1 { "member" } else { "members" };
+
+    format!("{members} {noun} ({online} online)")
+}
+
+/// Report a failed moderation request, using the homeserver's explanation when there is one, since
+/// it usually says which power level was missing.
+fn moderation_error(err: matrix_sdk::Error) -> UIError<IambInfo> {
+    match err.as_client_api_error().map(|e| &e.body) {
+        Some(ErrorBody::Standard { message, .. }) => UIError::Failure(message.clone()),
+        _ => IambError::from(err).into(),
+    }
+}
+
+/// Change or clear a room's canonical alias, leaving its alternative aliases alone.
+async fn set_canonical_alias(
+    room: &MatrixRoom,
+    user_id: &UserId,
+    alias: Option<OwnedRoomAliasId>,
+) -> IambResult<()> {
+    let allowed = room
+        .can_user_send_state(user_id, StateEventType::RoomCanonicalAlias)
+        .await
+        .map_err(IambError::from)?;
+
+    if !allowed {
+        return Err(IambError::PermissionDenied.into());
+    }
+
+    let mut ev = RoomCanonicalAliasEventContent::new();
+    ev.alias = alias;
+    ev.alt_aliases = room.alt_aliases();
+
+    let _ = room.send_state_event(ev).await.map_err(IambError::from)?;
+
+    Ok(())
+}
+
 /// State for a Matrix room or space.
 ///
 /// Since spaces function as special rooms within Matrix, we wrap their window state together, so
@@ -163,6 +227,7 @@ impl RoomState {
     ) -> IambResult<EditInfo> {
         match self {
             RoomState::Chat(chat) => chat.message_command(act, ctx, store).await,
+            RoomState::Space(_) if act.is_cancel() => Ok(None),
             RoomState::Space(_) => Err(IambError::NoSelectedMessage.into()),
         }
     }
@@ -182,7 +247,7 @@ impl RoomState {
     pub async fn room_command(
         &mut self,
         act: RoomAction,
-        _: ProgramContext,
+        ctx: ProgramContext,
         store: &mut ProgramStore,
     ) -> IambResult<Vec<(Action<IambInfo>, ProgramContext)>> {
         match act {
@@ -193,6 +258,7 @@ impl RoomState {
                     let is_direct = details.and_then(|ev| ev.is_direct).unwrap_or_default();
 
                     room.join().await.map_err(IambError::from)?;
+                    store.application.version_checks.insert(self.id().to_owned());
 
                     if is_direct {
                         room.set_is_direct(true).await.map_err(IambError::from)?;
@@ -212,6 +278,33 @@ impl RoomState {
                     Err(IambError::NotInvited.into())
                 }
             },
+            RoomAction::InviteLink => {
+                let room =
+                    store.application.get_joined_room(self.id()).ok_or(IambError::NotJoined)?;
+                let link = room.matrix_to_permalink().await.map_err(IambError::from)?.to_string();
+
+                let cell = RegisterCell::new(TargetShape::CharWise, link.as_str().into());
+                let flags = RegisterPutFlags::NONE;
+                store
+                    .registers
+                    .put(&Register::SelectionClipboard, cell, flags)
+                    .map_err(EditError::from)?;
+
+                let msg = match room.join_rule() {
+                    JoinRule::Public | JoinRule::Knock | JoinRule::KnockRestricted(_) => {
+                        format!("Copied {link} to the clipboard")
+                    },
+                    _ => {
+                        format!(
+                            "Copied {link} to the clipboard, but this room is private, so only \
+                             people who are invited or otherwise allowed to join can use it"
+                        )
+                    },
+                };
+                let act = Action::ShowInfoMessage(msg.into());
+
+                Ok(vec![(act, ctx)])
+            },
             RoomAction::InviteSend(user) => {
                 if let Some(room) = store.application.worker.client.get_room(self.id()) {
                     room.invite_user_by_id(user.as_ref()).await.map_err(IambError::from)?;
@@ -221,6 +314,55 @@ impl RoomState {
                     Err(IambError::NotJoined.into())
                 }
             },
+            RoomAction::InviteRevoke(user) => {
+                let Some(room) = store.application.worker.client.get_room(self.id()) else {
+                    return Err(IambError::NotJoined.into());
+                };
+
+                let member = room.get_member_no_sync(&user).await.map_err(IambError::from)?;
+
+                if !matches!(member, Some(m) if m.membership() == &MembershipState::Invite) {
+                    let msg = format!("{user} doesn't have a pending invite to this room");
+                    return Err(UIError::Failure(msg));
+                }
+
+                // Kicking an invited user is how the invite gets withdrawn.
+                room.kick_user(&user, None).await.map_err(moderation_error)?;
+                let act =
+                    Action::ShowInfoMessage(format!("Cancelled the invite for {user}").into());
+
+                Ok(vec![(act, ctx)])
+            },
+            RoomAction::Kick(user, reason) => {
+                let Some(room) = store.application.worker.client.get_room(self.id()) else {
+                    return Err(IambError::NotJoined.into());
+                };
+
+                room.kick_user(&user, reason.as_deref()).await.map_err(moderation_error)?;
+                let act = Action::ShowInfoMessage(format!("Kicked {user}").into());
+
+                Ok(vec![(act, ctx)])
+            },
+            RoomAction::Ban(user, reason) => {
+                let Some(room) = store.application.worker.client.get_room(self.id()) else {
+                    return Err(IambError::NotJoined.into());
+                };
+
+                room.ban_user(&user, reason.as_deref()).await.map_err(moderation_error)?;
+                let act = Action::ShowInfoMessage(format!("Banned {user}").into());
+
+                Ok(vec![(act, ctx)])
+            },
+            RoomAction::Unban(user) => {
+                let Some(room) = store.application.worker.client.get_room(self.id()) else {
+                    return Err(IambError::NotJoined.into());
+                };
+
+                room.unban_user(&user, None).await.map_err(moderation_error)?;
+                let act = Action::ShowInfoMessage(format!("Unbanned {user}").into());
+
+                Ok(vec![(act, ctx)])
+            },
             RoomAction::Leave(skip_confirm) => {
                 if let Some(room) = store.application.worker.client.get_room(self.id()) {
                     if skip_confirm {
@@ -228,7 +370,8 @@ impl RoomState {
 
                         Ok(vec![])
                     } else {
-                        let msg = "Do you really want to leave this room?";
+                        let target = self.confirm_target(store);
+                        let msg = format!("Do you really want to leave {target}?");
                         let leave = IambAction::Room(RoomAction::Leave(true));
                         let prompt = PromptYesNo::new(msg, vec![Action::from(leave)]);
                         let prompt = Box::new(prompt);
@@ -239,6 +382,55 @@ impl RoomState {
                     Err(IambError::NotJoined.into())
                 }
             },
+            RoomAction::LeaveSpace(skip_confirm) => {
+                if !matches!(self, RoomState::Space(_)) {
+                    let msg = "Only spaces can be left along with their rooms";
+                    let err = UIError::Failure(msg.into());
+
+                    return Err(err);
+                }
+
+                let space_id = self.id().to_owned();
+                let worker = &store.application.worker;
+                let mut rooms = vec![];
+                let mut names = vec![];
+
+                for room_id in worker.space_members(space_id.clone())? {
+                    if room_id == space_id {
+                        continue;
+                    }
+
+                    let Ok((room, name, _)) = worker.get_room(room_id.clone()) else {
+                        continue;
+                    };
+
+                    if room.state() == MatrixRoomState::Joined {
+                        names.push(name.to_string());
+                        rooms.push(room_id);
+                    }
+                }
+
+                // Leave the space itself last, once its rooms are done.
+                rooms.push(space_id);
+
+                let leave = IambAction::from(HomeserverAction::LeaveRooms(rooms));
+
+                if skip_confirm {
+                    return Ok(vec![(leave.into(), ctx)]);
+                }
+
+                let target = self.confirm_target(store);
+                let msg = if names.is_empty() {
+                    format!("You haven't joined any rooms in {target}. Leave it anyway?")
+                } else {
+                    let list = names.iter().map(|name| format!("  {name}")).collect::<Vec<_>>();
+                    format!("Leave {target} and these rooms in it?\n{}\n", list.join("\n"))
+                };
+                let prompt = PromptYesNo::new(msg, vec![Action::from(leave)]);
+                let prompt = Box::new(prompt);
+
+                Err(UIError::NeedConfirm(prompt))
+            },
             RoomAction::Members(mut cmd) => {
                 let width = Count::Exact(30);
                 let act =
@@ -249,6 +441,101 @@ impl RoomState {
 
                 Ok(vec![(act, cmd.context.clone())])
             },
+            RoomAction::Gallery => {
+                let gallery = IambId::Gallery(self.id().to_owned());
+                let act = WindowAction::Switch(OpenTarget::Application(gallery));
+
+                Ok(vec![(act.into(), ctx)])
+            },
+            RoomAction::Pins => {
+                let pins = IambId::PinList(self.id().to_owned());
+                let act = WindowAction::Switch(OpenTarget::Application(pins));
+
+                Ok(vec![(act.into(), ctx)])
+            },
+            RoomAction::MarkRead(here) => {
+                let user_id = store.application.settings.profile.user_id.clone();
+                let info = store.application.rooms.get_or_default(self.id().to_owned());
+
+                let moved = match (&*self, here) {
+                    (RoomState::Chat(chat), true) => chat.mark_read_here(info, &user_id)?,
+                    (RoomState::Space(_), true) => return Err(IambError::NoSelectedMessage.into()),
+                    (_, false) => info.mark_read(&user_id),
+                };
+
+                // The receipts get sent the next time the worker checks for new ones.
+                let msg = if moved {
+                    "Marked as read"
+                } else {
+                    "Already read"
+                };
+                let act = Action::ShowInfoMessage(msg.into());
+
+                Ok(vec![(act, ctx)])
+            },
+            RoomAction::Search(text, dir) => {
+                let RoomState::Chat(chat) = self else {
+                    let msg = "Spaces don't have any messages to search";
+                    return Err(UIError::Failure(msg.into()));
+                };
+
+                let msg = chat.search(&text, dir, store)?;
+                let acts = msg.map(|msg| (Action::ShowInfoMessage(msg), ctx));
+
+                Ok(acts.into_iter().collect())
+            },
+            RoomAction::SearchServer(query) => {
+                let room_id = self.id().to_owned();
+                let Some(room) = store.application.worker.client.get_room(&room_id) else {
+                    return Err(IambError::UnknownRoom(room_id).into());
+                };
+
+                // Servers only index plaintext, so encrypted rooms always come back empty.
+                if room.is_encrypted().await.map_err(IambError::from)? {
+                    return Err(IambError::EncryptedSearch.into());
+                }
+
+                // The results get fetched a page at a time in the background.
+                let info = store.application.get_room_info(room_id.clone());
+                info.search_results = Some((query, vec![]));
+                info.search_next_batch = None;
+                info.searching = true;
+                store.application.need_load.insert(room_id.clone(), Need::SEARCH);
+
+                let results = IambId::SearchResults(room_id);
+                let act = WindowAction::Switch(OpenTarget::Application(results));
+
+                Ok(vec![(act.into(), ctx)])
+            },
+            RoomAction::MemberCount => {
+                let ChatStore { rooms, presences, worker, .. } = &mut store.application;
+                let info = rooms.get_or_default(self.id().to_owned());
+                let msg = if info.members.is_empty() {
+                    // The member list is fetched in the background, so use the homeserver's
+                    // count until it arrives.
+                    let joined = worker
+                        .client
+                        .get_room(self.id())
+                        .map_or(0, |room| room.joined_members_count());
+                    let noun = if joined == 1 { "member" } else { "members" };
+
+                    format!("{joined} {noun} (member list not loaded yet)")
+                } else {
+                    member_count_text(info.member_counts(presences))
+                };
+                let act = Action::ShowInfoMessage(msg.into());
+
+                Ok(vec![(act, ctx)])
+            },
+            RoomAction::NotificationTest => {
+                let client = store.application.worker.client.clone();
+                let settings = store.application.settings.clone();
+                let room = self.room().clone();
+                let msg = send_test_notification(&client, &room, &settings, store).await?;
+                let act = Action::ShowInfoMessage(msg.into());
+
+                Ok(vec![(act, ctx)])
+            },
             RoomAction::SetDirect(is_direct) => {
                 let room = store
                     .application
@@ -259,6 +546,132 @@ impl RoomState {
 
                 Ok(vec![])
             },
+            RoomAction::Edit(field) => {
+                let room = self.room();
+                let current = match &field {
+                    RoomField::Avatar => {
+                        let msg = "Room avatars can't be edited";
+                        return Err(UIError::Failure(msg.into()));
+                    },
+                    RoomField::CanonicalAlias => room.canonical_alias().map(|a| a.to_string()),
+                    RoomField::MessageTimestamp => {
+                        let info = store.application.get_room_info(self.id().to_owned());
+                        info.timestamp_format.clone()
+                    },
+                    RoomField::Name => room.name(),
+                    RoomField::NotificationMode => {
+                        let msg = "Notification modes can't be edited";
+                        return Err(UIError::Failure(msg.into()));
+                    },
+                    RoomField::Topic => room.topic(),
+                    RoomField::Tag(_) => {
+                        let msg = "Room tags can't be edited";
+                        return Err(UIError::Failure(msg.into()));
+                    },
+                };
+                let current = current.unwrap_or_default();
+
+                let suffix = store.application.settings.tunables.external_edit_file_suffix.as_str();
+                let value = external_edit(current.clone(), Builder::new().suffix(suffix))?;
+                let value = value.trim();
+
+                // Topics are sent as plain text, since m.room.topic has no formatted body.
+                let act = if value == current.trim() {
+                    return Ok(vec![]);
+                } else if value.is_empty() {
+                    RoomAction::Unset(field)
+                } else {
+                    RoomAction::Set(field, value.to_string())
+                };
+
+                Ok(vec![(IambAction::from(act).into(), ctx)])
+            },
+            RoomAction::ShowAvatar => {
+                let msg = match self.room().avatar_url() {
+                    Some(url) => format!("Room avatar: {url}"),
+                    None => "This room has no avatar".into(),
+                };
+                let act = Action::ShowInfoMessage(msg.into());
+
+                Ok(vec![(act, ctx)])
+            },
+            RoomAction::ShowCanonicalAlias => {
+                let msg = match self.room().canonical_alias() {
+                    Some(alias) => format!("Canonical alias: {alias}"),
+                    None => "This room has no canonical alias".into(),
+                };
+                let act = Action::ShowInfoMessage(msg.into());
+
+                Ok(vec![(act, ctx)])
+            },
+            RoomAction::ShowNotificationMode => {
+                let room = self.room();
+                let mode = room.user_defined_notification_mode().await;
+                let info = store.application.get_room_info(self.id().to_owned());
+                info.notification_mode = mode;
+
+                let msg = match (mode, room.notification_mode().await) {
+                    (Some(mode), _) => format!("Notifications: {}", notification_mode_name(mode)),
+                    (None, Some(mode)) => {
+                        let name = notification_mode_name(mode);
+                        format!("Notifications: {name} (account default)")
+                    },
+                    (None, None) => "This room uses the default notification mode".into(),
+                };
+                let act = Action::ShowInfoMessage(msg.into());
+
+                Ok(vec![(act, ctx)])
+            },
+            RoomAction::ShowTimestampFormat => {
+                let info = store.application.get_room_info(self.id().to_owned());
+                let msg = match &info.timestamp_format {
+                    Some(format) => format!("Timestamp format: {format:?}"),
+                    None => "This room uses the default timestamp format".into(),
+                };
+                let act = Action::ShowInfoMessage(msg.into());
+
+                Ok(vec![(act, ctx)])
+            },
+            RoomAction::Set(RoomField::MessageTimestamp, format) => {
+                let info = store.application.get_room_info(self.id().to_owned());
+                info.timestamp_format = Some(format);
+
+                Ok(vec![])
+            },
+            RoomAction::Unset(RoomField::MessageTimestamp) => {
+                let info = store.application.get_room_info(self.id().to_owned());
+                info.timestamp_format = None;
+
+                Ok(vec![])
+            },
+            RoomAction::Set(RoomField::NotificationMode, level) => {
+                let mode = parse_notification_mode(&level).ok_or_else(|| {
+                    UIError::Failure(format!("Invalid notification level: {level}"))
+                })?;
+
+                let settings = store.application.worker.client.notification_settings().await;
+                settings
+                    .set_room_notification_mode(self.id(), mode)
+                    .await
+                    .map_err(IambError::from)?;
+
+                let info = store.application.get_room_info(self.id().to_owned());
+                info.notification_mode = Some(mode);
+
+                Ok(vec![])
+            },
+            RoomAction::Unset(RoomField::NotificationMode) => {
+                let settings = store.application.worker.client.notification_settings().await;
+                settings
+                    .delete_user_defined_room_rules(self.id())
+                    .await
+                    .map_err(IambError::from)?;
+
+                let info = store.application.get_room_info(self.id().to_owned());
+                info.notification_mode = None;
+
+                Ok(vec![])
+            },
             RoomAction::Set(field, value) => {
                 let room = store
                     .application
@@ -266,10 +679,45 @@ impl RoomState {
                     .ok_or(UIError::Application(IambError::NotJoined))?;
 
                 match field {
+                    RoomField::Avatar => {
+                        let path = Path::new(value.as_str());
+                        let mime = mime_guess::from_path(path).first();
+
+                        let Some(mime) = mime.filter(|m| m.type_() == mime::IMAGE) else {
+                            let msg = format!("Room avatars need to be images: {value}");
+                            return Err(UIError::Failure(msg));
+                        };
+
+                        let bytes = std::fs::read(path).map_err(IambError::from)?;
+                        let _ = room
+                            .upload_avatar(&mime, bytes, None)
+                            .await
+                            .map_err(IambError::from)?;
+                    },
+                    RoomField::CanonicalAlias => {
+                        let alias = RoomAliasId::parse(value.as_str())
+                            .map_err(|_| IambError::InvalidRoomAlias(value))?;
+
+                        // Only let the alias become canonical if it already resolves here.
+                        let client = &store.application.worker.client;
+                        let resp =
+                            client.resolve_room_alias(&alias).await.map_err(IambError::from)?;
+
+                        if resp.room_id != self.id() {
+                            return Err(IambError::AliasMismatch(alias).into());
+                        }
+
+                        let user_id = &store.application.settings.profile.user_id;
+                        set_canonical_alias(&room, user_id, Some(alias.clone())).await?;
+
+                        store.application.names.insert(alias.to_string(), self.id().to_owned());
+                    },
                     RoomField::Name => {
                         let ev = RoomNameEventContent::new(value);
                         let _ = room.send_state_event(ev).await.map_err(IambError::from)?;
                     },
+                    RoomField::MessageTimestamp => unreachable!(),
+                    RoomField::NotificationMode => unreachable!(),
                     RoomField::Tag(tag) => {
                         let mut info = TagInfo::new();
                         info.order = Some(1.0);
@@ -291,10 +739,19 @@ impl RoomState {
                     .ok_or(UIError::Application(IambError::NotJoined))?;
 
                 match field {
+                    RoomField::Avatar => {
+                        let _ = room.remove_avatar().await.map_err(IambError::from)?;
+                    },
+                    RoomField::CanonicalAlias => {
+                        let user_id = &store.application.settings.profile.user_id;
+                        set_canonical_alias(&room, user_id, None).await?;
+                    },
                     RoomField::Name => {
                         let ev = RoomNameEventContent::new("".into());
                         let _ = room.send_state_event(ev).await.map_err(IambError::from)?;
                     },
+                    RoomField::MessageTimestamp => unreachable!(),
+                    RoomField::NotificationMode => unreachable!(),
                     RoomField::Tag(tag) => {
                         let _ = room.remove_tag(tag).await.map_err(IambError::from)?;
                     },
@@ -306,6 +763,72 @@ impl RoomState {
 
                 Ok(vec![])
             },
+            RoomAction::Upgrade(version, skip_confirm) => {
+                let room = store
+                    .application
+                    .get_joined_room(self.id())
+                    .ok_or(UIError::Application(IambError::NotJoined))?;
+
+                if !skip_confirm {
+                    let target = self.confirm_target(store);
+                    let msg = format!(
+                        "Do you really want to upgrade {target}? Members will need to join the new room."
+                    );
+                    let upgrade = IambAction::Room(RoomAction::Upgrade(version, true));
+                    let prompt = PromptYesNo::new(msg, vec![Action::from(upgrade)]);
+                    let prompt = Box::new(prompt);
+
+                    return Err(UIError::NeedConfirm(prompt));
+                }
+
+                let client = &store.application.worker.client;
+                let version = match version {
+                    Some(version) => version,
+                    None => {
+                        let caps = client.get_capabilities().await.map_err(IambError::from)?;
+                        caps.room_versions.default
+                    },
+                };
+
+                let req = UpgradeRoomRequest::new(room.room_id().to_owned(), version.clone());
+                let resp = client.send(req, None).await.map_err(IambError::from)?;
+
+                let msg = format!(
+                    "Upgraded room to version {version}; the new room is {}",
+                    resp.replacement_room
+                );
+                let act = Action::ShowInfoMessage(msg.into());
+
+                Ok(vec![(act, ctx)])
+            },
+            RoomAction::VersionCheck => {
+                let info = self.room().clone_info();
+
+                let msg = match info.room_version() {
+                    Some(v) if room_version_outdated(v) => {
+                        format!("This room uses version {v}, which is outdated and has known security issues; consider :room version upgrade")
+                    },
+                    Some(v) => format!("This room uses version {v}, which is up to date"),
+                    None => "Unable to determine this room's version".into(),
+                };
+                let act = Action::ShowInfoMessage(msg.into());
+
+                Ok(vec![(act, ctx)])
+            },
+            RoomAction::VersionDismiss => {
+                store.application.version_warnings_dismissed.insert(self.id().to_owned());
+
+                Ok(vec![])
+            },
+        }
+    }
+
+    /// Describe this room in confirmation prompts, so that it's clear which room they affect.
+    fn confirm_target(&self, store: &ProgramStore) -> String {
+        if store.application.settings.tunables.confirm_room_name {
+            format!("{:?}", store.application.get_room_title(self.id()))
+        } else {
+            "this room".into()
         }
     }
 
@@ -331,6 +854,44 @@ impl RoomState {
             _ => {},
         }
 
+        if store.application.settings.tunables.member_count_display {
+            let ChatStore { rooms, presences, .. } = &mut store.application;
+            let info = rooms.get_or_default(self.id().to_owned());
+
+            if !info.members.is_empty() {
+                spans.push(" - ".into());
+                spans.push(member_count_text(info.member_counts(presences)).into());
+            }
+        }
+
+        let warn = store.application.settings.tunables.room_version_warning;
+
+        if warn && !store.application.version_warnings_dismissed.contains(self.id()) {
+            match self.room().clone_info().room_version() {
+                Some(v) if room_version_outdated(v) => {
+                    let style = Style::default().fg(Color::Yellow);
+                    let warning = format!("outdated room version {v}");
+                    spans.push(" - ".into());
+                    spans.push(Span::styled(warning, style));
+                },
+                _ => {},
+            }
+        }
+
+        let ChatStore { rooms, identity_alerts, .. } = &mut store.application;
+        let info = rooms.get_or_default(self.id().to_owned());
+        let mut changed = identity_alerts.keys().filter(|u| info.members.contains(*u));
+
+        if let Some(user_id) = changed.next() {
+            let style = Style::default().fg(Color::Red).add_modifier(StyleModifier::BOLD);
+            let warning = match changed.count() {
+                0 => format!("identity of {user_id} changed"),
+                n => format!("identities of {user_id} and {n} others changed"),
+            };
+            spans.push(" - ".into());
+            spans.push(Span::styled(warning, style));
+        }
+
         Line::from(spans)
     }
 
@@ -1,8 +1,10 @@
 //! Message scrollback
+use std::collections::HashSet;
+
 use ratatui_image::Image;
 use regex::Regex;
 
-use matrix_sdk::ruma::{OwnedEventId, OwnedRoomId};
+use matrix_sdk::ruma::{EventId, OwnedEventId, OwnedRoomId, UInt, UserId};
 
 use modalkit_ratatui::{ScrollActions, TerminalCursor, WindowOps};
 use ratatui::{
@@ -43,6 +45,7 @@ use modalkit::prelude::*;
 
 use crate::{
     base::{
+        IambAction,
         IambBufferId,
         IambId,
         IambInfo,
@@ -53,9 +56,10 @@ use crate::{
         RoomFetchStatus,
         RoomFocus,
         RoomInfo,
+        ScrollPosition,
     },
     config::ApplicationSettings,
-    message::{Message, MessageCursor, MessageKey, Messages},
+    message::{Message, MessageCursor, MessageEvent, MessageKey, MessageTimeStamp, Messages},
 };
 
 fn no_msgs() -> EditError<IambInfo> {
@@ -129,6 +133,15 @@ pub struct ScrollbackState {
     /// This is used to ensure that ^E/^Y work nicely when the cursor is currently
     /// on a multiline message.
     show_full_on_redraw: bool,
+
+    /// A message to select once it's been fetched from the server.
+    pending_jump: Option<OwnedEventId>,
+
+    /// A saved position to return to once enough of the room's history has been loaded.
+    pending_restore: Option<ScrollPosition>,
+
+    /// Messages picked out with `:select` for commands to act on all at once.
+    selected: HashSet<OwnedEventId>,
 }
 
 impl ScrollbackState {
@@ -147,6 +160,9 @@ impl ScrollbackState {
             viewctx,
             jumped,
             show_full_on_redraw,
+            pending_jump: None,
+            pending_restore: None,
+            selected: HashSet::default(),
         }
     }
 
@@ -176,6 +192,161 @@ impl ScrollbackState {
         }
     }
 
+    /// Move the cursor to the most recent message sent by `user_id` that hasn't been redacted,
+    /// returning whether one was found.
+    pub fn select_last_from(&mut self, info: &RoomInfo, user_id: &UserId) -> bool {
+        let Some(thread) = self.get_thread(info) else {
+            return false;
+        };
+
+        let found = thread
+            .iter()
+            .rev()
+            .find(|(_, msg)| {
+                msg.sender == user_id &&
+                    !msg.event.is_redacted() &&
+                    !matches!(msg.event, MessageEvent::Membership(_))
+            })
+            .map(|(key, _)| key.clone());
+
+        let Some(key) = found else {
+            return false;
+        };
+
+        self.select(key);
+
+        true
+    }
+
+    /// Move the cursor to the message with the given event identifier, if it's been loaded into
+    /// this scrollback's timeline.
+    pub fn select_event(&mut self, info: &RoomInfo, event_id: &EventId) -> bool {
+        let Some(key) = info.get_message_key(event_id) else {
+            return false;
+        };
+
+        if !self.get_thread(info).is_some_and(|thread| thread.contains_key(key)) {
+            return false;
+        }
+
+        self.select(key.clone());
+
+        true
+    }
+
+    /// Select a message as soon as it shows up in the room's loaded messages.
+    pub fn select_event_later(&mut self, event_id: OwnedEventId) {
+        self.pending_jump = Some(event_id);
+    }
+
+    /// Select the closest message in a direction whose body matches a pattern, returning whether
+    /// one was found, and if not, whether older messages could still be loaded to look through.
+    pub fn find_text(
+        &mut self,
+        needle: &Regex,
+        dir: MoveDir1D,
+        info: &mut RoomInfo,
+    ) -> (bool, bool) {
+        let Some(key) = self.get_key(info) else {
+            return (false, false);
+        };
+
+        let (mc, needs_load) = self.find_message(key, dir, needle, 1, info);
+
+        match mc.and_then(|mc| mc.timestamp) {
+            Some(key) => {
+                self.select(key);
+                (true, false)
+            },
+            None => (false, needs_load),
+        }
+    }
+
+    /// Return to a saved position, loading older messages until it can be found.
+    pub fn restore_position(&mut self, info: &RoomInfo, pos: ScrollPosition) {
+        if !self.try_restore(info, &pos) {
+            self.pending_restore = Some(pos);
+        }
+    }
+
+    /// Select the message at a saved position, or the closest one sent after it if it's no
+    /// longer in the timeline, returning false if older messages need to be loaded first.
+    fn try_restore(&mut self, info: &RoomInfo, pos: &ScrollPosition) -> bool {
+        if self.select_event(info, &pos.event_id) {
+            return true;
+        }
+
+        let (Some(thread), Some(millis)) = (self.get_thread(info), UInt::new(pos.timestamp)) else {
+            return true;
+        };
+        let timestamp = MessageTimeStamp::OriginServer(millis);
+        let loaded = matches!(info.fetch_id, RoomFetchStatus::Done) ||
+            thread.first_key_value().is_some_and(|(key, _)| key.0 <= timestamp);
+
+        if !loaded {
+            return false;
+        }
+
+        let closest = thread.keys().find(|key| key.0 >= timestamp).cloned();
+
+        if let Some(key) = closest {
+            self.select(key);
+        }
+
+        true
+    }
+
+    /// Add a message to the selection, or remove it if it's already there, returning whether it's
+    /// now selected.
+    pub fn toggle_selected(&mut self, event_id: OwnedEventId) -> bool {
+        if self.selected.remove(&event_id) {
+            return false;
+        }
+
+        self.selected.insert(event_id)
+    }
+
+    pub fn deselect(&mut self, event_id: &EventId) {
+        self.selected.remove(event_id);
+    }
+
+    pub fn clear_selected(&mut self) {
+        self.selected.clear();
+    }
+
+    pub fn has_selected(&self) -> bool {
+        !self.selected.is_empty()
+    }
+
+    pub fn is_selected(&self, event_id: &EventId) -> bool {
+        self.selected.contains(event_id)
+    }
+
+    /// The keys of the selected messages that are currently loaded, in timeline order.
+    pub fn selected_keys(&self, info: &RoomInfo) -> Vec<MessageKey> {
+        let Some(thread) = self.get_thread(info) else {
+            return vec![];
+        };
+
+        let mut keys = self
+            .selected
+            .iter()
+            .filter_map(|event_id| info.get_message_key(event_id))
+            .filter(|key| thread.contains_key(key))
+            .cloned()
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys
+    }
+
+    fn select(&mut self, key: MessageKey) {
+        if self.jump_changed() {
+            self.push_jump();
+        }
+
+        self.cursor = key.into();
+    }
+
     pub fn thread(&self) -> Option<&OwnedEventId> {
         self.thread.as_ref()
     }
@@ -579,6 +750,9 @@ impl WindowOps<IambInfo> for ScrollbackState {
             viewctx: self.viewctx.clone(),
             jumped: self.jumped.clone(),
             show_full_on_redraw: false,
+            pending_jump: self.pending_jump.clone(),
+            pending_restore: self.pending_restore.clone(),
+            selected: self.selected.clone(),
         }
     }
 
@@ -1021,7 +1195,15 @@ impl Promptable<ProgramContext, ProgramStore, IambInfo> for ScrollbackState {
 
         match act {
             PromptAction::Submit => {
-                if self.thread.is_some() {
+                // Selecting a message that's just a link opens it, unless it has a thread
+                // that can be opened instead.
+                let link = thread.get(key).and_then(Message::link_url);
+                let replies = self.thread.is_none() && info.get_thread(Some(&key.1)).is_some();
+
+                if let (Some(url), false) = (link, replies) {
+                    let open = IambAction::OpenLink(url.into());
+                    Ok(vec![(open.into(), ctx.clone())])
+                } else if self.thread.is_some() {
                     let msg =
                         "You are already in a thread. Use :reply to reply to a specific message.";
                     let err = EditError::Failure(msg.into());
@@ -1292,6 +1474,42 @@ impl<'a> StatefulWidget for Scrollback<'a> {
     fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
         let info = self.store.application.rooms.get_or_default(state.room_id.clone());
         let settings = &self.store.application.settings;
+
+        if let Some(event_id) = state.pending_jump.take() {
+            if !state.select_event(info, &event_id) {
+                state.pending_jump = Some(event_id);
+            }
+        }
+
+        if let Some(pos) = state.pending_restore.take() {
+            if !state.try_restore(info, &pos) {
+                state.pending_restore = Some(pos);
+                self.store
+                    .application
+                    .need_load
+                    .insert(state.room_id.clone(), Need::MESSAGES);
+            }
+        }
+
+        if state.thread.is_none() && state.pending_restore.is_none() {
+            let positions = &mut self.store.application.scroll_positions;
+
+            match &state.cursor.timestamp {
+                Some((MessageTimeStamp::OriginServer(millis), event_id)) => {
+                    if positions.get(&state.room_id).map(|pos| &pos.event_id) != Some(event_id) {
+                        let pos = ScrollPosition {
+                            event_id: event_id.clone(),
+                            timestamp: (*millis).into(),
+                        };
+                        positions.insert(state.room_id.clone(), pos);
+                    }
+                },
+                _ => {
+                    positions.remove(&state.room_id);
+                },
+            }
+        }
+
         let area = if state.cursor.timestamp.is_some() {
             render_jump_to_recent(area, buf, self.focused)
         } else {
@@ -1342,9 +1560,13 @@ impl<'a> StatefulWidget for Scrollback<'a> {
 
         for (key, item) in thread.range(&corner_key..) {
             let sel = key == cursor_key;
-            let (txt, mut msg_preview) =
+            let (mut txt, mut msg_preview) =
                 item.show_with_preview(prev, foc && sel, &state.viewctx, info, settings);
 
+            if state.is_selected(item.event.event_id()) {
+                txt.patch_style(Style::default().add_modifier(StyleModifier::UNDERLINED));
+            }
+
             let incomplete_ok = !full || !sel;
 
             for (row, line) in txt.lines.into_iter().enumerate() {
@@ -1407,13 +1629,24 @@ impl<'a> StatefulWidget for Scrollback<'a> {
             }
         }
 
+        // Reading in the background can be left unsent until the terminal is focused again.
+        let terminal_focused = self.store.application.terminal_focused ||
+            settings.tunables.read_receipt_send_unfocused;
+
         if self.room_focused &&
+            terminal_focused &&
             settings.tunables.read_receipt_send &&
             state.cursor.timestamp.is_none()
         {
             // If the cursor is at the last message, then update the read marker.
             if let Some((k, _)) = thread.last_key_value() {
-                info.set_receipt(settings.profile.user_id.clone(), k.1.clone());
+                let user_id = settings.profile.user_id.clone();
+
+                if let Some(root) = &state.thread {
+                    info.set_thread_receipt(root.clone(), user_id, k.1.clone());
+                } else {
+                    info.set_receipt(user_id, k.1.clone(), None);
+                }
             }
         }
 
@@ -1433,7 +1666,15 @@ impl<'a> StatefulWidget for Scrollback<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::base::EventLocation;
+    use crate::message::MessageTimeStamp::OriginServer;
     use crate::tests::*;
+    use matrix_sdk::ruma::{
+        events::room::message::RoomMessageEventContent,
+        server_name,
+        EventId,
+        UInt,
+    };
 
     #[tokio::test]
     async fn test_search_messages() {
@@ -1675,6 +1916,165 @@ mod tests {
         assert_eq!(scrollback.viewctx.corner, MessageCursor::new(MSG3_KEY.clone(), 4));
     }
 
+    #[tokio::test]
+    async fn test_read_receipt_unfocused() {
+        let mut store = mock_store().await;
+        let mut scrollback = ScrollbackState::new(TEST_ROOM1_ID.clone(), None);
+        let area = Rect::new(0, 0, 60, 4);
+        let mut buffer = Buffer::empty(area);
+        let user_id = store.application.settings.profile.user_id.clone();
+
+        store.application.settings.tunables.read_receipt_send_unfocused = false;
+        store.application.terminal_focused = false;
+        store
+            .application
+            .rooms
+            .get_or_default(TEST_ROOM1_ID.clone())
+            .user_receipts
+            .clear();
+
+        // Nothing gets marked as read while the terminal is in the background.
+        Scrollback::new(&mut store)
+            .room_focus(true)
+            .render(area, &mut buffer, &mut scrollback);
+        let info = store.application.rooms.get_or_default(TEST_ROOM1_ID.clone());
+        assert_eq!(info.get_receipt(&user_id), None);
+
+        // Once it's focused again, the receipt catches up.
+        store.application.terminal_focused = true;
+        Scrollback::new(&mut store)
+            .room_focus(true)
+            .render(area, &mut buffer, &mut scrollback);
+        let info = store.application.rooms.get_or_default(TEST_ROOM1_ID.clone());
+        assert_eq!(info.get_receipt(&user_id), Some(&MSG1_EVID.clone()));
+    }
+
+    #[tokio::test]
+    async fn test_select_event_later() {
+        let mut store = mock_store().await;
+        let mut scrollback = ScrollbackState::new(TEST_ROOM1_ID.clone(), None);
+        let area = Rect::new(0, 0, 60, 4);
+        let mut buffer = Buffer::empty(area);
+
+        let event_id = EventId::new(server_name!("example.com"));
+        let key = (OriginServer(UInt::new(3).unwrap()), event_id.clone());
+        let content = RoomMessageEventContent::text_plain("found it");
+        let msg = mock_room1_message(content, TEST_USER1.clone(), key.clone());
+
+        // Nothing happens while the message hasn't been loaded yet.
+        scrollback.select_event_later(event_id.clone());
+        scrollback.draw(area, &mut buffer, true, &mut store);
+        assert_eq!(scrollback.cursor, MessageCursor::latest());
+        assert_eq!(scrollback.pending_jump, Some(event_id.clone()));
+
+        // Once it arrives, the next draw selects it.
+        let info = store.application.rooms.get_or_default(TEST_ROOM1_ID.clone());
+        info.keys.insert(event_id, EventLocation::Message(None, key.clone()));
+        info.get_thread_mut(None).insert_message(key.clone(), msg);
+
+        scrollback.draw(area, &mut buffer, true, &mut store);
+        assert_eq!(scrollback.cursor, key.into());
+        assert_eq!(scrollback.pending_jump, None);
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let mut store = mock_store().await;
+        let mut scrollback = ScrollbackState::new(TEST_ROOM1_ID.clone(), None);
+        let info = store.application.rooms.get_or_default(TEST_ROOM1_ID.clone());
+        let needle = Regex::new("(?i)HEL").unwrap();
+
+        assert_eq!(scrollback.find_text(&needle, MoveDir1D::Previous, info), (true, false));
+        assert_eq!(scrollback.cursor, MSG4_KEY.clone().into());
+
+        assert_eq!(scrollback.find_text(&needle, MoveDir1D::Previous, info), (true, false));
+        assert_eq!(scrollback.cursor, MSG2_KEY.clone().into());
+
+        // Running out of loaded messages going back asks for older ones.
+        assert_eq!(scrollback.find_text(&needle, MoveDir1D::Previous, info), (false, true));
+        assert_eq!(scrollback.cursor, MSG2_KEY.clone().into());
+
+        let needle = Regex::new("(?i)CHAR").unwrap();
+        assert_eq!(scrollback.find_text(&needle, MoveDir1D::Next, info), (true, false));
+        assert_eq!(scrollback.cursor, MSG5_KEY.clone().into());
+
+        assert_eq!(scrollback.find_text(&needle, MoveDir1D::Next, info), (false, false));
+        assert_eq!(scrollback.cursor, MSG5_KEY.clone().into());
+    }
+
+    #[tokio::test]
+    async fn test_restore_position() {
+        let mut store = mock_store().await;
+        let mut scrollback = ScrollbackState::new(TEST_ROOM1_ID.clone(), None);
+        let area = Rect::new(0, 0, 60, 4);
+        let mut buffer = Buffer::empty(area);
+        let pos = |event_id: &EventId, timestamp| {
+            ScrollPosition { event_id: event_id.to_owned(), timestamp }
+        };
+
+        // Messages that are still around get selected right away.
+        let info = store.application.rooms.get_or_default(TEST_ROOM1_ID.clone());
+        scrollback.restore_position(info, pos(&MSG5_EVID, 8));
+        assert_eq!(scrollback.cursor, MSG5_KEY.clone().into());
+        assert_eq!(scrollback.pending_restore, None);
+
+        // Missing messages fall back to the next one sent after them.
+        let missing = EventId::new(server_name!("example.com"));
+        scrollback.restore_position(info, pos(&missing, 5));
+        assert_eq!(scrollback.cursor, MSG5_KEY.clone().into());
+
+        // Positions from before the loaded messages wait for older ones to be fetched.
+        scrollback.goto_latest();
+        scrollback.restore_position(info, pos(&missing, 0));
+        assert_eq!(scrollback.cursor, MessageCursor::latest());
+
+        store.application.need_load = Default::default();
+        scrollback.draw(area, &mut buffer, true, &mut store);
+        assert_eq!(scrollback.cursor, MessageCursor::latest());
+        assert!(scrollback.pending_restore.is_some());
+        assert_eq!(store.application.need_load.rooms(), 1);
+
+        // Once the whole history is loaded, the earliest message gets used.
+        let info = store.application.rooms.get_or_default(TEST_ROOM1_ID.clone());
+        info.fetch_id = RoomFetchStatus::Done;
+        scrollback.draw(area, &mut buffer, true, &mut store);
+        assert_eq!(scrollback.cursor, MSG2_KEY.clone().into());
+        assert_eq!(scrollback.pending_restore, None);
+
+        // The new position gets saved for next time.
+        let saved = store.application.scroll_positions.get(&*TEST_ROOM1_ID);
+        assert_eq!(saved, Some(&pos(&MSG2_EVID, 1)));
+
+        scrollback.goto_latest();
+        scrollback.draw(area, &mut buffer, true, &mut store);
+        assert_eq!(store.application.scroll_positions.get(&*TEST_ROOM1_ID), None);
+    }
+
+    #[tokio::test]
+    async fn test_selected_keys() {
+        let mut store = mock_store().await;
+        let mut scrollback = ScrollbackState::new(TEST_ROOM1_ID.clone(), None);
+        let unloaded = EventId::new(server_name!("example.com"));
+
+        assert!(scrollback.toggle_selected(MSG4_EVID.clone()));
+        assert!(scrollback.toggle_selected(MSG2_EVID.clone()));
+        assert!(scrollback.toggle_selected(unloaded.clone()));
+        assert!(scrollback.is_selected(&MSG4_EVID));
+
+        // Only loaded messages are returned, in the order they were sent.
+        let info = store.application.rooms.get_or_default(TEST_ROOM1_ID.clone());
+        let keys = scrollback.selected_keys(info);
+        assert_eq!(keys, vec![MSG2_KEY.clone(), MSG4_KEY.clone()]);
+
+        // Selecting a message a second time removes it from the selection.
+        assert!(!scrollback.toggle_selected(MSG4_EVID.clone()));
+        assert_eq!(scrollback.selected_keys(info), vec![MSG2_KEY.clone()]);
+        assert!(scrollback.is_selected(&unloaded));
+
+        scrollback.clear_selected();
+        assert!(!scrollback.has_selected());
+    }
+
     #[tokio::test]
     async fn test_cursorpos() {
         let mut store = mock_store().await;
@@ -2,17 +2,21 @@
 //!
 //! The worker thread handles asynchronous work, and can receive messages from the main thread that
 //! block on a reply from the async worker.
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
 use std::fmt::{Debug, Formatter};
 use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 use futures::{stream::FuturesUnordered, StreamExt};
 use gethostname::gethostname;
+use rand::Rng;
+use serde::Deserialize;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tokio::sync::Semaphore;
 use tokio::task::JoinHandle;
@@ -21,21 +25,38 @@ use url::Url;
 
 use matrix_sdk::{
     config::{RequestConfig, SyncSettings},
+    deserialized_responses::SyncOrStrippedState,
+    encryption::recovery::RecoveryError,
     encryption::verification::{SasVerification, Verification},
     encryption::{BackupDownloadStrategy, EncryptionSettings},
-    event_handler::Ctx,
+    event_handler::{Ctx, RawEvent},
     matrix_auth::MatrixSession,
     reqwest,
     room::{Messages, MessagesOptions, Room as MatrixRoom, RoomMember},
     ruma::{
         api::client::{
+            context::get_context,
+            error::ErrorBody,
             filter::{FilterDefinition, LazyLoadOptions, RoomEventFilter, RoomFilter},
+            presence::set_presence::v3::Request as SetPresenceRequest,
             room::create_room::v3::{CreationContent, Request as CreateRoomRequest, RoomPreset},
             room::Visibility,
+            search::search_events::v3::{
+                Categories as SearchCategories,
+                Criteria as SearchCriteria,
+                EventContext as SearchContext,
+                Request as SearchRequest,
+                SearchResult,
+            },
+            session::get_login_types::v3::LoginType,
             space::get_hierarchy::v1::Request as SpaceHierarchyRequest,
+            uiaa::{AuthType, UiaaInfo},
         },
+        api::Direction,
         assign,
         events::{
+            forwarded_room_key::ToDeviceForwardedRoomKeyEvent,
+            ignored_user_list::{IgnoredUserListEvent, IgnoredUserListEventContent},
             key::verification::{
                 done::{OriginalSyncKeyVerificationDoneEvent, ToDeviceKeyVerificationDoneEvent},
                 key::{OriginalSyncKeyVerificationKeyEvent, ToDeviceKeyVerificationKeyEvent},
@@ -48,59 +69,93 @@ use matrix_sdk::{
             receipt::ReceiptType,
             receipt::{ReceiptEventContent, ReceiptThread},
             room::{
+                encrypted::{EncryptedEventScheme, OriginalRoomEncryptedEvent},
                 encryption::RoomEncryptionEventContent,
-                member::OriginalSyncRoomMemberEvent,
-                message::{MessageType, RoomMessageEventContent},
+                member::{MembershipState, OriginalSyncRoomMemberEvent},
+                message::{MessageType, RoomMessageEvent, RoomMessageEventContent},
                 name::RoomNameEventContent,
+                pinned_events::RoomPinnedEventsEventContent,
                 redaction::OriginalSyncRoomRedactionEvent,
+                MediaSource,
             },
+            room_key::ToDeviceRoomKeyEvent,
+            space::child::SpaceChildEventContent,
             tag::Tags,
             typing::SyncTypingEvent,
             AnyInitialStateEvent,
             AnyMessageLikeEvent,
+            AnyStateEvent,
+            AnySyncEphemeralRoomEvent,
+            AnySyncMessageLikeEvent,
+            AnySyncTimelineEvent,
             AnyTimelineEvent,
             EmptyStateKey,
+            GlobalAccountDataEventType,
             InitialStateEvent,
+            MessageLikeEvent,
+            StateEvent,
             SyncEphemeralRoomEvent,
             SyncMessageLikeEvent,
             SyncStateEvent,
         },
+        presence::PresenceState,
         room::RoomType,
         serde::Raw,
         EventEncryptionAlgorithm,
         EventId,
+        MilliSecondsSinceUnixEpoch,
         OwnedEventId,
         OwnedRoomId,
         OwnedRoomOrAliasId,
         OwnedUserId,
         RoomId,
         RoomVersionId,
+        UInt,
+        UserId,
     },
     Client,
     ClientBuildError,
     DisplayName,
     Error as MatrixError,
+    LoopCtrl,
     RoomMemberships,
 };
 
 use modalkit::errors::UIError;
 use modalkit::prelude::{EditInfo, InfoMessage};
 
-use crate::base::Need;
+use crate::base::{Need, MAX_SEARCH_HITS};
+use crate::message::{
+    is_native_event_type,
+    strip_reply_fallback,
+    CustomEvent,
+    MembershipEvent,
+    MessageTimeStamp,
+};
 use crate::notifications::register_notifications;
+use crate::preview::{spawn_auto_download, Attachment};
 use crate::{
     base::{
+        bundled_reaction_counts,
+        is_forwarded,
         AsyncProgramStore,
         ChatStore,
         CreateRoomFlags,
         CreateRoomType,
+        DebugEvent,
         IambError,
         IambResult,
+        IdentityInfo,
+        IdentityStatus,
         ProgramStore,
         RoomFetchStatus,
         RoomInfo,
+        SearchHit,
+        SessionInfo,
+        UserPresence,
         VerifyAction,
     },
+    config::RoomKeyRotation,
     ApplicationSettings,
 };
 
@@ -112,10 +167,22 @@ const DEFAULT_ENCRYPTION_SETTINGS: EncryptionSettings = EncryptionSettings {
 
 const IAMB_DEVICE_NAME: &str = "iamb";
 const IAMB_USER_AGENT: &str = "iamb";
-const MIN_MSG_LOAD: u32 = 50;
 
-type MessageFetchResult =
-    IambResult<(Option<String>, Vec<(AnyMessageLikeEvent, Vec<OwnedUserId>)>)>;
+/// The files used by the non-crypto part of the SQLite store.
+const STATE_STORE_FILES: [&str; 3] = [
+    "matrix-sdk-state.sqlite3",
+    "matrix-sdk-state.sqlite3-wal",
+    "matrix-sdk-state.sqlite3-shm",
+];
+
+/// Files next to the store directory that mark it, or just its state store, for removal once
+/// nothing has it open anymore.
+const ERASE_STORE_MARKER: &str = "erase-store";
+const CLEAR_CACHE_MARKER: &str = "clear-cache";
+
+type FetchedReceipt = (OwnedUserId, Option<MilliSecondsSinceUnixEpoch>);
+type FetchedEvent = (AnyMessageLikeEvent, Raw<AnyTimelineEvent>, Vec<FetchedReceipt>);
+type MessageFetchResult = IambResult<(Option<String>, Vec<FetchedEvent>, Vec<MembershipEvent>)>;
 
 fn initial_devname() -> String {
     format!("{} on {}", IAMB_DEVICE_NAME, gethostname().to_string_lossy())
@@ -128,8 +195,10 @@ async fn is_direct(room: &MatrixRoom) -> bool {
 pub async fn create_room(
     client: &Client,
     room_alias_name: Option<String>,
+    name: Option<String>,
     rt: CreateRoomType,
     flags: CreateRoomFlags,
+    rotation: &RoomKeyRotation,
 ) -> IambResult<OwnedRoomId> {
     let mut creation_content = None;
     let mut initial_state = vec![];
@@ -163,7 +232,9 @@ pub async fn create_room(
     if flags.contains(CreateRoomFlags::ENCRYPTED) {
         // XXX: Once matrix-sdk uses ruma 0.8, then this can skip the cast.
         let algo = EventEncryptionAlgorithm::MegolmV1AesSha2;
-        let content = RoomEncryptionEventContent::new(algo);
+        let mut content = RoomEncryptionEventContent::new(algo);
+        content.rotation_period_ms = rotation.period_ms.map(UInt::new_saturating);
+        content.rotation_period_msgs = rotation.messages.map(UInt::new_saturating);
         let encr = InitialStateEvent { content, state_key: EmptyStateKey };
         let encr_raw = Raw::new(&encr).map_err(IambError::from)?;
         let encr_raw = encr_raw.cast::<AnyInitialStateEvent>();
@@ -172,6 +243,7 @@ pub async fn create_room(
 
     let request = assign!(CreateRoomRequest::new(), {
         room_alias_name,
+        name,
         creation_content,
         initial_state,
         invite,
@@ -196,6 +268,53 @@ pub async fn create_room(
     return Ok(resp.room_id().to_owned());
 }
 
+/// Create a space with the given name, and then add each of the given rooms to it, written as
+/// room identifiers or aliases.
+pub async fn create_space(
+    client: &Client,
+    name: String,
+    children: Vec<String>,
+    rotation: &RoomKeyRotation,
+) -> IambResult<OwnedRoomId> {
+    // Look up every child first, so that a typo doesn't leave behind a half-filled space.
+    let mut child_ids = vec![];
+
+    for child in children {
+        let Ok(id) = OwnedRoomOrAliasId::from_str(child.as_str()) else {
+            return Err(IambError::InvalidRoomAlias(child).into());
+        };
+
+        let room_id = match OwnedRoomId::try_from(id) {
+            Ok(room_id) => room_id,
+            Err(alias) => client.resolve_room_alias(&alias).await.map_err(IambError::from)?.room_id,
+        };
+
+        child_ids.push(room_id);
+    }
+
+    let rt = CreateRoomType::Space;
+    let flags = CreateRoomFlags::NONE;
+    let space_id = create_room(client, None, Some(name), rt, flags, rotation).await?;
+    let space = client
+        .get_room(&space_id)
+        .ok_or_else(|| IambError::UnknownRoom(space_id.clone()))?;
+    let via = client
+        .user_id()
+        .map(|u| u.server_name().to_owned())
+        .into_iter()
+        .collect::<Vec<_>>();
+
+    for child in child_ids {
+        let content = SpaceChildEventContent::new(via.clone());
+        space
+            .send_state_event_for_key(&child, content)
+            .await
+            .map_err(IambError::from)?;
+    }
+
+    return Ok(space_id);
+}
+
 async fn update_event_receipts(info: &mut RoomInfo, room: &MatrixRoom, event_id: &EventId) {
     let receipts = match room
         .load_event_receipts(ReceiptType::Read, ReceiptThread::Main, event_id)
@@ -208,15 +327,18 @@ async fn update_event_receipts(info: &mut RoomInfo, room: &MatrixRoom, event_id:
         },
     };
 
-    for (user_id, _) in receipts {
-        info.set_receipt(user_id, event_id.to_owned());
+    for (user_id, receipt) in receipts {
+        info.set_receipt(user_id, event_id.to_owned(), receipt.ts);
     }
 }
 
 #[derive(Debug)]
 enum Plan {
-    Messages(OwnedRoomId, Option<String>),
+    Messages(OwnedRoomId, Direction, Option<String>),
     Members(OwnedRoomId),
+    MemberList(OwnedRoomId),
+    Pinned(OwnedRoomId),
+    Search(OwnedRoomId, String, Option<String>),
 }
 
 async fn load_plans(store: &AsyncProgramStore) -> Vec<Plan> {
@@ -225,27 +347,76 @@ async fn load_plans(store: &AsyncProgramStore) -> Vec<Plan> {
     let mut plan = Vec::with_capacity(need_load.rooms() * 2);
 
     for (room_id, mut need) in std::mem::take(need_load).into_iter() {
-        if need.contains(Need::MESSAGES) {
+        if need.intersects(Need::MESSAGES | Need::GAP) {
             let info = rooms.get_or_default(room_id.clone());
 
             if !info.recently_fetched() && !info.fetching {
-                info.fetch_last = Instant::now().into();
-                info.fetching = true;
+                let mut fetch = None;
+
+                // Older messages that are being looked at come first, and any gap left
+                // after jumping back in history gets filled in the rest of the time.
+                if need.contains(Need::MESSAGES) {
+                    need.remove(Need::MESSAGES);
+
+                    fetch = match &info.fetch_id {
+                        RoomFetchStatus::Done => None,
+                        RoomFetchStatus::HaveMore(fetch_id) => {
+                            Some((Direction::Backward, Some(fetch_id.clone())))
+                        },
+                        RoomFetchStatus::NotStarted => Some((Direction::Backward, None)),
+                    };
+                }
+
+                if fetch.is_none() && need.contains(Need::GAP) {
+                    need.remove(Need::GAP);
 
-                let fetch_id = match &info.fetch_id {
-                    RoomFetchStatus::Done => continue,
-                    RoomFetchStatus::HaveMore(fetch_id) => Some(fetch_id.clone()),
-                    RoomFetchStatus::NotStarted => None,
-                };
+                    fetch = info.fetch_gap.clone().map(|id| (Direction::Forward, Some(id)));
+                }
+
+                if let Some((dir, fetch_id)) = fetch {
+                    info.fetch_last = Instant::now().into();
+                    info.fetching = true;
 
-                plan.push(Plan::Messages(room_id.to_owned(), fetch_id));
-                need.remove(Need::MESSAGES);
+                    plan.push(Plan::Messages(room_id.to_owned(), dir, fetch_id));
+                }
             }
         }
         if need.contains(Need::MEMBERS) {
             plan.push(Plan::Members(room_id.to_owned()));
             need.remove(Need::MEMBERS);
         }
+        if need.contains(Need::MEMBER_LIST) {
+            let info = rooms.get_or_default(room_id.clone());
+
+            if !info.members_recently_fetched() && !info.fetching_members {
+                info.fetching_members = true;
+                plan.push(Plan::MemberList(room_id.to_owned()));
+            }
+
+            need.remove(Need::MEMBER_LIST);
+        }
+        if need.contains(Need::PINNED) {
+            let info = rooms.get_or_default(room_id.clone());
+
+            if !info.pinned_recently_fetched() && !info.fetching_pinned {
+                info.fetching_pinned = true;
+                plan.push(Plan::Pinned(room_id.to_owned()));
+            }
+
+            need.remove(Need::PINNED);
+        }
+        if need.contains(Need::SEARCH) {
+            let info = rooms.get_or_default(room_id.clone());
+
+            if let (Some((query, _)), false) = (&info.search_results, info.fetching_search) {
+                let plan_search =
+                    Plan::Search(room_id.to_owned(), query.clone(), info.search_next_batch.clone());
+                info.fetching_search = true;
+                plan.push(plan_search);
+            }
+
+            need.remove(Need::SEARCH);
+        }
         if !need.is_empty() {
             need_load.insert(room_id, need);
         }
@@ -254,47 +425,75 @@ async fn load_plans(store: &AsyncProgramStore) -> Vec<Plan> {
     return plan;
 }
 
-async fn run_plan(client: &Client, store: &AsyncProgramStore, plan: Plan, permits: &Semaphore) {
+async fn run_plan(
+    client: &Client,
+    store: &AsyncProgramStore,
+    plan: Plan,
+    permits: &Semaphore,
+    limit: u32,
+) {
     let permit = permits.acquire().await;
     match plan {
-        Plan::Messages(room_id, fetch_id) => {
-            let limit = MIN_MSG_LOAD;
+        Plan::Messages(room_id, dir, fetch_id) => {
             let client = client.clone();
             let store_clone = store.clone();
 
-            let res = load_older_one(&client, &room_id, fetch_id, limit).await;
+            let res = load_messages_one(&client, &room_id, dir, fetch_id.clone(), limit).await;
             let mut locked = store.lock().await;
-            load_insert(room_id, res, locked.deref_mut(), store_clone);
+            load_insert(room_id, dir, fetch_id, res, locked.deref_mut(), store_clone);
         },
         Plan::Members(room_id) => {
             let res = members_load(client, &room_id).await;
             let mut locked = store.lock().await;
             members_insert(room_id, res, locked.deref_mut());
         },
+        Plan::MemberList(room_id) => {
+            let res = member_list_load(client, &room_id).await;
+            let mut locked = store.lock().await;
+            member_list_insert(room_id, res, locked.deref_mut());
+        },
+        Plan::Pinned(room_id) => {
+            let res = pinned_load(client, &room_id).await;
+            let mut locked = store.lock().await;
+            pinned_insert(room_id, res, locked.deref_mut());
+        },
+        Plan::Search(room_id, query, next_batch) => {
+            let res = search_load(client, &room_id, query.clone(), next_batch).await;
+            let mut locked = store.lock().await;
+            search_insert(room_id, query, res, locked.deref_mut());
+        },
     }
     drop(permit);
 }
 
-async fn load_older_one(
+async fn load_messages_one(
     client: &Client,
     room_id: &RoomId,
+    dir: Direction,
     fetch_id: Option<String>,
     limit: u32,
 ) -> MessageFetchResult {
     if let Some(room) = client.get_room(room_id) {
         let mut opts = match &fetch_id {
-            Some(id) => MessagesOptions::backward().from(id.as_str()),
-            None => MessagesOptions::backward(),
+            Some(id) => MessagesOptions::new(dir).from(id.as_str()),
+            None => MessagesOptions::new(dir),
         };
         opts.limit = limit.into();
 
         let Messages { end, chunk, .. } = room.messages(opts).await.map_err(IambError::from)?;
 
         let mut msgs = vec![];
+        let mut members = vec![];
 
         for ev in chunk.into_iter() {
             let msg = match ev.event.deserialize() {
                 Ok(AnyTimelineEvent::MessageLike(msg)) => msg,
+                Ok(AnyTimelineEvent::State(AnyStateEvent::RoomMember(StateEvent::Original(
+                    ev,
+                )))) => {
+                    members.extend(MembershipEvent::new(&ev));
+                    continue;
+                },
                 Ok(AnyTimelineEvent::State(_)) => continue,
                 Err(_) => continue,
             };
@@ -304,43 +503,115 @@ async fn load_older_one(
                 .load_event_receipts(ReceiptType::Read, ReceiptThread::Main, event_id)
                 .await
             {
-                Ok(receipts) => receipts.into_iter().map(|(u, _)| u).collect(),
+                Ok(receipts) => receipts.into_iter().map(|(u, r)| (u, r.ts)).collect(),
                 Err(e) => {
                     tracing::warn!(?event_id, "failed to get event receipts: {e}");
                     vec![]
                 },
             };
 
-            msgs.push((msg, receipts));
+            msgs.push((msg, ev.event, receipts));
         }
 
-        Ok((end, msgs))
+        Ok((end, msgs, members))
     } else {
         Err(IambError::UnknownRoom(room_id.to_owned()).into())
     }
 }
 
+/// Pull the text messages out of the results of a server-side search, skipping any other kinds
+/// of events that matched.
+fn search_hits(results: Vec<SearchResult>) -> Vec<SearchHit> {
+    results
+        .into_iter()
+        .filter_map(|res| {
+            let ev = res.result?.deserialize().ok()?;
+            let AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomMessage(
+                MessageLikeEvent::Original(ev),
+            )) = ev
+            else {
+                return None;
+            };
+
+            let mut msgtype = ev.content.msgtype;
+            strip_reply_fallback(&mut msgtype);
+
+            let profile = res.context.profile_info.get(&ev.sender);
+            let sender_name = profile.and_then(|p| p.displayname.clone());
+
+            Some(SearchHit {
+                event_id: ev.event_id,
+                sender: ev.sender,
+                sender_name,
+                timestamp: ev.origin_server_ts,
+                body: msgtype.body().to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Get a token for paginating forwards through a room's history from just before an event.
+pub async fn fetch_event_token(
+    client: &Client,
+    room_id: &RoomId,
+    event_id: &EventId,
+) -> IambResult<String> {
+    // Asking for a little context around the event makes sure that the token comes from before
+    // it, so that paginating forwards from there includes the event itself.
+    let mut req = get_context::v3::Request::new(room_id.to_owned(), event_id.to_owned());
+    req.limit = UInt::new(2).unwrap();
+
+    let resp = client.send(req, None).await.map_err(IambError::from)?;
+
+    resp.start
+        .ok_or_else(|| IambError::MessageNotLoaded(event_id.to_owned()).into())
+}
+
 fn load_insert(
     room_id: OwnedRoomId,
+    dir: Direction,
+    from: Option<String>,
     res: MessageFetchResult,
     locked: &mut ProgramStore,
     store: AsyncProgramStore,
 ) {
-    let ChatStore { presences, rooms, worker, picker, settings, .. } = &mut locked.application;
+    let ChatStore {
+        presences,
+        rooms,
+        worker,
+        picker,
+        settings,
+        need_load,
+        ignored_users,
+        ..
+    } = &mut locked.application;
     let info = rooms.get_or_default(room_id.clone());
     info.fetching = false;
     let client = &worker.client;
 
     match res {
-        Ok((fetch_id, msgs)) => {
-            for (msg, receipts) in msgs.into_iter() {
+        Ok((fetch_id, msgs, members)) => {
+            // Running into messages we already have means that a gap has been filled in.
+            let overlaps = msgs.iter().any(|(msg, _, _)| info.keys.contains_key(msg.event_id()));
+
+            for (msg, raw, receipts) in msgs.into_iter() {
+                if ignored_users.contains(msg.sender()) {
+                    continue;
+                }
+
                 let sender = msg.sender().to_owned();
                 let _ = presences.get_or_default(sender);
 
-                for user_id in receipts {
-                    info.set_receipt(user_id, msg.event_id().to_owned());
+                for (user_id, ts) in receipts {
+                    info.set_receipt(user_id, msg.event_id().to_owned(), ts);
+                }
+
+                if let Some(counts) = bundled_reaction_counts(raw.json().get()) {
+                    info.set_bundled_reactions(msg.event_id().to_owned(), counts);
                 }
 
+                let forwarded = is_forwarded(raw.json().get()).then(|| msg.event_id().to_owned());
+
                 match msg {
                     AnyMessageLikeEvent::RoomEncrypted(msg) => {
                         info.insert_encrypted(msg);
@@ -352,28 +623,76 @@ fn load_insert(
                             *picker,
                             msg,
                             settings,
-                            client.media(),
+                            client,
                         );
+
+                        if let Some(event_id) = forwarded {
+                            info.set_forwarded(&event_id);
+                        }
                     },
                     AnyMessageLikeEvent::Reaction(ev) => {
                         info.insert_reaction(ev);
                     },
-                    _ => continue,
+                    ev => {
+                        let event_type = ev.event_type().to_string();
+                        let templates = &settings.tunables.custom_events;
+
+                        let Some(template) = templates.get(&event_type) else {
+                            continue;
+                        };
+
+                        if let Ok(custom) = raw.deserialize_as::<CustomEvent>() {
+                            info.insert_custom(custom.render(template));
+                        }
+                    },
+                }
+            }
+
+            if settings.tunables.membership_display {
+                for ev in members {
+                    info.insert_membership(ev);
                 }
             }
 
-            info.fetch_id = fetch_id.map_or(RoomFetchStatus::Done, RoomFetchStatus::HaveMore);
+            match dir {
+                Direction::Backward => {
+                    // The scrollback may have jumped elsewhere in history while this was loading,
+                    // in which case we keep going from there instead.
+                    let current = match &info.fetch_id {
+                        RoomFetchStatus::HaveMore(id) => Some(id),
+                        _ => None,
+                    };
+
+                    if current == from.as_ref() {
+                        info.fetch_id =
+                            fetch_id.map_or(RoomFetchStatus::Done, RoomFetchStatus::HaveMore);
+                    }
+                },
+                Direction::Forward if info.fetch_gap == from => {
+                    if overlaps || fetch_id.is_none() || fetch_id == from {
+                        info.fetch_gap = None;
+                    } else {
+                        info.fetch_gap = fetch_id;
+                        need_load.insert(room_id, Need::GAP);
+                    }
+                },
+                Direction::Forward => {},
+            }
         },
         Err(e) => {
-            warn!(room_id = room_id.as_str(), err = e.to_string(), "Failed to load older messages");
+            warn!(room_id = room_id.as_str(), err = e.to_string(), "Failed to load messages");
 
             // Wait and try again.
-            locked.application.need_load.insert(room_id, Need::MESSAGES);
+            let need = match dir {
+                Direction::Backward => Need::MESSAGES,
+                Direction::Forward => Need::GAP,
+            };
+            need_load.insert(room_id, need);
         },
     }
 }
 
-async fn load_older(client: &Client, store: &AsyncProgramStore) -> usize {
+async fn load_older(client: &Client, store: &AsyncProgramStore, limit: u32) -> usize {
     // This is an arbitrary limit on how much work we do in parallel to avoid
     // spawning too many tasks at startup and overwhelming the client. We
     // should normally only surpass this limit at startup when doing an initial.
@@ -386,7 +705,7 @@ async fn load_older(client: &Client, store: &AsyncProgramStore) -> usize {
 
     plans
         .into_iter()
-        .map(|plan| run_plan(client, store, plan, &permits))
+        .map(|plan| run_plan(client, store, plan, &permits, limit))
         .collect::<FuturesUnordered<_>>()
         .count()
         .await
@@ -417,27 +736,373 @@ fn members_insert(
             let display_name =
                 member.display_name().map_or(user_id.to_string(), |str| str.to_string());
             info.display_names.insert(user_id.to_owned(), display_name);
+
+            if member.membership() == &MembershipState::Join {
+                info.members.insert(user_id.to_owned());
+            } else {
+                info.members.remove(user_id);
+            }
         }
     }
     // else ???
 }
 
-async fn load_older_forever(client: &Client, store: &AsyncProgramStore) {
-    // Load any pending older messages or members every 2 seconds.
-    let mut interval = tokio::time::interval(Duration::from_secs(2));
+/// Fetch the joined and invited members for `:members`, which for a large room can take a while
+/// when the server hasn't sent us the full list yet.
+async fn member_list_load(client: &Client, room_id: &RoomId) -> IambResult<Vec<RoomMember>> {
+    if let Some(room) = client.get_room(room_id) {
+        Ok(room.members(RoomMemberships::ACTIVE).await.map_err(IambError::from)?)
+    } else {
+        Err(IambError::UnknownRoom(room_id.to_owned()).into())
+    }
+}
+
+fn member_list_insert(
+    room_id: OwnedRoomId,
+    res: IambResult<Vec<RoomMember>>,
+    store: &mut ProgramStore,
+) {
+    let info = store.application.rooms.get_or_default(room_id.clone());
+    info.fetching_members = false;
+
+    match res {
+        Ok(members) => info.member_list = Some((Instant::now(), members)),
+        Err(e) => warn!(?room_id, "Failed to fetch members: {e}"),
+    }
+}
+
+/// Fetch the events pinned in a room for `:pins`.
+async fn pinned_load(client: &Client, room_id: &RoomId) -> IambResult<Vec<OwnedEventId>> {
+    let Some(room) = client.get_room(room_id) else {
+        return Err(IambError::UnknownRoom(room_id.to_owned()).into());
+    };
+
+    let ev = room
+        .get_state_event_static::<RoomPinnedEventsEventContent>()
+        .await
+        .map_err(IambError::from)?;
+    let ev = ev.map(|raw| raw.deserialize()).transpose().map_err(IambError::from)?;
+
+    let pinned = match ev {
+        Some(SyncOrStrippedState::Sync(SyncStateEvent::Original(ev))) => ev.content.pinned,
+        Some(SyncOrStrippedState::Stripped(ev)) => ev.content.pinned.unwrap_or_default(),
+        _ => vec![],
+    };
+
+    Ok(pinned)
+}
+
+fn pinned_insert(
+    room_id: OwnedRoomId,
+    res: IambResult<Vec<OwnedEventId>>,
+    store: &mut ProgramStore,
+) {
+    let info = store.application.rooms.get_or_default(room_id.clone());
+    info.fetching_pinned = false;
+
+    match res {
+        Ok(pinned) => {
+            info.pinned = pinned;
+            info.pinned_fetched = Some(Instant::now());
+        },
+        Err(e) => warn!(?room_id, "Failed to fetch pinned events: {e}"),
+    }
+}
+
+/// Fetch a page of the messages in a room that the server finds for a search.
+async fn search_load(
+    client: &Client,
+    room_id: &RoomId,
+    query: String,
+    next_batch: Option<String>,
+) -> IambResult<(Vec<SearchHit>, Option<String>)> {
+    let mut context = SearchContext::new();
+    context.before_limit = UInt::MIN;
+    context.after_limit = UInt::MIN;
+    context.include_profile = true;
+
+    let mut criteria = SearchCriteria::new(query);
+    criteria.filter.rooms = Some(vec![room_id.to_owned()]);
+    criteria.event_context = context;
+
+    let categories = assign!(SearchCategories::new(), { room_events: Some(criteria) });
+    let req = assign!(SearchRequest::new(categories), { next_batch });
+    let resp = client.send(req, None).await.map_err(IambError::from)?;
+    let events = resp.search_categories.room_events;
+
+    Ok((search_hits(events.results), events.next_batch))
+}
+
+fn search_insert(
+    room_id: OwnedRoomId,
+    query: String,
+    res: IambResult<(Vec<SearchHit>, Option<String>)>,
+    store: &mut ProgramStore,
+) {
+    let ChatStore { rooms, need_load, send_errors, .. } = &mut store.application;
+    let info = rooms.get_or_default(room_id.clone());
+    info.fetching_search = false;
+
+    // The search was replaced by a newer one while this page was being fetched.
+    let Some((current, hits)) = info.search_results.as_mut().filter(|(q, _)| *q == query) else {
+        need_load.insert(room_id, Need::SEARCH);
+        return;
+    };
+
+    match res {
+        Ok((mut page, next_batch)) => {
+            page.truncate(MAX_SEARCH_HITS.saturating_sub(hits.len()));
+            hits.append(&mut page);
+
+            info.search_next_batch = next_batch.filter(|_| hits.len() < MAX_SEARCH_HITS);
+
+            info.searching = info.search_next_batch.is_some();
+
+            if info.searching {
+                need_load.insert(room_id, Need::SEARCH);
+            }
+        },
+        Err(e) => {
+            info.search_next_batch = None;
+            info.searching = false;
+            send_errors.push(format!("Failed to search for {current:?}: {e}"));
+        },
+    }
+}
+
+/// The longest to wait between attempts to sync while the homeserver keeps failing.
+const SYNC_BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+/// How long to wait before syncing again after `failures` syncs in a row have failed.
+///
+/// The delay doubles with each failure, plus up to a quarter more at random so that clients that
+/// lost their connection at the same time don't all retry together.
+fn sync_backoff(failures: u32) -> Duration {
+    let exp = failures.saturating_sub(1).min(6);
+    let delay = Duration::from_secs(1 << exp).min(SYNC_BACKOFF_MAX);
+    let jitter = rand::thread_rng().gen_range(0..=delay.as_millis() as u64 / 4);
+
+    delay + Duration::from_millis(jitter)
+}
+
+async fn load_older_forever(
+    client: &Client,
+    store: &AsyncProgramStore,
+    settings: &ApplicationSettings,
+) {
+    // Load any pending older messages or members every few seconds.
+    let limit = settings.tunables.message_load_batch;
+    let period = Duration::from_secs(settings.tunables.message_load_interval);
+    let mut interval = tokio::time::interval(period);
 
     loop {
         interval.tick().await;
-        load_older(client, store).await;
+        load_older(client, store, limit).await;
+    }
+}
+
+/// Account data used by Element and other clients to track recently visited rooms.
+const BREADCRUMBS_EVENT_TYPE: &str = "im.vector.setting.breadcrumbs";
+
+#[derive(Deserialize)]
+struct BreadcrumbsEventContent {
+    #[serde(default)]
+    recent_rooms: Vec<OwnedRoomId>,
+}
+
+async fn load_breadcrumbs(client: &Client) -> Vec<OwnedRoomId> {
+    let ev_type = GlobalAccountDataEventType::from(BREADCRUMBS_EVENT_TYPE);
+
+    match client.account().account_data_raw(ev_type).await {
+        Ok(Some(raw)) => {
+            match raw.deserialize_as::<BreadcrumbsEventContent>() {
+                Ok(content) => content.recent_rooms,
+                Err(e) => {
+                    warn!(err = %e, "Failed to parse breadcrumbs account data");
+                    vec![]
+                },
+            }
+        },
+        Ok(None) => vec![],
+        Err(e) => {
+            warn!(err = %e, "Failed to load breadcrumbs account data");
+            vec![]
+        },
+    }
+}
+
+/// Remove whatever `:clearcache` or `:logout!` marked for removal. This must only happen while
+/// the store isn't open, since the SDK would keep using the removed files.
+pub fn remove_marked_stores(settings: &ApplicationSettings) -> std::io::Result<()> {
+    fn ignore_missing(res: std::io::Result<()>) -> std::io::Result<()> {
+        match res {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            res => res,
+        }
+    }
+
+    let erase = settings.sqlite_dir.with_file_name(ERASE_STORE_MARKER);
+
+    if erase.exists() {
+        ignore_missing(std::fs::remove_dir_all(&settings.sqlite_dir))?;
+        std::fs::remove_file(erase)?;
+    }
+
+    // Clearing the cache only removes the state store, leaving the crypto store and its keys.
+    let clear = settings.sqlite_dir.with_file_name(CLEAR_CACHE_MARKER);
+
+    if clear.exists() {
+        for name in STATE_STORE_FILES {
+            ignore_missing(std::fs::remove_file(settings.sqlite_dir.join(name)))?;
+        }
+
+        std::fs::remove_file(clear)?;
+    }
+
+    Ok(())
+}
+
+/// Fetch the backed up keys for each encrypted room after a recovery, and then try again to
+/// decrypt the messages that were waiting on them.
+pub async fn restore_room_keys(client: Client, store: AsyncProgramStore) {
+    let backups = client.encryption().backups();
+    let mut count = 0;
+
+    for room in client.joined_rooms() {
+        if !room.is_encrypted().await.unwrap_or(false) {
+            continue;
+        }
+
+        if let Err(e) = backups.download_room_keys_for_room(room.room_id()).await {
+            warn!(room_id = %room.room_id(), err = %e, "Failed to download room keys");
+            continue;
+        }
+
+        count += 1;
+    }
+
+    retry_decryption(client, store.clone()).await;
+
+    let msg = format!("Restored keys from the backup for {count} encrypted rooms");
+    store.lock().await.application.notices.push(msg);
+}
+
+/// Try again to decrypt the loaded messages that we didn't have the keys for, after fetching
+/// more of them from the key backup.
+pub async fn retry_decryption(client: Client, store: AsyncProgramStore) {
+    for room in client.joined_rooms() {
+        retry_room_decryption(&room, None, &store).await;
+    }
+}
+
+/// Get the Megolm session that an encrypted message needs the key for.
+pub fn megolm_session_id(ev: &OriginalRoomEncryptedEvent) -> Option<&str> {
+    match &ev.content.scheme {
+        EncryptedEventScheme::MegolmV1AesSha2(content) => Some(content.session_id.as_str()),
+        _ => None,
+    }
+}
+
+/// Try again to decrypt a room's loaded messages, and replace the ones that now decrypt. If a
+/// session is given, then only messages encrypted with it are tried.
+async fn retry_room_decryption(
+    room: &MatrixRoom,
+    session_id: Option<&str>,
+    store: &AsyncProgramStore,
+) {
+    let events = match store.lock().await.application.rooms.get(room.room_id()) {
+        Some(info) => info.undecrypted(),
+        None => return,
+    };
+
+    for ev in events {
+        if session_id.is_some() && megolm_session_id(&ev) != session_id {
+            continue;
+        }
+
+        let Some(msg) = decrypt_loaded(room, &ev).await else {
+            continue;
+        };
+
+        let mut locked = store.lock().await;
+        let info = locked.application.get_room_info(room.room_id().to_owned());
+        info.insert_decrypted(msg);
     }
 }
 
+/// Try to decrypt a message that was loaded before we had its room key.
+pub async fn decrypt_loaded(
+    room: &MatrixRoom,
+    ev: &OriginalRoomEncryptedEvent,
+) -> Option<RoomMessageEvent> {
+    // Ruma's events can't be serialized, so the event gets rebuilt from its fields.
+    let json = serde_json::json!({
+        "type": "m.room.encrypted",
+        "event_id": ev.event_id,
+        "sender": ev.sender,
+        "origin_server_ts": ev.origin_server_ts,
+        "room_id": ev.room_id,
+        "content": ev.content,
+    });
+    let raw = Raw::new(&json).ok()?;
+    let decrypted = room.decrypt_event(&raw.cast()).await.ok()?;
+
+    match decrypted.event.deserialize().ok()? {
+        AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomMessage(msg)) => Some(msg),
+        _ => None,
+    }
+}
+
+/// Load the users that the account has ignored, as of the last sync.
+async fn load_ignored_users(client: &Client) -> HashSet<OwnedUserId> {
+    match client.account().account_data::<IgnoredUserListEventContent>().await {
+        Ok(Some(raw)) => {
+            match raw.deserialize() {
+                Ok(content) => content.ignored_users.into_keys().collect(),
+                Err(e) => {
+                    warn!(err = %e, "Failed to parse ignored users account data");
+                    HashSet::new()
+                },
+            }
+        },
+        Ok(None) => HashSet::new(),
+        Err(e) => {
+            warn!(err = %e, "Failed to load ignored users account data");
+            HashSet::new()
+        },
+    }
+}
+
+/// Find the rooms that a space lists as its children, according to its synced state. Children
+/// without any servers to join through have been removed from the space, and are skipped.
+async fn space_children(space: &MatrixRoom) -> Vec<OwnedRoomId> {
+    let Ok(events) = space.get_state_events_static::<SpaceChildEventContent>().await else {
+        return vec![];
+    };
+
+    events
+        .into_iter()
+        .filter_map(|raw| {
+            match raw.deserialize().ok()? {
+                SyncOrStrippedState::Sync(SyncStateEvent::Original(ev))
+                    if !ev.content.via.is_empty() =>
+                {
+                    Some(ev.state_key)
+                },
+                _ => None,
+            }
+        })
+        .collect()
+}
+
 async fn refresh_rooms(client: &Client, store: &AsyncProgramStore) {
     let mut names = vec![];
 
     let mut spaces = vec![];
     let mut rooms = vec![];
     let mut dms = vec![];
+    let mut children = HashMap::new();
+    let mut modes = vec![];
 
     for room in client.invited_rooms().into_iter() {
         let name = room.display_name().await.unwrap_or(DisplayName::Empty).to_string();
@@ -454,29 +1119,42 @@ async fn refresh_rooms(client: &Client, store: &AsyncProgramStore) {
         }
     }
 
+    let notifications = client.notification_settings().await;
+
     for room in client.joined_rooms().into_iter() {
         let name = room.display_name().await.unwrap_or(DisplayName::Empty).to_string();
         let tags = room.tags().await.unwrap_or_default();
+        let mode = notifications.get_user_defined_room_notification_mode(room.room_id()).await;
 
         names.push((room.room_id().to_owned(), name));
+        modes.push((room.room_id().to_owned(), mode));
 
         if is_direct(&room).await {
             dms.push(Arc::new((room, tags)));
         } else if room.is_space() {
+            children.insert(room.room_id().to_owned(), space_children(&room).await);
             spaces.push(Arc::new((room, tags)));
         } else {
             rooms.push(Arc::new((room, tags)));
         }
     }
 
+    let breadcrumbs = load_breadcrumbs(client).await;
+
     let mut locked = store.lock().await;
     locked.application.sync_info.spaces = spaces;
     locked.application.sync_info.rooms = rooms;
     locked.application.sync_info.dms = dms;
+    locked.application.sync_info.space_children = children;
+    locked.application.sync_info.breadcrumbs = breadcrumbs;
 
     for (room_id, name) in names {
         locked.application.set_room_name(&room_id, &name);
     }
+
+    for (room_id, mode) in modes {
+        locked.application.rooms.get_or_default(room_id).notification_mode = mode;
+    }
 }
 
 async fn refresh_rooms_forever(client: &Client, store: &AsyncProgramStore) {
@@ -488,14 +1166,41 @@ async fn refresh_rooms_forever(client: &Client, store: &AsyncProgramStore) {
     }
 }
 
+/// Get a room's unread notification and highlight counts, as of the last sync.
+fn notification_counts(room: &MatrixRoom) -> (usize, usize) {
+    let counts = room.unread_notification_counts();
+    let count = |n: u64| usize::try_from(n).unwrap_or(usize::MAX);
+
+    (count(counts.notification_count), count(counts.highlight_count))
+}
+
 async fn send_receipts_forever(client: &Client, store: &AsyncProgramStore) {
     let mut interval = tokio::time::interval(Duration::from_secs(2));
-    let mut sent = HashMap::<OwnedRoomId, OwnedEventId>::default();
+    let mut sent = HashMap::<(OwnedRoomId, ReceiptThread), OwnedEventId>::default();
+
+    // The counts that rooms had when we last sent a read receipt for them, which stay stale until
+    // the server syncs the new ones.
+    let mut stale = HashMap::<OwnedRoomId, (usize, usize)>::default();
 
     loop {
         interval.tick().await;
 
-        let locked = store.lock().await;
+        let mut locked = store.lock().await;
+
+        for room in client.joined_rooms() {
+            let room_id = room.room_id();
+            let counts = notification_counts(&room);
+
+            if stale.get(room_id) == Some(&counts) {
+                continue;
+            }
+
+            stale.remove(room_id);
+
+            let info = locked.application.rooms.get_or_default(room_id.to_owned());
+            info.set_notification_counts(counts.0, counts.1);
+        }
+
         let user_id = &locked.application.settings.profile.user_id;
         let updates = client
             .joined_rooms()
@@ -503,41 +1208,427 @@ async fn send_receipts_forever(client: &Client, store: &AsyncProgramStore) {
             .filter_map(|room| {
                 let room_id = room.room_id().to_owned();
                 let info = locked.application.rooms.get(&room_id)?;
-                let new_receipt = info.get_receipt(user_id)?;
-                let old_receipt = sent.get(&room_id);
-                if Some(new_receipt) != old_receipt {
-                    Some((room_id, new_receipt.clone()))
-                } else {
-                    None
-                }
+                let main = info
+                    .get_receipt(user_id)
+                    .map(|receipt| (ReceiptThread::Unthreaded, receipt.clone()));
+                let threads = info.thread_receipts.iter().filter_map(|(root, receipts)| {
+                    let receipt = receipts.get(user_id)?;
+                    Some((ReceiptThread::Thread(root.clone()), receipt.clone()))
+                });
+
+                let updates = main
+                    .into_iter()
+                    .chain(threads)
+                    .filter(|(thread, new_receipt)| {
+                        let key = (room_id.clone(), thread.clone());
+                        sent.get(&key) != Some(new_receipt)
+                    })
+                    .map(|(thread, new_receipt)| (room_id.clone(), thread, new_receipt))
+                    .collect::<Vec<_>>();
+
+                Some(updates)
             })
+            .flatten()
             .collect::<Vec<_>>();
         drop(locked);
 
-        for (room_id, new_receipt) in updates {
+        for (room_id, thread, new_receipt) in updates {
             use matrix_sdk::ruma::api::client::receipt::create_receipt::v3::ReceiptType;
 
             let Some(room) = client.get_room(&room_id) else {
                 continue;
             };
 
-            match room
-                .send_single_receipt(
-                    ReceiptType::Read,
-                    ReceiptThread::Unthreaded,
-                    new_receipt.clone(),
-                )
-                .await
-            {
-                Ok(()) => {
-                    sent.insert(room_id, new_receipt);
+            let res = room
+                .send_single_receipt(ReceiptType::Read, thread.clone(), new_receipt.clone())
+                .await;
+
+            match (res, &thread) {
+                (Ok(()), ReceiptThread::Unthreaded) => {
+                    stale.insert(room_id.clone(), notification_counts(&room));
+
+                    let mut locked = store.lock().await;
+                    let info = locked.application.rooms.get_or_default(room_id.clone());
+                    info.set_notification_counts(0, 0);
+                    drop(locked);
+
+                    sent.insert((room_id, thread), new_receipt);
+                },
+                (Ok(()), _) => {
+                    sent.insert((room_id, thread), new_receipt);
+                },
+                (Err(e), ReceiptThread::Thread(_)) => {
+                    // Servers without threaded receipts will reject these, so don't keep retrying.
+                    tracing::warn!(?room_id, "Failed to set threaded read receipt: {e}");
+                    sent.insert((room_id, thread), new_receipt);
+                },
+                (Err(e), _) => tracing::warn!(?room_id, "Failed to set read receipt: {e}"),
+            }
+        }
+    }
+}
+
+async fn send_queue_forever(client: &Client, store: &AsyncProgramStore, online: &AtomicBool) {
+    let mut interval = tokio::time::interval(Duration::from_secs(2));
+
+    loop {
+        interval.tick().await;
+
+        while online.load(Ordering::Relaxed) {
+            // Messages stay in the queue until they've been sent, so that anything sent from the
+            // message bar in the meantime still knows to wait behind them.
+            let pending = {
+                let mut locked = store.lock().await;
+
+                match locked.application.send_queue.first_mut() {
+                    // Messages are queued in order, so everything after this can still be unsent.
+                    Some(pending) if pending.send_after <= Instant::now() => {
+                        pending.sending = true;
+                        pending.clone()
+                    },
+                    _ => break,
+                }
+            };
+
+            let room_id = pending.room_id.clone();
+            let txn_id = pending.txn_id.clone();
+
+            let res = match client.get_room(&room_id) {
+                Some(room) => {
+                    let content = pending.content.clone();
+                    Some(room.send(content).with_transaction_id(&txn_id).await)
+                },
+                None => None,
+            };
+
+            let mut locked = store.lock().await;
+            let ChatStore { send_queue, failed_sends, send_errors, rooms, .. } =
+                &mut locked.application;
+            let idx = send_queue.iter().position(|p| p.txn_id == txn_id);
+
+            match res {
+                Some(Ok(resp)) => {
+                    if let Some(idx) = idx {
+                        send_queue.remove(idx);
+                    }
+
+                    let Some(echo_id) = pending.echo_id else {
+                        continue;
+                    };
+
+                    let info = rooms.get_or_default(room_id);
+                    info.update_echo(pending.thread, &echo_id, resp.event_id);
+                },
+                Some(Err(e)) if e.as_client_api_error().is_some() => {
+                    warn!(?room_id, "Server rejected queued message: {e}");
+
+                    let Some(mut pending) = idx.map(|idx| send_queue.remove(idx)) else {
+                        continue;
+                    };
+
+                    // Keep the message around so that it can be resent with :resend.
+                    let Some(echo_id) = &pending.echo_id else {
+                        continue;
+                    };
+
+                    let info = rooms.get_or_default(room_id);
+                    let key = (MessageTimeStamp::LocalEcho, echo_id.clone());
+
+                    if let Some(msg) = info.get_thread_mut(pending.thread.clone()).get_mut(&key) {
+                        msg.send_failed = true;
+                        pending.sending = false;
+                        failed_sends.push(pending);
+                    }
+                },
+                Some(Err(e)) => {
+                    // Still can't reach the server, so leave everything queued in order and wait.
+                    warn!(?room_id, "Failed to send queued message: {e}");
+                    online.store(false, Ordering::Relaxed);
+
+                    if let Some(idx) = idx {
+                        send_queue[idx].sending = false;
+                    }
+                },
+                None => {
+                    warn!(?room_id, "Dropping queued message for unknown room");
+
+                    if let Some(idx) = idx {
+                        send_queue.remove(idx);
+                    }
+
+                    if let Some(echo_id) = pending.echo_id {
+                        let info = rooms.get_or_default(room_id.clone());
+                        let key = (MessageTimeStamp::LocalEcho, echo_id);
+                        info.get_thread_mut(pending.thread).remove(&key);
+                    }
+
+                    send_errors
+                        .push(format!("Couldn't send a queued message to unknown room {room_id}"));
                 },
-                Err(e) => tracing::warn!(?room_id, "Failed to set read receipt: {e}"),
             }
         }
     }
 }
 
+/// Check whether a newly arrived message's attachment should be downloaded right away, and if
+/// so, where to.
+fn auto_download_target(
+    ev: &SyncMessageLikeEvent<RoomMessageEventContent>,
+    settings: &ApplicationSettings,
+) -> Option<(OwnedEventId, MediaSource, PathBuf)> {
+    let auto = settings.tunables.auto_download.as_ref()?;
+    let dir = settings.dirs.downloads.as_ref()?;
+    let ev = ev.as_original()?;
+
+    // Our own attachments came from this machine, or from another one of our devices.
+    if ev.sender == settings.profile.user_id {
+        return None;
+    }
+
+    let attachment = Attachment::from_msgtype(&ev.content.msgtype)?;
+
+    if !auto.allows(attachment.kind, attachment.size) {
+        return None;
+    }
+
+    // The filename comes from the sender, so make sure it can't escape the download directory.
+    let filename = Path::new(attachment.filename).file_name()?;
+
+    Some((ev.event_id.clone(), attachment.source, dir.join(filename)))
+}
+
+/// Look up the trust status of everyone we share an encrypted room with.
+///
+/// The first master key seen for each user gets recorded, so that a later change to their
+/// identity can be flagged until they're verified again.
+async fn fetch_identities(
+    client: &Client,
+    settings: &ApplicationSettings,
+) -> IambResult<Vec<IdentityInfo>> {
+    let mut users: HashMap<OwnedUserId, usize> = HashMap::new();
+
+    for room in client.joined_rooms() {
+        if !room.is_encrypted().await.unwrap_or(false) {
+            continue;
+        }
+
+        let members = room.members_no_sync(RoomMemberships::JOIN).await;
+
+        for member in members.map_err(IambError::from)? {
+            if member.user_id() != settings.profile.user_id {
+                *users.entry(member.user_id().to_owned()).or_default() += 1;
+            }
+        }
+    }
+
+    let encryption = client.encryption();
+    let mut keys = vec![];
+
+    for (user_id, rooms) in users {
+        let identity = encryption.get_user_identity(&user_id).await.map_err(IambError::from)?;
+        let verified = identity.as_ref().is_some_and(|i| i.is_verified());
+        let key = identity
+            .as_ref()
+            .and_then(|i| i.master_key().get_first_key())
+            .map(|k| k.to_base64());
+
+        keys.push((user_id, rooms, verified, key));
+    }
+
+    let identities = settings.update_known_identities(|known| {
+        let mut identities = vec![];
+
+        for (user_id, rooms, verified, key) in keys {
+            let prev = known.get(&user_id).map(String::as_str);
+            let status = IdentityStatus::new(verified, key.as_deref(), prev);
+
+            // Keep the old key around for changed identities, until they're verified.
+            if let Some(key) = key {
+                if status != IdentityStatus::Changed && prev != Some(key.as_str()) {
+                    known.insert(user_id.clone(), key);
+                }
+            }
+
+            identities.push(IdentityInfo { user_id, status, rooms });
+        }
+
+        identities
+    });
+
+    Ok(identities)
+}
+
+/// Keep [ChatStore::identities] up to date for the verification list, without making the UI
+/// wait on the crypto store.
+async fn refresh_identities_forever(
+    client: &Client,
+    store: &AsyncProgramStore,
+    settings: &ApplicationSettings,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(5));
+
+    loop {
+        interval.tick().await;
+
+        match fetch_identities(client, settings).await {
+            Ok(identities) => store.lock().await.application.identities = identities,
+            Err(e) => warn!("Failed to fetch user identities: {e}"),
+        }
+    }
+}
+
+/// Watch for other users' identities changing from the one first seen for them, and raise an
+/// alert for each change until it's acknowledged.
+async fn watch_identities_forever(
+    client: &Client,
+    store: &AsyncProgramStore,
+    settings: &ApplicationSettings,
+) {
+    let updates = match client.encryption().user_identities_stream().await {
+        Ok(updates) => updates,
+        Err(e) => {
+            warn!("Failed to watch for identity changes: {e}");
+            return;
+        },
+    };
+    futures::pin_mut!(updates);
+
+    // Changes seen before a restart still need acknowledging, so raise their alerts again.
+    let encryption = client.encryption();
+
+    for (user_id, prev) in settings.read_known_identities() {
+        let Ok(Some(identity)) = encryption.get_user_identity(&user_id).await else {
+            continue;
+        };
+        let Some(key) = identity.master_key().get_first_key().map(|k| k.to_base64()) else {
+            continue;
+        };
+
+        if IdentityStatus::new(identity.is_verified(), Some(&key), Some(&prev)) ==
+            IdentityStatus::Changed
+        {
+            store.lock().await.application.identity_alerts.insert(user_id, key);
+        }
+    }
+
+    while let Some(updates) = updates.next().await {
+        let keys = updates
+            .new
+            .into_iter()
+            .chain(updates.changed)
+            .filter(|(user_id, _)| *user_id != settings.profile.user_id)
+            .filter_map(|(user_id, identity)| {
+                let key = identity.master_key().get_first_key()?.to_base64();
+                Some((user_id, identity.is_verified(), key))
+            })
+            .collect::<Vec<_>>();
+
+        let changed = settings.update_known_identities(|known| {
+            let mut changed = vec![];
+
+            for (user_id, verified, key) in keys {
+                let prev = known.get(&user_id).map(String::as_str);
+
+                match IdentityStatus::new(verified, Some(&key), prev) {
+                    IdentityStatus::Changed => changed.push((user_id, key)),
+                    _ if prev != Some(key.as_str()) => {
+                        known.insert(user_id, key);
+                    },
+                    _ => {},
+                }
+            }
+
+            changed
+        });
+
+        let mut locked = store.lock().await;
+
+        for (user_id, key) in changed {
+            let alerts = &mut locked.application.identity_alerts;
+
+            // Only make noise the first time we see each new key.
+            if alerts.insert(user_id.clone(), key.clone()).as_ref() != Some(&key) {
+                warn!(user_id = user_id.as_str(), "User's identity has changed");
+                locked.application.ring_bell = true;
+            }
+        }
+    }
+}
+
+/// Find the user's other sessions that haven't been verified, either directly or through
+/// cross-signing.
+async fn unverified_sessions(client: &Client, user_id: &UserId) -> IambResult<Vec<SessionInfo>> {
+    let devices = client
+        .encryption()
+        .get_user_devices(user_id)
+        .await
+        .map_err(IambError::from)?;
+    let own = client.device_id();
+
+    let mut sessions = devices
+        .devices()
+        .filter(|device| Some(device.device_id()) != own && !device.is_verified())
+        .map(|device| {
+            SessionInfo {
+                device_id: device.device_id().to_owned(),
+                display_name: device.display_name().map(String::from),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    sessions.sort_by(|a, b| a.device_id.cmp(&b.device_id));
+
+    Ok(sessions)
+}
+
+/// Keep track of the user's unverified sessions, checking once on startup and then again whenever
+/// their sessions change, and warn about them when there's one that hasn't been seen before.
+async fn watch_sessions_forever(
+    client: &Client,
+    store: &AsyncProgramStore,
+    settings: &ApplicationSettings,
+) {
+    let user_id = settings.profile.user_id.as_ref();
+    let updates = match client.encryption().devices_stream().await {
+        Ok(updates) => updates,
+        Err(e) => {
+            warn!("Failed to watch for session changes: {e}");
+            return;
+        },
+    };
+    futures::pin_mut!(updates);
+
+    // Sessions that were already known are checked right away, and the first sync after a fresh
+    // login will show up as an update once they've been fetched.
+    let mut check = true;
+
+    loop {
+        if check {
+            match unverified_sessions(client, user_id).await {
+                Ok(sessions) => {
+                    let mut locked = store.lock().await;
+                    let app = &mut locked.application;
+
+                    if sessions.iter().any(|s| !app.unverified_sessions.contains(s)) {
+                        app.session_warning = settings.tunables.unverified_session_warning;
+                    } else if sessions.is_empty() {
+                        app.session_warning = false;
+                    }
+
+                    app.unverified_sessions = sessions;
+                },
+                Err(e) => warn!("Failed to check for unverified sessions: {e}"),
+            }
+        }
+
+        let Some(updates) = updates.next().await else {
+            return;
+        };
+
+        check = updates.new.contains_key(user_id) || updates.changed.contains_key(user_id);
+    }
+}
+
 pub async fn do_first_sync(client: &Client, store: &AsyncProgramStore) -> Result<(), MatrixError> {
     // Perform an initial, lazily-loaded sync.
     let mut room = RoomEventFilter::default();
@@ -573,6 +1664,70 @@ pub async fn do_first_sync(client: &Client, store: &AsyncProgramStore) -> Result
     Ok(())
 }
 
+/// Describe a User-Interactive Authentication stage to someone trying to log in.
+fn auth_stage_name(stage: &AuthType) -> String {
+    let name = match stage.as_str() {
+        "m.login.password" => "password",
+        "m.login.recaptcha" => "CAPTCHA",
+        "m.login.email.identity" => "email verification",
+        "m.login.msisdn" => "phone number verification",
+        "m.login.sso" => "single sign-on",
+        "m.login.dummy" => "confirmation",
+        "m.login.registration_token" => "registration token",
+        "m.login.terms" => "accepting the terms of service",
+        other => return other.to_string(),
+    };
+
+    format!("{name} ({stage})")
+}
+
+/// List the stages that still need completing in each of the flows that a homeserver offered.
+fn describe_login_stages(info: &UiaaInfo) -> String {
+    info.flows
+        .iter()
+        .map(|flow| {
+            flow.stages
+                .iter()
+                .filter(|stage| !info.completed.contains(stage))
+                .map(auth_stage_name)
+                .collect::<Vec<_>>()
+                .join(", then ")
+        })
+        .filter(|stages| !stages.is_empty())
+        .collect::<Vec<_>>()
+        .join("; or ")
+}
+
+/// Get the User-Interactive Authentication stages that a homeserver answered a login attempt
+/// with, if any.
+fn login_stages(err: &matrix_sdk::Error) -> Option<UiaaInfo> {
+    if let Some(info) = err.as_uiaa_response() {
+        return Some(info.clone());
+    }
+
+    // The login endpoint isn't expected to ask for these, so they show up as an unknown body.
+    match &err.as_client_api_error()?.body {
+        ErrorBody::Json(body) => serde_json::from_str(&body.to_string()).ok(),
+        _ => None,
+    }
+}
+
+/// Check that a homeserver accepts passwords before trying to log in with one.
+async fn check_password_login(client: &Client) -> IambResult<()> {
+    // If the homeserver can't tell us, then the login attempt will report the problem instead.
+    let Ok(resp) = client.matrix_auth().get_login_types().await else {
+        return Ok(());
+    };
+
+    if resp.flows.iter().any(|flow| matches!(flow, LoginType::Password(_))) {
+        return Ok(());
+    }
+
+    let offered = resp.flows.iter().map(LoginType::login_type).collect::<Vec<_>>().join(", ");
+
+    Err(IambError::PasswordLoginUnsupported(offered).into())
+}
+
 #[derive(Debug)]
 pub enum LoginStyle {
     SessionRestore(MatrixSession),
@@ -608,13 +1763,19 @@ pub type FetchedRoom = (MatrixRoom, DisplayName, Option<Tags>);
 pub enum WorkerTask {
     Init(AsyncProgramStore, ClientReply<()>),
     Login(LoginStyle, ClientReply<IambResult<EditInfo>>),
-    Logout(String, ClientReply<IambResult<EditInfo>>),
+    Logout(String, bool, ClientReply<IambResult<EditInfo>>),
+    ClearCache(ClientReply<IambResult<()>>),
     GetInviter(MatrixRoom, ClientReply<IambResult<Option<RoomMember>>>),
     GetRoom(OwnedRoomId, ClientReply<IambResult<FetchedRoom>>),
     JoinRoom(String, ClientReply<IambResult<OwnedRoomId>>),
-    Members(OwnedRoomId, ClientReply<IambResult<Vec<RoomMember>>>),
+    LeaveRooms(Vec<OwnedRoomId>, ClientReply<Vec<(OwnedRoomId, IambResult<()>)>>),
+    SetPresence(PresenceState, Option<String>, ClientReply<IambResult<EditInfo>>),
+    SetIgnored(OwnedUserId, bool, ClientReply<IambResult<EditInfo>>),
+    RecoverySetup(ClientReply<IambResult<String>>),
+    RestoreRoomKey(OwnedRoomId, String, ClientReply<IambResult<()>>),
+    RecoveryRestore(String, ClientReply<IambResult<()>>),
     SpaceMembers(OwnedRoomId, ClientReply<IambResult<Vec<OwnedRoomId>>>),
-    TypingNotice(OwnedRoomId),
+    TypingNotice(OwnedRoomId, bool),
     Verify(VerifyAction, SasVerification, ClientReply<IambResult<EditInfo>>),
     VerifyRequest(OwnedUserId, ClientReply<IambResult<EditInfo>>),
 }
@@ -634,8 +1795,15 @@ impl Debug for WorkerTask {
                     .field(&format_args!("_"))
                     .finish()
             },
-            WorkerTask::Logout(user_id, _) => {
-                f.debug_tuple("WorkerTask::Logout").field(user_id).finish()
+            WorkerTask::Logout(user_id, erase, _) => {
+                f.debug_tuple("WorkerTask::Logout")
+                    .field(user_id)
+                    .field(erase)
+                    .field(&format_args!("_"))
+                    .finish()
+            },
+            WorkerTask::ClearCache(_) => {
+                f.debug_tuple("WorkerTask::ClearCache").field(&format_args!("_")).finish()
             },
             WorkerTask::GetInviter(invite, _) => {
                 f.debug_tuple("WorkerTask::GetInviter").field(invite).finish()
@@ -652,9 +1820,42 @@ impl Debug for WorkerTask {
                     .field(&format_args!("_"))
                     .finish()
             },
-            WorkerTask::Members(room_id, _) => {
-                f.debug_tuple("WorkerTask::Members")
+            WorkerTask::SetPresence(presence, status_msg, _) => {
+                f.debug_tuple("WorkerTask::SetPresence")
+                    .field(presence)
+                    .field(status_msg)
+                    .field(&format_args!("_"))
+                    .finish()
+            },
+            WorkerTask::SetIgnored(user_id, ignore, _) => {
+                f.debug_tuple("WorkerTask::SetIgnored")
+                    .field(user_id)
+                    .field(ignore)
+                    .field(&format_args!("_"))
+                    .finish()
+            },
+            WorkerTask::RestoreRoomKey(room_id, session_id, _) => {
+                f.debug_tuple("WorkerTask::RestoreRoomKey")
                     .field(room_id)
+                    .field(session_id)
+                    .field(&format_args!("_"))
+                    .finish()
+            },
+            WorkerTask::RecoverySetup(_) => {
+                f.debug_tuple("WorkerTask::RecoverySetup")
+                    .field(&format_args!("_"))
+                    .finish()
+            },
+            WorkerTask::RecoveryRestore(_, _) => {
+                // Don't log the recovery key.
+                f.debug_tuple("WorkerTask::RecoveryRestore")
+                    .field(&format_args!("_"))
+                    .field(&format_args!("_"))
+                    .finish()
+            },
+            WorkerTask::LeaveRooms(rooms, _) => {
+                f.debug_tuple("WorkerTask::LeaveRooms")
+                    .field(rooms)
                     .field(&format_args!("_"))
                     .finish()
             },
@@ -664,8 +1865,11 @@ impl Debug for WorkerTask {
                     .field(&format_args!("_"))
                     .finish()
             },
-            WorkerTask::TypingNotice(room_id) => {
-                f.debug_tuple("WorkerTask::TypingNotice").field(room_id).finish()
+            WorkerTask::TypingNotice(room_id, typing) => {
+                f.debug_tuple("WorkerTask::TypingNotice")
+                    .field(room_id)
+                    .field(typing)
+                    .finish()
             },
             WorkerTask::Verify(act, sasv1, _) => {
                 f.debug_tuple("WorkerTask::Verify")
@@ -739,9 +1943,21 @@ pub async fn create_client(settings: &ApplicationSettings) -> Client {
 pub struct Requester {
     pub client: Client,
     pub tx: UnboundedSender<WorkerTask>,
+    pub online: Arc<AtomicBool>,
+    pub sync_failures: Arc<AtomicU32>,
 }
 
 impl Requester {
+    /// Whether the most recent sync with the homeserver succeeded.
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::Relaxed)
+    }
+
+    /// Whether syncs with the homeserver are currently failing and being retried.
+    pub fn is_reconnecting(&self) -> bool {
+        self.sync_failures.load(Ordering::Relaxed) > 0
+    }
+
     pub fn init(&self, store: AsyncProgramStore) {
         let (reply, response) = oneshot();
 
@@ -750,18 +1966,26 @@ impl Requester {
         return response.recv();
     }
 
-    pub fn login(&self, style: LoginStyle) -> IambResult<EditInfo> {
+    pub fn login(&self, style: LoginStyle) -> IambResult<EditInfo> {
+        let (reply, response) = oneshot();
+
+        self.tx.send(WorkerTask::Login(style, reply)).unwrap();
+
+        return response.recv();
+    }
+
+    pub fn logout(&self, user_id: String, erase: bool) -> IambResult<EditInfo> {
         let (reply, response) = oneshot();
 
-        self.tx.send(WorkerTask::Login(style, reply)).unwrap();
+        self.tx.send(WorkerTask::Logout(user_id, erase, reply)).unwrap();
 
         return response.recv();
     }
 
-    pub fn logout(&self, user_id: String) -> IambResult<EditInfo> {
+    pub fn clear_cache(&self) -> IambResult<()> {
         let (reply, response) = oneshot();
 
-        self.tx.send(WorkerTask::Logout(user_id, reply)).unwrap();
+        self.tx.send(WorkerTask::ClearCache(reply)).unwrap();
 
         return response.recv();
     }
@@ -790,10 +2014,56 @@ impl Requester {
         return response.recv();
     }
 
-    pub fn members(&self, room_id: OwnedRoomId) -> IambResult<Vec<RoomMember>> {
+    pub fn leave_rooms(&self, rooms: Vec<OwnedRoomId>) -> Vec<(OwnedRoomId, IambResult<()>)> {
+        let (reply, response) = oneshot();
+
+        self.tx.send(WorkerTask::LeaveRooms(rooms, reply)).unwrap();
+
+        return response.recv();
+    }
+
+    pub fn set_presence(
+        &self,
+        presence: PresenceState,
+        status_msg: Option<String>,
+    ) -> IambResult<EditInfo> {
+        let (reply, response) = oneshot();
+
+        self.tx.send(WorkerTask::SetPresence(presence, status_msg, reply)).unwrap();
+
+        return response.recv();
+    }
+
+    pub fn set_ignored(&self, user_id: OwnedUserId, ignore: bool) -> IambResult<EditInfo> {
+        let (reply, response) = oneshot();
+
+        self.tx.send(WorkerTask::SetIgnored(user_id, ignore, reply)).unwrap();
+
+        return response.recv();
+    }
+
+    pub fn restore_room_key(&self, room_id: OwnedRoomId, session_id: String) -> IambResult<()> {
+        let (reply, response) = oneshot();
+
+        self.tx
+            .send(WorkerTask::RestoreRoomKey(room_id, session_id, reply))
+            .unwrap();
+
+        return response.recv();
+    }
+
+    pub fn recovery_setup(&self) -> IambResult<String> {
+        let (reply, response) = oneshot();
+
+        self.tx.send(WorkerTask::RecoverySetup(reply)).unwrap();
+
+        return response.recv();
+    }
+
+    pub fn recovery_restore(&self, key: String) -> IambResult<()> {
         let (reply, response) = oneshot();
 
-        self.tx.send(WorkerTask::Members(room_id, reply)).unwrap();
+        self.tx.send(WorkerTask::RecoveryRestore(key, reply)).unwrap();
 
         return response.recv();
     }
@@ -806,8 +2076,8 @@ impl Requester {
         return response.recv();
     }
 
-    pub fn typing_notice(&self, room_id: OwnedRoomId) {
-        self.tx.send(WorkerTask::TypingNotice(room_id)).unwrap();
+    pub fn typing_notice(&self, room_id: OwnedRoomId, typing: bool) {
+        self.tx.send(WorkerTask::TypingNotice(room_id, typing)).unwrap();
     }
 
     pub fn verify(&self, act: VerifyAction, sas: SasVerification) -> IambResult<EditInfo> {
@@ -831,27 +2101,41 @@ pub struct ClientWorker {
     initialized: bool,
     settings: ApplicationSettings,
     client: Client,
+    online: Arc<AtomicBool>,
+    sync_failures: Arc<AtomicU32>,
     load_handle: Option<JoinHandle<()>>,
     sync_handle: Option<JoinHandle<()>>,
+
+    /// The presence and status message most recently set with `:presence`.
+    presence: Option<(PresenceState, Option<String>)>,
+
+    /// The presence that the sync loop reports, so that syncing doesn't undo `:presence`.
+    sync_presence: Arc<Mutex<PresenceState>>,
 }
 
 impl ClientWorker {
     pub async fn spawn(client: Client, settings: ApplicationSettings) -> Requester {
         let (tx, rx) = unbounded_channel();
+        let online = Arc::new(AtomicBool::new(true));
+        let sync_failures = Arc::new(AtomicU32::new(0));
 
         let mut worker = ClientWorker {
             initialized: false,
             settings,
             client: client.clone(),
+            online: online.clone(),
+            sync_failures: sync_failures.clone(),
             load_handle: None,
             sync_handle: None,
+            presence: None,
+            sync_presence: Arc::new(Mutex::new(PresenceState::Online)),
         };
 
         tokio::spawn(async move {
             worker.work(rx).await;
         });
 
-        return Requester { client, tx };
+        return Requester { client, tx, online, sync_failures };
     }
 
     async fn work(&mut self, mut rx: UnboundedReceiver<WorkerTask>) {
@@ -894,21 +2178,45 @@ impl ClientWorker {
                 assert!(self.initialized);
                 reply.send(self.login_and_sync(style).await);
             },
-            WorkerTask::Logout(user_id, reply) => {
+            WorkerTask::Logout(user_id, erase, reply) => {
+                assert!(self.initialized);
+                reply.send(self.logout(user_id, erase).await);
+            },
+            WorkerTask::ClearCache(reply) => {
+                assert!(self.initialized);
+                reply.send(self.clear_cache());
+            },
+            WorkerTask::SetPresence(presence, status_msg, reply) => {
+                assert!(self.initialized);
+                reply.send(self.set_presence(presence, status_msg).await);
+            },
+            WorkerTask::SetIgnored(user_id, ignore, reply) => {
                 assert!(self.initialized);
-                reply.send(self.logout(user_id).await);
+                reply.send(self.set_ignored(user_id, ignore).await);
             },
-            WorkerTask::Members(room_id, reply) => {
+            WorkerTask::RestoreRoomKey(room_id, session_id, reply) => {
                 assert!(self.initialized);
-                reply.send(self.members(room_id).await);
+                reply.send(self.restore_room_key(room_id, session_id).await);
+            },
+            WorkerTask::RecoverySetup(reply) => {
+                assert!(self.initialized);
+                reply.send(self.recovery_setup().await);
+            },
+            WorkerTask::RecoveryRestore(key, reply) => {
+                assert!(self.initialized);
+                reply.send(self.recovery_restore(key).await);
+            },
+            WorkerTask::LeaveRooms(rooms, reply) => {
+                assert!(self.initialized);
+                reply.send(self.leave_rooms(rooms).await);
             },
             WorkerTask::SpaceMembers(space, reply) => {
                 assert!(self.initialized);
                 reply.send(self.space_members(space).await);
             },
-            WorkerTask::TypingNotice(room_id) => {
+            WorkerTask::TypingNotice(room_id, typing) => {
                 assert!(self.initialized);
-                self.typing_notice(room_id).await;
+                self.typing_notice(room_id, typing).await;
             },
             WorkerTask::Verify(act, sas, reply) => {
                 assert!(self.initialized);
@@ -924,6 +2232,76 @@ impl ClientWorker {
     async fn init(&mut self, store: AsyncProgramStore) {
         self.client.add_event_handler_context(store.clone());
 
+        let ignored = load_ignored_users(&self.client).await;
+        store.lock().await.application.ignored_users = ignored;
+
+        if store.lock().await.application.settings.tunables.debug_event_log {
+            let _ = self.client.add_event_handler(
+                |ev: Raw<AnySyncTimelineEvent>, room: MatrixRoom, store: Ctx<AsyncProgramStore>| {
+                    async move {
+                        let Ok(Some(event_type)) = ev.get_field::<String>("type") else {
+                            return;
+                        };
+                        let sender = ev.get_field::<OwnedUserId>("sender").ok().flatten();
+                        let room_id = room.room_id().to_owned();
+
+                        let event = DebugEvent { event_type, room_id, sender };
+                        store.lock().await.application.debug_events.push(event);
+                    }
+                },
+            );
+
+            let _ = self.client.add_event_handler(
+                |ev: Raw<AnySyncEphemeralRoomEvent>,
+                 room: MatrixRoom,
+                 store: Ctx<AsyncProgramStore>| {
+                    async move {
+                        let Ok(Some(event_type)) = ev.get_field::<String>("type") else {
+                            return;
+                        };
+                        let room_id = room.room_id().to_owned();
+
+                        let event = DebugEvent { event_type, room_id, sender: None };
+                        store.lock().await.application.debug_events.push(event);
+                    }
+                },
+            );
+        }
+
+        let _ = self.client.add_event_handler(
+            |ev: IgnoredUserListEvent, store: Ctx<AsyncProgramStore>| {
+                async move {
+                    let ignored = ev.content.ignored_users.into_keys().collect();
+                    store.lock().await.application.ignored_users = ignored;
+                }
+            },
+        );
+
+        // Messages that arrived before their keys can be decrypted once the keys show up.
+        let _ = self.client.add_event_handler(
+            |ev: ToDeviceRoomKeyEvent, client: Client, store: Ctx<AsyncProgramStore>| {
+                async move {
+                    let Some(room) = client.get_room(&ev.content.room_id) else {
+                        return;
+                    };
+
+                    retry_room_decryption(&room, Some(&ev.content.session_id), &store).await;
+                }
+            },
+        );
+
+        let _ = self.client.add_event_handler(
+            |ev: ToDeviceForwardedRoomKeyEvent, client: Client, store: Ctx<AsyncProgramStore>| {
+                async move {
+                    let Some(room) = client.get_room(&ev.content.room_id) else {
+                        return;
+                    };
+
+                    retry_room_decryption(&room, Some(&ev.content.session_id), &store).await;
+                }
+            },
+        );
+
         let _ = self.client.add_event_handler(
             |ev: SyncTypingEvent, room: MatrixRoom, store: Ctx<AsyncProgramStore>| {
                 async move {
@@ -947,7 +2325,11 @@ impl ClientWorker {
                 .add_event_handler(|ev: PresenceEvent, store: Ctx<AsyncProgramStore>| {
                     async move {
                         let mut locked = store.lock().await;
-                        locked.application.presences.insert(ev.sender, ev.content.presence);
+                        let presence = UserPresence {
+                            state: ev.content.presence,
+                            status_msg: ev.content.status_msg,
+                        };
+                        locked.application.presences.insert(ev.sender, Some(presence));
                     }
                 });
 
@@ -967,8 +2349,24 @@ impl ClientWorker {
             },
         );
 
+        let _ = self.client.add_event_handler(
+            |ev: SyncStateEvent<RoomPinnedEventsEventContent>,
+             room: MatrixRoom,
+             store: Ctx<AsyncProgramStore>| {
+                async move {
+                    if let SyncStateEvent::Original(ev) = ev {
+                        let room_id = room.room_id().to_owned();
+                        let mut locked = store.lock().await;
+                        let info = locked.application.rooms.get_or_default(room_id);
+                        info.pinned = ev.content.pinned;
+                    }
+                }
+            },
+        );
+
         let _ = self.client.add_event_handler(
             |ev: SyncMessageLikeEvent<RoomMessageEventContent>,
+             raw: RawEvent,
              room: MatrixRoom,
              client: Client,
              store: Ctx<AsyncProgramStore>| {
@@ -977,18 +2375,30 @@ impl ClientWorker {
 
                     if let Some(msg) = ev.as_original() {
                         if let MessageType::VerificationRequest(_) = msg.content.msgtype {
-                            if let Some(request) = client
-                                .encryption()
-                                .get_verification_request(ev.sender(), ev.event_id())
-                                .await
-                            {
-                                request.accept().await.expect("Failed to accept request");
+                            let locked = store.lock().await;
+                            let auto_accept =
+                                locked.application.settings.tunables.verification_auto_accept;
+                            drop(locked);
+
+                            // Otherwise, the request gets answered from the scrollback.
+                            if auto_accept {
+                                if let Some(request) = client
+                                    .encryption()
+                                    .get_verification_request(ev.sender(), ev.event_id())
+                                    .await
+                                {
+                                    request.accept().await.expect("Failed to accept request");
+                                }
                             }
                         }
                     }
 
                     let mut locked = store.lock().await;
 
+                    if locked.application.ignored_users.contains(ev.sender()) {
+                        return;
+                    }
+
                     let sender = ev.sender().to_owned();
                     let _ = locked.application.presences.get_or_default(sender);
 
@@ -997,6 +2407,13 @@ impl ClientWorker {
 
                     update_event_receipts(info, &room, ev.event_id()).await;
 
+                    if let Some(counts) = bundled_reaction_counts(raw.get()) {
+                        info.set_bundled_reactions(ev.event_id().to_owned(), counts);
+                    }
+
+                    let download = auto_download_target(&ev, settings);
+
+                    let forwarded = is_forwarded(raw.get()).then(|| ev.event_id().to_owned());
                     let full_ev = ev.into_full_event(room_id.to_owned());
                     info.insert_with_preview(
                         room_id.to_owned(),
@@ -1004,8 +2421,53 @@ impl ClientWorker {
                         *picker,
                         full_ev,
                         settings,
-                        client.media(),
+                        &client,
                     );
+
+                    if let Some(event_id) = forwarded {
+                        info.set_forwarded(&event_id);
+                    }
+
+                    if let Some((event_id, source, filename)) = download {
+                        let room_id = room_id.to_owned();
+                        let media = client.media();
+                        spawn_auto_download(
+                            store.clone(),
+                            room_id,
+                            event_id,
+                            source,
+                            media,
+                            filename,
+                        );
+                    }
+                }
+            },
+        );
+
+        let _ = self.client.add_event_handler(
+            |ev: Raw<AnySyncMessageLikeEvent>, room: MatrixRoom, store: Ctx<AsyncProgramStore>| {
+                async move {
+                    let Ok(Some(event_type)) = ev.get_field::<String>("type") else {
+                        return;
+                    };
+
+                    if is_native_event_type(&event_type) {
+                        return;
+                    }
+
+                    let mut locked = store.lock().await;
+                    let ChatStore { rooms, settings, .. } = &mut locked.application;
+
+                    let Some(template) = settings.tunables.custom_events.get(&event_type) else {
+                        return;
+                    };
+
+                    let Ok(custom) = ev.deserialize_as::<CustomEvent>() else {
+                        return;
+                    };
+
+                    let info = rooms.get_or_default(room.room_id().to_owned());
+                    info.insert_custom(custom.render(template));
                 }
             },
         );
@@ -1019,6 +2481,10 @@ impl ClientWorker {
 
                     let mut locked = store.lock().await;
 
+                    if locked.application.ignored_users.contains(ev.sender()) {
+                        return;
+                    }
+
                     let sender = ev.sender().to_owned();
                     let _ = locked.application.presences.get_or_default(sender);
 
@@ -1043,8 +2509,13 @@ impl ClientWorker {
                         let Some(receipts) = receipts.get(&ReceiptType::Read) else {
                             continue;
                         };
-                        for user_id in receipts.keys() {
-                            info.set_receipt(user_id.to_owned(), event_id.clone());
+                        for (user_id, receipt) in receipts.iter() {
+                            if let ReceiptThread::Thread(root) = &receipt.thread {
+                                let user_id = user_id.to_owned();
+                                info.set_thread_receipt(root.clone(), user_id, event_id.clone());
+                            } else {
+                                info.set_receipt(user_id.to_owned(), event_id.clone(), receipt.ts);
+                            }
                         }
                     }
                 }
@@ -1062,7 +2533,32 @@ impl ClientWorker {
 
                     let mut locked = store.lock().await;
                     let info = locked.application.get_room_info(room_id.to_owned());
+                    let unknown = ev.redacts.as_ref().is_some_and(|id| !info.keys.contains_key(id));
                     info.redact(ev, room_version);
+
+                    // A reaction that was only part of a bundled count can't be traced back to
+                    // its message, so get fresh counts for the messages that have them.
+                    let stale = if unknown {
+                        info.bundled_reactions
+                            .iter()
+                            .filter(|(_, counts)| counts.values().any(|n| *n > 0))
+                            .map(|(event_id, _)| event_id.clone())
+                            .collect()
+                    } else {
+                        vec![]
+                    };
+                    drop(locked);
+
+                    for event_id in stale {
+                        let Ok(ev) = room.event(&event_id).await else {
+                            continue;
+                        };
+                        let counts = bundled_reaction_counts(ev.event.json().get());
+
+                        let mut locked = store.lock().await;
+                        let info = locked.application.get_room_info(room_id.to_owned());
+                        info.set_bundled_reactions(event_id, counts.unwrap_or_default());
+                    }
                 }
             },
         );
@@ -1074,6 +2570,7 @@ impl ClientWorker {
              store: Ctx<AsyncProgramStore>| {
                 async move {
                     let room_id = room.room_id();
+                    let change = MembershipEvent::new(&ev.clone().into_full_event(room_id.into()));
                     let user_id = ev.state_key;
 
                     let ambiguous_name =
@@ -1086,8 +2583,26 @@ impl ClientWorker {
                         .unwrap_or_default();
 
                     let mut locked = store.lock().await;
+                    let display = locked.application.settings.tunables.membership_display;
                     let info = locked.application.get_room_info(room_id.to_owned());
 
+                    if let Some(change) = change.filter(|_| display) {
+                        // Changes from before the loaded history, like the ones that come with
+                        // the room's state, get picked up once that part of it gets fetched.
+                        let ts = MessageTimeStamp::from(change.origin_server_ts);
+                        let oldest = info.get_thread(None).and_then(|t| t.first_key_value());
+
+                        if matches!(oldest, Some(((first, _), _)) if *first <= ts) {
+                            info.insert_membership(change);
+                        }
+                    }
+
+                    if ev.content.membership == MembershipState::Join {
+                        info.members.insert(user_id.clone());
+                    } else {
+                        info.members.remove(&user_id);
+                    }
+
                     if ambiguous {
                         info.display_names.remove(&user_id);
                     } else if let Some(display) = ev.content.displayname {
@@ -1215,17 +2730,31 @@ impl ClientWorker {
         self.load_handle = tokio::spawn({
             let client = self.client.clone();
             let settings = self.settings.clone();
+            let online = self.online.clone();
 
             async move {
                 while !client.logged_in() {
                     tokio::time::sleep(Duration::from_millis(100)).await;
                 }
 
-                let load = load_older_forever(&client, &store);
+                let load = load_older_forever(&client, &store, &settings);
                 let rcpt = send_receipts_forever(&client, &store);
                 let room = refresh_rooms_forever(&client, &store);
                 let notifications = register_notifications(&client, &settings, &store);
-                let ((), (), (), ()) = tokio::join!(load, rcpt, room, notifications);
+                let queue = send_queue_forever(&client, &store, &online);
+                let identities = watch_identities_forever(&client, &store, &settings);
+                let trust = refresh_identities_forever(&client, &store, &settings);
+                let sessions = watch_sessions_forever(&client, &store, &settings);
+                let ((), (), (), (), (), (), (), ()) = tokio::join!(
+                    load,
+                    rcpt,
+                    room,
+                    notifications,
+                    queue,
+                    identities,
+                    trust,
+                    sessions
+                );
             }
         })
         .into();
@@ -1241,13 +2770,24 @@ impl ClientWorker {
                 client.restore_session(session).await.map_err(IambError::from)?;
             },
             LoginStyle::Password(password) => {
+                check_password_login(&client).await?;
+
                 let resp = client
                     .matrix_auth()
                     .login_username(&self.settings.profile.user_id, &password)
                     .initial_device_display_name(initial_devname().as_str())
                     .send()
                     .await
-                    .map_err(IambError::from)?;
+                    .map_err(|e| {
+                        // Logins can't carry answers to extra authentication stages, so the best
+                        // we can do is say which ones the homeserver wanted.
+                        match login_stages(&e) {
+                            Some(info) => {
+                                IambError::UnsupportedLoginStages(describe_login_stages(&info))
+                            },
+                            None => IambError::from(e),
+                        }
+                    })?;
                 let session = MatrixSession::from(&resp);
                 self.settings.write_session(session)?;
             },
@@ -1275,11 +2815,48 @@ impl ClientWorker {
             },
         }
 
+        let online = self.online.clone();
+        let failures = self.sync_failures.clone();
+        let presence = self.sync_presence.clone();
+
         self.sync_handle = tokio::spawn(async move {
             loop {
-                let settings = SyncSettings::default();
+                let chosen = presence.lock().unwrap().clone();
+                let settings = SyncSettings::default().set_presence(chosen.clone());
+
+                let _ = client
+                    .sync_with_result_callback(settings, |res| {
+                        online.store(res.is_ok(), Ordering::Relaxed);
+
+                        // Start syncing again with the new presence after it gets changed.
+                        let changed = *presence.lock().unwrap() != chosen;
+
+                        let delay = match res {
+                            Ok(_) => {
+                                failures.store(0, Ordering::Relaxed);
+                                None
+                            },
+                            Err(e) => {
+                                let n = failures.fetch_add(1, Ordering::Relaxed).saturating_add(1);
+                                let delay = sync_backoff(n);
+                                warn!(failures = n, "Sync failed, retrying in {delay:?}: {e}");
+                                Some(delay)
+                            },
+                        };
 
-                let _ = client.sync(settings).await;
+                        async move {
+                            if let Some(delay) = delay {
+                                tokio::time::sleep(delay).await;
+                            }
+
+                            if changed {
+                                Ok(LoopCtrl::Break)
+                            } else {
+                                Ok(LoopCtrl::Continue)
+                            }
+                        }
+                    })
+                    .await;
             }
         })
         .into();
@@ -1287,7 +2864,7 @@ impl ClientWorker {
         Ok(Some(InfoMessage::from("* Successfully logged in!")))
     }
 
-    async fn logout(&mut self, user_id: String) -> IambResult<EditInfo> {
+    async fn logout(&mut self, user_id: String, erase: bool) -> IambResult<EditInfo> {
         // Verify that the user is logging out of the correct profile.
         let curr = self.settings.profile.user_id.as_str();
 
@@ -1309,7 +2886,21 @@ impl ClientWorker {
         // Remove the session.json file.
         std::fs::remove_file(&self.settings.session_json)?;
 
-        Ok(Some(InfoMessage::from("Sucessfully logged out")))
+        if !erase {
+            return Ok(Some(InfoMessage::from("Sucessfully logged out")));
+        }
+
+        // The state and crypto stores are still open, so they get removed once we've exited.
+        std::fs::File::create(self.settings.sqlite_dir.with_file_name(ERASE_STORE_MARKER))?;
+
+        Ok(Some(InfoMessage::from("Sucessfully logged out; the local store will be erased")))
+    }
+
+    fn clear_cache(&mut self) -> IambResult<()> {
+        // The state store is still open, so it gets removed once we've exited.
+        std::fs::File::create(self.settings.sqlite_dir.with_file_name(CLEAR_CACHE_MARKER))?;
+
+        Ok(())
     }
 
     async fn direct_message(&mut self, user: OwnedUserId) -> IambResult<OwnedRoomId> {
@@ -1326,16 +2917,20 @@ impl ClientWorker {
         let rt = CreateRoomType::Direct(user.clone());
         let flags = CreateRoomFlags::ENCRYPTED;
 
-        create_room(&self.client, None, rt, flags).await.map_err(|e| {
-            error!(
-                user_id = user.as_str(),
-                err = e.to_string(),
-                "Failed to create direct message room"
-            );
+        let rotation = &self.settings.tunables.room_key_rotation;
 
-            let msg = format!("Could not open a room with {user}");
-            UIError::Failure(msg)
-        })
+        create_room(&self.client, None, None, rt, flags, rotation)
+            .await
+            .map_err(|e| {
+                error!(
+                    user_id = user.as_str(),
+                    err = e.to_string(),
+                    "Failed to create direct message room"
+                );
+
+                let msg = format!("Could not open a room with {user}");
+                UIError::Failure(msg)
+            })
     }
 
     async fn get_inviter(&mut self, invited: MatrixRoom) -> IambResult<Option<RoomMember>> {
@@ -1376,12 +2971,106 @@ impl ClientWorker {
         }
     }
 
-    async fn members(&mut self, room_id: OwnedRoomId) -> IambResult<Vec<RoomMember>> {
-        if let Some(room) = self.client.get_room(room_id.as_ref()) {
-            Ok(room.members(RoomMemberships::ACTIVE).await.map_err(IambError::from)?)
+    async fn set_presence(
+        &mut self,
+        presence: PresenceState,
+        status_msg: Option<String>,
+    ) -> IambResult<EditInfo> {
+        let update = (presence, status_msg);
+
+        if self.presence.as_ref() == Some(&update) {
+            return Ok(Some(InfoMessage::from("Presence is already set to that")));
+        }
+
+        let user_id = self.settings.profile.user_id.clone();
+        let mut req = SetPresenceRequest::new(user_id, update.0.clone());
+        req.status_msg = update.1.clone();
+        self.client.send(req, None).await.map_err(IambError::from)?;
+
+        let msg = format!("Presence set to {}", update.0);
+        *self.sync_presence.lock().unwrap() = update.0.clone();
+        self.presence = Some(update);
+
+        Ok(Some(InfoMessage::from(msg)))
+    }
+
+    /// Fetch the key for a Megolm session from the key backup.
+    ///
+    /// This doesn't send an `m.room_key_request` to other devices, since the SDK doesn't expose
+    /// the crypto machine that would need to build it.
+    async fn restore_room_key(
+        &mut self,
+        room_id: OwnedRoomId,
+        session_id: String,
+    ) -> IambResult<()> {
+        let backups = self.client.encryption().backups();
+
+        if !backups.are_enabled().await {
+            return Err(IambError::NoKeyBackup.into());
+        }
+
+        backups
+            .download_room_key(&room_id, &session_id)
+            .await
+            .map_err(IambError::from)?;
+
+        Ok(())
+    }
+
+    async fn recovery_setup(&mut self) -> IambResult<String> {
+        let encryption = self.client.encryption();
+
+        if let Err(e) = encryption.bootstrap_cross_signing_if_needed(None).await {
+            if e.as_uiaa_response().is_some() {
+                return Err(IambError::CrossSigningAuth.into());
+            }
+
+            return Err(IambError::from(e).into());
+        }
+
+        match encryption.recovery().enable().await {
+            Ok(key) => Ok(key),
+            Err(RecoveryError::BackupExistsOnServer) => Err(IambError::RecoveryExists.into()),
+            Err(e) => Err(IambError::from(e).into()),
+        }
+    }
+
+    /// Import the secrets from secret storage. The room keys get fetched afterwards by
+    /// [restore_room_keys], so that the UI doesn't wait on them.
+    async fn recovery_restore(&mut self, key: String) -> IambResult<()> {
+        let recovery = self.client.encryption().recovery();
+        recovery.recover(key.trim()).await.map_err(IambError::from)?;
+
+        Ok(())
+    }
+
+    async fn set_ignored(&mut self, user_id: OwnedUserId, ignore: bool) -> IambResult<EditInfo> {
+        let account = self.client.account();
+
+        let msg = if ignore {
+            account.ignore_user(&user_id).await.map_err(IambError::from)?;
+            format!("Ignoring messages from {user_id}")
         } else {
-            Err(IambError::UnknownRoom(room_id).into())
+            account.unignore_user(&user_id).await.map_err(IambError::from)?;
+            format!("No longer ignoring messages from {user_id}")
+        };
+
+        Ok(Some(InfoMessage::from(msg)))
+    }
+
+    async fn leave_rooms(&mut self, rooms: Vec<OwnedRoomId>) -> Vec<(OwnedRoomId, IambResult<()>)> {
+        let mut results = vec![];
+
+        for room_id in rooms {
+            let res = match self.client.get_room(room_id.as_ref()) {
+                Some(room) => room.leave().await.map_err(|e| IambError::from(e).into()),
+                None => Err(IambError::UnknownRoom(room_id.clone()).into()),
+            };
+
+            results.push((room_id, res));
         }
+
+        results
     }
 
     async fn space_members(&mut self, space: OwnedRoomId) -> IambResult<Vec<OwnedRoomId>> {
@@ -1396,9 +3085,9 @@ impl ClientWorker {
         Ok(rooms)
     }
 
-    async fn typing_notice(&mut self, room_id: OwnedRoomId) {
+    async fn typing_notice(&mut self, room_id: OwnedRoomId, typing: bool) {
         if let Some(room) = self.client.get_room(room_id.as_ref()) {
-            let _ = room.typing_notice(true).await;
+            let _ = room.typing_notice(typing).await;
         }
     }
 
@@ -1469,3 +3158,272 @@ impl ClientWorker {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use matrix_sdk::ruma::api::client::uiaa::AuthFlow;
+    use serde_json::value::to_raw_value;
+
+    #[test]
+    fn test_sync_backoff() {
+        let within = |failures, secs: u64| {
+            let delay = sync_backoff(failures);
+            let min = Duration::from_secs(secs);
+            assert!(delay >= min && delay <= min + min / 4, "{}: {:?}", failures, delay);
+        };
+
+        within(1, 1);
+        within(2, 2);
+        within(3, 4);
+        within(6, 32);
+        within(7, 60);
+        within(u32::MAX, 60);
+    }
+
+    #[test]
+    fn test_remove_marked_stores() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let mut settings = crate::tests::mock_settings();
+        settings.sqlite_dir = dir.path().join("sqlite");
+
+        let create = |name: &str| {
+            std::fs::create_dir_all(&settings.sqlite_dir).unwrap();
+            std::fs::write(settings.sqlite_dir.join(name), "").unwrap();
+        };
+        let mark = |name: &str| {
+            std::fs::write(settings.sqlite_dir.with_file_name(name), "").unwrap();
+        };
+
+        // Nothing happens without a marker.
+        create(STATE_STORE_FILES[0]);
+        remove_marked_stores(&settings).unwrap();
+        assert!(settings.sqlite_dir.join(STATE_STORE_FILES[0]).exists());
+
+        // Clearing the cache keeps the crypto store.
+        create("matrix-sdk-crypto.sqlite3");
+        mark(CLEAR_CACHE_MARKER);
+        remove_marked_stores(&settings).unwrap();
+        assert!(!settings.sqlite_dir.join(STATE_STORE_FILES[0]).exists());
+        assert!(settings.sqlite_dir.join("matrix-sdk-crypto.sqlite3").exists());
+        assert!(!settings.sqlite_dir.with_file_name(CLEAR_CACHE_MARKER).exists());
+
+        // Erasing removes everything.
+        mark(ERASE_STORE_MARKER);
+        remove_marked_stores(&settings).unwrap();
+        assert!(!settings.sqlite_dir.exists());
+        assert!(!settings.sqlite_dir.with_file_name(ERASE_STORE_MARKER).exists());
+    }
+
+    #[test]
+    fn test_megolm_session_id() {
+        let event = |content: serde_json::Value| {
+            let ev = serde_json::json!({
+                "type": "m.room.encrypted",
+                "event_id": "$encrypted",
+                "sender": "@user1:example.com",
+                "origin_server_ts": 1234,
+                "room_id": "!room:example.com",
+                "content": content,
+            });
+            serde_json::from_value::<OriginalRoomEncryptedEvent>(ev).unwrap()
+        };
+
+        let megolm = event(serde_json::json!({
+            "algorithm": "m.megolm.v1.aes-sha2",
+            "ciphertext": "AwgAEnAC",
+            "device_id": "DEVICE",
+            "sender_key": "SENDERKEY",
+            "session_id": "SESSION",
+        }));
+        assert_eq!(megolm_session_id(&megolm), Some("SESSION"));
+
+        let olm = event(serde_json::json!({
+            "algorithm": "m.olm.v1.curve25519-aes-sha2",
+            "ciphertext": {},
+            "sender_key": "SENDERKEY",
+        }));
+        assert_eq!(megolm_session_id(&olm), None);
+    }
+
+    #[test]
+    fn test_describe_login_stages() {
+        let flows = vec![
+            AuthFlow::new(vec![AuthType::Password, AuthType::EmailIdentity]),
+            AuthFlow::new(vec![AuthType::Password, AuthType::from("m.login.terms")]),
+            AuthFlow::new(vec![AuthType::Password]),
+        ];
+        let mut info = UiaaInfo::new(flows, to_raw_value(&serde_json::json!({})).unwrap());
+        info.completed = vec![AuthType::Password];
+
+        assert_eq!(
+            describe_login_stages(&info),
+            "email verification (m.login.email.identity); \
+             or accepting the terms of service (m.login.terms)"
+        );
+
+        info.completed = vec![];
+        assert_eq!(
+            describe_login_stages(&info).split("; or ").next(),
+            Some("password (m.login.password), then email verification (m.login.email.identity)")
+        );
+    }
+
+    #[test]
+    fn test_search_hits() {
+        let message = |event_id: &str, body: &str| {
+            serde_json::json!({
+                "type": "m.room.message",
+                "event_id": event_id,
+                "room_id": "!room:example.com",
+                "sender": "@alice:example.com",
+                "origin_server_ts": 1000,
+                "content": { "msgtype": "m.text", "body": body },
+            })
+        };
+        let results: Vec<SearchResult> = serde_json::from_value(serde_json::json!([
+            {
+                "rank": 1.0,
+                "result": message("$a:example.com", "> <@bob:example.com> hi\n\nhello there"),
+                "context": {
+                    "profile_info": { "@alice:example.com": { "displayname": "Alice" } },
+                },
+            },
+            {
+                "result": {
+                    "type": "m.reaction",
+                    "event_id": "$b:example.com",
+                    "room_id": "!room:example.com",
+                    "sender": "@alice:example.com",
+                    "origin_server_ts": 2000,
+                    "content": {
+                        "m.relates_to": {
+                            "rel_type": "m.annotation",
+                            "event_id": "$a:example.com",
+                            "key": "hello",
+                        },
+                    },
+                },
+                "context": {},
+            },
+            { "result": message("$c:example.com", "hello again"), "context": {} },
+        ]))
+        .unwrap();
+
+        let hits = search_hits(results);
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].event_id, "$a:example.com");
+        assert_eq!(hits[0].sender_name.as_deref(), Some("Alice"));
+        assert_eq!(hits[0].body, "hello there");
+        assert_eq!(hits[1].event_id, "$c:example.com");
+        assert_eq!(hits[1].sender_name, None);
+        assert_eq!(hits[1].body, "hello again");
+    }
+
+    #[test]
+    fn test_auto_download_target() {
+        let mut settings = crate::tests::mock_settings();
+        settings.dirs.downloads = Some(PathBuf::from("/downloads"));
+        settings.tunables.auto_download = Some(crate::config::AutoDownloadValues {
+            types: vec![crate::config::MediaKind::Image],
+            max_size: 1000,
+        });
+
+        let image = |sender: &str, body: &str| {
+            serde_json::from_value::<SyncMessageLikeEvent<RoomMessageEventContent>>(
+                serde_json::json!({
+                    "type": "m.room.message",
+                    "event_id": "$a:example.com",
+                    "sender": sender,
+                    "origin_server_ts": 1000,
+                    "content": {
+                        "msgtype": "m.image",
+                        "body": body,
+                        "url": "mxc://example.com/abc",
+                        "info": { "size": 500 },
+                    },
+                }),
+            )
+            .unwrap()
+        };
+
+        let (_, _, path) =
+            auto_download_target(&image("@other:example.com", "cat.png"), &settings).unwrap();
+        assert_eq!(path, PathBuf::from("/downloads/cat.png"));
+
+        // Names can't escape the download directory.
+        let ev = image("@other:example.com", "../../cat.png");
+        let (_, _, path) = auto_download_target(&ev, &settings).unwrap();
+        assert_eq!(path, PathBuf::from("/downloads/cat.png"));
+
+        // Our own attachments are skipped.
+        let ev = image("@user:example.com", "cat.png");
+        assert!(auto_download_target(&ev, &settings).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_search_insert() {
+        use crate::tests::{mock_store, TEST_ROOM1_ID};
+
+        let store = Arc::new(tokio::sync::Mutex::new(mock_store().await));
+        let room_id = TEST_ROOM1_ID.clone();
+        let hit = |id: &str| {
+            SearchHit {
+                event_id: OwnedEventId::try_from(id).unwrap(),
+                sender: OwnedUserId::try_from("@alice:example.com").unwrap(),
+                sender_name: None,
+                timestamp: MilliSecondsSinceUnixEpoch(UInt::MIN),
+                body: "hello".into(),
+            }
+        };
+        let insert = |query: &str, id: &str, next: Option<&str>| {
+            let res = Ok((vec![hit(id)], next.map(String::from)));
+            let mut locked = store.try_lock().unwrap();
+            search_insert(room_id.clone(), query.into(), res, &mut locked);
+        };
+        let searches = || {
+            async {
+                let plans = load_plans(&store).await.into_iter();
+                plans
+                    .filter_map(|plan| {
+                        match plan {
+                            Plan::Search(_, query, next) => Some((query, next)),
+                            _ => None,
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        let mut locked = store.lock().await;
+        let info = locked.application.get_room_info(room_id.clone());
+        info.search_results = Some(("hello".into(), vec![]));
+        info.searching = true;
+        drop(locked);
+
+        // Pages keep getting fetched while the server has more.
+        insert("hello", "$a:example.com", Some("t1"));
+        assert_eq!(searches().await, vec![("hello".into(), Some("t1".into()))]);
+
+        insert("hello", "$b:example.com", None);
+        assert_eq!(searches().await, vec![]);
+
+        let mut locked = store.lock().await;
+        let info = locked.application.get_room_info(room_id.clone());
+        assert!(!info.searching);
+        assert_eq!(info.search_results.as_ref().unwrap().1.len(), 2);
+
+        // Results for an older search get dropped, and the newer one gets fetched.
+        info.search_results = Some(("bye".into(), vec![]));
+        info.searching = true;
+        drop(locked);
+
+        insert("hello", "$c:example.com", None);
+        assert_eq!(searches().await, vec![("bye".into(), None)]);
+
+        let mut locked = store.lock().await;
+        let info = locked.application.get_room_info(room_id.clone());
+        assert!(info.searching);
+        assert_eq!(info.search_results.as_ref().unwrap().1.len(), 0);
+    }
+}